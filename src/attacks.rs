@@ -0,0 +1,147 @@
+use crate::chess_init::ChessState;
+use crate::moves::{Move, MoveKind};
+use crate::types::{Color, Piece, PieceKind, Square};
+
+/// Queries about which pieces attack what. Square sets are `u64` bitboards with bit
+/// `Square::index` set for each square, as in [`crate::move_generation::LegalMasks`].
+impl ChessState {
+    /// Whether a piece of `by` attacks `square`
+    pub fn is_square_attacked(&self, square: Square, by: Color) -> bool {
+        self.attackers_to(square) & self.board.color_occupancy(by) != 0
+    }
+
+    /// Pieces of both colors attacking `square`
+    pub fn attackers_to(&self, square: Square) -> u64 {
+        ChessState::attackers(&self.board, square, self.board.occupancy())
+    }
+
+    /// Enemy pieces giving check to the side to move
+    pub fn checkers(&self) -> u64 {
+        let Some(king) = self.board.find(Piece::new(PieceKind::King, self.turn)) else {
+            return 0;
+        };
+        self.attackers_to(king) & self.board.color_occupancy(!self.turn)
+    }
+
+    pub fn check(&self) -> bool {
+        self.checkers() != 0
+    }
+
+    /// Pieces of `color` that may only move along the line between their king and an enemy
+    /// slider
+    pub fn pinned_pieces(&self, color: Color) -> u64 {
+        let Some(king) = self.board.find(Piece::new(PieceKind::King, color)) else {
+            return 0;
+        };
+        self.pin_rays(color, king)
+            .iter()
+            .enumerate()
+            .filter(|(_, ray)| **ray != !0)
+            .fold(0, |bits, (x, _)| bits | 1 << x)
+    }
+
+    /// Squares the pieces of `color` attack
+    pub fn attacked_squares(&self, color: Color) -> u64 {
+        ChessState::attacks(&self.board, color, self.board.occupancy())
+    }
+
+    /// Whether `m`, a legal move of the side to move, checks the enemy king, directly or by
+    /// uncovering a slider
+    pub fn gives_check(&self, m: &Move) -> bool {
+        let color = self.turn;
+        let Some(king) = self.board.find(Piece::new(PieceKind::King, !color)) else {
+            return false;
+        };
+        let mut vacated = m.from.bit();
+        let mut filled = m.to.bit();
+        let mut checker = Piece::new(m.promotion().unwrap_or(m.piece), color);
+        let mut checker_square = m.to;
+        match m.kind {
+            MoveKind::Castle { king_side } => {
+                //Only the rook can give check after castling
+                let (king_target, rook_target) = if king_side { (6, 5) } else { (2, 3) };
+                vacated |= m.to.bit();
+                checker = Piece::new(PieceKind::Rook, color);
+                checker_square = Square::new(rook_target, m.from.rank());
+                filled = Square::new(king_target, m.from.rank()).bit() | checker_square.bit();
+            }
+            MoveKind::EnPassant => vacated |= Square::new(m.to.file(), m.from.rank()).bit(),
+            _ => {}
+        }
+        let occupancy = (self.board.occupancy() & !vacated) | filled;
+        if ChessState::piece_attacks(checker, checker_square, occupancy) & king.bit() != 0 {
+            return true;
+        }
+        //The board still has the pieces where they were before the move, so leave out the
+        //squares it changes
+        ChessState::attackers(&self.board, king, occupancy)
+            & self.board.color_occupancy(color)
+            & !(vacated | filled)
+            != 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bits(squares: &[&str]) -> u64 {
+        squares
+            .iter()
+            .fold(0, |bits, x| bits | x.parse::<Square>().unwrap().bit())
+    }
+
+    #[test]
+    fn queries() {
+        let state = ChessState::from_fen("4k3/8/8/8/1b6/8/3N4/r3K2R w K - 0 1");
+        let square = |x: &str| x.parse::<Square>().unwrap();
+        assert_eq!(state.checkers(), bits(&["a1"]));
+        assert!(state.check());
+        assert!(state.is_square_attacked(square("b1"), Color::Black));
+        assert!(!state.is_square_attacked(square("e2"), Color::Black));
+        assert_eq!(state.attackers_to(square("e4")), bits(&["d2"]));
+        assert_eq!(state.pinned_pieces(Color::White), bits(&["d2"]));
+        assert_eq!(state.pinned_pieces(Color::Black), 0);
+        assert_eq!(
+            state.attacked_squares(Color::Black),
+            bits(&[
+                "b1", "c1", "d1", "e1", "a2", "a3", "a4", "a5", "a6", "a7", "a8", "d8", "f8", "d7",
+                "e7", "f7", "c5", "d6", "c3", "d2"
+            ])
+        );
+    }
+
+    #[test]
+    fn gives_check() {
+        let checks = |fen: &str| {
+            let state = ChessState::from_fen(fen);
+            let mut checks: Vec<String> = state.checks().map(|x| x.to_string()).collect();
+            checks.sort();
+            //Agrees with playing the moves out
+            let mut played: Vec<String> = state
+                .moves()
+                .filter(|x| state.do_move(x).check())
+                .map(|x| x.to_string())
+                .collect();
+            played.sort();
+            assert_eq!(checks, played);
+            checks
+        };
+        //Promotions and castling with check
+        assert_eq!(
+            checks("5k2/1P6/8/8/8/8/4B3/R3K2R w KQ - 0 1"),
+            ["O-O", "Ra1-a8", "Rh1-f1", "Rh1-h8", "b7-b8Q", "b7-b8R"]
+        );
+        //Every knight move uncovers the rook
+        assert_eq!(checks("5k2/8/8/8/8/5N2/8/5RK1 w - - 0 1").len(), 7);
+        //En passant uncovering the rook
+        assert_eq!(checks("8/8/8/R2pP2k/8/8/8/4K3 w - d6 0 1"), ["e5xd6"]);
+        for fen in [
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+            "bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w HFhf - 2 9",
+        ] {
+            checks(fen);
+        }
+    }
+}
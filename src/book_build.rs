@@ -0,0 +1,381 @@
+use crate::pgn::{PgnGame, PgnReader};
+use crate::polyglot::{self, BookEntry};
+use crate::types::Color;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Write};
+
+const USAGE: &str = "usage: chess-rs book-build [--max-ply N] [--min-games N] [--min-elo N] \
+[--result 1-0|0-1|1/2-1/2]... -o BOOK.bin GAMES.pgn...";
+
+/// Which games and moves go into the book
+#[derive(Debug, Clone, PartialEq)]
+pub struct BookBuildOptions {
+    /// Moves after this many plies are left out
+    pub max_ply: usize,
+    /// A move has to be played in at least this many games to be kept
+    pub min_games: u32,
+    /// Both players need at least this rating, if set
+    pub min_elo: Option<u32>,
+    /// Only games with one of these results are used, all decided games if empty
+    pub results: Vec<String>,
+}
+
+impl Default for BookBuildOptions {
+    fn default() -> BookBuildOptions {
+        BookBuildOptions {
+            max_ply: 20,
+            min_games: 1,
+            min_elo: None,
+            results: Vec::new(),
+        }
+    }
+}
+
+/// Wins, draws and losses for the side playing the move
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct MoveStats {
+    wins: u32,
+    draws: u32,
+    losses: u32,
+}
+
+impl MoveStats {
+    fn games(&self) -> u32 {
+        self.wins + self.draws + self.losses
+    }
+
+    /// The weight Polyglot's own book maker uses: two points a win, one a draw
+    fn weight(&self) -> u64 {
+        2 * self.wins as u64 + self.draws as u64
+    }
+}
+
+/// Collects move statistics from games and turns them into a Polyglot book
+pub struct BookBuilder {
+    options: BookBuildOptions,
+    stats: HashMap<(u64, u16), MoveStats>,
+    pub games: usize,
+    pub skipped: usize,
+    /// Games of PGN files that did not parse, as `file: line N: message`
+    pub errors: Vec<String>,
+}
+
+impl BookBuilder {
+    pub fn new(options: BookBuildOptions) -> BookBuilder {
+        BookBuilder {
+            options,
+            stats: HashMap::new(),
+            games: 0,
+            skipped: 0,
+            errors: Vec::new(),
+        }
+    }
+
+    fn accepts(&self, game: &PgnGame) -> bool {
+        let result = game.result.as_str();
+        if result == "*" || result.is_empty() {
+            return false;
+        }
+        if !self.options.results.is_empty() && !self.options.results.iter().any(|x| x == result) {
+            return false;
+        }
+        if let Some(min_elo) = self.options.min_elo {
+            let elo = |tag: &str| game.tag(tag).and_then(|x| x.parse::<u32>().ok());
+            if elo("WhiteElo").is_none_or(|x| x < min_elo)
+                || elo("BlackElo").is_none_or(|x| x < min_elo)
+            {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Adds the opening of one game, returning whether it passed the filters
+    pub fn add_game(&mut self, game: &PgnGame) -> bool {
+        if !self.accepts(game) {
+            self.skipped += 1;
+            return false;
+        }
+        let mut state = game.start_position();
+        for node in game.moves.iter().take(self.options.max_ply) {
+            let key = polyglot::polyglot_key(&state);
+            let entry = self
+                .stats
+                .entry((key, polyglot::encode_move(&state, &node.m)))
+                .or_default();
+            match (game.result.as_str(), state.turn) {
+                ("1/2-1/2", _) => entry.draws += 1,
+                ("1-0", Color::White) | ("0-1", Color::Black) => entry.wins += 1,
+                _ => entry.losses += 1,
+            }
+            state = state.do_move(&node.m);
+        }
+        self.games += 1;
+        true
+    }
+
+    /// Adds every game of a PGN file. Games that do not parse are counted as skipped
+    /// and their errors kept in `errors`.
+    pub fn add_pgn_file(&mut self, path: &str) -> io::Result<()> {
+        let reader = PgnReader::new(BufReader::new(File::open(path)?));
+        for game in reader {
+            match game {
+                Ok(game) => {
+                    self.add_game(&game);
+                }
+                Err(err) => {
+                    self.errors.push(format!("{}: {}", path, err));
+                    self.skipped += 1;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Book entries sorted by key and then by weight, as Polyglot expects. Moves that never
+    /// scored or were played in too few games are left out.
+    pub fn entries(&self) -> Vec<BookEntry> {
+        let kept: Vec<(&(u64, u16), &MoveStats)> = self
+            .stats
+            .iter()
+            .filter(|(_, x)| x.games() >= self.options.min_games && x.weight() > 0)
+            .collect();
+        //Weights are 16 bit, so big collections are scaled down
+        let max_weight = kept.iter().map(|(_, x)| x.weight()).max().unwrap_or(0);
+        let scale = max_weight.div_ceil(u16::MAX as u64).max(1);
+        let mut entries: Vec<BookEntry> = kept
+            .iter()
+            .map(|((key, m), stats)| BookEntry {
+                key: *key,
+                m: *m,
+                weight: (stats.weight() / scale).max(1) as u16,
+                learn: 0,
+            })
+            .collect();
+        entries.sort_by(|a, b| a.key.cmp(&b.key).then(b.weight.cmp(&a.weight)));
+        entries
+    }
+
+    pub fn write(&self, path: &str) -> io::Result<usize> {
+        let entries = self.entries();
+        let mut out = BufWriter::new(File::create(path)?);
+        for entry in entries.iter() {
+            out.write_all(&entry.to_bytes())?;
+        }
+        out.flush()?;
+        Ok(entries.len())
+    }
+}
+
+/// Runs `chess-rs book-build` with the arguments after the subcommand
+pub fn run(args: &[String]) -> Result<(), String> {
+    let mut options = BookBuildOptions::default();
+    let mut output: Option<String> = None;
+    let mut inputs: Vec<String> = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .cloned()
+                .ok_or_else(|| format!("{} needs a value\n{}", name, USAGE))
+        };
+        match arg.as_str() {
+            "--max-ply" => options.max_ply = parse_number(&value(arg)?)?,
+            "--min-games" => options.min_games = parse_number(&value(arg)?)?,
+            "--min-elo" => options.min_elo = Some(parse_number(&value(arg)?)?),
+            "--result" => {
+                let result = value(arg)?;
+                if !["1-0", "0-1", "1/2-1/2"].contains(&result.as_str()) {
+                    return Err(format!("Unknown result {}\n{}", result, USAGE));
+                }
+                options.results.push(result);
+            }
+            "-o" | "--output" => output = Some(value(arg)?),
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return Ok(());
+            }
+            x if x.starts_with('-') => return Err(format!("Unknown option {}\n{}", x, USAGE)),
+            x => inputs.push(x.to_string()),
+        }
+    }
+    let Some(output) = output else {
+        return Err(format!("No output file\n{}", USAGE));
+    };
+    if inputs.is_empty() {
+        return Err(format!("No PGN files\n{}", USAGE));
+    }
+
+    let mut builder = BookBuilder::new(options);
+    for input in inputs.iter() {
+        builder
+            .add_pgn_file(input)
+            .map_err(|err| format!("Could not read {}: {}", input, err))?;
+    }
+    for error in builder.errors.iter() {
+        eprintln!("{}", error);
+    }
+    let entries = builder
+        .write(&output)
+        .map_err(|err| format!("Could not write {}: {}", output, err))?;
+    println!(
+        "{} games used, {} skipped, {} entries written to {}",
+        builder.games, builder.skipped, entries, output
+    );
+    Ok(())
+}
+
+fn parse_number<T: std::str::FromStr>(text: &str) -> Result<T, String> {
+    text.parse()
+        .map_err(|_| format!("Expected a number, got {}", text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chess_init::ChessState;
+    use crate::polyglot::{BookSelection, PolyglotBook};
+
+    const GAMES: &str = r#"[WhiteElo "2500"]
+[BlackElo "2400"]
+
+1. e4 e5 2. Nf3 1-0
+
+[WhiteElo "2500"]
+[BlackElo "2400"]
+
+1. e4 c5 1/2-1/2
+
+[WhiteElo "2500"]
+[BlackElo "2400"]
+
+1. d4 d5 0-1
+
+[WhiteElo "2000"]
+[BlackElo "2000"]
+
+1. e4 e5 2. Nf3 1-0
+
+1. c4 *
+"#;
+
+    fn build(options: BookBuildOptions) -> BookBuilder {
+        let mut builder = BookBuilder::new(options);
+        for game in PgnReader::new(GAMES.as_bytes()) {
+            builder.add_game(&game.unwrap());
+        }
+        builder
+    }
+
+    /// The weights of the book moves after `moves`, in UCI notation
+    fn weights(entries: &[BookEntry], moves: &[&str]) -> Vec<(String, u16)> {
+        let mut state = ChessState::new_board();
+        for m in moves {
+            state = state.do_move(&state.parse_lan(m).unwrap());
+        }
+        let key = polyglot::polyglot_key(&state);
+        entries
+            .iter()
+            .filter(|x| x.key == key)
+            .map(|x| (x.uci_move(), x.weight))
+            .collect()
+    }
+
+    #[test]
+    fn weights_and_filters() {
+        let builder = build(BookBuildOptions::default());
+        assert_eq!((builder.games, builder.skipped), (4, 1));
+        let entries = builder.entries();
+        //Two points a win and one a draw, for the side playing the move. Moves that never
+        //scored, like 1. d4 and 1... e5, are left out.
+        assert_eq!(weights(&entries, &[]), [("e2e4".to_string(), 5)]);
+        assert_eq!(weights(&entries, &["e2-e4"]), [("c7c5".to_string(), 1)]);
+        assert_eq!(
+            weights(&entries, &["e2-e4", "e7-e5"]),
+            [("g1f3".to_string(), 4)]
+        );
+        assert_eq!(weights(&entries, &["d2-d4"]), [("d7d5".to_string(), 2)]);
+
+        let builder = build(BookBuildOptions {
+            min_elo: Some(2200),
+            ..BookBuildOptions::default()
+        });
+        assert_eq!((builder.games, builder.skipped), (3, 2));
+        let entries = builder.entries();
+        assert_eq!(weights(&entries, &[]), [("e2e4".to_string(), 3)]);
+        assert_eq!(
+            weights(&entries, &["e2-e4", "e7-e5"]),
+            [("g1f3".to_string(), 2)]
+        );
+
+        let entries = build(BookBuildOptions {
+            min_games: 2,
+            ..BookBuildOptions::default()
+        })
+        .entries();
+        assert_eq!(entries.len(), 2);
+        assert!(weights(&entries, &["d2-d4"]).is_empty());
+
+        let entries = build(BookBuildOptions {
+            results: vec!["1/2-1/2".to_string()],
+            ..BookBuildOptions::default()
+        })
+        .entries();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(weights(&entries, &[]), [("e2e4".to_string(), 1)]);
+    }
+
+    #[test]
+    fn scaling() {
+        let mut builder = BookBuilder::new(BookBuildOptions::default());
+        let stats = |wins: u32| MoveStats {
+            wins,
+            draws: 0,
+            losses: 0,
+        };
+        builder.stats.insert((1, 1), stats(70000));
+        builder.stats.insert((1, 2), stats(1));
+        builder.stats.insert((0, 3), stats(30000));
+        //The biggest weight, 140000, is divided by three to fit 16 bits, and nothing drops to 0
+        let weights: Vec<(u16, u16)> = builder.entries().iter().map(|x| (x.m, x.weight)).collect();
+        assert_eq!(weights, [(3, 20000), (1, 46666), (2, 1)]);
+    }
+
+    #[test]
+    fn sorted_book() {
+        let builder = build(BookBuildOptions::default());
+        let entries = builder.entries();
+        assert!(entries
+            .windows(2)
+            .all(|x| x[0].key < x[1].key || (x[0].key == x[1].key && x[0].weight >= x[1].weight)));
+
+        //The book reader's binary search finds every position again
+        let path = std::env::temp_dir().join(format!("chess-rs-book-{}.bin", std::process::id()));
+        let path = path.to_str().unwrap();
+        assert_eq!(builder.write(path).unwrap(), entries.len());
+        let mut book = PolyglotBook::open(path).unwrap();
+        for entry in entries.iter() {
+            assert!(book.entries(entry.key).unwrap().contains(entry));
+        }
+        let start = ChessState::new_board();
+        assert_eq!(
+            book.choose(&start, BookSelection::Best).unwrap(),
+            start.parse_lan("e2-e4")
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn parse_errors() {
+        let path = std::env::temp_dir().join(format!("chess-rs-games-{}.pgn", std::process::id()));
+        let path = path.to_str().unwrap();
+        std::fs::write(path, "1. e4 e5 1-0\n\n1. e4 e4 1-0\n\n1. d4 d5 0-1\n").unwrap();
+        let mut builder = BookBuilder::new(BookBuildOptions::default());
+        builder.add_pgn_file(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!((builder.games, builder.skipped), (2, 1));
+        assert_eq!(builder.errors.len(), 1);
+        assert!(builder.errors[0].starts_with(&format!("{}: line 3: ", path)));
+    }
+}
@@ -1,85 +1,138 @@
-use std::str;
-const BLACK_PIECE_INDICIES: &str = " prnbqk";
-const WHITE_PIECE_INDICIES: &str = " PRNBQK";
+use crate::types::{Board, CastlingRights, Color, Piece, PieceKind, Square};
 
 pub struct ChessState {
-    pub board: [[i8; 8]; 8],
-    pub turn: bool,
+    pub board: Board,
+    pub turn: Color,
     pub en_passant: [u8; 8],
-    pub castling: String,
-    pub halfmoves: i8,
-    pub fullmoves: i8,
+    /// Castling rights by rook file, so Chess960 needs nothing extra
+    pub castling: CastlingRights,
+    pub halfmoves: u16,
+    pub fullmoves: u16,
+    /// Encode castling as king-takes-rook in UCI (UCI_Chess960)
+    pub chess960: bool,
 }
 
+/// The standard back rank, a to h
+const BACK_RANK: [PieceKind; 8] = [
+    PieceKind::Rook,
+    PieceKind::Knight,
+    PieceKind::Bishop,
+    PieceKind::Queen,
+    PieceKind::King,
+    PieceKind::Bishop,
+    PieceKind::Knight,
+    PieceKind::Rook,
+];
+
 impl ChessState {
     pub fn new_board() -> ChessState {
-        return ChessState {
-            board: [
-                [2, 3, 4, 5, 6, 4, 3, 2],
-                [1, 1, 1, 1, 1, 1, 1, 1],
-                [0, 0, 0, 0, 0, 0, 0, 0],
-                [0, 0, 0, 0, 0, 0, 0, 0],
-                [0, 0, 0, 0, 0, 0, 0, 0],
-                [0, 0, 0, 0, 0, 0, 0, 0],
-                [-1, -1, -1, -1, -1, -1, -1, -1],
-                [-2, -3, -4, -5, -6, -4, -3, -2],
-            ],
-            en_passant: [0, 0, 0, 0, 0, 0, 0, 0],
-            turn: true,
-            castling: "kqKQ".to_string(),
-            halfmoves: 0,
-            fullmoves: 0,
+        let mut state = ChessState::empty_board();
+        state.set_back_ranks(BACK_RANK);
+        for file in 0..8 {
+            state.board[Square::new(file, 1)] = Some(Piece::new(PieceKind::Pawn, Color::White));
+            state.board[Square::new(file, 6)] = Some(Piece::new(PieceKind::Pawn, Color::Black));
+        }
+        for color in [Color::White, Color::Black] {
+            state.castling.insert(color, 7);
+            state.castling.insert(color, 0);
+        }
+        state
+    }
+
+    /// Chess960 starting position number `index` (0..960) in the standard numbering, 518 being the normal setup
+    pub fn new_chess960_board(index: usize) -> ChessState {
+        const KNIGHTS: [(usize, usize); 10] = [
+            (0, 1),
+            (0, 2),
+            (0, 3),
+            (0, 4),
+            (1, 2),
+            (1, 3),
+            (1, 4),
+            (2, 3),
+            (2, 4),
+            (3, 4),
+        ];
+        let mut back_rank: [Option<PieceKind>; 8] = [None; 8];
+        let mut n = index % 960;
+        back_rank[2 * (n % 4) + 1] = Some(PieceKind::Bishop);
+        n /= 4;
+        back_rank[2 * (n % 4)] = Some(PieceKind::Bishop);
+        n /= 4;
+        let empty = |back_rank: &[Option<PieceKind>; 8]| -> Vec<usize> {
+            (0..8).filter(|x| back_rank[*x].is_none()).collect()
         };
+        back_rank[empty(&back_rank)[n % 6]] = Some(PieceKind::Queen);
+        n /= 6;
+        let free = empty(&back_rank);
+        back_rank[free[KNIGHTS[n].0]] = Some(PieceKind::Knight);
+        back_rank[free[KNIGHTS[n].1]] = Some(PieceKind::Knight);
+        let free = empty(&back_rank);
+        back_rank[free[0]] = Some(PieceKind::Rook);
+        back_rank[free[1]] = Some(PieceKind::King);
+        back_rank[free[2]] = Some(PieceKind::Rook);
+
+        let mut state = ChessState::new_board();
+        state.set_back_ranks(back_rank.map(|x| x.unwrap()));
+        state.castling = CastlingRights::NONE;
+        for color in [Color::White, Color::Black] {
+            state.castling.insert(color, free[2]);
+            state.castling.insert(color, free[0]);
+        }
+        state.chess960 = true;
+        state
     }
+
+    /// Puts `pieces` on the first rank for white and mirrored on the eighth for black
+    fn set_back_ranks(&mut self, pieces: [PieceKind; 8]) {
+        for (file, kind) in pieces.into_iter().enumerate() {
+            for color in [Color::White, Color::Black] {
+                self.board[Square::new(file, color.back_rank())] = Some(Piece::new(kind, color));
+            }
+        }
+    }
+
     fn empty_board() -> ChessState {
-        return ChessState {
-            board: [
-                [0, 0, 0, 0, 0, 0, 0, 0],
-                [0, 0, 0, 0, 0, 0, 0, 0],
-                [0, 0, 0, 0, 0, 0, 0, 0],
-                [0, 0, 0, 0, 0, 0, 0, 0],
-                [0, 0, 0, 0, 0, 0, 0, 0],
-                [0, 0, 0, 0, 0, 0, 0, 0],
-                [0, 0, 0, 0, 0, 0, 0, 0],
-                [0, 0, 0, 0, 0, 0, 0, 0],
-            ],
+        ChessState {
+            board: Board::empty(),
             en_passant: [0, 0, 0, 0, 0, 0, 0, 0],
-            turn: true,
-            castling: "kqKQ".to_string(),
+            turn: Color::White,
+            castling: CastlingRights::NONE,
             halfmoves: 0,
-            fullmoves: 0,
-        };
+            fullmoves: 1,
+            chess960: false,
+        }
     }
 
     pub fn from_fen(fen_string: &str) -> ChessState {
         let mut board: ChessState = ChessState::empty_board();
-        let fen_string_parts: Vec<&str> = fen_string.split(" ").collect();
-        ChessState::board_from_fen(&mut board.board, fen_string_parts[0]);
-        board.turn = fen_string_parts[1] == "w";
-        board.castling = fen_string_parts[2].to_string();
-        ChessState::en_passant_from_fen(&mut board.en_passant, fen_string_parts[3]);
-        board.halfmoves = fen_string_parts[4].parse::<i8>().unwrap();
-        board.fullmoves = fen_string_parts[5].parse::<i8>().unwrap();
-        return board;
+        //Missing fields, such as the clocks in EPD style FENs, fall back to defaults
+        let fen_string_parts: Vec<&str> = fen_string.split_whitespace().collect();
+        let field =
+            |idx: usize, default: &'static str| *fen_string_parts.get(idx).unwrap_or(&default);
+        ChessState::board_from_fen(&mut board.board, field(0, ""));
+        board.turn = if field(1, "w") == "b" {
+            Color::Black
+        } else {
+            Color::White
+        };
+        board.castling = ChessState::castling_from_fen(&board.board, field(2, "-"));
+        ChessState::en_passant_from_fen(&mut board.en_passant, field(3, "-"));
+        board.halfmoves = field(4, "0").parse::<u16>().unwrap_or(0);
+        board.fullmoves = field(5, "1").parse::<u16>().unwrap_or(1).max(1);
+        board
     }
 
-    fn board_from_fen(board: &mut [[i8; 8]; 8], fen_string: &str) {
+    fn board_from_fen(board: &mut Board, fen_string: &str) {
         let rows = fen_string.split("/");
-        for (idx, row) in rows.into_iter().enumerate() {
+        for (idx, row) in rows.into_iter().take(8).enumerate() {
             let mut jdx: usize = 0;
             for s in row.chars() {
-                if WHITE_PIECE_INDICIES.contains(s) {
-                    board[7 - idx][jdx] = WHITE_PIECE_INDICIES
-                        .find(s)
-                        .expect("Error in parsing FEN string")
-                        as i8;
-                    jdx += 1;
+                if jdx > 7 {
+                    break;
                 }
-                if BLACK_PIECE_INDICIES.contains(s) {
-                    board[7 - idx][jdx] = -1
-                        * BLACK_PIECE_INDICIES
-                            .find(s)
-                            .expect("Error in parsing FEN string") as i8;
+                if let Some(piece) = Piece::from_char(s) {
+                    board[Square::new(jdx, 7 - idx)] = Some(piece);
                     jdx += 1;
                 }
                 if s.is_ascii_digit() {
@@ -89,22 +142,142 @@ impl ChessState {
         }
     }
 
+    pub fn to_fen(&self) -> String {
+        let rows: Vec<String> = (0..8)
+            .rev()
+            .map(|rank| {
+                let mut fen_row = String::new();
+                let mut empty: u8 = 0;
+                for file in 0..8 {
+                    let Some(piece) = self.board[Square::new(file, rank)] else {
+                        empty += 1;
+                        continue;
+                    };
+                    if empty > 0 {
+                        fen_row.push((empty + 48) as char);
+                        empty = 0;
+                    }
+                    fen_row.push(piece.to_char());
+                }
+                if empty > 0 {
+                    fen_row.push((empty + 48) as char);
+                }
+                fen_row
+            })
+            .collect();
+        let en_passant = match self.en_passant.iter().position(|x| *x != 0) {
+            Some(file) => format!(
+                "{}{}",
+                (file as u8 + 97) as char,
+                if self.turn.is_white() { 6 } else { 3 }
+            ),
+            None => "-".to_string(),
+        };
+        format!(
+            "{} {} {} {} {} {}",
+            rows.join("/"),
+            if self.turn.is_white() { "w" } else { "b" },
+            self.castling_to_fen(),
+            en_passant,
+            self.halfmoves,
+            self.fullmoves
+        )
+    }
+
+    /// File of the king of `color` if it stands on its back rank
+    pub fn king_file(&self, color: Color) -> Option<usize> {
+        let king = Piece::new(PieceKind::King, color);
+        (0..8).find(|x| self.board[Square::new(*x, color.back_rank())] == Some(king))
+    }
+
+    /// Writes X-FEN castling rights, using KQkq for the outermost rooks and the file otherwise
+    fn castling_to_fen(&self) -> String {
+        let mut rights: Vec<char> = Vec::new();
+        for color in [Color::White, Color::Black] {
+            let back_rank = color.back_rank();
+            let rook = Some(Piece::new(PieceKind::Rook, color));
+            let Some(king_file) = self.king_file(color) else {
+                continue;
+            };
+            for file in self.castling.files(color) {
+                let outermost = if file > king_file {
+                    (file + 1..8).all(|x| self.board[Square::new(x, back_rank)] != rook)
+                } else {
+                    (0..file).all(|x| self.board[Square::new(x, back_rank)] != rook)
+                };
+                let right = match (outermost, file > king_file) {
+                    (true, true) => 'k',
+                    (true, false) => 'q',
+                    _ => (file as u8 + 97) as char,
+                };
+                rights.push(if color.is_white() {
+                    right.to_ascii_uppercase()
+                } else {
+                    right
+                });
+            }
+        }
+        if rights.is_empty() {
+            return "-".to_string();
+        }
+        rights.into_iter().collect()
+    }
+
+    /// Reads both X-FEN (KQkq, outermost rook) and Shredder-FEN (HAha, rook file) castling fields
+    fn castling_from_fen(board: &Board, castling_str: &str) -> CastlingRights {
+        let mut castling = CastlingRights::NONE;
+        for x in castling_str.chars() {
+            let color = if x.is_ascii_uppercase() {
+                Color::White
+            } else {
+                Color::Black
+            };
+            let back_rank = color.back_rank();
+            let is = |file: usize, kind: PieceKind| {
+                board[Square::new(file, back_rank)] == Some(Piece::new(kind, color))
+            };
+            let Some(king_file) = (0..8).find(|x| is(*x, PieceKind::King)) else {
+                continue;
+            };
+            let rook_file = match x.to_ascii_lowercase() {
+                'k' => (king_file + 1..8).rev().find(|f| is(*f, PieceKind::Rook)),
+                'q' => (0..king_file).find(|f| is(*f, PieceKind::Rook)),
+                'a'..='h' => Some((x.to_ascii_lowercase() as u8 - 97) as usize),
+                _ => None,
+            };
+            if let Some(rook_file) = rook_file {
+                castling.insert(color, rook_file);
+            }
+        }
+        castling
+    }
+
     fn en_passant_from_fen(en_passant_field: &mut [u8], en_passant_str: &str) {
         if en_passant_str == "-" {
             return;
         }
-        let pos: usize = (en_passant_str.chars().nth(0).unwrap() as u8 - 97) as usize;
-        en_passant_field[pos] = 1;
+        if let Some(pos @ 'a'..='h') = en_passant_str.chars().next() {
+            en_passant_field[(pos as u8 - 97) as usize] = 1;
+        }
+    }
+
+    /// File of the rook the side to move may castle with on the given side, if any
+    pub fn castling_rook(&self, king_side: bool) -> Option<usize> {
+        let king_file = self.king_file(self.turn)?;
+        self.castling
+            .files(self.turn)
+            .find(|x| (*x > king_file) == king_side)
     }
 
     pub fn copy(&self) -> ChessState {
         ChessState {
-            board: self.board.clone(),
-            turn: self.turn.clone(),
-            en_passant: self.en_passant.clone(),
-            castling: self.castling.clone(),
-            halfmoves: self.halfmoves.clone(),
-            fullmoves: self.fullmoves.clone(),
+            board: self.board,
+            turn: self.turn,
+            en_passant: self.en_passant,
+            castling: self.castling,
+            halfmoves: self.halfmoves,
+            fullmoves: self.fullmoves,
+            chess960: self.chess960,
         }
     }
 }
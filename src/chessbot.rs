@@ -1,65 +1,835 @@
 use crate::chess_init::ChessState;
+use crate::eval::{self, EvalWeights, MATE};
+use crate::moves::{Move, MoveList, MAX_MOVES};
+#[cfg(feature = "tablebase")]
+use crate::syzygy::{Tablebases, Wdl};
+use crate::transposition::{Bound, TranspositionTable, TtEntry};
+use crate::types::Color;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
 pub struct MinimaxBot {
-    pub player: bool,
+    pub player: Color,
     pub max_depth: u8,
+    /// Search threads, the first one reporting and the rest helping through `tt` (Lazy SMP)
+    pub threads: usize,
+    /// 0 for the main thread, otherwise the index of a helper
+    pub thread: usize,
+    /// Tells a running search when to give up
+    pub control: Arc<SearchControl>,
+    /// Shared between every thread and kept between searches
+    pub tt: Arc<TranspositionTable>,
+    pub weights: EvalWeights,
+    /// The root moves to choose from, as with UCI `searchmoves`, or every move when empty
+    pub root_moves: Vec<Move>,
+    /// Syzygy tables probed at the root and after captures and pawn moves
+    #[cfg(feature = "tablebase")]
+    pub tablebases: Arc<Tablebases>,
+}
+
+/// Deepest search, in half-moves from the root
+pub const MAX_PLY: usize = 128;
+
+/// Score of a tablebase win, below any mate the search finds itself
+#[cfg(feature = "tablebase")]
+const TB_WIN: i32 = 1_000_000;
+
+/// Mate scores count half-moves from the root. The table counts them from the stored position
+/// instead, so they stay right when the position comes up again at another ply.
+fn score_to_tt(score: i32, ply: u8) -> i32 {
+    match eval::mate_plies(score) {
+        Some(_) => score + ply as i32 * score.signum(),
+        None => score,
+    }
+}
+
+fn score_from_tt(score: i32, ply: u8) -> i32 {
+    match eval::mate_plies(score) {
+        Some(_) => score - ply as i32 * score.signum(),
+        None => score,
+    }
+}
+
+/// Shared between a search and the thread that started it
+#[derive(Debug)]
+pub struct SearchControl {
+    stop: AtomicBool,
+    /// Set when the main thread is done, so the helpers stop with it
+    helpers_stop: AtomicBool,
+    pondering: AtomicBool,
+    start: Instant,
+    /// Milliseconds after `start` when the search has to stop, 0 for no limit
+    deadline: AtomicU64,
+    /// Nodes after which the search has to stop, 0 for no limit
+    node_limit: AtomicU64,
+    /// Nodes visited by all threads
+    nodes: AtomicU64,
+    /// Positions found in the tablebases
+    tbhits: AtomicU64,
+}
+
+impl Default for SearchControl {
+    fn default() -> SearchControl {
+        SearchControl::new(None, false)
+    }
+}
+
+impl SearchControl {
+    /// A pondering search ignores `time_limit` until `ponderhit`
+    pub fn new(time_limit: Option<Duration>, pondering: bool) -> SearchControl {
+        SearchControl {
+            stop: AtomicBool::new(false),
+            helpers_stop: AtomicBool::new(false),
+            pondering: AtomicBool::new(pondering),
+            start: Instant::now(),
+            deadline: AtomicU64::new(time_limit.map_or(0, |x| x.as_millis().max(1) as u64)),
+            node_limit: AtomicU64::new(0),
+            nodes: AtomicU64::new(0),
+            tbhits: AtomicU64::new(0),
+        }
+    }
+
+    pub fn stop(&self) {
+        self.stop.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.stop.load(Ordering::SeqCst)
+    }
+
+    pub fn is_pondering(&self) -> bool {
+        self.pondering.load(Ordering::SeqCst)
+    }
+
+    /// The opponent played the expected move. The search keeps running, now against the clock.
+    pub fn ponderhit(&self, time_limit: Option<Duration>) {
+        let deadline =
+            time_limit.map_or(0, |x| (self.start.elapsed() + x).as_millis().max(1) as u64);
+        self.deadline.store(deadline, Ordering::SeqCst);
+        self.pondering.store(false, Ordering::SeqCst);
+    }
+
+    /// Stops the search once it has visited `nodes` nodes, counted over all threads. Like the
+    /// time limit it waits for `ponderhit`.
+    pub fn limit_nodes(&self, nodes: u64) {
+        self.node_limit.store(nodes.max(1), Ordering::SeqCst);
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    pub fn nodes(&self) -> u64 {
+        self.nodes.load(Ordering::Relaxed)
+    }
+
+    pub fn tbhits(&self) -> u64 {
+        self.tbhits.load(Ordering::Relaxed)
+    }
+
+    pub fn should_stop(&self) -> bool {
+        if self.stop.load(Ordering::Relaxed) {
+            return true;
+        }
+        if self.pondering.load(Ordering::Relaxed) {
+            return false;
+        }
+        let deadline = self.deadline.load(Ordering::Relaxed);
+        let node_limit = self.node_limit.load(Ordering::Relaxed);
+        (deadline != 0 && self.start.elapsed().as_millis() as u64 >= deadline)
+            || (node_limit != 0 && self.nodes() >= node_limit)
+    }
+}
+
+/// The best lines of the nodes on the current path, kept for a whole search. Row `ply` holds
+/// the line from that ply on: the best move there followed by row `ply + 1`.
+struct PvTable {
+    moves: [[Move; MAX_PLY]; MAX_PLY],
+    len: [usize; MAX_PLY + 1],
+}
+
+impl PvTable {
+    fn new() -> PvTable {
+        PvTable {
+            moves: [[Move::NULL; MAX_PLY]; MAX_PLY],
+            len: [0; MAX_PLY + 1],
+        }
+    }
+
+    /// No line from `ply` on yet, as at a leaf
+    fn clear(&mut self, ply: u8) {
+        self.len[ply as usize] = 0;
+    }
+
+    /// Makes `m` followed by the line from `ply + 1` the line from `ply`
+    fn update(&mut self, ply: u8, m: Move) {
+        let ply = ply as usize;
+        let child = self.len[ply + 1];
+        let (rows, below) = self.moves.split_at_mut(ply + 1);
+        rows[ply][0] = m;
+        if child > 0 {
+            rows[ply][1..=child].copy_from_slice(&below[0][..child]);
+        }
+        self.len[ply] = child + 1;
+    }
+
+    fn line(&self, ply: u8) -> &[Move] {
+        &self.moves[ply as usize][..self.len[ply as usize]]
+    }
+}
+
+/// Hands out the moves of a list from the highest score to the lowest, picking the best of
+/// the rest each time so moves after a cutoff are never sorted
+struct MovePicker {
+    moves: MoveList,
+    scores: [i32; MAX_MOVES],
+    next: usize,
+}
+
+impl Iterator for MovePicker {
+    type Item = Move;
+
+    fn next(&mut self) -> Option<Move> {
+        let best = (self.next..self.moves.len()).max_by_key(|x| self.scores[*x])?;
+        self.moves.swap(self.next, best);
+        self.scores.swap(self.next, best);
+        self.next += 1;
+        Some(self.moves[self.next - 1])
+    }
+}
+
+/// A principal variation and its score from white's point of view
+#[derive(Debug, Clone, PartialEq)]
+pub struct PvLine {
+    pub moves: Vec<Move>,
+    pub score: i32,
 }
 
 pub trait ChessBot {
-    fn respond(&self, state: ChessState) -> String;
+    fn respond(&self, state: ChessState) -> Option<Move>;
 }
 
 impl ChessBot for MinimaxBot {
-    fn respond(&self, state: ChessState) -> String {
+    fn respond(&self, state: ChessState) -> Option<Move> {
         let res = self.minimax_search(state);
-        return res.0;
+        res.0
     }
 }
 impl MinimaxBot {
-    fn max_value(&self, state: ChessState, depth: u8, alpha: i32, beta: i32) -> (String, i32) {
+    /// A single threaded bot searching to `max_depth` with its own table of `hash_mb`
+    /// megabytes and no tablebases
+    pub fn new(
+        player: Color,
+        max_depth: u8,
+        control: Arc<SearchControl>,
+        hash_mb: usize,
+    ) -> MinimaxBot {
+        MinimaxBot {
+            player,
+            max_depth,
+            threads: 1,
+            thread: 0,
+            control,
+            tt: Arc::new(TranspositionTable::new(hash_mb)),
+            weights: EvalWeights::default(),
+            root_moves: Vec::new(),
+            #[cfg(feature = "tablebase")]
+            tablebases: Arc::new(Tablebases::default()),
+        }
+    }
+
+    fn should_stop(&self) -> bool {
+        self.control.should_stop()
+            || (self.thread > 0 && self.control.helpers_stop.load(Ordering::Relaxed))
+    }
+
+    /// The moves of `state`, the table move first. Helpers rotate the rest so the threads
+    /// spread over different parts of the tree.
+    fn ordered_moves(
+        &self,
+        state: &ChessState,
+        entry: Option<TtEntry>,
+    ) -> impl Iterator<Item = Move> {
+        let moves = state.get_all_possible_moves();
+        let len = moves.len();
+        let shift = if self.thread > 0 && len > 0 {
+            self.thread % len
+        } else {
+            0
+        };
+        let best = entry.and_then(|x| x.best);
+        let mut scores = [0; MAX_MOVES];
+        for (idx, m) in moves.iter().enumerate() {
+            //On a hash collision the table move belongs to another position, so it is only
+            //used if it is legal here
+            scores[idx] = if Some(m.pack()) == best {
+                i32::MAX
+            } else {
+                -(((idx + len - shift) % len) as i32)
+            };
+        }
+        MovePicker {
+            moves,
+            scores,
+            next: 0,
+        }
+    }
+
+    /// The evaluation of a leaf, with a mate counted from the root
+    fn leaf_score(&self, state: ChessState, ply: u8) -> i32 {
+        match ChessState::eval_with(state, &self.weights) {
+            MATE => MATE - ply as i32,
+            x if x == -MATE => ply as i32 - MATE,
+            x => x,
+        }
+    }
+
+    /// A score from the table that settles the node without searching it
+    fn tt_cutoff(entry: Option<TtEntry>, depth: u8, alpha: i32, beta: i32) -> Option<i32> {
+        let entry = entry.filter(|x| x.depth >= depth)?;
+        match entry.bound {
+            Bound::Exact => Some(entry.score),
+            Bound::Lower if entry.score >= beta => Some(entry.score),
+            Bound::Upper if entry.score <= alpha => Some(entry.score),
+            _ => None,
+        }
+    }
+
+    #[cfg(not(feature = "tablebase"))]
+    fn tablebase_score(&self, _: &ChessState, _: u8) -> Option<i32> {
+        None
+    }
+
+    /// The tablebase score of `state` from white's point of view. Only probed right after a
+    /// capture or pawn move, as WDL tables know nothing of the fifty-move counter.
+    #[cfg(feature = "tablebase")]
+    fn tablebase_score(&self, state: &ChessState, ply: u8) -> Option<i32> {
+        if ply == 0 || state.halfmoves != 0 || !self.tablebases.covers(state) {
+            return None;
+        }
+        let score = match self.tablebases.probe_wdl(state)? {
+            Wdl::Win => TB_WIN,
+            Wdl::Loss => -TB_WIN,
+            _ => 0,
+        };
+        self.control.tbhits.fetch_add(1, Ordering::Relaxed);
+        Some(if state.turn.is_white() { score } else { -score })
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn max_value(
+        &self,
+        state: ChessState,
+        depth: u8,
+        ply: u8,
+        alpha: i32,
+        beta: i32,
+        excluded: &[Move],
+        pv: &mut PvTable,
+    ) -> i32 {
+        self.control.nodes.fetch_add(1, Ordering::Relaxed);
+        pv.clear(ply);
         if depth == 0 || state.is_terminal() {
-            return (String::new(), ChessState::eval(state));
-        }
-        let mut alpha_clone = alpha.clone();
-        let beta_clone = beta.clone();
-        let mut best: (String, i32) = (String::new(), -2147483648);
-        for m in state.get_all_possible_moves().iter() {
-            let res = self.min_value(state.do_move(m), depth - 1, alpha_clone, beta_clone);
-            if res.1 > best.1 {
-                best = (m.clone(), res.1);
-                alpha_clone = alpha_clone.max(best.1);
+            return self.leaf_score(state, ply);
+        }
+        if let Some(score) = self.tablebase_score(&state, ply) {
+            return score;
+        }
+        let hash = state.hash();
+        let entry = self.tt.probe(hash).map(|x| TtEntry {
+            score: score_from_tt(x.score, ply),
+            ..x
+        });
+        //The root has to return a move, so it never stops at the table
+        if ply > 0 {
+            if let Some(score) = MinimaxBot::tt_cutoff(entry, depth, alpha, beta) {
+                return score;
+            }
+        }
+        let mut alpha_clone = alpha;
+        let beta_clone = beta;
+        let mut best_move: Option<Move> = None;
+        let mut best_score: i32 = -2147483648;
+        for m in self.ordered_moves(&state, entry) {
+            if excluded.contains(&m) {
+                continue;
+            }
+            //Keep the first move so an interrupted search still has something to play
+            if best_move.is_some() && self.should_stop() {
+                return best_score;
+            }
+            let score = self.min_value(
+                state.do_move(&m),
+                depth - 1,
+                ply + 1,
+                alpha_clone,
+                beta_clone,
+                &[],
+                pv,
+            );
+            if score > best_score || best_move.is_none() {
+                best_move = Some(m);
+                best_score = score;
+                pv.update(ply, m);
+                alpha_clone = alpha_clone.max(best_score);
             }
             if alpha_clone >= beta_clone {
-                return best;
+                break;
             }
         }
-        return best;
+        if excluded.is_empty() && !self.should_stop() {
+            let bound = if best_score >= beta {
+                Bound::Lower
+            } else if best_score <= alpha {
+                Bound::Upper
+            } else {
+                Bound::Exact
+            };
+            self.tt.store(
+                hash,
+                TtEntry {
+                    score: score_to_tt(best_score, ply),
+                    depth,
+                    bound,
+                    best: best_move.as_ref().map(Move::pack),
+                },
+            );
+        }
+        best_score
     }
 
-    fn min_value(&self, state: ChessState, depth: u8, alpha: i32, beta: i32) -> (String, i32) {
+    #[allow(clippy::too_many_arguments)]
+    fn min_value(
+        &self,
+        state: ChessState,
+        depth: u8,
+        ply: u8,
+        alpha: i32,
+        beta: i32,
+        excluded: &[Move],
+        pv: &mut PvTable,
+    ) -> i32 {
+        self.control.nodes.fetch_add(1, Ordering::Relaxed);
+        pv.clear(ply);
         if depth == 0 || state.is_terminal() {
-            return (String::new(), ChessState::eval(state));
-        }
-        let alpha_clone = alpha.clone();
-        let mut beta_clone = beta.clone();
-        let mut best: (String, i32) = (String::new(), 2147483647);
-        for m in state.get_all_possible_moves().iter() {
-            let res = self.max_value(state.do_move(m), depth - 1, alpha_clone, beta_clone);
-            if res.1 < best.1 {
-                best = (m.clone(), res.1);
-                beta_clone = beta_clone.min(best.1);
+            return self.leaf_score(state, ply);
+        }
+        if let Some(score) = self.tablebase_score(&state, ply) {
+            return score;
+        }
+        let hash = state.hash();
+        let entry = self.tt.probe(hash).map(|x| TtEntry {
+            score: score_from_tt(x.score, ply),
+            ..x
+        });
+        if ply > 0 {
+            if let Some(score) = MinimaxBot::tt_cutoff(entry, depth, alpha, beta) {
+                return score;
+            }
+        }
+        let alpha_clone = alpha;
+        let mut beta_clone = beta;
+        let mut best_move: Option<Move> = None;
+        let mut best_score: i32 = 2147483647;
+        for m in self.ordered_moves(&state, entry) {
+            if excluded.contains(&m) {
+                continue;
+            }
+            if best_move.is_some() && self.should_stop() {
+                return best_score;
+            }
+            let score = self.max_value(
+                state.do_move(&m),
+                depth - 1,
+                ply + 1,
+                alpha_clone,
+                beta_clone,
+                &[],
+                pv,
+            );
+            if score < best_score || best_move.is_none() {
+                best_move = Some(m);
+                best_score = score;
+                pv.update(ply, m);
+                beta_clone = beta_clone.min(best_score);
             }
             if beta_clone <= alpha_clone {
-                return best;
+                break;
             }
         }
-        return best;
+        if excluded.is_empty() && !self.should_stop() {
+            let bound = if best_score <= alpha {
+                Bound::Upper
+            } else if best_score >= beta {
+                Bound::Lower
+            } else {
+                Bound::Exact
+            };
+            self.tt.store(
+                hash,
+                TtEntry {
+                    score: score_to_tt(best_score, ply),
+                    depth,
+                    bound,
+                    best: best_move.as_ref().map(Move::pack),
+                },
+            );
+        }
+        best_score
     }
 
-    pub fn minimax_search(&self, state: ChessState) -> (String, i32) {
-        if state.turn {
-            self.max_value(state, self.max_depth, -2147483648, 2147483647)
-        } else {
-            self.min_value(state, self.max_depth, -2147483648, 2147483647)
+    pub fn minimax_search(&self, state: ChessState) -> (Option<Move>, i32) {
+        let lines = self.search(&state, 1, &mut |_, _| {});
+        match lines.first() {
+            Some(line) => (Some(line.moves[0]), line.score),
+            None => (None, ChessState::eval_with(state, &self.weights)),
+        }
+    }
+
+    /// The best `multi_pv` lines at `depth`, at most [`MAX_PLY`], found by searching the
+    /// root again with the moves of the earlier lines excluded, so every score is exact.
+    /// Moves outside `root_moves` are excluded from the start.
+    pub fn search_depth(&self, state: &ChessState, depth: u8, multi_pv: usize) -> Vec<PvLine> {
+        let depth = depth.min(MAX_PLY as u8);
+        let mut lines: Vec<PvLine> = Vec::new();
+        let all_moves = state.get_all_possible_moves();
+        let mut excluded: Vec<Move> = all_moves
+            .iter()
+            .filter(|x| !self.root_moves.is_empty() && !self.root_moves.contains(x))
+            .copied()
+            .collect();
+        let mut pv = PvTable::new();
+        let root_moves = all_moves.len() - excluded.len();
+        while lines.len() < multi_pv.min(root_moves) {
+            let (alpha, beta) = (-2147483648, 2147483647);
+            let score = if state.turn.is_white() {
+                self.max_value(state.copy(), depth, 0, alpha, beta, &excluded, &mut pv)
+            } else {
+                self.min_value(state.copy(), depth, 0, alpha, beta, &excluded, &mut pv)
+            };
+            let moves = pv.line(0).to_vec();
+            if moves.is_empty() {
+                break;
+            }
+            excluded.push(moves[0]);
+            lines.push(PvLine { moves, score });
+        }
+        lines
+    }
+
+    /// Iterative deepening up to `max_depth` or until `control` stops it, calling `report`
+    /// with the lines of every finished depth. Helper threads search the same root at the
+    /// same time, half of them a depth ahead, and share what they find through the table.
+    pub fn search(
+        &self,
+        state: &ChessState,
+        multi_pv: usize,
+        report: &mut dyn FnMut(u8, &[PvLine]),
+    ) -> Vec<PvLine> {
+        if let Some(line) = self.tablebase_root(state) {
+            report(1, std::slice::from_ref(&line));
+            return vec![line];
+        }
+        self.control.helpers_stop.store(false, Ordering::SeqCst);
+        thread::scope(|scope| {
+            for thread in 1..self.threads {
+                let helper = MinimaxBot {
+                    player: self.player,
+                    max_depth: self.max_depth,
+                    threads: 1,
+                    thread,
+                    control: self.control.clone(),
+                    tt: self.tt.clone(),
+                    weights: self.weights,
+                    root_moves: self.root_moves.clone(),
+                    #[cfg(feature = "tablebase")]
+                    tablebases: self.tablebases.clone(),
+                };
+                scope.spawn(move || {
+                    for depth in (1 + thread % 2) as u8..=helper.max_depth.min(MAX_PLY as u8) {
+                        helper.search_depth(state, depth, 1);
+                        if helper.should_stop() {
+                            break;
+                        }
+                    }
+                });
+            }
+            let lines = self.iterate(state, multi_pv, report);
+            self.control.helpers_stop.store(true, Ordering::SeqCst);
+            lines
+        })
+    }
+
+    #[cfg(not(feature = "tablebase"))]
+    fn tablebase_root(&self, _: &ChessState) -> Option<PvLine> {
+        None
+    }
+
+    /// The DTZ-optimal move when the tablebases cover the root, so a won ending is converted
+    /// within the fifty-move rule
+    #[cfg(feature = "tablebase")]
+    fn tablebase_root(&self, state: &ChessState) -> Option<PvLine> {
+        //The tables pick from every move, not only the allowed ones
+        if !self.root_moves.is_empty() || !self.tablebases.covers(state) {
+            return None;
+        }
+        let (m, wdl) = self.tablebases.probe_root(state)?;
+        self.control.tbhits.fetch_add(1, Ordering::Relaxed);
+        let score = match wdl {
+            Wdl::Win => TB_WIN,
+            Wdl::Loss => -TB_WIN,
+            _ => 0,
+        };
+        Some(PvLine {
+            moves: vec![m],
+            score: if state.turn.is_white() { score } else { -score },
+        })
+    }
+
+    fn iterate(
+        &self,
+        state: &ChessState,
+        multi_pv: usize,
+        report: &mut dyn FnMut(u8, &[PvLine]),
+    ) -> Vec<PvLine> {
+        let mut lines: Vec<PvLine> = Vec::new();
+        for depth in 1..=self.max_depth.min(MAX_PLY as u8) {
+            let depth_lines = self.search_depth(state, depth, multi_pv);
+            //An unfinished depth is only used when there is nothing else
+            if self.should_stop() && !lines.is_empty() {
+                break;
+            }
+            lines = depth_lines;
+            report(depth, &lines);
+            if self.should_stop() {
+                break;
+            }
+        }
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::translator;
+
+    fn bot(state: &ChessState, depth: u8) -> MinimaxBot {
+        MinimaxBot::new(state.turn, depth, Arc::new(SearchControl::default()), 1)
+    }
+
+    #[test]
+    fn mate_scores() {
+        //Mate in two: 1. Kb6 Kb8 2. Rh8# or 1. Kc7 Ka7 2. Ra1#
+        let state = ChessState::from_fen("k7/8/2K5/8/8/8/8/7R w - - 0 1");
+        let bot = bot(&state, 4);
+        for _ in 0..2 {
+            //The second search finds the mate through the table
+            let line = &bot.search(&state, 1, &mut |_, _| {})[0];
+            assert_eq!(line.score, MATE - 3);
+        }
+        assert_eq!(eval::mate_in(MATE - 3, Color::White), Some(2));
+        assert_eq!(eval::mate_in(MATE - 3, Color::Black), Some(-2));
+        assert_eq!(eval::mate_in(4 - MATE, Color::White), Some(-2));
+        assert_eq!(eval::mate_in(1500, Color::White), None);
+
+        //With a mate in one and longer mates available, the shortest one is played
+        let state = ChessState::from_fen("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1");
+        let line = &bot.search(&state, 1, &mut |_, _| {})[0];
+        assert_eq!(line.score, MATE - 1);
+        assert_eq!(translator::to_san(&state, &line.moves[0]), "Ra8#");
+
+        //Getting mated as black
+        let state = ChessState::from_fen("k7/8/1K6/8/8/8/8/7R b - - 0 1");
+        let line = &bot.search(&state, 1, &mut |_, _| {})[0];
+        assert_eq!(line.score, MATE - 2);
+    }
+
+    #[test]
+    fn table_moves() {
+        let state = ChessState::from_fen(
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        );
+        let bot = bot(&state, 1);
+        let moves: Vec<Move> = state.moves().collect();
+        let entry = |best: Option<u16>| {
+            Some(TtEntry {
+                score: 0,
+                depth: 1,
+                bound: Bound::Exact,
+                best,
+            })
+        };
+        let ordered: Vec<Move> = bot
+            .ordered_moves(&state, entry(Some(moves[20].pack())))
+            .collect();
+        assert_eq!(ordered[0], moves[20]);
+        assert_eq!(ordered.len(), moves.len());
+        assert!(moves.iter().all(|x| ordered.contains(x)));
+
+        //A move of another position, as after a hash collision, is not played
+        let other = ChessState::new_board().parse_lan("Ng1-f3").unwrap();
+        let ordered: Vec<Move> = bot
+            .ordered_moves(&state, entry(Some(other.pack())))
+            .collect();
+        assert_eq!(ordered, moves);
+
+        //Packed moves tell promotions apart
+        let state = ChessState::from_fen("1r2k3/P7/8/8/8/8/8/4K3 w - - 0 1");
+        let mut packed: Vec<u16> = state.moves().map(|x| x.pack()).collect();
+        let count = packed.len();
+        packed.sort();
+        packed.dedup();
+        assert_eq!(packed.len(), count);
+        let tt = TranspositionTable::new(1);
+        let best = state.parse_lan("a7xb8N").unwrap().pack();
+        tt.store(7, entry(Some(best)).unwrap());
+        assert_eq!(tt.probe(7), entry(Some(best)));
+    }
+
+    #[test]
+    fn multi_pv() {
+        let state = ChessState::from_fen(
+            "r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 2 3",
+        );
+        for depth in [1, 3] {
+            let lines = bot(&state, depth).search_depth(&state, depth, 4);
+            assert_eq!(lines.len(), 4);
+            for (idx, line) in lines.iter().enumerate() {
+                //Distinct root moves, best first
+                assert!(lines[..idx].iter().all(|x| x.moves[0] != line.moves[0]));
+                if idx > 0 {
+                    assert!(line.score <= lines[idx - 1].score);
+                }
+                //The same score as searching that move alone, with a fresh table
+                let others: Vec<Move> = state.moves().filter(|x| *x != line.moves[0]).collect();
+                let mut pv = PvTable::new();
+                let alone = bot(&state, depth).max_value(
+                    state.copy(),
+                    depth,
+                    0,
+                    i32::MIN,
+                    i32::MAX,
+                    &others,
+                    &mut pv,
+                );
+                assert_eq!(pv.line(0)[0], line.moves[0]);
+                assert_eq!(alone, line.score);
+            }
+        }
+        //Black's lines are ordered the other way, as scores are from white's point of view
+        let black = state.do_move(&state.parse_lan("Bf1-b5").unwrap());
+        let lines = bot(&black, 2).search_depth(&black, 2, 3);
+        assert_eq!(lines.len(), 3);
+        assert!(lines.windows(2).all(|x| x[0].score <= x[1].score));
+        //Never more lines than moves
+        let few = ChessState::from_fen("k7/8/1K6/8/8/8/8/7R b - - 0 1");
+        assert_eq!(bot(&few, 2).search_depth(&few, 2, 5).len(), 1);
+    }
+
+    /// Searches the start position as deep as `control` allows on another thread
+    fn search_thread(control: &Arc<SearchControl>) -> thread::JoinHandle<Vec<PvLine>> {
+        let control = control.clone();
+        thread::spawn(move || {
+            let state = ChessState::new_board();
+            let bot = MinimaxBot::new(state.turn, 64, control, 1);
+            bot.search(&state, 1, &mut |_, _| {})
+        })
+    }
+
+    fn is_legal(lines: &[PvLine]) -> bool {
+        let m = lines[0].moves[0];
+        ChessState::new_board().moves().any(|x| x == m)
+    }
+
+    #[test]
+    fn stop_and_deadline() {
+        let control = SearchControl::new(None, false);
+        assert!(!control.should_stop());
+        control.stop();
+        assert!(control.is_stopped() && control.should_stop());
+
+        let control = SearchControl::new(Some(Duration::from_millis(30)), false);
+        assert!(!control.should_stop());
+        thread::sleep(Duration::from_millis(40));
+        assert!(control.should_stop() && !control.is_stopped());
+
+        //A search without a limit runs until stopped, and still has a move to play
+        let control = Arc::new(SearchControl::default());
+        let search = search_thread(&control);
+        thread::sleep(Duration::from_millis(100));
+        assert!(!search.is_finished());
+        control.stop();
+        assert!(is_legal(&search.join().unwrap()));
+
+        //A timed search stops by itself
+        let control = Arc::new(SearchControl::new(Some(Duration::from_millis(100)), false));
+        let lines = search_thread(&control).join().unwrap();
+        assert!(is_legal(&lines));
+        assert!(control.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn node_limit() {
+        let control = Arc::new(SearchControl::default());
+        control.limit_nodes(1000);
+        let lines = search_thread(&control).join().unwrap();
+        assert!(is_legal(&lines));
+        //Nodes are only checked between moves, so the search runs a little past the limit
+        assert!((1000..1100).contains(&control.nodes()));
+    }
+
+    #[test]
+    fn root_moves() {
+        let state = ChessState::new_board();
+        let mut bot = bot(&state, 2);
+        bot.root_moves = vec![
+            state.parse_lan("a2-a3").unwrap(),
+            state.parse_lan("h2-h3").unwrap(),
+        ];
+        let lines = bot.search(&state, 4, &mut |_, _| {});
+        let mut firsts: Vec<String> = lines.iter().map(|x| x.moves[0].to_string()).collect();
+        firsts.sort();
+        assert_eq!(firsts, ["a2-a3", "h2-h3"]);
+    }
+
+    #[test]
+    fn ponderhit() {
+        //Pondering ignores the time limit
+        let control = SearchControl::new(Some(Duration::from_millis(10)), true);
+        thread::sleep(Duration::from_millis(20));
+        assert!(control.is_pondering() && !control.should_stop());
+        //The clock starts at the ponderhit
+        control.ponderhit(Some(Duration::from_millis(30)));
+        assert!(!control.is_pondering() && !control.should_stop());
+        thread::sleep(Duration::from_millis(40));
+        assert!(control.should_stop());
+
+        let control = Arc::new(SearchControl::new(Some(Duration::from_millis(10)), true));
+        let search = search_thread(&control);
+        thread::sleep(Duration::from_millis(100));
+        assert!(!search.is_finished());
+        control.ponderhit(Some(Duration::from_millis(100)));
+        assert!(is_legal(&search.join().unwrap()));
+        assert!(!control.is_stopped());
+        assert!(control.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn lazy_smp() {
+        //Mate in two: 1. Kc7 Ka7 2. Ra2#
+        let state = ChessState::from_fen("k7/8/2K5/8/8/8/1R6/8 w - - 0 1");
+        let single = bot(&state, 4).search(&state, 1, &mut |_, _| {});
+        for threads in [2, 4] {
+            let mut smp = bot(&state, 4);
+            smp.threads = threads;
+            let lines = smp.search(&state, 1, &mut |_, _| {});
+            assert!(state.moves().any(|x| x == lines[0].moves[0]));
+            assert_eq!(lines[0].score, single[0].score);
+            //Helpers stop with the main thread
+            assert!(smp.control.helpers_stop.load(Ordering::SeqCst));
         }
+        assert_eq!(eval::mate_in(single[0].score, state.turn), Some(2));
     }
 }
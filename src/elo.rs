@@ -0,0 +1,257 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// Wins, draws and losses of one side of a match
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Score {
+    pub wins: u32,
+    pub draws: u32,
+    pub losses: u32,
+}
+
+impl Score {
+    pub fn games(&self) -> u32 {
+        self.wins + self.draws + self.losses
+    }
+
+    /// Points per game, a draw being half a point
+    pub fn ratio(&self) -> f64 {
+        (self.wins as f64 + self.draws as f64 / 2.0) / self.games() as f64
+    }
+
+    /// The Elo difference the score suggests and the half width of its 95% confidence
+    /// interval. `None` until there is a game, and while every game has been won or lost.
+    pub fn elo(&self) -> Option<(f64, f64)> {
+        let games = self.games() as f64;
+        let ratio = self.ratio();
+        if self.games() == 0 || ratio <= 0.0 || ratio >= 1.0 {
+            return None;
+        }
+        //The spread of the points of a single game around the mean
+        let variance = (self.wins as f64 * (1.0 - ratio).powi(2)
+            + self.draws as f64 * (0.5 - ratio).powi(2)
+            + self.losses as f64 * ratio.powi(2))
+            / games;
+        let margin = 1.96 * (variance / games).sqrt();
+        let low = elo_difference((ratio - margin).max(f64::EPSILON));
+        let high = elo_difference((ratio + margin).min(1.0 - f64::EPSILON));
+        Some((elo_difference(ratio), (high - low) / 2.0))
+    }
+}
+
+impl fmt::Display for Score {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} - {} - {}", self.wins, self.losses, self.draws)
+    }
+}
+
+/// The Elo difference at which the stronger side is expected to score `ratio`
+pub fn elo_difference(ratio: f64) -> f64 {
+    -400.0 * (1.0 / ratio - 1.0).log10()
+}
+
+/// The points the first engine scored in pairs of games played from the same opening with
+/// colours swapped, counted by half points: `counts[0]` pairs lost twice up to `counts[4]`
+/// pairs won twice. Pairing cancels out much of what the openings favour.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Pentanomial {
+    pub counts: [u32; 5],
+}
+
+impl Pentanomial {
+    /// Counts a pair from the points of its two games, each 0, 0.5 or 1
+    pub fn add(&mut self, first: f64, second: f64) {
+        let half_points = ((first + second) * 2.0).round() as usize;
+        self.counts[half_points.min(4)] += 1;
+    }
+
+    pub fn pairs(&self) -> u32 {
+        self.counts.iter().sum()
+    }
+
+    /// The mean and variance of the score of a pair, scaled to 0 to 1
+    fn mean_variance(&self) -> (f64, f64) {
+        let pairs = self.pairs() as f64;
+        let share = |idx: usize| self.counts[idx] as f64 / pairs;
+        let mean: f64 = (0..5).map(|x| share(x) * x as f64 / 4.0).sum();
+        let variance: f64 = (0..5)
+            .map(|x| share(x) * (x as f64 / 4.0 - mean).powi(2))
+            .sum();
+        (mean, variance)
+    }
+}
+
+impl fmt::Display for Pentanomial {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let counts: Vec<String> = self.counts.iter().map(|x| x.to_string()).collect();
+        write!(f, "[{}]", counts.join(", "))
+    }
+}
+
+/// What a sequential probability ratio test has decided so far
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SprtResult {
+    Continue,
+    /// The first engine is not `elo1` stronger, it is at most `elo0` stronger
+    AcceptH0,
+    /// The first engine is at least `elo1` stronger
+    AcceptH1,
+}
+
+/// A sequential probability ratio test of whether the first engine is `elo0` or `elo1`
+/// stronger, wrongly accepting `elo1` with probability `alpha` and `elo0` with probability
+/// `beta`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sprt {
+    pub elo0: f64,
+    pub elo1: f64,
+    pub alpha: f64,
+    pub beta: f64,
+}
+
+impl Default for Sprt {
+    fn default() -> Sprt {
+        Sprt {
+            elo0: 0.0,
+            elo1: 5.0,
+            alpha: 0.05,
+            beta: 0.05,
+        }
+    }
+}
+
+impl Sprt {
+    /// The log likelihood ratios where H0 and H1 are accepted
+    pub fn bounds(&self) -> (f64, f64) {
+        (
+            (self.beta / (1.0 - self.alpha)).ln(),
+            ((1.0 - self.beta) / self.alpha).ln(),
+        )
+    }
+
+    /// The log likelihood ratio of H1 against H0 for the pairs played, using the normal
+    /// approximation of the pentanomial model
+    pub fn llr(&self, results: &Pentanomial) -> f64 {
+        if results.pairs() == 0 {
+            return 0.0;
+        }
+        let (mean, variance) = results.mean_variance();
+        if variance <= 0.0 {
+            return 0.0;
+        }
+        let expected = |elo: f64| 1.0 / (1.0 + 10f64.powf(-elo / 400.0));
+        let (score0, score1) = (expected(self.elo0), expected(self.elo1));
+        results.pairs() as f64 * (score1 - score0) * (2.0 * mean - score0 - score1)
+            / (2.0 * variance)
+    }
+
+    pub fn result(&self, results: &Pentanomial) -> SprtResult {
+        let llr = self.llr(results);
+        let (lower, upper) = self.bounds();
+        if llr >= upper {
+            SprtResult::AcceptH1
+        } else if llr <= lower {
+            SprtResult::AcceptH0
+        } else {
+            SprtResult::Continue
+        }
+    }
+}
+
+impl FromStr for Sprt {
+    type Err = String;
+
+    /// Reads `elo0=0,elo1=5,alpha=0.05,beta=0.05`, any of them left out keeping its default
+    fn from_str(text: &str) -> Result<Sprt, String> {
+        let mut sprt = Sprt::default();
+        for pair in text.split(',').filter(|x| !x.trim().is_empty()) {
+            let Some((key, value)) = pair.split_once('=') else {
+                return Err(format!("Expected key=value, got {}", pair));
+            };
+            let value: f64 = value
+                .trim()
+                .parse()
+                .map_err(|_| format!("Expected a number for {}, got {}", key, value))?;
+            match key.trim() {
+                "elo0" => sprt.elo0 = value,
+                "elo1" => sprt.elo1 = value,
+                "alpha" => sprt.alpha = value,
+                "beta" => sprt.beta = value,
+                x => return Err(format!("Unknown SPRT setting {}", x)),
+            }
+        }
+        let probability = |x: f64| x > 0.0 && x < 1.0;
+        if sprt.elo1 <= sprt.elo0 || !probability(sprt.alpha) || !probability(sprt.beta) {
+            return Err("SPRT needs elo0 < elo1 and alpha and beta between 0 and 1".to_string());
+        }
+        Ok(sprt)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn elo() {
+        assert!((elo_difference(0.5)).abs() < 1e-9);
+        assert!((elo_difference(0.75) - 190.85).abs() < 0.01);
+        let even = Score {
+            wins: 10,
+            draws: 20,
+            losses: 10,
+        };
+        let (elo, margin) = even.elo().unwrap();
+        assert!(elo.abs() < 1e-9);
+        assert!(margin > 50.0 && margin < 100.0);
+        let ahead = Score {
+            wins: 30,
+            draws: 10,
+            losses: 10,
+        };
+        assert!(ahead.elo().unwrap().0 > 100.0);
+        assert_eq!(Score::default().elo(), None);
+        assert_eq!(
+            Score {
+                wins: 3,
+                draws: 0,
+                losses: 0
+            }
+            .elo(),
+            None
+        );
+    }
+
+    #[test]
+    fn sprt() {
+        let sprt: Sprt = "elo0=0, elo1=10".parse().unwrap();
+        assert_eq!(sprt.alpha, 0.05);
+        let (lower, upper) = sprt.bounds();
+        assert!((lower + 2.944).abs() < 0.001 && (upper - 2.944).abs() < 0.001);
+        assert!("elo0=5,elo1=0".parse::<Sprt>().is_err());
+        assert!("gamma=1".parse::<Sprt>().is_err());
+
+        let mut results = Pentanomial::default();
+        assert_eq!(sprt.llr(&results), 0.0);
+        results.add(1.0, 0.5);
+        results.add(0.5, 0.5);
+        results.add(0.0, 1.0);
+        assert_eq!(results.counts, [0, 0, 2, 1, 0]);
+        assert_eq!(results.to_string(), "[0, 0, 2, 1, 0]");
+
+        //Winning most pairs proves the better engine, losing them the other way round
+        let ahead = Pentanomial {
+            counts: [10, 40, 200, 80, 20],
+        };
+        assert!(sprt.llr(&ahead) > upper);
+        assert_eq!(sprt.result(&ahead), SprtResult::AcceptH1);
+        let behind = Pentanomial {
+            counts: [20, 80, 200, 40, 10],
+        };
+        assert_eq!(sprt.result(&behind), SprtResult::AcceptH0);
+        let even = Pentanomial {
+            counts: [1, 4, 10, 4, 1],
+        };
+        assert_eq!(sprt.result(&even), SprtResult::Continue);
+    }
+}
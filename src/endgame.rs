@@ -0,0 +1,491 @@
+use crate::chess_init::ChessState;
+use crate::types::{Color, Piece, PieceKind, Square};
+use std::fmt;
+use std::sync::OnceLock;
+
+/// Piece letters from king to pawn, the order material signatures use
+pub const PIECE_ORDER: &str = "KQRBNP";
+
+/// How many pieces of each kind one side has, indexed by [`PieceKind::index`]. Shown as
+/// letters from king to pawn, such as "KRP".
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Material([u8; 7]);
+
+impl Material {
+    /// The material written as letters, such as "KBN"
+    pub const fn of(letters: &str) -> Material {
+        let letters = letters.as_bytes();
+        let mut counts = [0; 7];
+        let mut idx = 0;
+        while idx < letters.len() {
+            let kind = match letters[idx] {
+                b'P' => PieceKind::Pawn,
+                b'R' => PieceKind::Rook,
+                b'N' => PieceKind::Knight,
+                b'B' => PieceKind::Bishop,
+                b'Q' => PieceKind::Queen,
+                b'K' => PieceKind::King,
+                _ => panic!("Not a piece letter"),
+            };
+            counts[kind as usize] += 1;
+            idx += 1;
+        }
+        Material(counts)
+    }
+
+    pub fn count(self, kind: PieceKind) -> u8 {
+        self.0[kind.index()]
+    }
+
+    /// The same material with the pawns taken off
+    pub fn without_pawns(self) -> Material {
+        let mut counts = self.0;
+        counts[PieceKind::Pawn.index()] = 0;
+        Material(counts)
+    }
+}
+
+impl fmt::Display for Material {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for letter in PIECE_ORDER.chars() {
+            let kind = PieceKind::from_letter(letter).unwrap();
+            for _ in 0..self.count(kind) {
+                write!(f, "{}", letter)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+const K: Material = Material::of("K");
+const KP: Material = Material::of("KP");
+const KB: Material = Material::of("KB");
+const KBN: Material = Material::of("KBN");
+const KR: Material = Material::of("KR");
+const KQ: Material = Material::of("KQ");
+/// Score of a won endgame, above anything mobility gives
+pub(crate) const KNOWN_WIN: i32 = 10000;
+const ROOK_VALUE: i32 = 1000;
+
+//Positions of the KPK bitbase: side to move, both kings and a pawn on files a to d
+const KPK_SIZE: usize = 2 * 24 * 64 * 64;
+//Results while generating the bitbase, as bits so results of successors can be combined
+const INVALID: u8 = 0;
+const UNKNOWN: u8 = 1;
+const DRAW: u8 = 2;
+const WIN: u8 = 4;
+
+fn distance(a: Square, b: Square) -> i32 {
+    a.rank().abs_diff(b.rank()).max(a.file().abs_diff(b.file())) as i32
+}
+
+fn square_distance(a: usize, b: usize) -> i32 {
+    distance(Square::from_index(a), Square::from_index(b))
+}
+
+fn king_attacks(sq: usize) -> impl Iterator<Item = usize> {
+    (0..64).filter(move |x| *x != sq && square_distance(sq, *x) == 1)
+}
+
+/// Squares a white pawn on `sq` attacks
+fn pawn_attacks(sq: usize) -> impl Iterator<Item = usize> {
+    (0..64).filter(move |x| *x / 8 == sq / 8 + 1 && (*x % 8).abs_diff(sq % 8) == 1)
+}
+
+/// White king, black king and white pawn, with the pawn on files a to d
+fn kpk_index(white_to_move: bool, black_king: usize, white_king: usize, pawn: usize) -> usize {
+    white_king
+        | (black_king << 6)
+        | ((!white_to_move as usize) << 12)
+        | ((pawn % 8) << 13)
+        | ((6 - pawn / 8) << 15)
+}
+
+fn kpk_initial(idx: usize) -> u8 {
+    let white_king = idx & 63;
+    let black_king = (idx >> 6) & 63;
+    let white_to_move = (idx >> 12) & 1 == 0;
+    let pawn = (6 - (idx >> 15)) * 8 + ((idx >> 13) & 3);
+    let push = pawn + 8;
+    if square_distance(white_king, black_king) <= 1
+        || white_king == pawn
+        || black_king == pawn
+        || (white_to_move && pawn_attacks(pawn).any(|x| x == black_king))
+    {
+        return INVALID;
+    }
+    //The pawn promotes without being taken
+    if white_to_move
+        && pawn / 8 == 6
+        && white_king != push
+        && (square_distance(black_king, push) > 1 || square_distance(white_king, push) == 1)
+    {
+        return WIN;
+    }
+    //Stalemate, or the pawn is lost
+    let guarded =
+        |x: usize| square_distance(white_king, x) == 1 || pawn_attacks(pawn).any(|y| y == x);
+    if !white_to_move
+        && (king_attacks(black_king).all(guarded)
+            || (square_distance(black_king, pawn) == 1 && square_distance(white_king, pawn) > 1))
+    {
+        return DRAW;
+    }
+    UNKNOWN
+}
+
+/// A win for white if some move wins, a draw for black if some move draws
+fn kpk_classify(db: &[u8], idx: usize) -> u8 {
+    let white_king = idx & 63;
+    let black_king = (idx >> 6) & 63;
+    let white_to_move = (idx >> 12) & 1 == 0;
+    let pawn = (6 - (idx >> 15)) * 8 + ((idx >> 13) & 3);
+    let (good, bad) = if white_to_move {
+        (WIN, DRAW)
+    } else {
+        (DRAW, WIN)
+    };
+    let mut result = INVALID;
+    if white_to_move {
+        for sq in king_attacks(white_king) {
+            result |= db[kpk_index(false, black_king, sq, pawn)];
+        }
+        if pawn / 8 < 6 {
+            result |= db[kpk_index(false, black_king, white_king, pawn + 8)];
+        }
+        if pawn / 8 == 1 && pawn + 8 != white_king && pawn + 8 != black_king {
+            result |= db[kpk_index(false, black_king, white_king, pawn + 16)];
+        }
+    } else {
+        for sq in king_attacks(black_king) {
+            result |= db[kpk_index(true, sq, white_king, pawn)];
+        }
+    }
+    if result & good != 0 {
+        return good;
+    }
+    if result & UNKNOWN != 0 {
+        UNKNOWN
+    } else {
+        bad
+    }
+}
+
+/// One bit per KPK position telling whether white wins, built by retrograde analysis the
+/// first time it is needed
+fn kpk_bitbase() -> &'static [u64] {
+    static BITBASE: OnceLock<Vec<u64>> = OnceLock::new();
+    BITBASE.get_or_init(|| {
+        let mut db: Vec<u8> = (0..KPK_SIZE).map(kpk_initial).collect();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for idx in 0..KPK_SIZE {
+                if db[idx] == UNKNOWN {
+                    db[idx] = kpk_classify(&db, idx);
+                    changed |= db[idx] != UNKNOWN;
+                }
+            }
+        }
+        let mut bits = vec![0u64; KPK_SIZE / 64];
+        for (idx, result) in db.iter().enumerate() {
+            if *result == WIN {
+                bits[idx / 64] |= 1 << (idx % 64);
+            }
+        }
+        bits
+    })
+}
+
+/// Whether the side with the pawn wins, squares seen from that side
+fn kpk_wins(strong_to_move: bool, strong_king: Square, pawn: Square, weak_king: Square) -> bool {
+    let mirror = if pawn.file() > 3 { 7 } else { 0 };
+    let sq = |x: Square| x.index() ^ mirror;
+    let idx = kpk_index(strong_to_move, sq(weak_king), sq(strong_king), sq(pawn));
+    kpk_bitbase()[idx / 64] & (1 << (idx % 64)) != 0
+}
+
+/// How far a square is from the centre, 2 in the middle up to 14 in a corner
+fn center_distance(sq: Square) -> i32 {
+    (2 * sq.rank() as i32 - 7).abs() + (2 * sq.file() as i32 - 7).abs()
+}
+
+fn dark_square(sq: Square) -> bool {
+    (sq.rank() + sq.file()).is_multiple_of(2)
+}
+
+impl ChessState {
+    /// The pieces of one side, shown as "KRP". The two sides together give the material
+    /// signature, such as KRPvKR.
+    pub fn material(&self, color: Color) -> Material {
+        let mut counts = [0; 7];
+        for (_, piece) in self.board.pieces() {
+            if piece.color == color {
+                counts[piece.kind.index()] += 1;
+            }
+        }
+        Material(counts)
+    }
+
+    /// Square of a piece seen from `strong`, so its pawns move up the board. `ours` picks a
+    /// piece of `strong` rather than of the other side.
+    fn relative_position(&self, kind: PieceKind, ours: bool, strong: Color) -> Square {
+        let color = if ours { strong } else { !strong };
+        let square = self
+            .board
+            .find(Piece::new(kind, color))
+            .expect("The material signature has the piece");
+        if strong.is_white() {
+            square
+        } else {
+            square.flip()
+        }
+    }
+
+    /// A score from an evaluator for the material, if it has one. Known endgames are
+    /// scored by rule rather than by mobility.
+    pub fn endgame_eval(&self) -> Option<i32> {
+        let white = self.material(Color::White);
+        let black = self.material(Color::Black);
+        for strong in [Color::White, Color::Black] {
+            let (ours, theirs) = if strong.is_white() {
+                (white, black)
+            } else {
+                (black, white)
+            };
+            let evaluator: fn(&ChessState, Color) -> i32 = match (ours, theirs) {
+                (KP, K) => ChessState::kpk,
+                (KBN, K) => ChessState::kbnk,
+                (KQ, K) | (KR, K) => ChessState::kxk,
+                (KR, KP) => ChessState::krkp,
+                _ => continue,
+            };
+            //A tolerant FEN may put pawns on the first or last rank, which no evaluator handles
+            if self
+                .board
+                .pieces()
+                .any(|(sq, x)| x.kind == PieceKind::Pawn && (sq.rank() == 0 || sq.rank() == 7))
+            {
+                return None;
+            }
+            //Checkmate is handled before, so no moves means stalemate
+            if self.is_terminal() {
+                return Some(0);
+            }
+            let score = evaluator(self, strong);
+            return Some(score * strong.sign() as i32);
+        }
+        None
+    }
+
+    fn kpk(&self, strong: Color) -> i32 {
+        let king = self.relative_position(PieceKind::King, true, strong);
+        let pawn = self.relative_position(PieceKind::Pawn, true, strong);
+        let weak_king = self.relative_position(PieceKind::King, false, strong);
+        if kpk_wins(self.turn == strong, king, pawn, weak_king) {
+            return KNOWN_WIN + 20 * pawn.rank() as i32;
+        }
+        0
+    }
+
+    /// Mating with a queen or rook: drive the king to the edge and follow it
+    fn kxk(&self, strong: Color) -> i32 {
+        let king = self.relative_position(PieceKind::King, true, strong);
+        let weak_king = self.relative_position(PieceKind::King, false, strong);
+        KNOWN_WIN + 10 * center_distance(weak_king) + 20 * (7 - distance(king, weak_king))
+    }
+
+    /// Bishop and knight only mate in a corner of the bishop's color
+    fn kbnk(&self, strong: Color) -> i32 {
+        let king = self.relative_position(PieceKind::King, true, strong);
+        let bishop = self.relative_position(PieceKind::Bishop, true, strong);
+        let weak_king = self.relative_position(PieceKind::King, false, strong);
+        let corners = if dark_square(bishop) {
+            [Square::new(0, 0), Square::new(7, 7)]
+        } else {
+            [Square::new(7, 0), Square::new(0, 7)]
+        };
+        let corner_distance = corners
+            .iter()
+            .map(|x| {
+                (x.rank().abs_diff(weak_king.rank()) + x.file().abs_diff(weak_king.file())) as i32
+            })
+            .min()
+            .unwrap();
+        KNOWN_WIN + 20 * (14 - corner_distance) + 20 * (7 - distance(king, weak_king))
+    }
+
+    /// Rook against pawn: a win unless the pawn is far on and its king supports it
+    fn krkp(&self, strong: Color) -> i32 {
+        let king = self.relative_position(PieceKind::King, true, strong);
+        let rook = self.relative_position(PieceKind::Rook, true, strong);
+        let weak_king = self.relative_position(PieceKind::King, false, strong);
+        let pawn = self.relative_position(PieceKind::Pawn, false, strong);
+        let strong_to_move = self.turn == strong;
+        let queening = Square::new(pawn.file(), 0);
+        let below = Square::new(pawn.file(), pawn.rank() - 1);
+        if king.file() == pawn.file() && king.rank() < pawn.rank() {
+            //The king is in front of the pawn
+            return ROOK_VALUE - distance(king, pawn);
+        }
+        if distance(weak_king, pawn) >= 3 + !strong_to_move as i32 && distance(weak_king, rook) >= 3
+        {
+            return ROOK_VALUE - distance(king, pawn);
+        }
+        if weak_king.rank() <= 2
+            && distance(weak_king, pawn) == 1
+            && king.rank() >= 3
+            && distance(king, pawn) > 2 + strong_to_move as i32
+        {
+            return 80 - 8 * distance(king, pawn);
+        }
+        200 - 8 * (distance(king, below) - distance(weak_king, below) - distance(pawn, queening))
+    }
+
+    /// Scales a mobility score down for material that is hard to win with
+    pub fn scale_drawish(&self, score: i32) -> i32 {
+        let white = self.material(Color::White);
+        let black = self.material(Color::Black);
+        //Opposite coloured bishops with nothing but pawns
+        if white.without_pawns() == KB && black.without_pawns() == KB {
+            let bishop = |color: Color| {
+                self.board
+                    .find(Piece::new(PieceKind::Bishop, color))
+                    .unwrap()
+            };
+            if dark_square(bishop(Color::White)) != dark_square(bishop(Color::Black)) {
+                return score / 4;
+            }
+        }
+        for strong in [Color::White, Color::Black] {
+            let (ours, theirs) = if strong.is_white() {
+                (white, black)
+            } else {
+                (black, white)
+            };
+            if theirs != K || !(ours.without_pawns() == K || ours.without_pawns() == KB) {
+                continue;
+            }
+            if ours.count(PieceKind::Pawn) > 0 && self.wrong_rook_pawns(strong) {
+                return 0;
+            }
+        }
+        score
+    }
+
+    /// Rook pawns of `strong` on a single file, with the bishop unable to cover the queening
+    /// square and the other king already there
+    fn wrong_rook_pawns(&self, strong: Color) -> bool {
+        let pawns = self.board.find_all(Piece::new(PieceKind::Pawn, strong));
+        let file = Square::from_index(pawns.trailing_zeros() as usize).file();
+        if (file != 0 && file != 7) || Square::in_set(pawns).any(|x| x.file() != file) {
+            return false;
+        }
+        let queening = Square::new(file, (!strong).back_rank());
+        let bishops = self.board.find_all(Piece::new(PieceKind::Bishop, strong));
+        if Square::in_set(bishops).any(|x| dark_square(x) == dark_square(queening)) {
+            return false;
+        }
+        let Some(weak_king) = self.board.find(Piece::new(PieceKind::King, !strong)) else {
+            return false;
+        };
+        distance(weak_king, queening) <= 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kpk() {
+        let wins = |fen: &str| ChessState::from_fen(fen).endgame_eval().unwrap();
+        //King on the sixth in front of the pawn wins whoever moves
+        assert!(wins("4k3/8/4K3/4P3/8/8/8/8 w - - 0 1") >= KNOWN_WIN);
+        assert!(wins("4k3/8/4K3/4P3/8/8/8/8 b - - 0 1") >= KNOWN_WIN);
+        assert_eq!(wins("8/8/8/8/8/1k6/P7/7K b - - 0 1"), 0);
+        assert_eq!(wins("k7/8/8/8/8/8/P7/K7 w - - 0 1"), 0);
+        assert!(wins("8/8/8/8/4p3/4k3/8/4K3 b - - 0 1") <= -KNOWN_WIN);
+        //Stalemate
+        assert_eq!(wins("4k3/4P3/4K3/8/8/8/8/8 b - - 0 1"), 0);
+    }
+
+    #[test]
+    fn drawish() {
+        let state = ChessState::from_fen("k7/8/8/8/8/8/P7/K1B5 w - - 0 1");
+        assert_eq!(state.scale_drawish(500), 0);
+        let state = ChessState::from_fen("k7/8/8/8/8/8/P7/KB6 w - - 0 1");
+        assert_eq!(state.scale_drawish(500), 500);
+        let state = ChessState::from_fen("4k3/3b4/8/8/8/3P4/3B4/4K3 w - - 0 1");
+        assert_eq!(state.scale_drawish(500), 125);
+    }
+
+    fn eval(fen: &str) -> i32 {
+        ChessState::from_fen(fen).endgame_eval().unwrap()
+    }
+
+    #[test]
+    fn kbnk() {
+        //The dark squared bishop mates on a1 or h8, not on h1
+        let right = eval("8/8/8/8/8/2K5/k7/2B1N3 w - - 0 1");
+        let wrong = eval("8/8/8/8/8/5K2/7k/2B1N3 w - - 0 1");
+        assert!(wrong >= KNOWN_WIN);
+        assert!(right > wrong);
+        //Black's bishop and knight score for black
+        assert!(eval("2b1n3/K7/2k5/8/8/8/8/8 b - - 0 1") <= -KNOWN_WIN);
+    }
+
+    #[test]
+    fn kxk() {
+        for piece in ['R', 'Q'] {
+            let center = eval(&format!("8/8/8/4k3/8/4K3/8/1{}6 w - - 0 1", piece));
+            let edge = eval(&format!("4k3/8/4K3/8/8/8/8/1{}6 w - - 0 1", piece));
+            let far = eval(&format!("4k3/8/8/8/8/4K3/8/1{}6 w - - 0 1", piece));
+            assert!(center >= KNOWN_WIN);
+            //The weak king on the edge, then the strong king close to it, scores higher
+            assert!(edge > center);
+            assert!(edge > far);
+        }
+    }
+
+    #[test]
+    fn krkp() {
+        //The king in front of the pawn wins
+        let won = eval("R7/8/8/3p4/8/3K4/8/7k w - - 0 1");
+        //The pawn is about to queen with its king next to it and the other king far away
+        let drawn = eval("8/8/K7/8/8/8/2kp4/7R w - - 0 1");
+        assert!(won > ROOK_VALUE - 10);
+        assert!(drawn > 0 && drawn < 100);
+        //The pawn's side is the weaker one whichever color it is
+        assert!(eval("7K/3k4/8/3P4/8/8/8/r7 b - - 0 1") < -(ROOK_VALUE - 10));
+    }
+
+    #[test]
+    fn back_rank_pawns() {
+        //The tolerant FEN reader accepts these, the evaluators must not index with them
+        assert_eq!(
+            ChessState::from_fen("8/8/8/8/8/8/8/P3K2k w - - 0 1").endgame_eval(),
+            None
+        );
+        assert_eq!(
+            ChessState::from_fen("P7/8/8/8/8/8/8/4K2k w - - 0 1").endgame_eval(),
+            None
+        );
+        assert_eq!(
+            ChessState::from_fen("8/8/8/8/8/8/4K3/R2p3k w - - 0 1").endgame_eval(),
+            None
+        );
+    }
+
+    #[test]
+    fn material() {
+        let state = ChessState::from_fen("4k3/1pp5/8/8/8/8/P1P5/1NBRK3 w - - 0 1");
+        let white = state.material(Color::White);
+        assert_eq!(white, Material::of("KRBNPP"));
+        assert_eq!(white.to_string(), "KRBNPP");
+        assert_eq!(white.count(PieceKind::Pawn), 2);
+        assert_eq!(white.without_pawns(), Material::of("KRBN"));
+        assert_eq!(state.material(Color::Black).without_pawns(), K);
+        assert_eq!(format!("{}v{}", KR, KP), "KRvKP");
+    }
+}
@@ -0,0 +1,210 @@
+use crate::chess_init::ChessState;
+use crate::moves::Move;
+use crate::translator;
+use std::fs;
+use std::io;
+
+/// One line of an EPD file: a position and the operations describing it, such as
+/// `bm Nf3;` or `id "WAC.001";`
+pub struct EpdRecord {
+    pub state: ChessState,
+    /// Opcodes with their operands, unquoted, in the order of the line
+    pub operations: Vec<(String, Vec<String>)>,
+}
+
+impl EpdRecord {
+    /// The operands of the first operation with `opcode`
+    pub fn operation(&self, opcode: &str) -> Option<&[String]> {
+        self.operations
+            .iter()
+            .find(|(x, _)| x == opcode)
+            .map(|(_, operands)| operands.as_slice())
+    }
+
+    /// The `id` operand, if the line has one
+    pub fn id(&self) -> Option<&str> {
+        self.operation("id")?.first().map(String::as_str)
+    }
+
+    /// The moves of an operation such as `bm` or `am`, in SAN. Operands that are not legal
+    /// moves are left out.
+    pub fn moves(&self, opcode: &str) -> Vec<Move> {
+        self.operation(opcode)
+            .unwrap_or(&[])
+            .iter()
+            .filter_map(|x| self.parse_move(x))
+            .collect()
+    }
+
+    /// The `dm` operand, the number of moves to mate in
+    pub fn mate_in(&self) -> Option<u32> {
+        self.operation("dm")?.first()?.parse().ok()
+    }
+
+    /// The points for moves the Strategic Test Suite gives in its comment, as in
+    /// `c0 "Nf3=10, Nc3=7, e4=3";`. Empty when `c0` is an ordinary comment.
+    pub fn move_points(&self) -> Vec<(Move, u32)> {
+        let Some(comment) = self.operation("c0").and_then(|x| x.first()) else {
+            return Vec::new();
+        };
+        let points: Option<Vec<(Move, u32)>> = comment
+            .split(',')
+            .map(|pair| {
+                //Promotions have an `=` of their own, as in `e8=Q=10`
+                let (san, points) = pair.rsplit_once('=')?;
+                Some((self.parse_move(san)?, points.trim().parse().ok()?))
+            })
+            .collect();
+        points.unwrap_or_default()
+    }
+
+    /// A move in SAN, or in UCI notation as some suites write them
+    fn parse_move(&self, text: &str) -> Option<Move> {
+        translator::parse_san(&self.state, text).ok().or_else(|| {
+            translator::uci_to_lan(&self.state, text.trim())
+                .filter(|x| self.state.get_all_possible_moves().contains(x))
+        })
+    }
+}
+
+/// The first word of `text` and what follows it
+fn split_word(text: &str) -> (&str, &str) {
+    let text = text.trim_start();
+    text.split_once(char::is_whitespace).unwrap_or((text, ""))
+}
+
+/// Reads an EPD line. Plain FENs with their two clocks are accepted too. `None` for blank
+/// lines, comments starting with `#` and lines without a position.
+pub fn parse_line(line: &str) -> Option<EpdRecord> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+    let mut fields: Vec<&str> = Vec::new();
+    let mut rest = line;
+    for _ in 0..4 {
+        let (field, after) = split_word(rest);
+        fields.push(field);
+        rest = after;
+    }
+    if fields[3].is_empty() || fields[0].split('/').count() != 8 || !["w", "b"].contains(&fields[1])
+    {
+        return None;
+    }
+    let mut fen = fields.join(" ");
+    //A FEN has the halfmove clock and the move number where EPD has its operations
+    let (halfmoves, after) = split_word(rest);
+    let (fullmoves, after) = split_word(after);
+    if halfmoves.parse::<u16>().is_ok() && fullmoves.parse::<u16>().is_ok() {
+        fen = format!("{} {} {}", fen, halfmoves, fullmoves);
+        rest = after;
+    }
+    Some(EpdRecord {
+        state: ChessState::from_fen(&fen),
+        operations: parse_operations(rest),
+    })
+}
+
+/// Splits `bm Nf3 Nc3; id "a; b";` into opcodes and operands, keeping quoted strings whole
+fn parse_operations(text: &str) -> Vec<(String, Vec<String>)> {
+    let mut operations: Vec<(String, Vec<String>)> = Vec::new();
+    let mut words: Vec<String> = Vec::new();
+    let mut word = String::new();
+    let mut quoted = false;
+    let mut was_quoted = false;
+    for c in text.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                was_quoted = true;
+            }
+            ';' | ' ' | '\t' if !quoted => {
+                if !word.is_empty() || was_quoted {
+                    words.push(std::mem::take(&mut word));
+                }
+                was_quoted = false;
+                if c == ';' && !words.is_empty() {
+                    let opcode = words.remove(0);
+                    operations.push((opcode, std::mem::take(&mut words)));
+                }
+            }
+            c => word.push(c),
+        }
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    if !words.is_empty() {
+        let opcode = words.remove(0);
+        operations.push((opcode, words));
+    }
+    operations
+}
+
+/// Every position in an EPD file
+pub fn read_file(path: &str) -> io::Result<Vec<EpdRecord>> {
+    let text = fs::read_to_string(path)?;
+    Ok(text.lines().filter_map(parse_line).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records() {
+        let record = parse_line(
+            "2rr3k/pp3pp1/1nnqbN1p/3pN3/2pP4/2P3Q1/PPB4P/R4RK1 w - - bm Qg6; id \"WAC.001\";",
+        )
+        .unwrap();
+        assert_eq!(record.operation("bm"), Some(&["Qg6".to_string()][..]));
+        assert_eq!(record.id(), Some("WAC.001"));
+        assert_eq!(
+            record.state.to_fen(),
+            "2rr3k/pp3pp1/1nnqbN1p/3pN3/2pP4/2P3Q1/PPB4P/R4RK1 w - - 0 1"
+        );
+
+        let record = parse_line("4k3/8/8/8/8/8/8/4K3 b - - 12 40 am Kd7 Kf7; c0 \"a; b\"").unwrap();
+        assert_eq!(record.state.halfmoves, 12);
+        assert_eq!(record.state.fullmoves, 40);
+        assert_eq!(record.operation("am").unwrap(), ["Kd7", "Kf7"]);
+        assert_eq!(record.operation("c0").unwrap(), ["a; b"]);
+
+        assert!(parse_line("# a comment").is_none());
+        assert!(parse_line("not a position").is_none());
+    }
+
+    #[test]
+    fn move_operations() {
+        let record = parse_line(
+            "1kr5/3n4/q3p2p/p2n2p1/PppB1P2/5BP1/1P2Q2P/3R2K1 w - - bm f5; id \"STS(v1.0) Undermine.001\"; \
+             c0 \"f5=10, Be5+=2, Bf2=3, Bg4=2\";",
+        )
+        .unwrap();
+        let state = &record.state;
+        assert_eq!(record.moves("bm"), [state.parse_lan("f4-f5").unwrap()]);
+        assert_eq!(record.moves("am"), []);
+        let points = record.move_points();
+        assert_eq!(points.len(), 4);
+        assert_eq!(points[1], (state.parse_lan("Bd4-e5").unwrap(), 2));
+
+        let mate =
+            parse_line("6k1/5ppp/8/8/8/8/8/R5K1 w - - dm 1; bm a1a8; c0 \"back rank\";").unwrap();
+        assert_eq!(mate.mate_in(), Some(1));
+        assert_eq!(mate.moves("bm"), [mate.state.parse_lan("Ra1-a8").unwrap()]);
+        assert_eq!(mate.move_points(), []);
+
+        let promotion =
+            parse_line("8/4P1k1/8/8/8/8/8/4K3 w - - bm e8=Q; c0 \"e8=Q=10, e8=N=2, Kd2=1\";")
+                .unwrap();
+        let state = &promotion.state;
+        assert_eq!(
+            promotion.move_points(),
+            [
+                (state.parse_lan("e7-e8Q").unwrap(), 10),
+                (state.parse_lan("e7-e8N").unwrap(), 2),
+                (state.parse_lan("Ke1-d2").unwrap(), 1),
+            ]
+        );
+    }
+}
@@ -1,123 +1,100 @@
 use crate::chess_init::ChessState;
-use std::collections::HashSet;
-impl ChessState {
-    fn mobility(&self, player: bool) -> i32 {
-        let mut score: i32 = 0;
-        let coefficient: i8 = if player { 1 } else { -1 };
-        let back_rank: usize = if player { 0 } else { 7 };
-        let p_pos: Vec<(usize, usize)> = ChessState::find_positions(self.board, 1 * coefficient);
-        let r_pos: Vec<(usize, usize)> = ChessState::find_positions(self.board, 2 * coefficient);
-        let n_pos: Vec<(usize, usize)> = ChessState::find_positions(self.board, 3 * coefficient);
-        let b_pos: Vec<(usize, usize)> = ChessState::find_positions(self.board, 4 * coefficient);
-        let q_pos: Vec<(usize, usize)> = ChessState::find_positions(self.board, 5 * coefficient);
-        let k_pos: (usize, usize) = ChessState::find_position(self.board, 6 * coefficient);
-        let horisontal_pin: Vec<(usize, usize)> =
-            ChessState::horisontal_pin(self.board, k_pos, coefficient);
-        let vertical_pin: Vec<(usize, usize)> =
-            ChessState::vertical_pin(self.board, k_pos, coefficient);
-        let left_diagonal_pin: Vec<(usize, usize)> =
-            ChessState::left_diagonal_pin(self.board, k_pos, coefficient);
-        let right_diagonal_pin: Vec<(usize, usize)> =
-            ChessState::right_diagonal_pin(self.board, k_pos, coefficient);
-        let danger_squares: HashSet<(usize, usize)> =
-            ChessState::danger_squares(self.board, coefficient);
+use crate::move_generation::GenType;
+use crate::moves::MoveList;
+use crate::types::{Color, PieceKind};
+
+/// Score of a checkmate for the side giving it. A mate found `ply` half-moves into a search
+/// scores `MATE - ply`, so shorter mates score higher.
+pub const MATE: i32 = 1_000_000_000;
+/// Longest mate a score can describe, in half-moves
+const MAX_MATE_PLY: i32 = 1000;
+
+/// Half-moves until mate for a score from white's point of view, positive when white mates
+/// and negative when black does. `None` when the score is not a mate.
+pub fn mate_plies(score: i32) -> Option<i32> {
+    if score > MATE - MAX_MATE_PLY {
+        Some(MATE - score)
+    } else if score < MAX_MATE_PLY - MATE {
+        Some(-(MATE + score))
+    } else {
+        None
+    }
+}
+
+/// Moves until mate for a score from white's point of view, as `color` sees it: positive
+/// when `color` mates and negative when it gets mated
+pub fn mate_in(score: i32, color: Color) -> Option<i32> {
+    let plies = mate_plies(score)? * color.sign() as i32;
+    let moves = (plies.abs() + 1) / 2;
+    Some(if plies > 0 { moves } else { -moves })
+}
+
+/// What one legal move of each kind of piece is worth to the mobility score. King moves
+/// count castling too and are weighed negatively to keep the king home.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EvalWeights {
+    pub pawn: i32,
+    pub rook: i32,
+    pub knight: i32,
+    pub bishop: i32,
+    pub queen: i32,
+    pub king: i32,
+}
 
-        if player {
-            score += ChessState::white_pawn_moves(
-                self,
-                coefficient,
-                &vertical_pin,
-                &horisontal_pin,
-                &left_diagonal_pin,
-                &right_diagonal_pin,
-                &p_pos,
-            )
-            .len() as i32;
-        } else {
-            score += ChessState::black_pawn_moves(
-                self,
-                coefficient,
-                &vertical_pin,
-                &horisontal_pin,
-                &left_diagonal_pin,
-                &right_diagonal_pin,
-                &p_pos,
-            )
-            .len() as i32;
+impl Default for EvalWeights {
+    fn default() -> EvalWeights {
+        EvalWeights {
+            pawn: 1,
+            rook: 5,
+            knight: 3,
+            bishop: 3,
+            queen: 100,
+            king: -1,
         }
-        score += 5 * ChessState::straight_moves(
-            self,
-            coefficient,
-            &vertical_pin,
-            &horisontal_pin,
-            &left_diagonal_pin,
-            &right_diagonal_pin,
-            &r_pos,
-            player,
-            "R",
-        )
-        .len() as i32;
-        score += 3 * ChessState::diagonal_moves(
-            self,
-            coefficient,
-            &vertical_pin,
-            &horisontal_pin,
-            &left_diagonal_pin,
-            &right_diagonal_pin,
-            &b_pos,
-            player,
-            "B",
-        )
-        .len() as i32;
+    }
+}
 
-        score += 100
-            * ChessState::straight_moves(
-                self,
-                coefficient,
-                &vertical_pin,
-                &horisontal_pin,
-                &left_diagonal_pin,
-                &right_diagonal_pin,
-                &q_pos,
-                player,
-                "Q",
-            )
-            .len() as i32;
-        score += 100
-            * ChessState::diagonal_moves(
-                self,
-                coefficient,
-                &vertical_pin,
-                &horisontal_pin,
-                &left_diagonal_pin,
-                &right_diagonal_pin,
-                &q_pos,
-                player,
-                "Q",
-            )
-            .len() as i32;
-        score += 3 * ChessState::knight_moves(
-            self,
-            coefficient,
-            &vertical_pin,
-            &horisontal_pin,
-            &left_diagonal_pin,
-            &right_diagonal_pin,
-            &n_pos,
-        )
-        .len() as i32;
-        score -= ChessState::king_moves(self, coefficient, &danger_squares, k_pos, back_rank).len()
-            as i32;
-        return score * coefficient as i32;
+impl ChessState {
+    fn mobility(&self, color: Color, weights: &EvalWeights) -> i32 {
+        let masks = self.legal_masks(color);
+        let mut list = MoveList::new();
+        self.pawn_moves(color, &masks, GenType::All, &mut list);
+        let mut score: i32 = weights.pawn * list.len() as i32;
+        for (kind, weight) in [
+            (PieceKind::Rook, weights.rook),
+            (PieceKind::Bishop, weights.bishop),
+            (PieceKind::Queen, weights.queen),
+            (PieceKind::Knight, weights.knight),
+        ] {
+            list.clear();
+            self.piece_moves(color, kind, &masks, GenType::All, &mut list);
+            score += weight * list.len() as i32;
+        }
+        list.clear();
+        self.king_moves(color, &masks, GenType::All, &mut list);
+        if color == self.turn {
+            self.castling_moves(&masks, &mut list);
+        }
+        score += weights.king * list.len() as i32;
+        score * color.sign() as i32
     }
 
     pub fn eval(state: ChessState) -> i32 {
+        ChessState::eval_with(state, &EvalWeights::default())
+    }
+
+    /// The evaluation from white's point of view with mobility weighed by `weights`
+    pub fn eval_with(state: ChessState, weights: &EvalWeights) -> i32 {
         //let mut evaluation: i32 = 0;
         if state.is_terminal() && state.check() {
-            return if state.turn { i32::MIN } else { i32::MAX };
+            return if state.turn.is_white() { -MATE } else { MATE };
+        }
+        if let Some(score) = state.endgame_eval() {
+            return score;
         }
-        let mut score: i32 = state.mobility(true) + state.mobility(false);
+        let score: i32 =
+            state.mobility(Color::White, weights) + state.mobility(Color::Black, weights);
 
-        return score;
+        state.scale_drawish(score)
     }
 }
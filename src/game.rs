@@ -0,0 +1,215 @@
+use crate::chess_init::ChessState;
+use crate::moves::Move;
+#[cfg(feature = "pgn")]
+use crate::pgn::PgnGame;
+use crate::types::{Color, PieceKind};
+use std::fmt;
+
+/// How a game ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// The side that gave mate
+    Checkmate(Color),
+    Stalemate,
+    FiftyMoves,
+    Repetition,
+    InsufficientMaterial,
+    /// The side that did not resign
+    Resignation(Color),
+    /// The side whose opponent ran out of time
+    Time(Color),
+    /// Ended by a referee without a rule deciding it, `None` for a draw
+    Adjudication(Option<Color>),
+}
+
+impl Outcome {
+    pub fn winner(self) -> Option<Color> {
+        match self {
+            Outcome::Checkmate(color) | Outcome::Resignation(color) | Outcome::Time(color) => {
+                Some(color)
+            }
+            Outcome::Adjudication(winner) => winner,
+            _ => None,
+        }
+    }
+
+    /// The result as PGN writes it
+    pub fn result(self) -> &'static str {
+        match self.winner() {
+            Some(Color::White) => "1-0",
+            Some(Color::Black) => "0-1",
+            None => "1/2-1/2",
+        }
+    }
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let reason = match self {
+            Outcome::Checkmate(_) => "by checkmate",
+            Outcome::Stalemate => "by stalemate",
+            Outcome::FiftyMoves => "by the fifty-move rule",
+            Outcome::Repetition => "by threefold repetition",
+            Outcome::InsufficientMaterial => "by insufficient material",
+            Outcome::Resignation(_) => "by resignation",
+            Outcome::Time(_) => "on time",
+            Outcome::Adjudication(_) => "by adjudication",
+        };
+        match self.winner() {
+            Some(Color::White) => write!(f, "White wins {}", reason),
+            Some(Color::Black) => write!(f, "Black wins {}", reason),
+            None => write!(f, "Draw {}", reason),
+        }
+    }
+}
+
+/// A game played from a start position, keeping every position on the way for repetitions
+/// and taking moves back
+pub struct Game {
+    pub start: ChessState,
+    pub moves: Vec<Move>,
+    /// The start position followed by the position after each move
+    positions: Vec<ChessState>,
+}
+
+impl Game {
+    pub fn new(start: ChessState) -> Game {
+        Game {
+            positions: vec![start.copy()],
+            start,
+            moves: Vec::new(),
+        }
+    }
+
+    /// The current position
+    pub fn state(&self) -> &ChessState {
+        self.positions.last().unwrap()
+    }
+
+    /// Plays `m`, which has to be legal
+    pub fn play(&mut self, m: Move) {
+        let next = self.state().do_move(&m);
+        self.positions.push(next);
+        self.moves.push(m);
+    }
+
+    /// Takes the last move back, returning it
+    pub fn undo(&mut self) -> Option<Move> {
+        let m = self.moves.pop()?;
+        self.positions.pop();
+        Some(m)
+    }
+
+    /// How often the current position has occurred, counting this time. Only positions
+    /// since the last capture or pawn move can repeat it.
+    pub fn repetitions(&self) -> usize {
+        let state = self.state();
+        let hash = state.hash();
+        self.positions
+            .iter()
+            .rev()
+            .take(state.halfmoves as usize + 1)
+            .filter(|x| x.hash() == hash)
+            .count()
+    }
+
+    /// The outcome the rules decide on their own, if the game is over
+    pub fn outcome(&self) -> Option<Outcome> {
+        let state = self.state();
+        if state.is_terminal() {
+            return Some(if state.check() {
+                Outcome::Checkmate(!state.turn)
+            } else {
+                Outcome::Stalemate
+            });
+        }
+        if state.halfmoves >= 100 {
+            return Some(Outcome::FiftyMoves);
+        }
+        if self.repetitions() >= 3 {
+            return Some(Outcome::Repetition);
+        }
+        if state.insufficient_material() {
+            return Some(Outcome::InsufficientMaterial);
+        }
+        None
+    }
+
+    /// The game as PGN with the result of `outcome`, or the one the rules give
+    #[cfg(feature = "pgn")]
+    pub fn to_pgn(&self, outcome: Option<Outcome>) -> PgnGame {
+        let mut game = PgnGame::from_moves(&self.start, &self.moves);
+        if let Some(outcome) = outcome.or_else(|| self.outcome()) {
+            game.result = outcome.result().to_string();
+        }
+        game
+    }
+}
+
+impl ChessState {
+    /// Whether neither side has the material left to mate: bare kings, a single minor piece,
+    /// or only bishops all on squares of one colour
+    pub fn insufficient_material(&self) -> bool {
+        let mut knights = 0;
+        let mut bishop_squares = [false; 2];
+        for (square, piece) in self.board.pieces() {
+            match piece.kind {
+                PieceKind::King => {}
+                PieceKind::Knight => knights += 1,
+                PieceKind::Bishop => bishop_squares[(square.file() + square.rank()) % 2] = true,
+                _ => return false,
+            }
+        }
+        let bishops = bishop_squares.iter().filter(|x| **x).count();
+        match knights {
+            0 => bishops <= 1,
+            1 => bishops == 0,
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn outcomes() {
+        let mut game = Game::new(ChessState::new_board());
+        for m in ["f2-f3", "e7-e5", "g2-g4", "Qd8-h4"] {
+            let m = game.state().parse_lan(m).unwrap();
+            game.play(m);
+        }
+        assert_eq!(game.outcome(), Some(Outcome::Checkmate(Color::Black)));
+        assert_eq!(game.outcome().unwrap().result(), "0-1");
+        game.undo();
+        assert_eq!(game.outcome(), None);
+
+        let mut game = Game::new(ChessState::new_board());
+        for _ in 0..2 {
+            for m in ["Ng1-f3", "Ng8-f6", "Nf3-g1", "Nf6-g8"] {
+                assert_eq!(game.outcome(), None);
+                let m = game.state().parse_lan(m).unwrap();
+                game.play(m);
+            }
+        }
+        assert_eq!(game.repetitions(), 3);
+        assert_eq!(game.outcome(), Some(Outcome::Repetition));
+
+        let stalemate = Game::new(ChessState::from_fen("k7/2Q5/1K6/8/8/8/8/8 b - - 0 1"));
+        assert_eq!(stalemate.outcome(), Some(Outcome::Stalemate));
+        let fifty = Game::new(ChessState::from_fen("k7/8/1K6/8/8/8/8/7R b - - 100 80"));
+        assert_eq!(fifty.outcome(), Some(Outcome::FiftyMoves));
+    }
+
+    #[test]
+    fn insufficient_material() {
+        let dead = |fen: &str| ChessState::from_fen(fen).insufficient_material();
+        assert!(dead("k7/8/1K6/8/8/8/8/8 w - - 0 1"));
+        assert!(dead("k7/8/1K6/8/8/8/8/6N1 w - - 0 1"));
+        assert!(dead("k7/8/1KB5/8/8/8/8/5b2 w - - 0 1"));
+        assert!(!dead("k7/8/1KB5/8/8/8/8/6b1 w - - 0 1"));
+        assert!(!dead("k7/8/1K6/8/8/8/8/5nn1 w - - 0 1"));
+        assert!(!dead("k7/8/1K6/8/8/8/P7/8 w - - 0 1"));
+    }
+}
@@ -0,0 +1,63 @@
+//! Chess rules, evaluation and search, with a UCI engine built on top.
+//!
+//! The rules engine is always there: [`ChessState`] holds a position, generates and plays
+//! [`Move`]s and evaluates them, and [`chessbot::MinimaxBot`] searches it. The rest sits
+//! behind cargo features, all on by default:
+//!
+//! - `uci`: the UCI protocol and engine options
+//! - `pgn`: reading and writing PGN games, and the tools built on games and positions:
+//!   self-play, play against the engine, EPD test suites and Elo statistics, and with
+//!   `uci` engine tournaments
+//! - `book`: Polyglot opening books and building them from PGN, needs `pgn`
+//! - `tablebase`: Syzygy tablebase probing, used by the search when enabled
+
+pub mod attacks;
+pub mod chess_init;
+pub mod chessbot;
+pub mod endgame;
+pub mod eval;
+pub mod game;
+pub mod move_generation;
+pub mod moves;
+pub mod see;
+pub mod time_manager;
+pub mod translator;
+pub mod transposition;
+pub mod types;
+pub mod zobrist;
+
+#[cfg(feature = "book")]
+pub mod book_build;
+#[cfg(feature = "pgn")]
+pub mod elo;
+#[cfg(feature = "pgn")]
+pub mod epd;
+#[cfg(feature = "uci")]
+pub mod options;
+#[cfg(feature = "pgn")]
+pub mod pgn;
+#[cfg(feature = "pgn")]
+pub mod play;
+#[cfg(feature = "book")]
+pub mod polyglot;
+#[cfg(feature = "pgn")]
+pub mod selfplay;
+#[cfg(feature = "tablebase")]
+pub mod syzygy;
+#[cfg(feature = "pgn")]
+pub mod testsuite;
+#[cfg(all(feature = "uci", feature = "pgn"))]
+pub mod tournament;
+#[cfg(feature = "uci")]
+pub mod uci;
+#[cfg(feature = "uci")]
+pub mod uci_client;
+#[cfg(feature = "uci")]
+pub mod xboard;
+
+pub use chess_init::ChessState;
+pub use chessbot::{ChessBot, MinimaxBot, PvLine, SearchControl};
+pub use game::{Game, Outcome};
+pub use move_generation::GenType;
+pub use moves::{Move, MoveKind, MoveList};
+pub use types::{Board, CastlingRights, Color, Piece, PieceKind, Square};
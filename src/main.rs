@@ -1,86 +1,730 @@
-use chess_init::ChessState;
-use chessbot::{ChessBot, MinimaxBot};
+use chess_rs::book_build;
+use chess_rs::chessbot::{MinimaxBot, PvLine, SearchControl};
+use chess_rs::eval::EvalWeights;
+use chess_rs::options::Options;
+use chess_rs::pgn::{self, PgnGame};
+use chess_rs::play;
+use chess_rs::polyglot::{BookSelection, PolyglotBook};
+use chess_rs::selfplay;
+use chess_rs::syzygy::Tablebases;
+use chess_rs::testsuite;
+use chess_rs::time_manager;
+use chess_rs::tournament;
+use chess_rs::translator;
+use chess_rs::transposition::TranspositionTable;
+use chess_rs::uci::{self, GoParams, UciCommand};
+use chess_rs::xboard::{self, Level, XboardCommand};
+use chess_rs::{ChessState, Color, Game, Move};
 use log::{info, warn};
 use std::io;
-mod chess_init;
-mod chessbot;
-mod eval;
-mod move_generation;
-mod moves;
-mod translator;
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+/// What the main loop waits for
+enum Event {
+    /// A line from the GUI, `None` once stdin is closed
+    Input(Option<String>),
+    /// The search thread with this control is done
+    Searched(Arc<SearchControl>, Vec<PvLine>),
+}
+
+/// A search running on its own thread
+struct Search {
+    control: Arc<SearchControl>,
+    handle: JoinHandle<()>,
+    /// The searched position
+    state: ChessState,
+    /// Time for the move once a ponder search is hit
+    time_limit: Option<Duration>,
+    /// `go infinite` waits for `stop` before answering
+    infinite: bool,
+    /// False once a ponder search is stopped, so the move is not played
+    play: bool,
+    /// Lines of a finished search that cannot answer yet
+    result: Option<Vec<PvLine>>,
+}
+
 fn main() {
-    colog::init();
-    let mut message: String = String::new();
-    let bot: MinimaxBot = MinimaxBot {
-        player: true,
-        max_depth: 4,
+    let args: Vec<String> = std::env::args().collect();
+    let subcommand = match args.get(1).map(String::as_str) {
+        Some("book-build") => Some(book_build::run(&args[2..])),
+        Some("play") => Some(play::run(&args[2..])),
+        Some("match") => Some(selfplay::run(&args[2..])),
+        Some("tournament") => Some(tournament::run(&args[2..])),
+        Some("testsuite") => Some(testsuite::run(&args[2..])),
+        _ => None,
     };
+    if let Some(result) = subcommand {
+        if let Err(err) = result {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+    colog::init();
+    let mut options: Options = Options::new();
     let mut state: ChessState = ChessState::new_board();
-    //colog::init();
-    loop {
-        io::stdin()
+    //The game played so far, kept for the PgnFile option
+    let mut game_start: ChessState = ChessState::new_board();
+    let mut game_moves: Vec<Move> = Vec::new();
+    let mut engine_color: Option<Color> = None;
+    let mut search: Option<Search> = None;
+    let mut tt = Arc::new(TranspositionTable::new(options.spin("Hash") as usize));
+    let mut tablebases = Arc::new(Tablebases::default());
+    let (sender, receiver) = mpsc::channel::<Event>();
+    //Stdin is read on its own thread so commands still arrive while searching
+    let input_sender = sender.clone();
+    thread::spawn(move || loop {
+        let mut message: String = String::new();
+        let read = io::stdin()
             .read_line(&mut message)
             .expect("Something went wrong with reading stdin");
+        //A read of 0 bytes means the GUI closed our input
+        let line = if read == 0 { None } else { Some(message) };
+        let closed = line.is_none();
+        if input_sender.send(Event::Input(line)).is_err() || closed {
+            break;
+        }
+    });
+    //The first command tells which protocol the GUI speaks
+    let mut first = Some(receiver.recv().expect("The input thread stopped"));
+    if let Some(Event::Input(Some(line))) = &first {
+        if xboard::parse_command(line) == XboardCommand::Xboard {
+            xboard_loop(&receiver, &sender);
+            return;
+        }
+    }
+    loop {
+        let event = match first.take() {
+            Some(event) => event,
+            None => receiver.recv().expect("The input thread stopped"),
+        };
+        let message = match event {
+            Event::Searched(_, lines) => {
+                if let Some(mut current) = search.take() {
+                    let waiting = current.control.is_pondering() || current.infinite;
+                    if waiting && !current.control.is_stopped() {
+                        //Not allowed to answer before ponderhit or stop
+                        current.result = Some(lines);
+                        search = Some(current);
+                    } else {
+                        finish_search(
+                            current,
+                            lines,
+                            &mut state,
+                            &mut game_moves,
+                            &mut engine_color,
+                        );
+                    }
+                }
+                continue;
+            }
+            Event::Input(Some(message)) => message,
+            Event::Input(None) => {
+                stop_search(&mut search);
+                save_game(&options, &game_start, &mut game_moves, &mut engine_color);
+                break;
+            }
+        };
         info!("{}", message);
-        match message
-            .as_str()
-            .trim()
-            .split(|x: char| x.is_ascii_whitespace())
-            .nth(0)
-            .expect("Split error")
-        {
-            "uci" => {
+        match uci::parse_command(&message) {
+            UciCommand::Uci => {
+                println!("id name chess-rs {}", env!("CARGO_PKG_VERSION"));
+                println!("id author Trygve Eriksen");
+                for line in options.uci_lines() {
+                    println!("{}", line);
+                }
                 println!("uciok")
             }
-            "isready" => {
+            UciCommand::SetOption { name, value } => match options.set(&name, value.as_deref()) {
+                Ok(()) => {
+                    state.chess960 = options.check("UCI_Chess960");
+                    if name.trim().eq_ignore_ascii_case("Hash") {
+                        tt = Arc::new(TranspositionTable::new(options.spin("Hash") as usize));
+                    }
+                    if name.trim().eq_ignore_ascii_case("SyzygyPath") {
+                        tablebases = Arc::new(Tablebases::new(options.string("SyzygyPath")));
+                        println!("info string Found {} tablebases", tablebases.len());
+                    }
+                }
+                Err(err) => println!("info string {}", err),
+            },
+            UciCommand::IsReady => {
                 println!("readyok")
             }
-            "ucinewgame" => {
+            UciCommand::UciNewGame => {
+                save_game(&options, &game_start, &mut game_moves, &mut engine_color);
+                tt.clear();
                 state = ChessState::new_board();
+                state.chess960 = options.check("UCI_Chess960");
             }
-            "position" => {
-                if message.contains("startpos") {
-                    state = ChessState::new_board();
-                } else if message.contains("fen") {
-                    state = ChessState::from_fen(
-                        message
-                            .as_str()
-                            .trim()
-                            .split(|x: char| x.is_ascii_whitespace())
-                            .nth(2)
-                            .expect("Split error"),
-                    );
-                }
-                if message.contains("moves") {
-                    let moves: Vec<String> = message
-                        .as_str()
-                        .trim()
-                        .split(|x: char| x.is_ascii_whitespace())
-                        .map(|x| x.to_string())
-                        .collect::<Vec<String>>()
-                        .clone();
-                    let idx = moves
-                        .iter()
-                        .position(|x| x == "moves")
-                        .expect("Moves not found");
-                    for m in moves[idx + 1..].iter() {
-                        state = state.do_move(&translator::uci_to_lan(&state, &m.to_string()));
+            UciCommand::Position { fen, moves } => {
+                state = match fen {
+                    Some(fen) => ChessState::from_fen(&fen),
+                    None => ChessState::new_board(),
+                };
+                state.chess960 = options.check("UCI_Chess960");
+                let start = state.copy();
+                let mut position_moves: Vec<Move> = Vec::new();
+                for m in moves.iter() {
+                    if !uci::is_move(m) {
+                        warn!("Ignoring moves from invalid move {}", m);
+                        break;
                     }
+                    let Some(lan) = translator::uci_to_lan(&state, m)
+                        .filter(|x| state.get_all_possible_moves().contains(x))
+                    else {
+                        warn!("Ignoring moves from illegal move {}", m);
+                        break;
+                    };
+                    state = state.do_move(&lan);
+                    position_moves.push(lan);
+                }
+                //A position that does not continue the recorded game starts a new one
+                if start.to_fen() != game_start.to_fen() || !position_moves.starts_with(&game_moves)
+                {
+                    save_game(&options, &game_start, &mut game_moves, &mut engine_color);
                 }
+                game_start = start;
+                game_moves = position_moves;
             }
-            "go" => {
-                let m = bot.respond(state.copy());
-                println!("bestmove {}", translator::lan_to_uci(&state, &m));
-                state = state.do_move(&m);
+            UciCommand::Go(GoParams {
+                perft: Some(depth), ..
+            }) => {
+                println!("Nodes searched: {}", state.perft(depth));
             }
-            "stop" => {
-                break;
+            UciCommand::Go(_) if search.is_some() => {
+                warn!("Ignoring go while already searching");
             }
-            "quit" => {
+            UciCommand::Go(params) if !params.ponder && !params.infinite => {
+                let setup = uci_setup(&options, &state, &params);
+                //The book answers at once, without searching
+                let allowed =
+                    |m: &Move| setup.root_moves.is_empty() || setup.root_moves.contains(m);
+                match book_move(&options, &state).filter(allowed) {
+                    Some(m) => {
+                        let root = state.copy();
+                        send_bestmove(
+                            &root,
+                            &[m],
+                            true,
+                            &mut state,
+                            &mut game_moves,
+                            &mut engine_color,
+                        );
+                    }
+                    None => {
+                        search = Some(start_search(
+                            &options,
+                            &state,
+                            setup,
+                            &tt,
+                            &tablebases,
+                            &sender,
+                        ));
+                    }
+                }
+            }
+            UciCommand::Go(params) => {
+                search = Some(start_search(
+                    &options,
+                    &state,
+                    uci_setup(&options, &state, &params),
+                    &tt,
+                    &tablebases,
+                    &sender,
+                ));
+            }
+            UciCommand::PonderHit => {
+                if let Some(mut current) = search.take() {
+                    current.control.ponderhit(current.time_limit);
+                    match current.result.take() {
+                        //The ponder search already finished, so the answer is ready
+                        Some(lines) if !current.infinite => finish_search(
+                            current,
+                            lines,
+                            &mut state,
+                            &mut game_moves,
+                            &mut engine_color,
+                        ),
+                        result => {
+                            current.result = result;
+                            search = Some(current);
+                        }
+                    }
+                }
+            }
+            UciCommand::Stop => {
+                if let Some(mut current) = search.take() {
+                    if current.control.is_pondering() {
+                        current.play = false;
+                    }
+                    current.control.stop();
+                    match current.result.take() {
+                        Some(lines) => finish_search(
+                            current,
+                            lines,
+                            &mut state,
+                            &mut game_moves,
+                            &mut engine_color,
+                        ),
+                        None => search = Some(current),
+                    }
+                }
+            }
+            UciCommand::Quit => {
+                stop_search(&mut search);
+                save_game(&options, &game_start, &mut game_moves, &mut engine_color);
                 break;
             }
             _ => {}
         }
-        message = String::new();
     }
 }
+
+/// Talks CECP to an XBoard or WinBoard GUI once it has sent `xboard`. The engine plays the
+/// side `engine_color` says, which is `None` in force and analyze mode.
+fn xboard_loop(receiver: &mpsc::Receiver<Event>, sender: &mpsc::Sender<Event>) {
+    let mut options: Options = Options::new();
+    let mut tt = Arc::new(TranspositionTable::new(options.spin("Hash") as usize));
+    let tablebases = Arc::new(Tablebases::default());
+    let mut game = Game::new(ChessState::new_board());
+    let mut engine_color: Option<Color> = Some(Color::Black);
+    let mut level: Option<Level> = None;
+    let mut move_time: Option<Duration> = None;
+    let mut max_depth: Option<u8> = None;
+    //The engine's and the opponent's clocks in centiseconds, as the GUI last sent them
+    let mut clock: Option<u64> = None;
+    let mut opponent_clock: Option<u64> = None;
+    let mut post = false;
+    let mut analyzing = false;
+    let mut search: Option<Search> = None;
+    let mut pongs: Vec<String> = Vec::new();
+    loop {
+        let line = match receiver.recv().expect("The input thread stopped") {
+            Event::Searched(control, lines) => {
+                match search.take() {
+                    //A search stopped by a command has already been dropped
+                    Some(current) if !Arc::ptr_eq(&current.control, &control) => {
+                        search = Some(current)
+                    }
+                    //Analysis goes on until the position changes
+                    Some(mut current) if current.infinite => {
+                        current.result = Some(lines);
+                        search = Some(current);
+                    }
+                    Some(current) => {
+                        let _ = current.handle.join();
+                        if let Some(line) = lines.first() {
+                            let m = line.moves[0];
+                            println!("move {}", translator::lan_to_uci(game.state(), &m));
+                            game.play(m);
+                            announce_result(&game);
+                        }
+                    }
+                    None => {}
+                }
+                None
+            }
+            Event::Input(Some(line)) => Some(line),
+            Event::Input(None) => {
+                stop_search(&mut search);
+                break;
+            }
+        };
+        if let Some(line) = line {
+            info!("{}", line);
+            match xboard::parse_command(&line) {
+                XboardCommand::Protover(_) => println!("{}", xboard::features()),
+                XboardCommand::New => {
+                    stop_search(&mut search);
+                    game = Game::new(ChessState::new_board());
+                    engine_color = Some(Color::Black);
+                    max_depth = None;
+                    tt.clear();
+                }
+                XboardCommand::Quit => {
+                    stop_search(&mut search);
+                    break;
+                }
+                XboardCommand::Force => {
+                    stop_search(&mut search);
+                    engine_color = None;
+                }
+                XboardCommand::Go => engine_color = Some(game.state().turn),
+                XboardCommand::PlayOther => engine_color = Some(!game.state().turn),
+                XboardCommand::Level(x) => {
+                    level = Some(x);
+                    move_time = None;
+                }
+                XboardCommand::MoveTime(x) => move_time = Some(x),
+                XboardCommand::Depth(x) => max_depth = Some(x.max(1)),
+                XboardCommand::Time(x) => clock = Some(x),
+                XboardCommand::Otim(x) => opponent_clock = Some(x),
+                XboardCommand::UserMove(text) => {
+                    let state = game.state();
+                    match translator::uci_to_lan(state, &text)
+                        .filter(|x| state.get_all_possible_moves().contains(x))
+                    {
+                        Some(m) => {
+                            stop_search(&mut search);
+                            game.play(m);
+                            announce_result(&game);
+                        }
+                        None => println!("Illegal move: {}", text),
+                    }
+                }
+                XboardCommand::MoveNow => {
+                    if let Some(current) = &search {
+                        current.control.stop();
+                    }
+                }
+                //A ping while thinking on a move is answered after the move
+                XboardCommand::Ping(x) if search.as_ref().is_some_and(|x| !x.infinite) => {
+                    pongs.push(x)
+                }
+                XboardCommand::Ping(x) => println!("pong {}", x),
+                XboardCommand::Result(_) => {
+                    stop_search(&mut search);
+                    engine_color = None;
+                }
+                XboardCommand::SetBoard(fen) => {
+                    let state = ChessState::from_fen(&fen);
+                    if xboard::playable(&state) {
+                        stop_search(&mut search);
+                        game = Game::new(state);
+                    } else {
+                        println!("tellusererror Illegal position");
+                    }
+                }
+                XboardCommand::Undo => {
+                    stop_search(&mut search);
+                    game.undo();
+                }
+                XboardCommand::Remove => {
+                    stop_search(&mut search);
+                    game.undo();
+                    game.undo();
+                }
+                XboardCommand::Post => post = true,
+                XboardCommand::NoPost => post = false,
+                XboardCommand::Analyze => {
+                    stop_search(&mut search);
+                    analyzing = true;
+                    engine_color = None;
+                }
+                XboardCommand::Exit => {
+                    stop_search(&mut search);
+                    analyzing = false;
+                }
+                XboardCommand::Memory(x) if options.set("Hash", Some(&x.to_string())).is_ok() => {
+                    tt = Arc::new(TranspositionTable::new(options.spin("Hash") as usize));
+                }
+                XboardCommand::Cores(x) => {
+                    let _ = options.set("Threads", Some(&x.to_string()));
+                }
+                XboardCommand::Unknown(x) if !x.is_empty() => {
+                    println!("Error (unknown command): {}", x)
+                }
+                _ => {}
+            }
+        }
+        if search.as_ref().is_none_or(|x| x.infinite) {
+            for x in pongs.drain(..) {
+                println!("pong {}", x);
+            }
+        }
+        //Start thinking once it is the engine's turn and no old search is still stopping
+        let state = game.state();
+        if search.is_some() || game.outcome().is_some() {
+            continue;
+        }
+        let setup = if analyzing {
+            SearchSetup {
+                time_limit: None,
+                node_limit: None,
+                max_depth: 64,
+                root_moves: Vec::new(),
+                ponder: false,
+                infinite: true,
+                report: xboard_report,
+            }
+        } else if engine_color == Some(state.turn) {
+            let overhead = options.spin("Move Overhead") as u64;
+            let time_limit = move_time.or_else(|| {
+                let level = level?;
+                let moves_to_go = if level.moves > 0 {
+                    let played = (state.fullmoves - game.start.fullmoves) as u32;
+                    Some(level.moves - played % level.moves)
+                } else {
+                    None
+                };
+                Some(time_manager::allocate(
+                    clock? * 10,
+                    level.increment.as_millis() as u64,
+                    moves_to_go,
+                    opponent_clock.map(|x| x * 10),
+                    overhead,
+                ))
+            });
+            SearchSetup {
+                time_limit,
+                node_limit: None,
+                max_depth: max_depth.unwrap_or(options.spin("Depth") as u8),
+                root_moves: Vec::new(),
+                ponder: false,
+                infinite: false,
+                report: if post { xboard_report } else { |_, _, _, _| {} },
+            }
+        } else {
+            continue;
+        };
+        search = Some(start_search(
+            &options,
+            state,
+            setup,
+            &tt,
+            &tablebases,
+            sender,
+        ));
+    }
+}
+
+fn xboard_report(state: &ChessState, depth: u8, lines: &[PvLine], control: &SearchControl) {
+    if let Some(line) = lines.first() {
+        println!(
+            "{}",
+            xboard::thinking_line(state, depth, line, control.nodes(), control.elapsed())
+        );
+    }
+}
+
+/// Tells the GUI how the game ended, if it has
+fn announce_result(game: &Game) {
+    if let Some(outcome) = game.outcome() {
+        println!("{} {{{}}}", outcome.result(), outcome);
+    }
+}
+
+/// How far a search may go and how it shows its progress
+struct SearchSetup {
+    time_limit: Option<Duration>,
+    node_limit: Option<u64>,
+    max_depth: u8,
+    /// The root moves to choose from, every move when empty
+    root_moves: Vec<Move>,
+    ponder: bool,
+    infinite: bool,
+    /// Prints the lines found at a depth
+    report: fn(&ChessState, u8, &[PvLine], &SearchControl),
+}
+
+/// The search a UCI `go` asks for
+fn uci_setup(options: &Options, state: &ChessState, params: &GoParams) -> SearchSetup {
+    let depth = match params.depth {
+        Some(depth) => depth.max(1),
+        None if params.infinite || params.mate.is_some() || params.nodes.is_some() => 64,
+        None => options.spin("Depth") as u8,
+    };
+    //A mate in N moves is found within 2N - 1 plies
+    let mate_depth = params
+        .mate
+        .map_or(u8::MAX, |x| (2 * x.min(64)).max(2) as u8 - 1);
+    let mut root_moves: Vec<Move> = Vec::new();
+    for m in params.searchmoves.iter() {
+        match translator::uci_to_lan(state, m)
+            .filter(|x| state.get_all_possible_moves().contains(x))
+        {
+            Some(lan) => root_moves.push(lan),
+            None => warn!("Ignoring illegal searchmoves move {}", m),
+        }
+    }
+    SearchSetup {
+        time_limit: uci::time_limit(params, state.turn, options.spin("Move Overhead") as u64),
+        node_limit: params.nodes,
+        max_depth: depth.min(mate_depth),
+        root_moves,
+        ponder: params.ponder,
+        infinite: params.infinite,
+        report: if options.check("UCI_ShowWDL") {
+            |state, depth, lines, control| uci_info(state, depth, lines, control, true)
+        } else {
+            |state, depth, lines, control| uci_info(state, depth, lines, control, false)
+        },
+    }
+}
+
+fn uci_info(
+    state: &ChessState,
+    depth: u8,
+    lines: &[PvLine],
+    control: &SearchControl,
+    show_wdl: bool,
+) {
+    for (idx, line) in lines.iter().enumerate() {
+        println!(
+            "{}",
+            uci::info_line(
+                state,
+                depth,
+                idx + 1,
+                line,
+                control.nodes(),
+                control.tbhits(),
+                control.elapsed(),
+                show_wdl
+            )
+        );
+    }
+}
+
+/// Starts searching `state` on a new thread, which sends `Event::Searched` when done
+fn start_search(
+    options: &Options,
+    state: &ChessState,
+    setup: SearchSetup,
+    tt: &Arc<TranspositionTable>,
+    tablebases: &Arc<Tablebases>,
+    sender: &mpsc::Sender<Event>,
+) -> Search {
+    let time_limit = setup.time_limit;
+    let control = Arc::new(SearchControl::new(time_limit, setup.ponder));
+    if let Some(nodes) = setup.node_limit {
+        control.limit_nodes(nodes);
+    }
+    let bot: MinimaxBot = MinimaxBot {
+        player: state.turn,
+        max_depth: setup.max_depth,
+        threads: options.spin("Threads") as usize,
+        thread: 0,
+        control: control.clone(),
+        tt: tt.clone(),
+        weights: EvalWeights::default(),
+        root_moves: setup.root_moves,
+        tablebases: tablebases.clone(),
+    };
+    let search_state = state.copy();
+    let multi_pv = options.spin("MultiPV") as usize;
+    let search_sender = sender.clone();
+    let handle = thread::spawn(move || {
+        let lines = bot.search(&search_state, multi_pv, &mut |depth, lines| {
+            (setup.report)(&search_state, depth, lines, &bot.control)
+        });
+        let _ = search_sender.send(Event::Searched(bot.control.clone(), lines));
+    });
+    Search {
+        control,
+        handle,
+        state: state.copy(),
+        time_limit,
+        infinite: setup.infinite,
+        play: true,
+        result: None,
+    }
+}
+
+/// Prints `bestmove` for a search and plays the move unless it was a stopped ponder search
+fn finish_search(
+    search: Search,
+    lines: Vec<PvLine>,
+    state: &mut ChessState,
+    game_moves: &mut Vec<Move>,
+    engine_color: &mut Option<Color>,
+) {
+    let _ = search.handle.join();
+    match lines.first() {
+        Some(line) => send_bestmove(
+            &search.state,
+            &line.moves,
+            search.play,
+            state,
+            game_moves,
+            engine_color,
+        ),
+        None => println!("bestmove 0000"),
+    }
+}
+
+/// Prints `bestmove` for the first move of `pv` from `root`, with the reply to ponder on if
+/// there is one, and plays it when `play` is set
+fn send_bestmove(
+    root: &ChessState,
+    pv: &[Move],
+    play: bool,
+    state: &mut ChessState,
+    game_moves: &mut Vec<Move>,
+    engine_color: &mut Option<Color>,
+) {
+    let m = pv[0];
+    let after = root.do_move(&m);
+    match pv.get(1) {
+        Some(reply) => println!(
+            "bestmove {} ponder {}",
+            translator::lan_to_uci(root, &m),
+            translator::lan_to_uci(&after, reply)
+        ),
+        None => println!("bestmove {}", translator::lan_to_uci(root, &m)),
+    }
+    if play {
+        *state = after;
+        engine_color.get_or_insert(root.turn);
+        game_moves.push(m);
+    }
+}
+
+/// A move from the BookFile when OwnBook is on and the game is still within Book Depth
+fn book_move(options: &Options, state: &ChessState) -> Option<Move> {
+    let path = options.string("BookFile");
+    let ply = (state.fullmoves as i64 - 1) * 2 + if state.turn.is_white() { 0 } else { 1 };
+    if !options.check("OwnBook") || path.is_empty() || ply >= options.spin("Book Depth") {
+        return None;
+    }
+    let selection = if options.check("Best Book Move") {
+        BookSelection::Best
+    } else {
+        BookSelection::Weighted
+    };
+    match PolyglotBook::open(path).and_then(|mut book| book.choose(state, selection)) {
+        Ok(m) => m,
+        Err(err) => {
+            warn!("Could not read the book {}: {}", path, err);
+            None
+        }
+    }
+}
+
+/// Stops a running search and waits for it without answering
+fn stop_search(search: &mut Option<Search>) {
+    if let Some(current) = search.take() {
+        current.control.stop();
+        let _ = current.handle.join();
+    }
+}
+
+/// Appends the recorded game to the PgnFile, if one is set, and starts a new recording
+fn save_game(
+    options: &Options,
+    game_start: &ChessState,
+    game_moves: &mut Vec<Move>,
+    engine_color: &mut Option<Color>,
+) {
+    let pgn_file = options.string("PgnFile");
+    if !pgn_file.is_empty() && !game_moves.is_empty() {
+        let mut game = PgnGame::from_moves(game_start, game_moves);
+        game.set_tag("Event", "chess-rs engine game");
+        game.set_tag("Date", &pgn::today());
+        let (white, black) = match engine_color {
+            Some(Color::White) => ("chess-rs", "?"),
+            Some(Color::Black) => ("?", "chess-rs"),
+            None => ("?", "?"),
+        };
+        game.set_tag("White", white);
+        game.set_tag("Black", black);
+        if let Err(err) = game.append_to_file(pgn_file) {
+            warn!("Could not write to {}: {}", pgn_file, err);
+        }
+    }
+    game_moves.clear();
+    *engine_color = None;
+}
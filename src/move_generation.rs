@@ -1,839 +1,673 @@
-use std::{collections::HashSet, usize};
-
 use crate::chess_init::ChessState;
+use crate::moves::{Move, MoveKind, MoveList, MoveListIter};
+use crate::types::{Board, Color, Piece, PieceKind, Square};
 
-const VERTICAL: [(i8, i8); 2] = [(1, 0), (-1, 0)];
-const HORISONTAL: [(i8, i8); 2] = [(0, 1), (0, -1)]; //
-const LEFT_DIAGONAL: [(i8, i8); 2] = [(1, -1), (-1, 1)]; //TODO:fix
-const RIGHT_DIAGONAL: [(i8, i8); 2] = [(1, 1), (-1, -1)];
+//Directions are (files, ranks)
 const KNIGHT_DIRECTIONS: [(i8, i8); 8] = [
-    (1, 2),
-    (-1, 2),
-    (1, -2),
-    (-1, -2),
-    (2, -1),
     (2, 1),
+    (2, -1),
     (-2, 1),
     (-2, -1),
+    (-1, 2),
+    (1, 2),
+    (1, -2),
+    (-1, -2),
 ];
 const KING_DIRECTIONS: [(i8, i8); 8] = [
-    (1, 0),
-    (-1, 0),
     (0, 1),
     (0, -1),
-    (1, -1),
+    (1, 0),
+    (-1, 0),
     (-1, 1),
+    (1, -1),
     (1, 1),
     (-1, -1),
 ];
-const STRAIGHT: [(i8, i8); 4] = [(1, 0), (-1, 0), (0, 1), (0, -1)];
-const DIAGONAL: [(i8, i8); 4] = [(1, -1), (-1, 1), (1, 1), (-1, -1)];
-const PIECE_SYMBOLS: &'static [&'static str; 4] = &["R", "B", "N", "Q"];
+const STRAIGHT: [(i8, i8); 4] = [(0, 1), (0, -1), (1, 0), (-1, 0)];
+const DIAGONAL: [(i8, i8); 4] = [(-1, 1), (1, -1), (1, 1), (-1, -1)];
+const PROMOTIONS: [PieceKind; 4] = [
+    PieceKind::Rook,
+    PieceKind::Bishop,
+    PieceKind::Knight,
+    PieceKind::Queen,
+];
+
+/// What one side needs to know about threats to its king to only generate legal moves
+pub struct LegalMasks {
+    /// Enemy pieces giving check
+    pub checkers: u64,
+    /// Squares other pieces may move to: all of them out of check, the checker and the
+    /// squares between it and the king in single check and none in double check
+    pub check_mask: u64,
+    /// For a pinned piece, the squares from the king to the pinner it may move on, and all
+    /// squares for the other ones
+    pub pin_rays: [u64; 64],
+    /// Squares the enemy attacks with our king taken off the board, so the king cannot step
+    /// back along the ray of a checking slider
+    pub attacked: u64,
+}
+
+/// Which of the legal moves a generator adds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GenType {
+    All,
+    /// Captures, en passant and promotions that take a piece
+    Captures,
+    /// Everything else: quiet moves, castling and promotions on an empty square
+    Quiets,
+}
+
+impl GenType {
+    /// Squares pieces of `color` may move to under this type
+    fn targets(self, board: &Board, color: Color) -> u64 {
+        let own = board.color_occupancy(color);
+        let enemy = board.color_occupancy(!color);
+        match self {
+            GenType::All => !own,
+            GenType::Captures => enemy,
+            GenType::Quiets => !(own | enemy),
+        }
+    }
+}
+
+/// Squares strictly between `a` and `b` if they share a line, otherwise none
+pub fn between(a: Square, b: Square) -> u64 {
+    let file_step = (b.file() as i8 - a.file() as i8).signum();
+    let rank_step = (b.rank() as i8 - a.rank() as i8).signum();
+    let aligned = a.file() == b.file()
+        || a.rank() == b.rank()
+        || a.file().abs_diff(b.file()) == a.rank().abs_diff(b.rank());
+    let mut squares: u64 = 0;
+    if !aligned || a == b {
+        return squares;
+    }
+    let mut current = a.offset((file_step, rank_step)).unwrap();
+    while current != b {
+        squares |= current.bit();
+        current = current.offset((file_step, rank_step)).unwrap();
+    }
+    squares
+}
 
 impl ChessState {
-    pub fn get_all_possible_moves(&self) -> Vec<String> {
-        let coefficient: i8 = if self.turn { 1 } else { -1 };
-        let danger_squares: HashSet<(usize, usize)> =
-            ChessState::danger_squares(self.board, coefficient);
-        let k_pos: (usize, usize) = ChessState::find_position(self.board, 6 * coefficient);
-        if danger_squares.contains(&k_pos) {
-            let mut all_moves: Vec<String> = Vec::new();
-            for pos_move in self.raw_get_all_possible_moves().iter() {
-                let mut new_state = self.do_move(pos_move);
-                new_state.turn = !new_state.turn;
-                if !ChessState::danger_squares(new_state.board, coefficient)
-                    .contains(&ChessState::find_position(new_state.board, 6 * coefficient))
-                {
-                    all_moves.push(pos_move.clone());
-                }
-            }
-            return all_moves;
-        } else {
-            return ChessState::raw_get_all_possible_moves(&self);
+    /// The legal moves of the side to move
+    pub fn get_all_possible_moves(&self) -> MoveList {
+        let mut list = MoveList::new();
+        self.generate_moves(&mut list);
+        list
+    }
+
+    /// Adds the legal moves of the side to move to `list`, pawns first and castling last
+    pub fn generate_moves(&self, list: &mut MoveList) {
+        self.generate(GenType::All, list);
+    }
+
+    /// Adds the legal captures of the side to move to `list`
+    pub fn generate_captures(&self, list: &mut MoveList) {
+        self.generate(GenType::Captures, list);
+    }
+
+    /// Adds the legal moves that capture nothing to `list`
+    pub fn generate_quiets(&self, list: &mut MoveList) {
+        self.generate(GenType::Quiets, list);
+    }
+
+    /// Adds the moves out of check to `list`, none when the side to move is not in check
+    pub fn generate_evasions(&self, list: &mut MoveList) {
+        if self.check() {
+            self.generate(GenType::All, list);
         }
     }
 
-    fn raw_get_all_possible_moves(&self) -> Vec<String> {
-        let player: bool = self.turn;
-        let coefficient: i8 = if player { 1 } else { -1 };
-        let back_rank: usize = if player { 0 } else { 7 };
-        let mut all_moves: Vec<String> = Vec::new();
-        let p_pos: Vec<(usize, usize)> = ChessState::find_positions(self.board, 1 * coefficient);
-        let r_pos: Vec<(usize, usize)> = ChessState::find_positions(self.board, 2 * coefficient);
-        let n_pos: Vec<(usize, usize)> = ChessState::find_positions(self.board, 3 * coefficient);
-        let b_pos: Vec<(usize, usize)> = ChessState::find_positions(self.board, 4 * coefficient);
-        let q_pos: Vec<(usize, usize)> = ChessState::find_positions(self.board, 5 * coefficient);
-        let k_pos: (usize, usize) = ChessState::find_position(self.board, 6 * coefficient);
-        let horisontal_pin: Vec<(usize, usize)> =
-            ChessState::horisontal_pin(self.board, k_pos, coefficient);
-        let vertical_pin: Vec<(usize, usize)> =
-            ChessState::vertical_pin(self.board, k_pos, coefficient);
-        let left_diagonal_pin: Vec<(usize, usize)> =
-            ChessState::left_diagonal_pin(self.board, k_pos, coefficient);
-        let right_diagonal_pin: Vec<(usize, usize)> =
-            ChessState::right_diagonal_pin(self.board, k_pos, coefficient);
-        let danger_squares: HashSet<(usize, usize)> =
-            ChessState::danger_squares(self.board, coefficient);
-
-        (if player {
-            all_moves.append(&mut ChessState::white_pawn_moves(
-                self,
-                coefficient,
-                &vertical_pin,
-                &horisontal_pin,
-                &left_diagonal_pin,
-                &right_diagonal_pin,
-                &p_pos,
-            ))
-        } else {
-            all_moves.append(&mut ChessState::black_pawn_moves(
-                self,
-                coefficient,
-                &vertical_pin,
-                &horisontal_pin,
-                &left_diagonal_pin,
-                &right_diagonal_pin,
-                &p_pos,
-            ));
-        });
-        all_moves.append(&mut ChessState::straight_moves(
-            self,
-            coefficient,
-            &vertical_pin,
-            &horisontal_pin,
-            &left_diagonal_pin,
-            &right_diagonal_pin,
-            &r_pos,
-            player,
-            "R",
-        ));
-        all_moves.append(&mut ChessState::diagonal_moves(
-            self,
-            coefficient,
-            &vertical_pin,
-            &horisontal_pin,
-            &left_diagonal_pin,
-            &right_diagonal_pin,
-            &b_pos,
-            player,
-            "B",
-        ));
-
-        all_moves.append(&mut ChessState::straight_moves(
-            self,
-            coefficient,
-            &vertical_pin,
-            &horisontal_pin,
-            &left_diagonal_pin,
-            &right_diagonal_pin,
-            &q_pos,
-            player,
-            "Q",
-        ));
-        all_moves.append(&mut ChessState::diagonal_moves(
-            self,
-            coefficient,
-            &vertical_pin,
-            &horisontal_pin,
-            &left_diagonal_pin,
-            &right_diagonal_pin,
-            &q_pos,
-            player,
-            "Q",
-        ));
-        all_moves.append(&mut ChessState::knight_moves(
-            self,
-            coefficient,
-            &vertical_pin,
-            &horisontal_pin,
-            &left_diagonal_pin,
-            &right_diagonal_pin,
-            &n_pos,
-        ));
-        all_moves.append(&mut ChessState::king_moves(
-            self,
-            coefficient,
-            &danger_squares,
-            k_pos,
-            back_rank,
-        ));
-        return all_moves;
-    }
-
-    pub fn white_pawn_moves(
-        state: &ChessState,
-        coefficient: i8,
-        vertical_pin: &Vec<(usize, usize)>,
-        horisontal_pin: &Vec<(usize, usize)>,
-        left_diagonal_pin: &Vec<(usize, usize)>,
-        right_diagonal_pin: &Vec<(usize, usize)>,
-        p_pos: &Vec<(usize, usize)>,
-    ) -> Vec<String> {
-        let mut all_moves = Vec::new();
-        for pos in p_pos.iter() {
-            if state.board[pos.0 + 1][pos.1] == 0
-                && !(horisontal_pin.contains(pos)
-                    || left_diagonal_pin.contains(pos)
-                    || right_diagonal_pin.contains(pos))
-            {
-                let m = format!(
-                    "{}{}-{}{}",
-                    (pos.1 + 97) as u8 as char,
-                    pos.0 + 1,
-                    (pos.1 + 97) as u8 as char,
-                    pos.0 + 2
-                );
-                if pos.0 == 1 && state.board[3][pos.1] == 0 {
-                    //White double pawn move
-                    all_moves.push(format!(
-                        "{}{}-{}{}",
-                        (pos.1 + 97) as u8 as char,
-                        2,
-                        (pos.1 + 97) as u8 as char,
-                        4
-                    ));
-                }
-                if pos.0 == 6 {
-                    for piece in PIECE_SYMBOLS.iter() {
-                        all_moves.push(m.clone() + piece);
-                    }
-                } else {
-                    all_moves.push(m);
-                }
-            }
-            if pos.1 > 0
-                && (state.board[pos.0 + 1][pos.1 - 1] < 0
-                    || (state.en_passant[pos.1 - 1] != 0 && pos.0 == 4))
-                && !(vertical_pin.contains(pos)
-                    || horisontal_pin.contains(pos)
-                    || right_diagonal_pin.contains(pos))
-            {
-                let m = format!(
-                    "{}{}x{}{}",
-                    (pos.1 + 97) as u8 as char,
-                    pos.0 + 1,
-                    (pos.1 + 96) as u8 as char,
-                    pos.0 + 2
-                );
-                if pos.0 == 6 {
-                    for piece in PIECE_SYMBOLS {
-                        all_moves.push(m.clone() + piece);
-                    }
-                } else {
-                    all_moves.push(m.clone());
-                }
-            }
-            if pos.1 < 7
-                && (state.board[pos.0 + 1][pos.1 + 1] < 0
-                    || (state.en_passant[pos.1 + 1] != 0 && pos.0 == 4))
-                && !(vertical_pin.contains(pos)
-                    || horisontal_pin.contains(pos)
-                    || left_diagonal_pin.contains(pos))
-            {
-                let m = format!(
-                    "{}{}x{}{}",
-                    (pos.1 + 97) as u8 as char,
-                    pos.0 + 1,
-                    (pos.1 + 98) as u8 as char,
-                    pos.0 + 2
-                );
-                if pos.0 == 6 {
-                    for piece in PIECE_SYMBOLS {
-                        all_moves.push(m.clone() + piece);
-                    }
-                } else {
-                    all_moves.push(m);
-                }
+    /// Adds the legal moves that give check to `list`
+    pub fn generate_checks(&self, list: &mut MoveList) {
+        //Generate into `list` itself and keep only the checks at the end of it
+        let start = list.len();
+        self.generate(GenType::All, list);
+        let mut kept = start;
+        for idx in start..list.len() {
+            let m = list[idx];
+            if self.gives_check(&m) {
+                list[kept] = m;
+                kept += 1;
             }
         }
-        return all_moves;
-    }
-    pub fn black_pawn_moves(
-        state: &ChessState,
-        coefficient: i8,
-        vertical_pin: &Vec<(usize, usize)>,
-        horisontal_pin: &Vec<(usize, usize)>,
-        left_diagonal_pin: &Vec<(usize, usize)>,
-        right_diagonal_pin: &Vec<(usize, usize)>,
-        p_pos: &Vec<(usize, usize)>,
-    ) -> Vec<String> {
-        let mut all_moves: Vec<String> = Vec::new();
-        for pos in p_pos.iter() {
-            if state.board[pos.0 - 1][pos.1] == 0
-                && !(horisontal_pin.contains(pos)
-                    || left_diagonal_pin.contains(pos)
-                    || right_diagonal_pin.contains(pos))
-            {
-                let m = format!(
-                    "{}{}-{}{}",
-                    (pos.1 + 97) as u8 as char,
-                    pos.0 + 1,
-                    (pos.1 + 97) as u8 as char,
-                    pos.0
-                );
-                if pos.0 == 6 && state.board[4][pos.1] == 0 {
-                    //Black double pawn move
-                    let m = format!(
-                        "{}{}-{}{}",
-                        (pos.1 + 97) as u8 as char,
-                        pos.0 + 1,
-                        (pos.1 + 97) as u8 as char,
-                        pos.0 - 1
-                    );
-                    all_moves.push(m);
-                } else if pos.0 == 1 {
-                    for piece in PIECE_SYMBOLS {
-                        all_moves.push(m.clone() + piece);
-                    }
-                } else {
-                    all_moves.push(m);
-                }
-            }
-            if pos.1 > 0
-                && (state.board[pos.0 - 1][pos.1 - 1] > 0
-                    || (state.en_passant[pos.1 - 1] != 0 && pos.0 == 3))
-                && !(vertical_pin.contains(pos)
-                    || horisontal_pin.contains(pos)
-                    || left_diagonal_pin.contains(pos))
-            {
-                let m = format!(
-                    "{}{}x{}{}",
-                    (pos.1 + 97) as u8 as char,
-                    pos.0 + 1,
-                    (pos.1 + 96) as u8 as char,
-                    pos.0
-                );
-                if pos.0 == 1 {
-                    for piece in PIECE_SYMBOLS.iter() {
-                        all_moves.push(m.clone() + piece);
-                    }
-                } else {
-                    all_moves.push(m.clone());
-                }
+        list.truncate(kept);
+    }
+
+    pub fn moves(&self) -> MoveListIter {
+        self.get_all_possible_moves().into_iter()
+    }
+
+    pub fn captures(&self) -> MoveListIter {
+        let mut list = MoveList::new();
+        self.generate_captures(&mut list);
+        list.into_iter()
+    }
+
+    pub fn quiets(&self) -> MoveListIter {
+        let mut list = MoveList::new();
+        self.generate_quiets(&mut list);
+        list.into_iter()
+    }
+
+    pub fn evasions(&self) -> MoveListIter {
+        let mut list = MoveList::new();
+        self.generate_evasions(&mut list);
+        list.into_iter()
+    }
+
+    pub fn checks(&self) -> MoveListIter {
+        let mut list = MoveList::new();
+        self.generate_checks(&mut list);
+        list.into_iter()
+    }
+
+    fn generate(&self, gen: GenType, list: &mut MoveList) {
+        let color = self.turn;
+        let masks = self.legal_masks(color);
+        self.pawn_moves(color, &masks, gen, list);
+        for kind in [
+            PieceKind::Rook,
+            PieceKind::Bishop,
+            PieceKind::Queen,
+            PieceKind::Knight,
+        ] {
+            self.piece_moves(color, kind, &masks, gen, list);
+        }
+        self.king_moves(color, &masks, gen, list);
+        if gen != GenType::Captures {
+            self.castling_moves(&masks, list);
+        }
+    }
+
+    /// Checkers, check mask, pin rays and attacked squares for the king of `color`
+    pub fn legal_masks(&self, color: Color) -> LegalMasks {
+        let occupancy = self.board.occupancy();
+        let mut masks = LegalMasks {
+            checkers: 0,
+            check_mask: !0,
+            pin_rays: [!0; 64],
+            attacked: 0,
+        };
+        let Some(king) = self.board.find(Piece::new(PieceKind::King, color)) else {
+            masks.attacked = ChessState::attacks(&self.board, !color, occupancy);
+            return masks;
+        };
+        masks.attacked = ChessState::attacks(&self.board, !color, occupancy & !king.bit());
+        masks.checkers = self.attackers_to(king) & self.board.color_occupancy(!color);
+        masks.check_mask = match masks.checkers.count_ones() {
+            0 => !0,
+            1 => {
+                let checker = Square::from_index(masks.checkers.trailing_zeros() as usize);
+                masks.checkers | between(king, checker)
             }
-            if pos.1 < 7
-                && (state.board[pos.0 - 1][pos.1 + 1] > 0
-                    || (state.en_passant[pos.1 + 1] != 0 && pos.0 == 3))
-                && !(vertical_pin.contains(pos)
-                    || horisontal_pin.contains(pos)
-                    || right_diagonal_pin.contains(pos))
-            {
-                let m = format!(
-                    "{}{}x{}{}",
-                    (pos.1 + 97) as u8 as char,
-                    pos.0 + 1,
-                    (pos.1 + 98) as u8 as char,
-                    pos.0
-                );
-                if pos.0 == 1 {
-                    for piece in PIECE_SYMBOLS.iter() {
-                        all_moves.push(m.clone() + piece);
+            _ => 0,
+        };
+        masks.pin_rays = self.pin_rays(color, king);
+        masks
+    }
+
+    /// For each piece of `color` pinned to `king`, the squares from the king to the pinner,
+    /// and all squares for the rest
+    pub(crate) fn pin_rays(&self, color: Color, king: Square) -> [u64; 64] {
+        let mut pin_rays = [!0; 64];
+        //A piece is pinned when it is the only one between the king and an enemy slider
+        for (directions, kind) in [(STRAIGHT, PieceKind::Rook), (DIAGONAL, PieceKind::Bishop)] {
+            for direction in directions {
+                let mut current = king;
+                let mut pinned: Option<Square> = None;
+                while let Some(new_pos) = current.offset(direction) {
+                    current = new_pos;
+                    let Some(piece) = self.board[new_pos] else {
+                        continue;
+                    };
+                    match pinned {
+                        None if piece.color == color => pinned = Some(new_pos),
+                        Some(pinned)
+                            if piece.color != color
+                                && (piece.kind == kind || piece.kind == PieceKind::Queen) =>
+                        {
+                            pin_rays[pinned.index()] = between(king, new_pos) | new_pos.bit();
+                            break;
+                        }
+                        _ => break,
                     }
-                } else {
-                    all_moves.push(m);
                 }
             }
         }
-        return all_moves;
-    }
-    pub fn straight_moves(
-        state: &ChessState,
-        coefficient: i8,
-        vertical_pin: &Vec<(usize, usize)>,
-        horisontal_pin: &Vec<(usize, usize)>,
-        left_diagonal_pin: &Vec<(usize, usize)>,
-        right_diagonal_pin: &Vec<(usize, usize)>,
-        s_pos: &Vec<(usize, usize)>,
-        player: bool,
-        sign: &str,
-    ) -> Vec<String> {
-        let mut all_moves: Vec<String> = Vec::new();
-        for pos in s_pos.iter() {
-            let mut possible: Vec<(usize, usize)> = Vec::new();
-            if !(vertical_pin.contains(pos)
-                || right_diagonal_pin.contains(pos)
-                || left_diagonal_pin.contains(pos))
-            {
-                //Horisontal rook moves
-                for dir in HORISONTAL {
-                    possible.append(&mut ChessState::directional_moves(
-                        state.board,
-                        *pos,
-                        dir,
-                        player,
-                    ));
-                }
-            }
-            if !(horisontal_pin.contains(pos)
-                || right_diagonal_pin.contains(pos)
-                || left_diagonal_pin.contains(pos))
-            {
-                //Vertical rook moves
-                for dir in VERTICAL {
-                    possible.append(&mut ChessState::directional_moves(
-                        state.board,
-                        *pos,
-                        dir,
-                        player,
-                    ));
+        pin_rays
+    }
+
+    /// Legal pawn moves of `color`, en passant only for the side to move
+    pub fn pawn_moves(&self, color: Color, masks: &LegalMasks, gen: GenType, list: &mut MoveList) {
+        let forward = color.sign();
+        //Pawns start on the second rank from their side and promote on the last
+        let (start_rank, promotion_rank) = if color.is_white() { (1, 7) } else { (6, 0) };
+        let push = |from: Square, to: Square, capture: bool, list: &mut MoveList| {
+            if to.rank() == promotion_rank {
+                for piece in PROMOTIONS {
+                    let kind = MoveKind::Promotion { piece, capture };
+                    list.push(Move::new(PieceKind::Pawn, from, to, kind));
                 }
-            }
-            for new_pos in possible.iter() {
-                all_moves.push(if state.board[new_pos.0][new_pos.1] == 0 {
-                    format!(
-                        "{}{}{}-{}{}",
-                        sign,
-                        (pos.1 + 97) as u8 as char,
-                        pos.0 + 1,
-                        (new_pos.1 + 97) as u8 as char,
-                        new_pos.0 + 1
-                    )
+            } else {
+                let kind = if capture {
+                    MoveKind::Capture
                 } else {
-                    format!(
-                        "{}{}{}x{}{}",
-                        sign,
-                        (pos.1 + 97) as u8 as char,
-                        pos.0 + 1,
-                        (new_pos.1 + 97) as u8 as char,
-                        new_pos.0 + 1
-                    )
-                });
+                    MoveKind::Quiet
+                };
+                list.push(Move::new(PieceKind::Pawn, from, to, kind));
             }
-        }
-        return all_moves;
-    }
-    pub fn diagonal_moves(
-        state: &ChessState,
-        coefficient: i8,
-        vertical_pin: &Vec<(usize, usize)>,
-        horisontal_pin: &Vec<(usize, usize)>,
-        left_diagonal_pin: &Vec<(usize, usize)>,
-        right_diagonal_pin: &Vec<(usize, usize)>,
-        d_pos: &Vec<(usize, usize)>,
-        player: bool,
-        sign: &str,
-    ) -> Vec<String> {
-        let mut all_moves: Vec<String> = Vec::new();
-        for pos in d_pos.iter() {
-            let mut possible: Vec<(usize, usize)> = Vec::new();
-            if !(vertical_pin.contains(pos)
-                || horisontal_pin.contains(pos)
-                || left_diagonal_pin.contains(pos))
-            {
-                //Horisontal rook moves
-                for dir in RIGHT_DIAGONAL {
-                    possible.append(&mut ChessState::directional_moves(
-                        state.board,
-                        *pos,
-                        dir,
-                        player,
-                    ));
+        };
+        for pos in Square::in_set(self.board.find_all(Piece::new(PieceKind::Pawn, color))) {
+            let mask = masks.check_mask & masks.pin_rays[pos.index()];
+            let Some(ahead) = pos.offset((0, forward)) else {
+                continue;
+            };
+            if gen != GenType::Captures && self.board[ahead].is_none() {
+                if pos.rank() == start_rank {
+                    let double = ahead.offset((0, forward)).unwrap();
+                    if self.board[double].is_none() && mask & double.bit() != 0 {
+                        list.push(Move::new(
+                            PieceKind::Pawn,
+                            pos,
+                            double,
+                            MoveKind::DoublePush,
+                        ));
+                    }
                 }
-            }
-            if !(vertical_pin.contains(pos)
-                || horisontal_pin.contains(pos)
-                || right_diagonal_pin.contains(pos))
-            {
-                //Vertical rook moves
-                for dir in LEFT_DIAGONAL {
-                    possible.append(&mut ChessState::directional_moves(
-                        state.board,
-                        *pos,
-                        dir,
-                        player,
-                    ));
+                if mask & ahead.bit() != 0 {
+                    push(pos, ahead, false, list);
                 }
             }
-            for new_pos in possible.iter() {
-                all_moves.push(if state.board[new_pos.0][new_pos.1] == 0 {
-                    format!(
-                        "{}{}{}-{}{}",
-                        sign,
-                        (pos.1 + 97) as u8 as char,
-                        pos.0 + 1,
-                        (new_pos.1 + 97) as u8 as char,
-                        new_pos.0 + 1
-                    )
-                } else {
-                    format!(
-                        "{}{}{}x{}{}",
-                        sign,
-                        (pos.1 + 97) as u8 as char,
-                        pos.0 + 1,
-                        (new_pos.1 + 97) as u8 as char,
-                        new_pos.0 + 1
-                    )
-                });
+            if gen == GenType::Quiets {
+                continue;
             }
-        }
-        return all_moves;
-    }
-    pub fn knight_moves(
-        state: &ChessState,
-        coefficient: i8,
-        vertical_pin: &Vec<(usize, usize)>,
-        horisontal_pin: &Vec<(usize, usize)>,
-        left_diagonal_pin: &Vec<(usize, usize)>,
-        right_diagonal_pin: &Vec<(usize, usize)>,
-        n_pos: &Vec<(usize, usize)>,
-    ) -> Vec<String> {
-        let mut all_moves: Vec<String> = Vec::new();
-        for pos in n_pos.iter() {
-            if !(vertical_pin.contains(pos)
-                || horisontal_pin.contains(pos)
-                || left_diagonal_pin.contains(pos)
-                || right_diagonal_pin.contains(pos))
-            {
-                for dir in KNIGHT_DIRECTIONS {
-                    let new_pos = (pos.0 as i8 + dir.0, pos.1 as i8 + dir.1);
-                    if -1 < new_pos.0 && new_pos.0 < 8 && -1 < new_pos.1 && new_pos.1 < 7 {
-                        if state.board[new_pos.0 as usize][new_pos.1 as usize] * coefficient < 1 {
-                            all_moves.push(
-                                if state.board[new_pos.0 as usize][new_pos.1 as usize] == 0 {
-                                    format!(
-                                        "N{}{}-{}{}",
-                                        (pos.1 + 97) as u8 as char,
-                                        pos.0 + 1,
-                                        (new_pos.1 + 97) as u8 as char,
-                                        new_pos.0 + 1
-                                    )
-                                } else {
-                                    format!(
-                                        "N{}{}x{}{}",
-                                        (pos.1 + 97) as u8 as char,
-                                        pos.0 + 1,
-                                        (new_pos.1 + 97) as u8 as char,
-                                        new_pos.0 + 1
-                                    )
-                                },
-                            );
-                        }
+            for side in [-1, 1] {
+                let Some(target) = pos.offset((side, forward)) else {
+                    continue;
+                };
+                if self.board[target].is_some_and(|x| x.color != color) {
+                    if mask & target.bit() != 0 {
+                        push(pos, target, true, list);
                     }
+                } else if color == self.turn
+                    && self.en_passant[target.file()] != 0
+                    && pos.rank() == if color.is_white() { 4 } else { 3 }
+                    && self.en_passant_legal(color, pos, target)
+                {
+                    list.push(Move::new(PieceKind::Pawn, pos, target, MoveKind::EnPassant));
                 }
             }
         }
-        return all_moves;
-    }
-    pub fn king_moves(
-        state: &ChessState,
-        coefficient: i8,
-        danger_squares: &HashSet<(usize, usize)>,
-        k_pos: (usize, usize),
-        back_rank: usize,
-    ) -> Vec<String> {
-        let mut all_moves: Vec<String> = Vec::new();
-        for dir in KING_DIRECTIONS {
-            let new_pos: (i8, i8) = (k_pos.0 as i8 + dir.0, k_pos.1 as i8 + dir.1);
-            if -1 < new_pos.0
-                && new_pos.0 < 8
-                && -1 < new_pos.1
-                && new_pos.1 < 8
-                && !danger_squares.contains(&(new_pos.0 as usize, new_pos.1 as usize))
-                && state.board[new_pos.0 as usize][new_pos.1 as usize] * coefficient < 1
-            {
-                all_moves.push(
-                    if state.board[new_pos.0 as usize][new_pos.1 as usize] == 0 {
-                        format!(
-                            "K{}{}-{}{}",
-                            (k_pos.1 + 97) as u8 as char,
-                            k_pos.0 + 1,
-                            (new_pos.1 + 97) as u8 as char,
-                            new_pos.0 + 1
-                        )
-                    } else {
-                        format!(
-                            "K{}{}x{}{}",
-                            (k_pos.1 + 97) as u8 as char,
-                            k_pos.0 + 1,
-                            (new_pos.1 + 97) as u8 as char,
-                            new_pos.0 + 1
-                        )
-                    },
-                );
+    }
+
+    /// Whether taking en passant from `source` to `target` leaves the king of `color` safe.
+    /// Two pawns leave the board's rank at once, so this looks at the position after it
+    /// rather than at the pins.
+    fn en_passant_legal(&self, color: Color, source: Square, target: Square) -> bool {
+        let Some(king) = self.board.find(Piece::new(PieceKind::King, color)) else {
+            return true;
+        };
+        let captured = Square::new(target.file(), source.rank());
+        let occupancy = (self.board.occupancy() & !source.bit() & !captured.bit()) | target.bit();
+        ChessState::attackers(&self.board, king, occupancy) & self.board.color_occupancy(!color)
+            == 0
+    }
+
+    /// Legal moves of the rooks, knights, bishops or queens of `color`
+    pub fn piece_moves(
+        &self,
+        color: Color,
+        kind: PieceKind,
+        masks: &LegalMasks,
+        gen: GenType,
+        list: &mut MoveList,
+    ) {
+        let piece = Piece::new(kind, color);
+        let occupancy = self.board.occupancy();
+        let targets = gen.targets(&self.board, color);
+        for pos in Square::in_set(self.board.find_all(piece)) {
+            let mask = masks.check_mask & masks.pin_rays[pos.index()] & targets;
+            for target in Square::in_set(ChessState::piece_attacks(piece, pos, occupancy) & mask) {
+                list.push(self.piece_move(kind, pos, target));
             }
         }
-        if state.castling.contains("K")
-            && state.board[back_rank][5] == 0
-            && state.board[back_rank][6] == 0
-            && !danger_squares.contains(&(back_rank, 4))
-            && !danger_squares.contains(&(back_rank, 5))
-            && !danger_squares.contains(&(back_rank, 6))
-        {
-            all_moves.push("O-O".into())
+    }
+
+    /// Legal king moves of `color`, not counting castling
+    pub fn king_moves(&self, color: Color, masks: &LegalMasks, gen: GenType, list: &mut MoveList) {
+        let king = Piece::new(PieceKind::King, color);
+        let Some(k_pos) = self.board.find(king) else {
+            return;
+        };
+        let targets = gen.targets(&self.board, color) & !masks.attacked;
+        for target in Square::in_set(ChessState::piece_attacks(king, k_pos, 0) & targets) {
+            list.push(self.piece_move(PieceKind::King, k_pos, target));
         }
-        if state.castling.contains("Q")
-            && state.board[back_rank][1] == 0
-            && state.board[back_rank][2] == 0
-            && state.board[back_rank][3] == 0
-            && !danger_squares.contains(&(back_rank, 1))
-            && !danger_squares.contains(&(back_rank, 2))
-            && !danger_squares.contains(&(back_rank, 3))
-            && !danger_squares.contains(&(back_rank, 4))
-        {
-            all_moves.push("O-O-O".into())
+    }
+
+    /// A move that is a capture exactly when `target` is occupied
+    fn piece_move(&self, kind: PieceKind, source: Square, target: Square) -> Move {
+        let move_kind = if self.board[target].is_some() {
+            MoveKind::Capture
+        } else {
+            MoveKind::Quiet
+        };
+        Move::new(kind, source, target, move_kind)
+    }
+
+    /// Castling moves of the side to move, also in Chess960
+    pub fn castling_moves(&self, masks: &LegalMasks, list: &mut MoveList) {
+        let color = self.turn;
+        let back_rank = color.back_rank();
+        let Some(king_file) = self.king_file(color) else {
+            return;
+        };
+        if masks.checkers != 0 {
+            return;
         }
-        return all_moves;
-    }
-
-    fn directional_moves(
-        board: [[i8; 8]; 8],
-        position: (usize, usize),
-        direction: (i8, i8),
-        player: bool,
-    ) -> Vec<(usize, usize)> {
-        let coefficient: i8 = if player { 1 } else { -1 };
-        let mut new_pos = (
-            position.0 as i8 + direction.0,
-            position.1 as i8 + direction.1,
-        );
-        let mut moves: Vec<(usize, usize)> = Vec::new();
-        while -1 < new_pos.0 && new_pos.0 < 8 && -1 < new_pos.1 && new_pos.1 < 8 {
-            if board[new_pos.0 as usize][new_pos.1 as usize] == 0 {
-                moves.push((new_pos.0 as usize, new_pos.1 as usize));
-            } else if board[new_pos.0 as usize][new_pos.1 as usize] * coefficient < 0 {
-                moves.push((new_pos.0 as usize, new_pos.1 as usize));
-                break;
-            } else {
-                break;
+        for king_side in [true, false] {
+            let Some(rook_file) = self.castling_rook(king_side) else {
+                continue;
+            };
+            let (king_target, rook_target) = if king_side { (6, 5) } else { (2, 3) };
+            let lowest = king_file.min(king_target).min(rook_file).min(rook_target);
+            let highest = king_file.max(king_target).max(rook_file).max(rook_target);
+            let square = |file: usize| Square::new(file, back_rank);
+            let free = (lowest..=highest)
+                .all(|x| x == king_file || x == rook_file || self.board[square(x)].is_none());
+            let safe = (king_file.min(king_target)..=king_file.max(king_target))
+                .all(|x| masks.attacked & square(x).bit() == 0);
+            if !free || !safe {
+                continue;
+            }
+            //In Chess960 the castling rook may be what shielded the king's target square
+            let occupancy =
+                (self.board.occupancy() & !square(king_file).bit() & !square(rook_file).bit())
+                    | square(king_target).bit()
+                    | square(rook_target).bit();
+            if ChessState::attackers(&self.board, square(king_target), occupancy)
+                & self.board.color_occupancy(!color)
+                == 0
+            {
+                list.push(Move::new(
+                    PieceKind::King,
+                    square(king_file),
+                    square(rook_file),
+                    MoveKind::Castle { king_side },
+                ));
             }
-            new_pos = (new_pos.0 as i8 + direction.0, new_pos.1 as i8 + direction.1);
-        }
-        return moves;
-    }
-
-    pub fn vertical_pin(
-        board: [[i8; 8]; 8],
-        position: (usize, usize),
-        coefficient: i8,
-    ) -> Vec<(usize, usize)> {
-        let mut pinned = Vec::new();
-        for direction in VERTICAL {
-            pinned.append(&mut ChessState::directional_pin(
-                board,
-                position,
-                coefficient,
-                direction,
-                vec![-2, -5],
-            ))
-        }
-        pinned
-    }
-
-    pub fn horisontal_pin(
-        board: [[i8; 8]; 8],
-        position: (usize, usize),
-        coefficient: i8,
-    ) -> Vec<(usize, usize)> {
-        let mut pinned = Vec::new();
-        for direction in HORISONTAL {
-            pinned.append(&mut ChessState::directional_pin(
-                board,
-                position,
-                coefficient,
-                direction,
-                vec![-2, -5],
-            ))
         }
-        pinned
-    }
-
-    pub fn right_diagonal_pin(
-        board: [[i8; 8]; 8],
-        position: (usize, usize),
-        coefficient: i8,
-    ) -> Vec<(usize, usize)> {
-        let mut pinned = Vec::new();
-        for direction in RIGHT_DIAGONAL {
-            pinned.append(&mut ChessState::directional_pin(
-                board,
-                position,
-                coefficient,
-                direction,
-                vec![-4, -5],
-            ))
+    }
+
+    /// Squares attacked by the pieces of `color`, sliders stopping at squares in `occupancy`
+    pub fn attacks(board: &Board, color: Color, occupancy: u64) -> u64 {
+        let mut attacked: u64 = 0;
+        for (square, piece) in board.pieces() {
+            if piece.color == color {
+                attacked |= ChessState::piece_attacks(piece, square, occupancy);
+            }
         }
-        pinned
-    }
-
-    pub fn left_diagonal_pin(
-        board: [[i8; 8]; 8],
-        position: (usize, usize),
-        coefficient: i8,
-    ) -> Vec<(usize, usize)> {
-        let mut pinned = Vec::new();
-        for direction in LEFT_DIAGONAL {
-            pinned.append(&mut ChessState::directional_pin(
-                board,
+        attacked
+    }
+
+    /// Squares `piece` attacks from `position`, sliders stopping at the first square set in
+    /// `occupancy`. The color of `piece` decides which way a pawn attacks.
+    pub fn piece_attacks(piece: Piece, position: Square, occupancy: u64) -> u64 {
+        match piece.kind {
+            PieceKind::Pawn => ChessState::steps(
                 position,
-                coefficient,
-                direction,
-                vec![-4, -5],
-            ))
+                &[(-1, piece.color.sign()), (1, piece.color.sign())],
+            ),
+            PieceKind::Rook => ChessState::slide(position, &STRAIGHT, occupancy),
+            PieceKind::Knight => ChessState::steps(position, &KNIGHT_DIRECTIONS),
+            PieceKind::Bishop => ChessState::slide(position, &DIAGONAL, occupancy),
+            PieceKind::Queen => ChessState::slide(position, &KING_DIRECTIONS, occupancy),
+            PieceKind::King => ChessState::steps(position, &KING_DIRECTIONS),
         }
-        pinned
-    }
-
-    fn directional_pin(
-        board: [[i8; 8]; 8],
-        position: (usize, usize),
-        coefficient: i8,
-        direction: (i8, i8),
-        dangerous: Vec<i8>,
-    ) -> Vec<(usize, usize)> {
-        let mut visited: Vec<(usize, usize)> = Vec::new();
-        let mut new_pos = (
-            position.0 as i8 + direction.0,
-            position.1 as i8 + direction.1,
-        );
-        let mut pin_flag = false;
-        while -1 < new_pos.0 && new_pos.0 < 8 && -1 < new_pos.1 && new_pos.1 < 8 {
-            if board[new_pos.0 as usize][new_pos.1 as usize] * coefficient < 0 {
-                if (dangerous
-                    .contains(&(board[new_pos.0 as usize][new_pos.1 as usize] * coefficient)))
-                    && pin_flag
-                {
-                    return visited;
-                } else {
-                    break;
-                }
-            } else if board[new_pos.0 as usize][new_pos.1 as usize] * coefficient > 0 {
-                if pin_flag {
-                    break;
+    }
+
+    /// Pieces of both colors attacking `square`. Only pieces on squares set in `occupancy`
+    /// count, and sliders see through the squares it leaves out, so taking pieces away
+    /// uncovers the attackers behind them.
+    pub fn attackers(board: &Board, square: Square, occupancy: u64) -> u64 {
+        let mut attackers: u64 = 0;
+        for kind in PieceKind::ALL {
+            for color in [Color::White, Color::Black] {
+                let piece = Piece::new(kind, color);
+                //Attacks are symmetric except for pawns, which attack back the other way
+                let reverse = if kind == PieceKind::Pawn {
+                    Piece::new(kind, !color)
                 } else {
-                    pin_flag = true;
-                    visited.push((new_pos.0 as usize, new_pos.1 as usize));
+                    piece
+                };
+                let reached = ChessState::piece_attacks(reverse, square, occupancy) & occupancy;
+                for position in Square::in_set(reached) {
+                    if board[position] == Some(piece) {
+                        attackers |= position.bit();
+                    }
                 }
-            } else {
             }
-            new_pos = (new_pos.0 as i8 + direction.0, new_pos.1 as i8 + direction.1);
         }
-        Vec::new()
-    }
-
-    pub fn danger_squares(board: [[i8; 8]; 8], coefficient: i8) -> HashSet<(usize, usize)> {
-        let mut danger_squares = HashSet::new();
-        for (idx, row) in board.iter().enumerate() {
-            for (jdx, field) in row.iter().enumerate() {
-                let position = (idx, jdx);
-                match field * coefficient {
-                    -1 => {
-                        if jdx > 0 {
-                            danger_squares.insert(((idx as i8 - coefficient) as usize, jdx - 1));
-                        }
-                        if jdx < 7 {
-                            danger_squares.insert(((idx as i8 - coefficient) as usize, jdx + 1));
-                        }
-                    }
-                    -2 => {
-                        for direction in STRAIGHT {
-                            for new_pos in
-                                ChessState::danger_squares_from_position(board, direction, position)
-                                    .iter()
-                            {
-                                danger_squares.insert(*new_pos);
-                            }
-                        }
-                    }
-                    -3 => {
-                        for direction in KNIGHT_DIRECTIONS {
-                            let new_pos = (
-                                (position.0 as i8 + direction.0),
-                                (position.1 as i8 + direction.1),
-                            );
-                            if -1 < new_pos.0 && new_pos.0 < 8 && -1 < new_pos.1 && new_pos.1 < 8 {
-                                danger_squares.insert((new_pos.0 as usize, new_pos.1 as usize));
-                            }
-                        }
-                    }
-                    -4 => {
-                        for direction in DIAGONAL {
-                            for new_pos in
-                                ChessState::danger_squares_from_position(board, direction, position)
-                                    .iter()
-                            {
-                                danger_squares.insert(*new_pos);
-                            }
-                        }
-                    }
-                    -5 => {
-                        for direction in KING_DIRECTIONS {
-                            for new_pos in
-                                ChessState::danger_squares_from_position(board, direction, position)
-                                    .iter()
-                            {
-                                danger_squares.insert(*new_pos);
-                            }
-                        }
-                    }
-                    -6 => {
-                        for direction in KING_DIRECTIONS {
-                            let new_pos = (
-                                (position.0 as i8 + direction.0),
-                                (position.1 as i8 + direction.1),
-                            );
-                            if -1 < new_pos.0 && new_pos.0 < 8 && -1 < new_pos.1 && new_pos.1 < 8 {
-                                danger_squares.insert((new_pos.0 as usize, new_pos.1 as usize));
-                            }
-                        }
-                    }
-                    _ => {}
+        attackers
+    }
+
+    /// Squares one step away in each of `directions`
+    fn steps(position: Square, directions: &[(i8, i8)]) -> u64 {
+        directions
+            .iter()
+            .filter_map(|direction| position.offset(*direction))
+            .fold(0, |bits, x| bits | x.bit())
+    }
+
+    fn slide(position: Square, directions: &[(i8, i8)], occupancy: u64) -> u64 {
+        let mut squares: u64 = 0;
+        for direction in directions {
+            let mut current = position;
+            while let Some(new_pos) = current.offset(*direction) {
+                squares |= new_pos.bit();
+                if occupancy & new_pos.bit() != 0 {
+                    break;
                 }
+                current = new_pos;
             }
         }
-        danger_squares
-    }
-
-    fn danger_squares_from_position(
-        board: [[i8; 8]; 8],
-        direction: (i8, i8),
-        position: (usize, usize),
-    ) -> HashSet<(usize, usize)> {
-        let mut danger_squares = HashSet::new();
-        let mut new_pos = (
-            position.0 as i8 + direction.0,
-            position.1 as i8 + direction.1,
-        );
-        while -1 < new_pos.0 && new_pos.0 < 8 && -1 < new_pos.1 && new_pos.1 < 8 {
-            if board[new_pos.0 as usize][new_pos.1 as usize] != 0 {
-                danger_squares.insert((new_pos.0 as usize, new_pos.1 as usize));
-                break;
-            } else {
-                danger_squares.insert((new_pos.0 as usize, new_pos.1 as usize));
-            }
-            new_pos = (new_pos.0 as i8 + direction.0, new_pos.1 as i8 + direction.1);
+        squares
+    }
+
+    pub fn is_terminal(&self) -> bool {
+        self.get_all_possible_moves().is_empty()
+    }
+
+    /// Counts the leaf nodes of the legal move tree to the given depth
+    pub fn perft(&self, depth: u8) -> u64 {
+        if depth == 0 {
+            return 1;
         }
-        return danger_squares;
+        let moves = self.get_all_possible_moves();
+        if depth == 1 {
+            return moves.len() as u64;
+        }
+        moves.iter().map(|m| self.do_move(m).perft(depth - 1)).sum()
     }
+}
 
-    pub fn find_positions(board: [[i8; 8]; 8], piece: i8) -> Vec<(usize, usize)> {
-        let mut positions: Vec<(usize, usize)> = Vec::new();
-        for (idx, row) in board.iter().enumerate() {
-            for (jdx, field) in row.iter().enumerate() {
-                if field - piece == 0 {
-                    positions.push((idx, jdx));
-                }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn perft(fen: &str, depth: u8) -> u64 {
+        ChessState::from_fen(fen).perft(depth)
+    }
+
+    #[test]
+    fn perft_suite() {
+        assert_eq!(ChessState::new_board().perft(3), 8902);
+        assert_eq!(
+            perft(
+                "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+                3
+            ),
+            97862
+        );
+        assert_eq!(perft("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1", 4), 43238);
+        assert_eq!(
+            perft(
+                "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+                3
+            ),
+            9467
+        );
+        assert_eq!(
+            perft(
+                "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+                3
+            ),
+            62379
+        );
+        //Chess960, castling with the rook on the king's target square
+        assert_eq!(
+            perft(
+                "bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w HFhf - 2 9",
+                3
+            ),
+            12189
+        );
+    }
+
+    #[test]
+    fn deep_perft() {
+        //A ply deeper than the suite above, far enough for promotions to be captured, rooks
+        //taken on their castling squares and pawns pinned against en passant
+        assert_eq!(
+            perft("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1", 5),
+            674624
+        );
+        assert_eq!(
+            perft(
+                "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+                4
+            ),
+            422333
+        );
+        assert_eq!(
+            perft(
+                "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+                4
+            ),
+            2103487
+        );
+    }
+
+    #[test]
+    fn chess960_perft() {
+        //The first positions of the published Chess960 perft suite, with depths 1 to 3
+        let suite: [(&str, [u64; 3]); 10] = [
+            (
+                "bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w HFhf - 2 9",
+                [21, 528, 12189],
+            ),
+            (
+                "2nnrbkr/p1qppppp/8/1ppb4/6PP/3PP3/PPP2P2/BQNNRBKR w HEhe - 1 9",
+                [21, 807, 18002],
+            ),
+            (
+                "b1q1rrkb/pppppppp/3nn3/8/P7/1PPP4/4PPPP/BQNNRKRB w GE - 1 9",
+                [20, 479, 10471],
+            ),
+            (
+                "qbbnnrkr/2pp2pp/p7/1p2pp2/8/P3PP2/1PPP1KPP/QBBNNR1R w hf - 0 9",
+                [22, 593, 13440],
+            ),
+            (
+                "1nbbnrkr/p1p1ppp1/3p4/1p3P1p/3Pq2P/8/PPP1P1P1/QNBBNRKR w HFhf - 0 9",
+                [28, 1120, 31058],
+            ),
+            (
+                "qnbnr1kr/ppp1b1pp/4p3/3p1p2/8/2NPP3/PPP1BPPP/QNB1R1KR w HEhe - 1 9",
+                [29, 899, 26578],
+            ),
+            (
+                "q1bnrkr1/ppppp2p/2n2p2/4b1p1/2NP4/8/PPP1PPPP/QNB1RRKB w ge - 1 9",
+                [30, 860, 24566],
+            ),
+            (
+                "qbn1brkr/ppp1p1p1/2n4p/3p1p2/P7/6PP/QPPPPP2/1BNNBRKR w HFhf - 0 9",
+                [25, 635, 17054],
+            ),
+            (
+                "qnnbbrkr/1p2ppp1/2pp3p/p7/1P5P/2NP4/P1P1PPP1/Q1NBBRKR w HFhf - 0 9",
+                [24, 572, 15243],
+            ),
+            (
+                "qn1rbbkr/ppp2p1p/1n1pp1p1/8/3P4/P6P/1PP1PPPK/QNNRBB1R w hd - 2 9",
+                [28, 811, 23175],
+            ),
+        ];
+        for (fen, counts) in suite {
+            for (depth, count) in counts.into_iter().enumerate() {
+                assert_eq!(
+                    perft(fen, depth as u8 + 1),
+                    count,
+                    "{} at depth {}",
+                    fen,
+                    depth + 1
+                );
             }
         }
-        return positions;
     }
-    pub fn find_position(board: [[i8; 8]; 8], piece: i8) -> (usize, usize) {
-        for (idx, row) in board.iter().enumerate() {
-            for (jdx, field) in row.iter().enumerate() {
-                if field - piece == 0 {
-                    return (idx, jdx);
-                }
+
+    #[test]
+    fn edge_cases() {
+        //En passant discovering a check along the rank, and taking the checking pawn
+        assert_eq!(perft("3k4/3p4/8/K1P4r/8/8/8/8 b - - 0 1", 4), 10138);
+        assert_eq!(perft("8/8/1k6/2b5/2pP4/8/5K2/8 b - d3 0 1", 4), 13931);
+        assert_eq!(perft("8/8/8/8/k2Pp2Q/8/8/3K4 b - d3 0 1", 1), 6);
+        //The king may not step back along the ray of a checking slider
+        assert_eq!(perft("8/8/2k5/5q2/5n2/8/5K2/8 b - - 0 1", 3), 6559);
+        //Double check leaves only king moves
+        assert_eq!(perft("4k3/8/8/8/8/5n2/8/r3K3 w - - 0 1", 1), 2);
+        //Castling through and out of check
+        assert_eq!(perft("r3k2r/1b4bq/8/8/8/8/7B/R3K2R w KQkq - 0 1", 2), 1141);
+        assert_eq!(perft("2r1k3/8/8/8/8/8/8/R3K2R w KQ - 0 1", 1), 25);
+    }
+
+    #[test]
+    fn generators() {
+        let fens = [
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+            "8/8/1k6/2b5/2pP4/8/5K2/8 b - d3 0 1",
+            "4k3/8/8/8/8/5n2/8/r3K3 w - - 0 1",
+        ];
+        for fen in fens {
+            let state = ChessState::from_fen(fen);
+            let all: Vec<Move> = state.moves().collect();
+            let captures: Vec<Move> = state.captures().collect();
+            let quiets: Vec<Move> = state.quiets().collect();
+            assert_eq!(captures.len() + quiets.len(), all.len());
+            assert!(captures.iter().all(|x| x.is_capture() && all.contains(x)));
+            assert!(quiets.iter().all(|x| !x.is_capture() && all.contains(x)));
+            let in_check = state.check();
+            assert_eq!(
+                state.evasions().count(),
+                if in_check { all.len() } else { 0 }
+            );
+            for m in state.checks() {
+                assert!(state.do_move(&m).check());
             }
         }
-        return (9, 9);
-    }
-    pub fn is_terminal(&self) -> bool {
-        return self.get_all_possible_moves().len() == 0;
-    }
-    pub fn check(&self) -> bool {
-        let coefficient: i8 = if self.turn { 1 } else { -1 };
-        let danger_squares: HashSet<(usize, usize)> =
-            ChessState::danger_squares(self.board, coefficient);
-        let k_pos: (usize, usize) = ChessState::find_position(self.board, 6 * coefficient);
-        danger_squares.contains(&k_pos)
+        //Kiwipete has 8 captures and no checks
+        let state = ChessState::from_fen(fens[0]);
+        assert_eq!(state.captures().count(), 8);
+        assert_eq!(state.checks().count(), 0);
+        let state = ChessState::from_fen(fens[1]);
+        assert_eq!(
+            state.parse_lan("d7xc8Q").map(|x| x.promotion()),
+            Some(Some(PieceKind::Queen))
+        );
+        assert!(state.parse_lan("O-O").is_some_and(|x| x.is_castle()));
+        assert_eq!(ChessState::from_fen(fens[3]).evasions().count(), 2);
     }
 }
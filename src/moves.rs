@@ -1,169 +1,265 @@
 use crate::chess_init::ChessState;
-const COLUMNS: &str = "abcdefgh";
-const PIECE_INDICIES: &str = " PRNBQ";
-
-fn slice_to_coord(col: char, row: char) -> (usize, usize) {
-    (
-        row.to_digit(10).unwrap() as u8 as usize - 1,
-        (col as u8 - 97) as usize,
-    )
+use crate::types::{Piece, PieceKind, Square};
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+
+/// Most moves any position has is 218, so a list never needs more
+pub const MAX_MOVES: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MoveKind {
+    Quiet,
+    DoublePush,
+    Capture,
+    EnPassant,
+    /// Promotion to `piece`, taking on the way if `capture`
+    Promotion {
+        piece: PieceKind,
+        capture: bool,
+    },
+    /// The king "takes" its own rook: `from` is the king and `to` the rook
+    Castle {
+        king_side: bool,
+    },
+}
+
+/// A move, written in the engine's long algebraic notation, e.g. `e2-e4`, `Ng1xf3`,
+/// `e7-e8Q` or `O-O`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Move {
+    pub piece: PieceKind,
+    pub from: Square,
+    pub to: Square,
+    pub kind: MoveKind,
+}
+
+impl Move {
+    /// Only a placeholder for unused slots, never a move of any position
+    pub(crate) const NULL: Move = Move {
+        piece: PieceKind::Pawn,
+        from: Square::from_index(0),
+        to: Square::from_index(0),
+        kind: MoveKind::Quiet,
+    };
+
+    pub fn new(piece: PieceKind, from: Square, to: Square, kind: MoveKind) -> Move {
+        Move {
+            piece,
+            from,
+            to,
+            kind,
+        }
+    }
+
+    pub fn is_capture(&self) -> bool {
+        matches!(
+            self.kind,
+            MoveKind::Capture | MoveKind::EnPassant | MoveKind::Promotion { capture: true, .. }
+        )
+    }
+
+    pub fn promotion(&self) -> Option<PieceKind> {
+        match self.kind {
+            MoveKind::Promotion { piece, .. } => Some(piece),
+            _ => None,
+        }
+    }
+
+    pub fn is_castle(&self) -> bool {
+        matches!(self.kind, MoveKind::Castle { .. })
+    }
+
+    /// The squares and promotion in 15 bits, which tell a move apart from the other moves of
+    /// its position
+    pub fn pack(&self) -> u16 {
+        let promotion: u16 = match self.promotion() {
+            None => 0,
+            Some(PieceKind::Knight) => 1,
+            Some(PieceKind::Bishop) => 2,
+            Some(PieceKind::Rook) => 3,
+            Some(_) => 4,
+        };
+        self.from.index() as u16 | (self.to.index() as u16) << 6 | promotion << 12
+    }
+}
+
+impl fmt::Display for Move {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let MoveKind::Castle { king_side } = self.kind {
+            return write!(f, "{}", if king_side { "O-O" } else { "O-O-O" });
+        }
+        if self.piece != PieceKind::Pawn {
+            write!(f, "{}", self.piece.letter())?;
+        }
+        let capture = if self.is_capture() { 'x' } else { '-' };
+        write!(f, "{}{}{}", self.from, capture, self.to)?;
+        if let Some(piece) = self.promotion() {
+            write!(f, "{}", piece.letter())?;
+        }
+        Ok(())
+    }
+}
+
+/// Moves kept on the stack, so generating them does not allocate
+#[derive(Clone)]
+pub struct MoveList {
+    moves: [Move; MAX_MOVES],
+    len: usize,
+}
+
+impl MoveList {
+    pub fn new() -> MoveList {
+        MoveList {
+            moves: [Move::NULL; MAX_MOVES],
+            len: 0,
+        }
+    }
+
+    pub fn push(&mut self, m: Move) {
+        self.moves[self.len] = m;
+        self.len += 1;
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    /// Keeps the first `len` moves
+    pub fn truncate(&mut self, len: usize) {
+        self.len = self.len.min(len);
+    }
+}
+
+impl Default for MoveList {
+    fn default() -> MoveList {
+        MoveList::new()
+    }
+}
+
+impl fmt::Debug for MoveList {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+impl Deref for MoveList {
+    type Target = [Move];
+
+    fn deref(&self) -> &[Move] {
+        &self.moves[..self.len]
+    }
+}
+
+impl DerefMut for MoveList {
+    fn deref_mut(&mut self) -> &mut [Move] {
+        &mut self.moves[..self.len]
+    }
+}
+
+/// Iterator over the moves of a [`MoveList`], owning the list
+pub struct MoveListIter {
+    list: MoveList,
+    idx: usize,
+}
+
+impl Iterator for MoveListIter {
+    type Item = Move;
+
+    fn next(&mut self) -> Option<Move> {
+        let m = self.list.get(self.idx).copied();
+        self.idx += 1;
+        m
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let left = self.list.len().saturating_sub(self.idx);
+        (left, Some(left))
+    }
+}
+
+impl ExactSizeIterator for MoveListIter {}
+
+impl IntoIterator for MoveList {
+    type Item = Move;
+    type IntoIter = MoveListIter;
+
+    fn into_iter(self) -> MoveListIter {
+        MoveListIter { list: self, idx: 0 }
+    }
+}
+
+impl<'a> IntoIterator for &'a MoveList {
+    type Item = &'a Move;
+    type IntoIter = std::slice::Iter<'a, Move>;
+
+    fn into_iter(self) -> std::slice::Iter<'a, Move> {
+        self.iter()
+    }
 }
 
 impl ChessState {
-    pub fn do_move(&self, _move: &String) -> ChessState {
+    pub fn do_move(&self, m: &Move) -> ChessState {
         //Assumes the move is already safe
-        let coefficient = if self.turn { 1 } else { -1 };
+        let color = self.turn;
         let mut new_state: ChessState = self.copy();
-        let m: String = _move.clone();
-        let back_rank: usize = if new_state.turn { 0 } else { 7 };
-        let mut ep: usize = 8;
-        //castling
-        if m == "O-O-O" {
-            new_state.board[back_rank][0] = 0;
-            new_state.board[back_rank][4] = 0;
-            new_state.board[back_rank][2] = 6 * coefficient;
-            new_state.board[back_rank][3] = 2 * coefficient;
-
-            new_state.castling = if new_state.turn {
-                new_state
-                    .castling
-                    .chars()
-                    .filter(|x| x.is_ascii_lowercase())
-                    .collect()
-            } else {
-                {
-                    new_state
-                        .castling
-                        .chars()
-                        .filter(|x| x.is_ascii_uppercase())
-                        .collect()
-                }
+        new_state.en_passant = [0, 0, 0, 0, 0, 0, 0, 0];
+        match m.kind {
+            MoveKind::Castle { king_side } => {
+                let back_rank: usize = color.back_rank();
+                let (king_target, rook_target) = if king_side { (6, 5) } else { (2, 3) };
+                new_state.board[m.from] = None;
+                new_state.board[m.to] = None;
+                new_state.board[Square::new(king_target, back_rank)] =
+                    Some(Piece::new(PieceKind::King, color));
+                new_state.board[Square::new(rook_target, back_rank)] =
+                    Some(Piece::new(PieceKind::Rook, color));
+                new_state.castling.remove_color(color);
             }
-        } else if m == "O-O" {
-            new_state.board[back_rank][7] = 0;
-            new_state.board[back_rank][4] = 0;
-            new_state.board[back_rank][6] = 6 * coefficient;
-            new_state.board[back_rank][5] = 2 * coefficient;
-            new_state.castling = if new_state.turn {
-                new_state
-                    .castling
-                    .chars()
-                    .filter(|x| x.is_ascii_lowercase())
-                    .collect()
-            } else {
-                {
-                    new_state
-                        .castling
-                        .chars()
-                        .filter(|x| x.is_ascii_uppercase())
-                        .collect()
+            _ => {
+                if m.kind == MoveKind::EnPassant {
+                    new_state.board[Square::new(m.to.file(), m.from.rank())] = None;
                 }
-            }
-        }
-        //pawn moves
-        else if COLUMNS.contains(m.chars().nth(0).unwrap()) {
-            let source: (usize, usize) =
-                slice_to_coord(m.chars().nth(0).unwrap(), m.chars().nth(1).unwrap());
-            let target: (usize, usize) =
-                slice_to_coord(m.chars().nth(3).unwrap(), m.chars().nth(4).unwrap());
-
-            if m.len() == 5 {
-                if m.contains("x") && new_state.board[target.0][target.1] == 0 {
-                    //en passant capture
-                    new_state.board[(target.0 as i8 - coefficient) as usize][target.1] = 0;
+                if m.kind == MoveKind::DoublePush {
+                    new_state.en_passant[m.from.file()] = 1;
                 }
+                let kind = m.promotion().unwrap_or(m.piece);
+                new_state.board[m.from] = None;
+                new_state.board[m.to] = Some(Piece::new(kind, color));
 
-                new_state.board[target.0][target.1] = 1 * coefficient;
-
-                if (source.0 as i8 - target.0 as i8) == 2 {
-                    ep = source.1;
-                }
-            }
-            //
-            else {
-                if m.contains("x") && new_state.board[target.0][target.1] == 0 {
-                    //en passant capture
-                    new_state.board[(target.0 as i8 - coefficient) as usize][target.1] = 0;
+                //Handle removing of castling opportunities
+                if m.piece == PieceKind::King {
+                    new_state.castling.remove_color(color);
                 }
-                new_state.board[target.0][target.1] =
-                    PIECE_INDICIES.find(m.chars().nth(5).unwrap()).unwrap() as u8 as i8
-                        * coefficient;
+                new_state.update_castling(m.from, m.to);
             }
-            new_state.board[source.0][source.1] = 0;
         }
-        //all other moves
-        else {
-            let source: (usize, usize) =
-                slice_to_coord(m.chars().nth(1).unwrap(), m.chars().nth(2).unwrap());
-            let target: (usize, usize) =
-                slice_to_coord(m.chars().nth(4).unwrap(), m.chars().nth(5).unwrap());
-            let piece = new_state.board[source.0][source.1];
-            new_state.board[source.0][source.1] = 0;
-            new_state.board[target.0][target.1] = piece;
-            new_state.en_passant = [0, 0, 0, 0, 0, 0, 0, 0];
-
-            //Handle removing of castling opportunities
-            if piece * coefficient == 6 {
-                new_state.castling = if new_state.turn {
-                    new_state
-                        .castling
-                        .chars()
-                        .filter(|x| x.is_ascii_lowercase())
-                        .collect()
-                } else {
-                    {
-                        new_state
-                            .castling
-                            .chars()
-                            .filter(|x| x.is_ascii_uppercase())
-                            .collect()
-                    }
-                }
-            } else if piece * coefficient == 4 {
-                new_state.castling = if new_state.turn {
-                    new_state
-                        .castling
-                        .chars()
-                        .filter(|x| {
-                            x.is_ascii_lowercase()
-                                || x.to_string() == {
-                                    if source == (back_rank, 0) {
-                                        "K"
-                                    } else if source == (back_rank, 7) {
-                                        "Q"
-                                    } else {
-                                        ""
-                                    }
-                                }
-                        })
-                        .collect()
-                } else {
-                    {
-                        new_state
-                            .castling
-                            .chars()
-                            .filter(|x| {
-                                x.is_ascii_uppercase()
-                                    || x.to_string() == {
-                                        if source == (back_rank, 0) {
-                                            "k"
-                                        } else if source == (back_rank, 7) {
-                                            "q"
-                                        } else {
-                                            ""
-                                        }
-                                    }
-                            })
-                            .collect()
-                    }
-                }
-            }
+
+        //Captures and pawn moves reset the fifty move counter
+        if m.is_capture() || m.piece == PieceKind::Pawn {
+            new_state.halfmoves = 0;
+        } else {
+            new_state.halfmoves += 1;
         }
-        if ep != 8 {
-            new_state.en_passant[ep] = 1;
+        if !new_state.turn.is_white() {
+            new_state.fullmoves += 1;
         }
 
         new_state.turn = !new_state.turn;
-        return new_state;
+        new_state
+    }
+
+    /// The legal move written as `lan` in the engine's notation, if there is one
+    pub fn parse_lan(&self, lan: &str) -> Option<Move> {
+        self.moves().find(|x| x.to_string() == lan)
+    }
+
+    /// Removes the castling rights of rooks leaving their own back rank square or being captured on it
+    fn update_castling(&mut self, source: Square, target: Square) {
+        if source.rank() == self.turn.back_rank() {
+            self.castling.remove(self.turn, source.file());
+        }
+        if target.rank() == (!self.turn).back_rank() {
+            self.castling.remove(!self.turn, target.file());
+        }
     }
 }
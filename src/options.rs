@@ -0,0 +1,195 @@
+#[derive(Debug, Clone, PartialEq)]
+pub enum OptionKind {
+    Spin { default: i64, min: i64, max: i64 },
+    Check { default: bool },
+    Combo { default: String, vars: Vec<String> },
+    String { default: String },
+    Button,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum OptionValue {
+    Int(i64),
+    Bool(bool),
+    Text(String),
+    None,
+}
+
+#[derive(Debug, Clone)]
+pub struct EngineOption {
+    pub name: String,
+    pub kind: OptionKind,
+    pub value: OptionValue,
+}
+
+impl EngineOption {
+    fn new(name: &str, kind: OptionKind) -> EngineOption {
+        let value = match &kind {
+            OptionKind::Spin { default, .. } => OptionValue::Int(*default),
+            OptionKind::Check { default } => OptionValue::Bool(*default),
+            OptionKind::Combo { default, .. } | OptionKind::String { default } => {
+                OptionValue::Text(default.clone())
+            }
+            OptionKind::Button => OptionValue::None,
+        };
+        EngineOption {
+            name: name.to_string(),
+            kind,
+            value,
+        }
+    }
+
+    /// The `option name ...` line advertised on `uci`
+    pub fn uci_line(&self) -> String {
+        let details = match &self.kind {
+            OptionKind::Spin { default, min, max } => {
+                format!("type spin default {} min {} max {}", default, min, max)
+            }
+            OptionKind::Check { default } => format!("type check default {}", default),
+            OptionKind::Combo { default, vars } => format!(
+                "type combo default {}{}",
+                default,
+                vars.iter()
+                    .map(|x| format!(" var {}", x))
+                    .collect::<String>()
+            ),
+            OptionKind::String { default } if default.is_empty() => {
+                "type string default <empty>".to_string()
+            }
+            OptionKind::String { default } => format!("type string default {}", default),
+            OptionKind::Button => "type button".to_string(),
+        };
+        format!("option name {} {}", self.name, details)
+    }
+
+    fn set(&mut self, value: Option<&str>) -> Result<(), String> {
+        let text = value.unwrap_or("").trim();
+        self.value = match &self.kind {
+            OptionKind::Spin { min, max, .. } => {
+                let x: i64 = text
+                    .parse()
+                    .map_err(|_| format!("{} must be a number, got '{}'", self.name, text))?;
+                if x < *min || x > *max {
+                    return Err(format!(
+                        "{} must be between {} and {}, got {}",
+                        self.name, min, max, x
+                    ));
+                }
+                OptionValue::Int(x)
+            }
+            OptionKind::Check { .. } => match text {
+                "true" => OptionValue::Bool(true),
+                "false" => OptionValue::Bool(false),
+                _ => {
+                    return Err(format!(
+                        "{} must be true or false, got '{}'",
+                        self.name, text
+                    ))
+                }
+            },
+            OptionKind::Combo { vars, .. } => {
+                match vars.iter().find(|x| x.eq_ignore_ascii_case(text)) {
+                    Some(var) => OptionValue::Text(var.clone()),
+                    None => {
+                        return Err(format!(
+                            "{} must be one of {}, got '{}'",
+                            self.name,
+                            vars.join(", "),
+                            text
+                        ))
+                    }
+                }
+            }
+            OptionKind::String { .. } if text == "<empty>" => OptionValue::Text(String::new()),
+            OptionKind::String { .. } => OptionValue::Text(text.to_string()),
+            OptionKind::Button => OptionValue::None,
+        };
+        Ok(())
+    }
+}
+
+/// The engine's UCI options and their current values
+#[derive(Debug, Clone)]
+pub struct Options {
+    options: Vec<EngineOption>,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options::new()
+    }
+}
+
+impl Options {
+    pub fn new() -> Options {
+        let spin = |default: i64, min: i64, max: i64| OptionKind::Spin { default, min, max };
+        let check = |default: bool| OptionKind::Check { default };
+        let string = || OptionKind::String {
+            default: String::new(),
+        };
+        Options {
+            options: vec![
+                EngineOption::new("Hash", spin(16, 1, 65536)),
+                EngineOption::new("Threads", spin(1, 1, 512)),
+                EngineOption::new("MultiPV", spin(1, 1, 500)),
+                EngineOption::new("Ponder", check(false)),
+                EngineOption::new("Move Overhead", spin(10, 0, 5000)),
+                EngineOption::new("Depth", spin(4, 1, 64)),
+                EngineOption::new("UCI_Chess960", check(false)),
+                EngineOption::new("UCI_ShowWDL", check(false)),
+                EngineOption::new("SyzygyPath", string()),
+                EngineOption::new("OwnBook", check(false)),
+                EngineOption::new("BookFile", string()),
+                EngineOption::new("Book Depth", spin(24, 1, 255)),
+                EngineOption::new("Best Book Move", check(false)),
+                EngineOption::new("PgnFile", string()),
+            ],
+        }
+    }
+
+    pub fn uci_lines(&self) -> Vec<String> {
+        self.options.iter().map(|x| x.uci_line()).collect()
+    }
+
+    /// Applies `setoption`, returning a message for the GUI if the name or value is bad
+    pub fn set(&mut self, name: &str, value: Option<&str>) -> Result<(), String> {
+        match self
+            .options
+            .iter_mut()
+            .find(|x| x.name.eq_ignore_ascii_case(name.trim()))
+        {
+            Some(option) => option.set(value),
+            None => Err(format!("No such option: {}", name)),
+        }
+    }
+
+    fn value(&self, name: &str) -> &OptionValue {
+        &self
+            .options
+            .iter()
+            .find(|x| x.name == name)
+            .unwrap_or_else(|| panic!("Unknown option {}", name))
+            .value
+    }
+
+    pub fn spin(&self, name: &str) -> i64 {
+        match self.value(name) {
+            OptionValue::Int(x) => *x,
+            _ => panic!("{} is not a spin option", name),
+        }
+    }
+
+    pub fn check(&self, name: &str) -> bool {
+        match self.value(name) {
+            OptionValue::Bool(x) => *x,
+            _ => panic!("{} is not a check option", name),
+        }
+    }
+
+    pub fn string(&self, name: &str) -> &str {
+        match self.value(name) {
+            OptionValue::Text(x) => x,
+            _ => panic!("{} is not a string or combo option", name),
+        }
+    }
+}
@@ -0,0 +1,824 @@
+use crate::chess_init::ChessState;
+use crate::moves::Move;
+use crate::translator;
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::{self, BufRead, Write};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Suffix annotations and the NAGs they stand for
+const SUFFIX_NAGS: [(&str, u8); 6] = [
+    ("!", 1),
+    ("?", 2),
+    ("!!", 3),
+    ("??", 4),
+    ("!?", 5),
+    ("?!", 6),
+];
+const RESULTS: [&str; 4] = ["1-0", "0-1", "1/2-1/2", "*"];
+
+#[derive(Debug)]
+pub enum PgnError {
+    Io(io::Error),
+    Parse { line: usize, message: String },
+}
+
+impl fmt::Display for PgnError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PgnError::Io(err) => write!(f, "{}", err),
+            PgnError::Parse { line, message } => write!(f, "line {}: {}", line, message),
+        }
+    }
+}
+
+impl std::error::Error for PgnError {}
+
+impl From<io::Error> for PgnError {
+    fn from(err: io::Error) -> PgnError {
+        PgnError::Io(err)
+    }
+}
+
+/// A move in the game tree together with everything annotating it
+#[derive(Debug, Clone)]
+pub struct PgnNode {
+    pub san: String,
+    /// The move in the engine's notation, ready for `ChessState::do_move`
+    pub m: Move,
+    pub nags: Vec<u8>,
+    /// NAGs placed before the move, only found at the start of a game or variation
+    pub starting_nags: Vec<u8>,
+    /// Comments placed before the move, only found at the start of a game or variation
+    pub starting_comments: Vec<String>,
+    /// Comments placed after the move
+    pub comments: Vec<String>,
+    /// Alternatives to this move, each played from the position before it
+    pub variations: Vec<Vec<PgnNode>>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PgnGame {
+    pub tags: Vec<(String, String)>,
+    pub moves: Vec<PgnNode>,
+    /// Comments of a game without any moves
+    pub comments: Vec<String>,
+    pub result: String,
+}
+
+impl PgnGame {
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|(tag, _)| tag == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The position the game starts from, taken from the `FEN` tag if present, and played as
+    /// Chess960 when the `Variant` tag says so
+    pub fn start_position(&self) -> ChessState {
+        let mut state = match self.tag("FEN") {
+            Some(fen) => ChessState::from_fen(fen),
+            None => ChessState::new_board(),
+        };
+        state.chess960 = self.tag("Variant").is_some_and(|x| {
+            x.eq_ignore_ascii_case("chess960") || x.eq_ignore_ascii_case("fischerandom")
+        });
+        state
+    }
+
+    /// Every position of the main line, starting with the start position
+    pub fn mainline_positions(&self) -> Vec<ChessState> {
+        let mut positions = vec![self.start_position()];
+        for node in self.moves.iter() {
+            let next = positions.last().unwrap().do_move(&node.m);
+            positions.push(next);
+        }
+        positions
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Tag(String, String),
+    Symbol(String),
+    Nag(u8),
+    Comment(String),
+    Result(String),
+    Open,
+    Close,
+}
+
+/// Reads games one at a time from any buffered source, so files of any size can be streamed
+pub struct PgnReader<R: BufRead> {
+    source: R,
+    buffer: Vec<char>,
+    pos: usize,
+    line: usize,
+    peeked: Option<(Token, usize)>,
+    eof: bool,
+}
+
+impl<R: BufRead> PgnReader<R> {
+    pub fn new(source: R) -> PgnReader<R> {
+        PgnReader {
+            source,
+            buffer: Vec::new(),
+            pos: 0,
+            line: 0,
+            peeked: None,
+            eof: false,
+        }
+    }
+
+    /// Reads the next game, or `None` at the end of the input
+    pub fn read_game(&mut self) -> Option<Result<PgnGame, PgnError>> {
+        match self.peek_token() {
+            Ok(None) => return None,
+            Err(err) => return Some(Err(err)),
+            Ok(Some(_)) => {}
+        }
+        let res = self.parse_game();
+        if res.is_err() {
+            self.skip_game();
+        }
+        Some(res)
+    }
+
+    fn parse_game(&mut self) -> Result<PgnGame, PgnError> {
+        let mut game = PgnGame::default();
+        while let Some((Token::Tag(name, value), _)) = self.peek_token()? {
+            game.tags.push((name, value));
+            self.next_token()?;
+        }
+        let start = game.start_position();
+        let mut comments = Vec::new();
+        game.moves = self.parse_line(&start, &mut comments, false)?;
+        game.comments = comments;
+        if let Some((Token::Result(result), _)) = self.peek_token()? {
+            game.result = result;
+            self.next_token()?;
+        } else {
+            game.result = game.tag("Result").unwrap_or("*").to_string();
+        }
+        Ok(game)
+    }
+
+    /// Parses moves until the end of the variation, the result or the next game.
+    /// Comments of a line without moves are left in `comments`.
+    fn parse_line(
+        &mut self,
+        start: &ChessState,
+        comments: &mut Vec<String>,
+        variation: bool,
+    ) -> Result<Vec<PgnNode>, PgnError> {
+        let mut line: Vec<PgnNode> = Vec::new();
+        let mut before = start.copy();
+        let mut state = start.copy();
+        let mut pending: Vec<String> = Vec::new();
+        let mut pending_nags: Vec<u8> = Vec::new();
+        loop {
+            let (token, line_number) = match self.peek_token()? {
+                Some(x) => x,
+                None if variation => return Err(self.error("unterminated variation")),
+                None => break,
+            };
+            match token {
+                Token::Tag(_, _) | Token::Result(_) if !variation => break,
+                Token::Tag(_, _) | Token::Result(_) => {
+                    return Err(self.error("unterminated variation"))
+                }
+                Token::Close if variation => {
+                    self.next_token()?;
+                    break;
+                }
+                Token::Close => {
+                    return Err(PgnError::Parse {
+                        line: line_number,
+                        message: "unexpected ')'".to_string(),
+                    })
+                }
+                Token::Open => {
+                    self.next_token()?;
+                    let mut variation_comments = Vec::new();
+                    let alternative = self.parse_line(&before, &mut variation_comments, true)?;
+                    match line.last_mut() {
+                        //A variation without moves only holds comments, which go to the move before it
+                        Some(node) if alternative.is_empty() => {
+                            node.comments.append(&mut variation_comments)
+                        }
+                        Some(node) => node.variations.push(alternative),
+                        None => {
+                            return Err(PgnError::Parse {
+                                line: line_number,
+                                message: "variation before the first move".to_string(),
+                            })
+                        }
+                    }
+                }
+                Token::Comment(comment) => {
+                    self.next_token()?;
+                    match line.last_mut() {
+                        Some(node) if pending.is_empty() => node.comments.push(comment),
+                        _ => pending.push(comment),
+                    }
+                }
+                Token::Nag(nag) => {
+                    self.next_token()?;
+                    match line.last_mut() {
+                        Some(node) => node.nags.push(nag),
+                        None => pending_nags.push(nag),
+                    }
+                }
+                Token::Symbol(san) => {
+                    self.next_token()?;
+                    let m = translator::parse_san(&state, &san).map_err(|err| PgnError::Parse {
+                        line: line_number,
+                        message: err.to_string(),
+                    })?;
+                    before = state;
+                    state = before.do_move(&m);
+                    let mut node = PgnNode::new(san, m);
+                    node.starting_comments = std::mem::take(&mut pending);
+                    node.starting_nags = std::mem::take(&mut pending_nags);
+                    line.push(node);
+                }
+            }
+        }
+        comments.append(&mut pending);
+        Ok(line)
+    }
+
+    /// Skips the rest of a game that failed to parse, up to its result or the next tag section
+    fn skip_game(&mut self) {
+        loop {
+            match self.peek_token() {
+                Ok(Some((Token::Tag(_, _), _))) | Ok(None) | Err(_) => return,
+                Ok(Some((Token::Result(_), _))) => {
+                    let _ = self.next_token();
+                    return;
+                }
+                Ok(Some(_)) => {
+                    let _ = self.next_token();
+                }
+            }
+        }
+    }
+
+    fn error(&self, message: &str) -> PgnError {
+        PgnError::Parse {
+            line: self.line,
+            message: message.to_string(),
+        }
+    }
+
+    fn peek_token(&mut self) -> Result<Option<(Token, usize)>, PgnError> {
+        if self.peeked.is_none() {
+            self.peeked = self.read_token()?;
+        }
+        Ok(self.peeked.clone())
+    }
+
+    fn next_token(&mut self) -> Result<Option<(Token, usize)>, PgnError> {
+        match self.peeked.take() {
+            Some(x) => Ok(Some(x)),
+            None => self.read_token(),
+        }
+    }
+
+    /// Loads the next line into the buffer, returning false at the end of the input
+    fn read_line(&mut self) -> Result<bool, PgnError> {
+        if self.eof {
+            return Ok(false);
+        }
+        let mut line = String::new();
+        if self.source.read_line(&mut line)? == 0 {
+            self.eof = true;
+            return Ok(false);
+        }
+        self.line += 1;
+        //Lines starting with % are escaped
+        if line.starts_with('%') {
+            line.clear();
+        }
+        self.buffer = line.chars().collect();
+        self.pos = 0;
+        Ok(true)
+    }
+
+    fn read_token(&mut self) -> Result<Option<(Token, usize)>, PgnError> {
+        loop {
+            while self.pos < self.buffer.len() && self.buffer[self.pos].is_whitespace() {
+                self.pos += 1;
+            }
+            if self.pos < self.buffer.len() {
+                break;
+            }
+            if !self.read_line()? {
+                return Ok(None);
+            }
+        }
+        let line = self.line;
+        let c = self.buffer[self.pos];
+        self.pos += 1;
+        let token = match c {
+            '(' => Token::Open,
+            ')' => Token::Close,
+            '[' => self.read_tag()?,
+            '{' => self.read_comment()?,
+            ';' => {
+                let comment: String = self.buffer[self.pos..].iter().collect();
+                self.pos = self.buffer.len();
+                Token::Comment(comment.trim().to_string())
+            }
+            '$' => {
+                let digits = self.take_while(|x| x.is_ascii_digit());
+                Token::Nag(digits.parse().map_err(|_| self.error("invalid NAG"))?)
+            }
+            '!' | '?' => {
+                let suffix = c.to_string() + &self.take_while(|x| x == '!' || x == '?');
+                match SUFFIX_NAGS.iter().find(|(s, _)| *s == suffix) {
+                    Some((_, nag)) => Token::Nag(*nag),
+                    None => return Err(self.error(&format!("invalid annotation {}", suffix))),
+                }
+            }
+            _ if c.is_alphanumeric() || c == '*' => {
+                let symbol = c.to_string()
+                    + &self.take_while(|x| x.is_alphanumeric() || "_+#=:-/".contains(x));
+                if RESULTS.contains(&symbol.as_str()) {
+                    Token::Result(symbol)
+                } else if c.is_ascii_digit() && !symbol.starts_with("0-0") {
+                    //Move number, possibly glued to the move as in 1.e4
+                    self.take_while(|x| x == '.');
+                    return self.read_token();
+                } else {
+                    Token::Symbol(symbol)
+                }
+            }
+            '.' => return self.read_token(),
+            _ => return Err(self.error(&format!("unexpected character '{}'", c))),
+        };
+        Ok(Some((token, line)))
+    }
+
+    fn take_while(&mut self, predicate: impl Fn(char) -> bool) -> String {
+        let start = self.pos;
+        while self.pos < self.buffer.len() && predicate(self.buffer[self.pos]) {
+            self.pos += 1;
+        }
+        self.buffer[start..self.pos].iter().collect()
+    }
+
+    /// Reads a tag pair up to the first `]` after its value, so several tags can share a line
+    fn read_tag(&mut self) -> Result<Token, PgnError> {
+        self.take_while(char::is_whitespace);
+        let name = self.take_while(|x| !x.is_whitespace() && x != '"' && x != ']');
+        self.take_while(char::is_whitespace);
+        if name.is_empty() {
+            return Err(self.error("invalid tag pair"));
+        }
+        if self.buffer.get(self.pos) != Some(&'"') {
+            return Err(self.error("tag value must be quoted"));
+        }
+        self.pos += 1;
+        let mut value = String::new();
+        loop {
+            match self.buffer.get(self.pos..self.pos + 2) {
+                Some(['\\', c]) if *c == '"' || *c == '\\' => {
+                    value.push(*c);
+                    self.pos += 2;
+                    continue;
+                }
+                _ => {}
+            }
+            match self.buffer.get(self.pos) {
+                Some('"') => break,
+                Some(c) => value.push(*c),
+                None => return Err(self.error("unterminated tag pair")),
+            }
+            self.pos += 1;
+        }
+        self.pos += 1;
+        self.take_while(char::is_whitespace);
+        if self.buffer.get(self.pos) != Some(&']') {
+            return Err(self.error("unterminated tag pair"));
+        }
+        self.pos += 1;
+        Ok(Token::Tag(name, value))
+    }
+
+    fn read_comment(&mut self) -> Result<Token, PgnError> {
+        let start_line = self.line;
+        let mut comment = String::new();
+        loop {
+            match self.buffer[self.pos..].iter().position(|x| *x == '}') {
+                Some(end) => {
+                    comment.extend(self.buffer[self.pos..self.pos + end].iter());
+                    self.pos += end + 1;
+                    return Ok(Token::Comment(comment.trim().to_string()));
+                }
+                None => {
+                    comment.extend(self.buffer[self.pos..].iter());
+                    if !self.read_line()? {
+                        return Err(PgnError::Parse {
+                            line: start_line,
+                            message: "unterminated comment".to_string(),
+                        });
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for PgnReader<R> {
+    type Item = Result<PgnGame, PgnError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_game()
+    }
+}
+
+const SEVEN_TAG_ROSTER: [(&str, &str); 7] = [
+    ("Event", "?"),
+    ("Site", "?"),
+    ("Date", "????.??.??"),
+    ("Round", "?"),
+    ("White", "?"),
+    ("Black", "?"),
+    ("Result", "*"),
+];
+const LINE_LENGTH: usize = 80;
+const START_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+impl PgnNode {
+    pub fn new(san: String, m: Move) -> PgnNode {
+        PgnNode {
+            san,
+            m,
+            nags: Vec::new(),
+            starting_nags: Vec::new(),
+            starting_comments: Vec::new(),
+            comments: Vec::new(),
+            variations: Vec::new(),
+        }
+    }
+
+    /// Adds a `[%eval]`/`[%clk]` comment, with the evaluation in centipawns from white's side
+    pub fn annotate(&mut self, eval: Option<i32>, clock: Option<Duration>) {
+        let mut commands: Vec<String> = Vec::new();
+        if let Some(eval) = eval {
+            commands.push(format!("[%eval {:.2}]", eval as f64 / 100.0));
+        }
+        if let Some(clock) = clock {
+            let seconds = clock.as_secs();
+            commands.push(format!(
+                "[%clk {}:{:02}:{:02}]",
+                seconds / 3600,
+                seconds / 60 % 60,
+                seconds % 60
+            ));
+        }
+        if !commands.is_empty() {
+            self.comments.push(commands.join(" "));
+        }
+    }
+}
+
+impl PgnGame {
+    /// Builds a game without variations from a start position and the moves played from it
+    pub fn from_moves(start: &ChessState, moves: &[Move]) -> PgnGame {
+        let mut game = PgnGame::default();
+        let fen = start.to_fen();
+        if start.chess960 {
+            game.tags
+                .push(("Variant".to_string(), "Chess960".to_string()));
+        }
+        if fen != START_FEN {
+            game.tags.push(("SetUp".to_string(), "1".to_string()));
+            game.tags.push(("FEN".to_string(), fen));
+        }
+        let mut state = start.copy();
+        for m in moves.iter() {
+            game.moves
+                .push(PgnNode::new(translator::to_san(&state, m), *m));
+            state = state.do_move(m);
+        }
+        game.result = if !state.is_terminal() {
+            "*"
+        } else if !state.check() {
+            "1/2-1/2"
+        } else if state.turn.is_white() {
+            "0-1"
+        } else {
+            "1-0"
+        }
+        .to_string();
+        game
+    }
+
+    pub fn set_tag(&mut self, name: &str, value: &str) {
+        match self.tags.iter_mut().find(|(tag, _)| tag == name) {
+            Some(tag) => tag.1 = value.to_string(),
+            None => self.tags.push((name.to_string(), value.to_string())),
+        }
+    }
+
+    /// Writes the game in PGN export format: Seven Tag Roster first, movetext wrapped at 80 columns
+    pub fn write<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        for (name, default) in SEVEN_TAG_ROSTER {
+            let value = if name == "Result" {
+                self.result.as_str()
+            } else {
+                self.tag(name).unwrap_or(default)
+            };
+            writeln!(out, "[{} \"{}\"]", name, escape_tag(value))?;
+        }
+        for (name, value) in self.tags.iter() {
+            if !SEVEN_TAG_ROSTER.iter().any(|(tag, _)| tag == name) {
+                writeln!(out, "[{} \"{}\"]", name, escape_tag(value))?;
+            }
+        }
+        writeln!(out)?;
+
+        let mut tokens: Vec<String> = Vec::new();
+        line_tokens(&self.start_position(), &self.moves, &mut tokens);
+        tokens.extend(self.comments.iter().map(|x| comment_token(x)));
+        tokens.push(self.result.clone());
+        let mut line = String::new();
+        for token in tokens {
+            if !line.is_empty() && line.len() + 1 + token.len() > LINE_LENGTH {
+                writeln!(out, "{}", line)?;
+                line.clear();
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line += &token;
+        }
+        writeln!(out, "{}", line)?;
+        writeln!(out)?;
+        Ok(())
+    }
+
+    /// Appends the game to a PGN file, creating it if needed
+    pub fn append_to_file(&self, path: &str) -> io::Result<()> {
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        self.write(&mut file)
+    }
+}
+
+/// Comments are written on one line, since a line break could fall inside them anyway
+fn comment_token(comment: &str) -> String {
+    format!(
+        "{{{}}}",
+        comment.split_whitespace().collect::<Vec<&str>>().join(" ")
+    )
+}
+
+fn escape_tag(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Movetext tokens for a line, with its variations in parentheses
+fn line_tokens(start: &ChessState, line: &[PgnNode], tokens: &mut Vec<String>) {
+    let mut state = start.copy();
+    //Black moves need their number at the start of a line and after comments or variations
+    let mut show_number = true;
+    for node in line.iter() {
+        for comment in node.starting_comments.iter() {
+            tokens.push(comment_token(comment));
+        }
+        tokens.extend(node.starting_nags.iter().map(|x| format!("${}", x)));
+        if state.turn.is_white() {
+            tokens.push(format!("{}.", state.fullmoves));
+        } else if show_number {
+            tokens.push(format!("{}...", state.fullmoves));
+        }
+        tokens.push(translator::to_san(&state, &node.m));
+        tokens.extend(node.nags.iter().map(|x| format!("${}", x)));
+        tokens.extend(node.comments.iter().map(|x| comment_token(x)));
+        for variation in node.variations.iter() {
+            let mut variation_tokens: Vec<String> = Vec::new();
+            line_tokens(&state, variation, &mut variation_tokens);
+            if let Some(first) = variation_tokens.first_mut() {
+                first.insert(0, '(');
+                variation_tokens.last_mut().unwrap().push(')');
+                tokens.append(&mut variation_tokens);
+            }
+        }
+        show_number = !node.comments.is_empty() || !node.variations.is_empty();
+        state = state.do_move(&node.m);
+    }
+}
+
+/// Today's date in PGN format
+pub fn today() -> String {
+    let days = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs() / 86400)
+        .unwrap_or(0) as i64;
+    //Civil date from days since 1970-01-01
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}.{:02}.{:02}", year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(pgn: &str) -> Vec<Result<PgnGame, PgnError>> {
+        PgnReader::new(pgn.as_bytes()).collect()
+    }
+
+    fn sans(line: &[PgnNode]) -> Vec<&str> {
+        line.iter().map(|x| x.san.as_str()).collect()
+    }
+
+    #[test]
+    fn reader() {
+        let pgn = r#"[Event "Test"] [Site "Here"]
+[White "A \"quoted\" name"]
+[Black "B"]
+[Result "1-0"]
+
+{Opening comment} $14 1. e4 $1 {Best by test} e5 (1... c5 {Sicilian} 2. Nf3 (2. Nc3
+Nc6) d6) (1... e6 $2 ({French is fine})) 2. Nf3 ; rest of line
+Nc6!? 3. Bb5 a6 1-0
+"#;
+        let games = read(pgn);
+        assert_eq!(games.len(), 1);
+        let game = games[0].as_ref().unwrap();
+        assert_eq!(game.tag("Event"), Some("Test"));
+        assert_eq!(game.tag("Site"), Some("Here"));
+        assert_eq!(game.tag("White"), Some("A \"quoted\" name"));
+        assert_eq!(game.result, "1-0");
+        assert_eq!(sans(&game.moves), ["e4", "e5", "Nf3", "Nc6", "Bb5", "a6"]);
+
+        let e4 = &game.moves[0];
+        assert_eq!(e4.starting_comments, ["Opening comment"]);
+        assert_eq!(e4.starting_nags, [14]);
+        assert_eq!(e4.nags, [1]);
+        assert_eq!(e4.comments, ["Best by test"]);
+        assert_eq!(game.moves[2].comments, ["rest of line"]);
+        assert_eq!(game.moves[3].nags, [5]);
+
+        //Nested variations, and a variation holding only a comment
+        let variations = &game.moves[1].variations;
+        assert_eq!(variations.len(), 2);
+        assert_eq!(sans(&variations[0]), ["c5", "Nf3", "d6"]);
+        assert_eq!(variations[0][0].comments, ["Sicilian"]);
+        assert_eq!(sans(&variations[0][1].variations[0]), ["Nc3", "Nc6"]);
+        assert_eq!(sans(&variations[1]), ["e6"]);
+        assert_eq!(variations[1][0].nags, [2]);
+        assert_eq!(variations[1][0].comments, ["French is fine"]);
+    }
+
+    #[test]
+    fn start_positions() {
+        let pgn = r#"[SetUp "1"]
+[FEN "4k3/8/8/8/8/8/4P3/4K3 b - - 0 40"]
+
+40... Kd7 41. e4 *
+
+[Variant "Chess960"]
+[FEN "4k3/8/8/8/8/8/8/1R2K1R1 w GB - 0 1"]
+
+1. O-O Kd7 *
+"#;
+        let games: Vec<PgnGame> = read(pgn).into_iter().map(Result::unwrap).collect();
+        assert_eq!(games.len(), 2);
+        let positions = games[0].mainline_positions();
+        assert_eq!(positions[0].to_fen(), "4k3/8/8/8/8/8/4P3/4K3 b - - 0 40");
+        assert!(positions[2].to_fen().starts_with("8/3k4/8/8/4P3/8/8/4K3 b"));
+        assert!(!games[0].start_position().chess960);
+
+        assert!(games[1].start_position().chess960);
+        assert!(games[1].moves[0].m.is_castle());
+    }
+
+    #[test]
+    fn errors() {
+        let pgn = r#"[Event "Bad"]
+
+1. e4 e5
+2. Nf3 Nc6 3. Bxh7 *
+
+[Event "Good"]
+
+1. d4 *
+
+1. e4 ) *
+
+1. e4 {never
+closed
+"#;
+        let games = read(pgn);
+        assert_eq!(games.len(), 4);
+        assert_eq!(
+            games[0].as_ref().unwrap_err().to_string(),
+            "line 4: illegal move: Bxh7"
+        );
+        //The reader carries on with the next game
+        assert_eq!(games[1].as_ref().unwrap().tag("Event"), Some("Good"));
+        assert_eq!(
+            games[2].as_ref().unwrap_err().to_string(),
+            "line 10: unexpected ')'"
+        );
+        assert_eq!(
+            games[3].as_ref().unwrap_err().to_string(),
+            "line 12: unterminated comment"
+        );
+        assert!(read("[Event Test]\n").first().unwrap().is_err());
+    }
+
+    fn written(game: &PgnGame) -> String {
+        let mut out: Vec<u8> = Vec::new();
+        game.write(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn writer() {
+        let start = ChessState::new_board();
+        let mut state = start.copy();
+        let mut moves: Vec<Move> = Vec::new();
+        for _ in 0..10 {
+            for san in ["Nf3", "Nf6", "Ng1", "Ng8"] {
+                let m = translator::parse_san(&state, san).unwrap();
+                state = state.do_move(&m);
+                moves.push(m);
+            }
+        }
+        let mut game = PgnGame::from_moves(&start, &moves);
+        game.set_tag("Opening", "Knights");
+        game.set_tag("White", "Engine");
+        game.set_tag("Event", "Test");
+        let text = written(&game);
+        let tags: Vec<&str> = text.lines().take(8).collect();
+        assert_eq!(
+            tags,
+            [
+                "[Event \"Test\"]",
+                "[Site \"?\"]",
+                "[Date \"????.??.??\"]",
+                "[Round \"?\"]",
+                "[White \"Engine\"]",
+                "[Black \"?\"]",
+                "[Result \"*\"]",
+                "[Opening \"Knights\"]",
+            ]
+        );
+        let movetext: Vec<&str> = text.lines().skip(9).filter(|x| !x.is_empty()).collect();
+        assert!(movetext.len() > 1);
+        assert!(movetext.iter().all(|x| x.len() <= LINE_LENGTH));
+        assert!(movetext[0].starts_with("1. Nf3 Nf6 2. Ng1 Ng8 3. Nf3"));
+        assert!(movetext.join(" ").ends_with("20. Ng1 Ng8 *"));
+
+        //Clock and evaluation comments, and black move numbers after comments and variations
+        let mut game = PgnGame::from_moves(&start, &moves[..3]);
+        game.moves[0].annotate(Some(-35), Some(Duration::from_secs(3725)));
+        game.moves[1].annotate(Some(120), None);
+        let c5 = translator::parse_san(&game.mainline_positions()[1], "c5").unwrap();
+        game.moves[1]
+            .variations
+            .push(vec![PgnNode::new("c5".to_string(), c5)]);
+        let text = written(&game);
+        assert!(text.ends_with(
+            "\n1. Nf3 {[%eval -0.35] [%clk 1:02:05]} 1... Nf6 {[%eval 1.20]} (1... c5) 2. Ng1 *\n\n"
+        ));
+    }
+
+    #[test]
+    fn round_trip() {
+        let pgn = r#"[Event "Round trip"]
+[Site "?"]
+[Date "2024.01.02"]
+[Round "3"]
+[White "A"]
+[Black "B"]
+[Result "1/2-1/2"]
+[Annotator "Someone"]
+
+{Start} $14 1. e4 $1 {Best by test} 1... e5 (1... c5 {Sicilian} 2. Nf3 (2. Nc3
+Nc6) 2... d6) (1... e6 $2) 2. Nf3 Nc6 3. Bb5 {[%eval 0.30] [%clk 0:05:00]} 3...
+a6 1/2-1/2
+
+"#;
+        let game = read(pgn).remove(0).unwrap();
+        let text = written(&game);
+        assert_eq!(text, pgn);
+        let again = read(&text).remove(0).unwrap();
+        assert_eq!(again.tags, game.tags);
+        assert_eq!(sans(&again.moves), sans(&game.moves));
+        assert_eq!(written(&again), text);
+    }
+}
@@ -0,0 +1,469 @@
+use crate::chess_init::ChessState;
+use crate::chessbot::{MinimaxBot, SearchControl};
+use crate::game::{Game, Outcome};
+use crate::moves::Move;
+use crate::pgn;
+use crate::time_manager;
+use crate::translator::{self, SanError};
+use crate::types::{Color, Square};
+use std::io::{self, BufRead, Write};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const USAGE: &str = "usage: chess-rs play [--color white|black] [--depth N] \
+[--time MINUTES+INCREMENT] [--fen FEN] [--ascii] [--no-color] [--no-flip]";
+
+const COMMANDS: &str = "Enter a move in SAN (Nf3, exd5, O-O, e8=Q) or UCI (g1f3), or one of:
+  undo    take back your last move
+  hint    let the engine suggest a move
+  fen     print the position as FEN
+  flip    turn the board around
+  resign  give up the game
+  quit    leave without finishing";
+
+/// ANSI backgrounds for light squares, dark squares and the squares of the last move
+const LIGHT: &str = "\x1b[48;5;223m";
+const DARK: &str = "\x1b[48;5;137m";
+const LAST_MOVE: &str = "\x1b[48;5;143m";
+const RESET: &str = "\x1b[0m";
+
+/// How the board is drawn
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BoardStyle {
+    /// Chess symbols instead of FEN letters
+    pub unicode: bool,
+    /// ANSI colours for the squares and pieces
+    pub colors: bool,
+    /// Black at the bottom
+    pub flipped: bool,
+}
+
+/// Base time and increment per move
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeControl {
+    pub base: Duration,
+    pub increment: Duration,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlayOptions {
+    /// The side the player has
+    pub human: Color,
+    /// How deep the engine searches
+    pub depth: u8,
+    /// Clocks for both sides, none to play without them
+    pub time_control: Option<TimeControl>,
+    pub fen: Option<String>,
+    pub style: BoardStyle,
+}
+
+impl Default for PlayOptions {
+    fn default() -> PlayOptions {
+        PlayOptions {
+            human: Color::White,
+            depth: 4,
+            time_control: None,
+            fen: None,
+            style: BoardStyle {
+                unicode: true,
+                colors: true,
+                flipped: false,
+            },
+        }
+    }
+}
+
+/// The board with coordinates, the last move highlighted when drawing in colour
+pub fn render(state: &ChessState, style: &BoardStyle, last: Option<&Move>) -> String {
+    let mut text = String::new();
+    let ranks: Vec<usize> = if style.flipped {
+        (0..8).collect()
+    } else {
+        (0..8).rev().collect()
+    };
+    let files: Vec<usize> = if style.flipped {
+        (0..8).rev().collect()
+    } else {
+        (0..8).collect()
+    };
+    for rank in ranks.iter() {
+        text += &format!(" {} ", rank + 1);
+        for file in files.iter() {
+            let square = Square::new(*file, *rank);
+            let symbol = match state.board[square] {
+                None if style.colors => ' ',
+                None if style.unicode => '·',
+                None => '.',
+                //Coloured pieces all use the filled symbols and differ in colour instead
+                Some(piece) if style.unicode => {
+                    let white = piece.color.is_white() && !style.colors;
+                    let symbols = if white {
+                        "♙♖♘♗♕♔"
+                    } else {
+                        "♟♜♞♝♛♚"
+                    };
+                    symbols.chars().nth(piece.kind.index() - 1).unwrap()
+                }
+                Some(piece) => piece.to_char(),
+            };
+            if style.colors {
+                let background = if last.is_some_and(|x| x.from == square || x.to == square) {
+                    LAST_MOVE
+                } else if (file + rank) % 2 == 0 {
+                    DARK
+                } else {
+                    LIGHT
+                };
+                let foreground = match state.board[square] {
+                    Some(piece) if piece.color.is_white() => "\x1b[97m",
+                    _ => "\x1b[30m",
+                };
+                text += &format!("{}{} {} ", background, foreground, symbol);
+            } else {
+                text += &format!(" {} ", symbol);
+            }
+        }
+        if style.colors {
+            text += RESET;
+        }
+        text.push('\n');
+    }
+    text += "   ";
+    for file in files {
+        text += &format!(" {} ", (b'a' + file as u8) as char);
+    }
+    text.push('\n');
+    text
+}
+
+/// The legal move `text` gives in SAN or UCI, or what is wrong with it
+pub fn parse_move(state: &ChessState, text: &str) -> Result<Move, String> {
+    let legal = state.get_all_possible_moves();
+    if let Some(m) = translator::uci_to_lan(state, text) {
+        if legal.contains(&m) {
+            return Ok(m);
+        }
+    }
+    translator::parse_san(state, text).map_err(|err| {
+        let mut message = match err {
+            SanError::Invalid(_) => format!(
+                "{} is not a move, enter SAN like Nf3 or UCI like g1f3",
+                text
+            ),
+            SanError::Ambiguous(_) => format!(
+                "{} could be more than one move, add the file or rank the piece comes from",
+                text
+            ),
+            SanError::Illegal(_) if state.check() => {
+                format!("{} is illegal, you are in check", text)
+            }
+            SanError::Illegal(_) => format!("{} is illegal here", text),
+        };
+        let moves: Vec<String> = legal.iter().map(|x| translator::to_san(state, x)).collect();
+        message += &format!("\nLegal moves: {}", moves.join(" "));
+        message
+    })
+}
+
+fn clock_text(time: Duration) -> String {
+    let seconds = time.as_secs();
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+/// The engine's move in `state`, searching to its depth and within the time the white and
+/// black `clocks` leave it, if set
+fn engine_move(
+    bot: &mut MinimaxBot,
+    state: &ChessState,
+    clocks: Option<[Duration; 2]>,
+    increment: Duration,
+) -> Option<Move> {
+    let limit = clocks.map(|clocks| {
+        let (ours, theirs) = if state.turn.is_white() {
+            (clocks[0], clocks[1])
+        } else {
+            (clocks[1], clocks[0])
+        };
+        time_manager::allocate(
+            ours.as_millis() as u64,
+            increment.as_millis() as u64,
+            None,
+            Some(theirs.as_millis() as u64),
+            50,
+        )
+    });
+    bot.player = state.turn;
+    bot.control = Arc::new(SearchControl::new(limit, false));
+    let lines = bot.search(state, 1, &mut |_, _| {});
+    lines.first().map(|x| x.moves[0])
+}
+
+/// Plays one game against the engine, reading commands from `input` and drawing to `out`
+pub fn play<R: BufRead, W: Write>(
+    options: &PlayOptions,
+    input: &mut R,
+    out: &mut W,
+) -> io::Result<()> {
+    let start = match &options.fen {
+        Some(fen) => ChessState::from_fen(fen),
+        None => ChessState::new_board(),
+    };
+    let mut game = Game::new(start);
+    let mut style = options.style;
+    let mut clocks = options.time_control.map(|x| [x.base; 2]);
+    let increment = options.time_control.map_or(Duration::ZERO, |x| x.increment);
+    let mut bot = MinimaxBot::new(!options.human, options.depth, Arc::default(), 16);
+    let clock = |clocks: &mut Option<[Duration; 2]>, color: Color, used: Duration| {
+        let Some(clocks) = clocks else {
+            return true;
+        };
+        let left = &mut clocks[if color.is_white() { 0 } else { 1 }];
+        if used > *left {
+            return false;
+        }
+        *left = *left - used + increment;
+        true
+    };
+
+    writeln!(out, "{}\n", COMMANDS)?;
+    writeln!(out, "{}", render(game.state(), &style, None))?;
+    let outcome = loop {
+        if let Some(outcome) = game.outcome() {
+            break outcome;
+        }
+        let state = game.state().copy();
+        let mover = state.turn;
+        if mover != options.human {
+            let thinking = Instant::now();
+            let Some(m) = engine_move(&mut bot, &state, clocks, increment) else {
+                break Outcome::Resignation(options.human);
+            };
+            if !clock(&mut clocks, mover, thinking.elapsed()) {
+                break Outcome::Time(options.human);
+            }
+            writeln!(out, "chess-rs plays {}\n", translator::to_san(&state, &m))?;
+            game.play(m);
+            writeln!(out, "{}", render(game.state(), &style, Some(&m)))?;
+            continue;
+        }
+
+        let side = if mover.is_white() { "White" } else { "Black" };
+        match clocks {
+            Some([white, black]) => write!(
+                out,
+                "[{} - {}] {}. {} to move: ",
+                clock_text(white),
+                clock_text(black),
+                state.fullmoves,
+                side
+            )?,
+            None => write!(out, "{}. {} to move: ", state.fullmoves, side)?,
+        }
+        out.flush()?;
+        let thinking = Instant::now();
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+        match line.trim() {
+            "" => {}
+            "help" | "?" => writeln!(out, "{}", COMMANDS)?,
+            "quit" | "exit" => return Ok(()),
+            "resign" => break Outcome::Resignation(!options.human),
+            "fen" => writeln!(out, "{}", state.to_fen())?,
+            "flip" => {
+                style.flipped = !style.flipped;
+                writeln!(out, "{}", render(&state, &style, game.moves.last()))?;
+            }
+            "hint" => {
+                let mut helper = MinimaxBot::new(mover, options.depth, Arc::default(), 16);
+                match engine_move(&mut helper, &state, None, increment) {
+                    Some(m) => writeln!(out, "Hint: {}", translator::to_san(&state, &m))?,
+                    None => writeln!(out, "No moves left")?,
+                }
+            }
+            "undo" => {
+                //The engine's reply goes too, so it is the player's turn again
+                if game.moves.len() < 2 {
+                    writeln!(out, "Nothing to take back")?;
+                    continue;
+                }
+                game.undo();
+                game.undo();
+                writeln!(out, "{}", render(game.state(), &style, game.moves.last()))?;
+            }
+            text => match parse_move(&state, text) {
+                Ok(m) => {
+                    if !clock(&mut clocks, mover, thinking.elapsed()) {
+                        break Outcome::Time(!options.human);
+                    }
+                    game.play(m);
+                    writeln!(out, "{}", render(game.state(), &style, Some(&m)))?;
+                }
+                Err(err) => writeln!(out, "{}", err)?,
+            },
+        }
+    };
+
+    writeln!(out, "Game over: {} ({})", outcome, outcome.result())?;
+    write!(
+        out,
+        "Save the game as PGN? Enter a file name, or nothing to skip: "
+    )?;
+    out.flush()?;
+    let mut line = String::new();
+    input.read_line(&mut line)?;
+    let path = line.trim();
+    if !path.is_empty() {
+        let mut pgn = game.to_pgn(Some(outcome));
+        pgn.set_tag("Event", "chess-rs terminal game");
+        pgn.set_tag("Date", &pgn::today());
+        let (white, black) = if options.human.is_white() {
+            ("Player", "chess-rs")
+        } else {
+            ("chess-rs", "Player")
+        };
+        pgn.set_tag("White", white);
+        pgn.set_tag("Black", black);
+        match pgn.append_to_file(path) {
+            Ok(()) => writeln!(out, "Saved to {}", path)?,
+            Err(err) => writeln!(out, "Could not write to {}: {}", path, err)?,
+        }
+    }
+    Ok(())
+}
+
+/// Runs `chess-rs play` with the arguments after the subcommand
+pub fn run(args: &[String]) -> Result<(), String> {
+    let mut options = PlayOptions::default();
+    let mut flip = true;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .cloned()
+                .ok_or_else(|| format!("{} needs a value\n{}", name, USAGE))
+        };
+        match arg.as_str() {
+            "--color" | "--colour" => {
+                options.human = match value(arg)?.to_ascii_lowercase().as_str() {
+                    "white" | "w" => Color::White,
+                    "black" | "b" => Color::Black,
+                    x => return Err(format!("Unknown color {}\n{}", x, USAGE)),
+                }
+            }
+            "--depth" => {
+                options.depth = value(arg)?
+                    .parse::<u8>()
+                    .ok()
+                    .filter(|x| *x > 0)
+                    .ok_or_else(|| format!("The depth has to be a number from 1\n{}", USAGE))?
+            }
+            "--time" => options.time_control = Some(parse_time_control(&value(arg)?)?),
+            "--fen" => options.fen = Some(value(arg)?),
+            "--ascii" => options.style.unicode = false,
+            "--no-color" | "--no-colour" => options.style.colors = false,
+            "--no-flip" => flip = false,
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return Ok(());
+            }
+            x => return Err(format!("Unknown option {}\n{}", x, USAGE)),
+        }
+    }
+    //The player's side goes at the bottom unless told otherwise
+    options.style.flipped = flip && !options.human.is_white();
+    let stdin = io::stdin();
+    play(&options, &mut stdin.lock(), &mut io::stdout()).map_err(|err| err.to_string())
+}
+
+/// Reads `MINUTES+INCREMENT`, the increment in seconds and optional
+fn parse_time_control(text: &str) -> Result<TimeControl, String> {
+    let (base, increment) = text.split_once('+').unwrap_or((text, "0"));
+    let number = |x: &str| {
+        x.trim()
+            .parse::<f64>()
+            .ok()
+            .filter(|x| *x >= 0.0)
+            .ok_or_else(|| format!("Expected a time control like 5+3, got {}", text))
+    };
+    Ok(TimeControl {
+        base: Duration::from_secs_f64(number(base)? * 60.0),
+        increment: Duration::from_secs_f64(number(increment)?),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plain(flipped: bool) -> BoardStyle {
+        BoardStyle {
+            unicode: false,
+            colors: false,
+            flipped,
+        }
+    }
+
+    #[test]
+    fn board() {
+        let state = ChessState::new_board();
+        let text = render(&state, &plain(false), None);
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0], " 8  r  n  b  q  k  b  n  r ");
+        assert_eq!(lines[4], " 4  .  .  .  .  .  .  .  . ");
+        assert_eq!(lines[8], "    a  b  c  d  e  f  g  h ");
+        let flipped = render(&state, &plain(true), None);
+        assert!(flipped.starts_with(" 1  R  N  B  K  Q  B  N  R "));
+        assert!(flipped.ends_with("    h  g  f  e  d  c  b  a \n"));
+        let unicode = BoardStyle {
+            unicode: true,
+            ..plain(false)
+        };
+        assert!(render(&state, &unicode, None).contains("♔"));
+    }
+
+    #[test]
+    fn moves_and_errors() {
+        let state = ChessState::new_board();
+        assert_eq!(
+            parse_move(&state, "Nf3"),
+            Ok(state.parse_lan("Ng1-f3").unwrap())
+        );
+        assert_eq!(
+            parse_move(&state, "g1f3"),
+            Ok(state.parse_lan("Ng1-f3").unwrap())
+        );
+        assert!(parse_move(&state, "e5")
+            .unwrap_err()
+            .starts_with("e5 is illegal here"));
+        assert!(parse_move(&state, "hello")
+            .unwrap_err()
+            .contains("is not a move"));
+        assert_eq!(
+            parse_time_control("5+3"),
+            Ok(TimeControl {
+                base: Duration::from_secs(300),
+                increment: Duration::from_secs(3),
+            })
+        );
+    }
+
+    #[test]
+    fn game() {
+        let options = PlayOptions {
+            depth: 1,
+            fen: Some("k7/8/1K6/8/8/8/8/7Q w - - 0 1".to_string()),
+            style: plain(false),
+            ..PlayOptions::default()
+        };
+        let mut input = io::Cursor::new("Qh9\nfen\nundo\nQh8\n\n");
+        let mut out: Vec<u8> = Vec::new();
+        play(&options, &mut input, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("Qh9 is not a move"));
+        assert!(out.contains("k7/8/1K6/8/8/8/8/7Q w - - 0 1"));
+        assert!(out.contains("Nothing to take back"));
+        assert!(out.contains("Game over: White wins by checkmate (1-0)"));
+    }
+}
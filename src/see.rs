@@ -0,0 +1,167 @@
+use crate::chess_init::ChessState;
+use crate::moves::{Move, MoveKind};
+use crate::types::{Color, Piece, PieceKind, Square};
+
+/// Piece values for exchanges
+fn see_value(kind: PieceKind) -> i32 {
+    match kind {
+        PieceKind::Pawn => 100,
+        PieceKind::Rook => 500,
+        PieceKind::Knight => 320,
+        PieceKind::Bishop => 330,
+        PieceKind::Queen => 900,
+        PieceKind::King => 20000,
+    }
+}
+
+impl ChessState {
+    /// Material won or lost by `m` once every capture on its target square is played out,
+    /// each side taking with its least valuable piece and stopping when that loses more.
+    /// Castling and quiet moves onto safe squares give 0.
+    pub fn see(&self, m: &Move) -> i32 {
+        if m.is_castle() {
+            return 0;
+        }
+        let (source, target, promotion) = (m.from, m.to, m.promotion());
+        let Some(moving) = self.board[source] else {
+            return 0;
+        };
+        let mut occupancy = self.board.occupancy();
+        let mut captured = self.board[target].map_or(0, |x| see_value(x.kind));
+        if m.kind == MoveKind::EnPassant {
+            //En passant, the pawn taken is beside the source
+            occupancy &= !Square::new(target.file(), source.rank()).bit();
+            captured = see_value(PieceKind::Pawn);
+        }
+        let mut on_target = see_value(moving.kind);
+        if let Some(kind) = promotion {
+            captured += see_value(kind) - see_value(PieceKind::Pawn);
+            on_target = see_value(kind);
+        }
+        occupancy &= !source.bit();
+        occupancy |= target.bit();
+
+        //Every capture takes a piece off one of the 64 squares
+        let mut gains = [0; 64];
+        gains[0] = captured;
+        let mut len = 1;
+        let mut color = !moving.color;
+        while let Some(attacker) = self.least_valuable_attacker(target, occupancy, color) {
+            let piece = self.board[attacker].unwrap();
+            occupancy &= !attacker.bit();
+            if piece.kind == PieceKind::King
+                && self
+                    .least_valuable_attacker(target, occupancy, !color)
+                    .is_some()
+            {
+                //The king cannot take a defended piece
+                break;
+            }
+            let mut value = see_value(piece.kind);
+            let mut gain = on_target - gains[len - 1];
+            if piece.kind == PieceKind::Pawn && (target.rank() == 0 || target.rank() == 7) {
+                gain += see_value(PieceKind::Queen) - see_value(PieceKind::Pawn);
+                value = see_value(PieceKind::Queen);
+            }
+            gains[len] = gain;
+            len += 1;
+            on_target = value;
+            color = !color;
+        }
+        //Either side may stop capturing when going on loses material
+        while len > 1 {
+            len -= 1;
+            gains[len - 1] = -(-gains[len - 1]).max(gains[len]);
+        }
+        gains[0]
+    }
+
+    /// Whether `m` wins at least `threshold` in the exchange on its target square
+    pub fn see_ge(&self, m: &Move, threshold: i32) -> bool {
+        self.see(m) >= threshold
+    }
+
+    /// The cheapest piece of `color` that can take on `target`, leaving out pieces whose
+    /// capture would uncover a slider's attack on their own king
+    fn least_valuable_attacker(
+        &self,
+        target: Square,
+        occupancy: u64,
+        color: Color,
+    ) -> Option<Square> {
+        let attackers = ChessState::attackers(&self.board, target, occupancy)
+            & self.board.color_occupancy(color);
+        let king = self.board.find(Piece::new(PieceKind::King, color));
+        //Kinds from the cheapest to the king
+        let mut kinds = PieceKind::ALL;
+        kinds.sort_by_key(|x| see_value(*x));
+        kinds
+            .into_iter()
+            .flat_map(|kind| {
+                Square::in_set(attackers).filter(move |x| self.board[*x].unwrap().kind == kind)
+            })
+            .find(|x| {
+                king.is_none_or(|king| *x == king || !self.pinned(*x, king, target, occupancy))
+            })
+    }
+
+    /// Whether moving the piece on `position` to `target` exposes `king` to a slider
+    fn pinned(&self, position: Square, king: Square, target: Square, occupancy: u64) -> bool {
+        let Some(color) = self.board[king].map(|x| x.color) else {
+            return false;
+        };
+        let enemy_sliders = Square::in_set(self.board.color_occupancy(!color))
+            .filter(|x| self.board[*x].is_some_and(|piece| piece.kind.is_slider()))
+            .fold(0, |bits, x| bits | x.bit());
+        let sliders = |occupancy: u64| {
+            ChessState::attackers(&self.board, king, occupancy) & enemy_sliders & !target.bit()
+        };
+        let before = sliders(occupancy);
+        let after = occupancy & !position.bit();
+        sliders(after) & !before != 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn see(fen: &str, m: &str) -> i32 {
+        let state = ChessState::from_fen(fen);
+        state.see(&state.parse_lan(m).unwrap())
+    }
+
+    fn see_ge_zero(fen: &str, m: &str) -> bool {
+        let state = ChessState::from_fen(fen);
+        state.see_ge(&state.parse_lan(m).unwrap(), 0)
+    }
+
+    #[test]
+    fn exchanges() {
+        //Undefended pawn
+        assert_eq!(
+            see("1k1r4/1pp4p/p7/4p3/8/P5P1/1PP4P/2K1R3 w - - 0 1", "Re1xe5"),
+            100
+        );
+        //Knight takes a pawn defended by a knight, with x-rays on both sides
+        assert_eq!(
+            see(
+                "1k1r3q/1ppn3p/p4b2/4p3/8/P2N2P1/1PP1R1BP/2K1Q3 w - - 0 1",
+                "Nd3xe5"
+            ),
+            -220
+        );
+        //Quiet move onto a square the pawn covers
+        assert_eq!(see("4k3/8/3p4/8/4N3/8/8/4K3 w - - 0 1", "Ne4-c5"), -320);
+        assert!(see_ge_zero("4k3/8/8/3p4/4P3/8/8/4K3 w - - 0 1", "e4xd5"));
+    }
+
+    #[test]
+    fn pins_and_promotions() {
+        //The bishop pins the pawn guarding the knight
+        assert_eq!(see("8/4k3/5p2/4n3/3P3B/8/8/4K3 w - - 0 1", "d4xe5"), 320);
+        assert_eq!(see("8/4k3/5p2/4n3/3P4/8/8/4K3 w - - 0 1", "d4xe5"), 220);
+        assert_eq!(see("6k1/1P6/8/8/8/8/8/4K3 w - - 0 1", "b7-b8Q"), 800);
+        assert_eq!(see("1n4k1/P7/8/8/8/8/8/4K3 w - - 0 1", "a7xb8N"), 540);
+    }
+}
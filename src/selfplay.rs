@@ -0,0 +1,547 @@
+use crate::chess_init::ChessState;
+use crate::chessbot::{MinimaxBot, SearchControl};
+use crate::elo::Score;
+use crate::epd;
+use crate::eval::EvalWeights;
+use crate::game::{Game, Outcome};
+use crate::pgn::{self, PgnGame, PgnReader};
+use crate::types::Color;
+use std::fs::File;
+use std::io::BufReader;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::Duration;
+
+const USAGE: &str = "usage: chess-rs match --engine SPEC --engine SPEC [--games N] \
+[--openings FILE.epd|FILE.pgn] [--concurrency N] [--pgn OUT.pgn] [--max-moves N] \
+[--resign-score CP] [--resign-moves N] [--draw-score CP] [--draw-moves N] [--draw-after N]
+SPEC is a comma separated list of name=NAME, depth=N, movetime=MS, hash=MB and the eval \
+weights pawn=N, rook=N, knight=N, bishop=N, queen=N and king=N";
+
+/// One side of a match: how the bot searches and evaluates
+#[derive(Debug, Clone, PartialEq)]
+pub struct EngineConfig {
+    pub name: String,
+    pub depth: u8,
+    /// Time per move on top of the depth limit
+    pub movetime: Option<Duration>,
+    /// Transposition table size in megabytes
+    pub hash: usize,
+    pub weights: EvalWeights,
+}
+
+impl Default for EngineConfig {
+    fn default() -> EngineConfig {
+        EngineConfig {
+            name: "chess-rs".to_string(),
+            depth: 4,
+            movetime: None,
+            hash: 16,
+            weights: EvalWeights::default(),
+        }
+    }
+}
+
+impl FromStr for EngineConfig {
+    type Err = String;
+
+    /// Reads `key=value` pairs separated by commas, such as `name=new,depth=5,queen=90`
+    fn from_str(spec: &str) -> Result<EngineConfig, String> {
+        let mut config = EngineConfig::default();
+        for pair in spec.split(',').filter(|x| !x.trim().is_empty()) {
+            let Some((key, value)) = pair.split_once('=') else {
+                return Err(format!("Expected key=value, got {}", pair));
+            };
+            let (key, value) = (key.trim(), value.trim());
+            let number = || {
+                value
+                    .parse::<i32>()
+                    .map_err(|_| format!("Expected a number for {}, got {}", key, value))
+            };
+            let positive = || {
+                number().and_then(|x| {
+                    if x > 0 {
+                        Ok(x as u32)
+                    } else {
+                        Err(format!("{} has to be at least 1", key))
+                    }
+                })
+            };
+            match key {
+                "name" => config.name = value.to_string(),
+                "depth" => config.depth = positive()?.min(64) as u8,
+                "movetime" => config.movetime = Some(Duration::from_millis(positive()? as u64)),
+                "hash" => config.hash = positive()? as usize,
+                "pawn" => config.weights.pawn = number()?,
+                "rook" => config.weights.rook = number()?,
+                "knight" => config.weights.knight = number()?,
+                "bishop" => config.weights.bishop = number()?,
+                "queen" => config.weights.queen = number()?,
+                "king" => config.weights.king = number()?,
+                _ => return Err(format!("Unknown engine setting {}", key)),
+            }
+        }
+        Ok(config)
+    }
+}
+
+impl EngineConfig {
+    fn bot(&self, player: Color) -> MinimaxBot {
+        let mut bot = MinimaxBot::new(player, self.depth, Arc::default(), self.hash);
+        bot.weights = self.weights;
+        bot
+    }
+}
+
+/// When a game is called before the rules end it. Scores are those the engines report, from
+/// white's point of view, and counts are in plies. The eval counts mobility, where a queen's
+/// moves alone are worth about a thousand, so the resign score sits well above that.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Adjudication {
+    /// A draw after this many moves, 0 to never cut games short
+    pub max_moves: u16,
+    /// A win once the score stays at least this far from 0 for `resign_moves` in a row
+    pub resign_score: i32,
+    pub resign_moves: u32,
+    /// A draw once the score stays within this of 0 for `draw_moves` in a row, from move
+    /// `draw_after` on
+    pub draw_score: i32,
+    pub draw_moves: u32,
+    pub draw_after: u16,
+}
+
+impl Default for Adjudication {
+    fn default() -> Adjudication {
+        Adjudication {
+            max_moves: 200,
+            resign_score: 2500,
+            resign_moves: 6,
+            draw_score: 10,
+            draw_moves: 12,
+            draw_after: 40,
+        }
+    }
+}
+
+/// Follows the scores of a game to call it once they have been lopsided or level for long
+/// enough
+#[derive(Debug, Clone)]
+pub struct Adjudicator {
+    rules: Adjudication,
+    leader: Option<Color>,
+    resign_streak: u32,
+    draw_streak: u32,
+}
+
+impl Adjudicator {
+    pub fn new(rules: Adjudication) -> Adjudicator {
+        Adjudicator {
+            rules,
+            leader: None,
+            resign_streak: 0,
+            draw_streak: 0,
+        }
+    }
+
+    /// Takes the score of the search in `state`, from white's point of view, and says how
+    /// the game ends if it should end there
+    pub fn update(&mut self, state: &ChessState, score: i32) -> Option<Outcome> {
+        let rules = &self.rules;
+        //Both sides have to agree, so the streak only grows while the same side leads
+        let ahead = if score >= rules.resign_score {
+            Some(Color::White)
+        } else if score <= -rules.resign_score {
+            Some(Color::Black)
+        } else {
+            None
+        };
+        self.resign_streak = match ahead {
+            Some(_) if ahead == self.leader => self.resign_streak + 1,
+            Some(_) => 1,
+            None => 0,
+        };
+        self.leader = ahead;
+        if self.leader.is_some() && self.resign_streak >= rules.resign_moves {
+            return Some(Outcome::Adjudication(self.leader));
+        }
+        if state.fullmoves >= rules.draw_after
+            && score >= -rules.draw_score
+            && score <= rules.draw_score
+        {
+            self.draw_streak += 1;
+        } else {
+            self.draw_streak = 0;
+        }
+        if rules.draw_moves > 0 && self.draw_streak >= rules.draw_moves {
+            return Some(Outcome::Adjudication(None));
+        }
+        None
+    }
+
+    /// Whether the game in `state` has gone past the move limit and is drawn
+    pub fn out_of_moves(&self, state: &ChessState) -> bool {
+        self.rules.max_moves > 0 && state.fullmoves > self.rules.max_moves
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MatchOptions {
+    pub engines: [EngineConfig; 2],
+    pub games: usize,
+    /// FENs to start from, each played twice with colours swapped
+    pub openings: Vec<String>,
+    /// Games played at the same time
+    pub concurrency: usize,
+    pub adjudication: Adjudication,
+}
+
+/// A finished game of a match
+pub struct MatchGame {
+    /// Counting from 0 in the order the games were started
+    pub round: usize,
+    /// Whether the first engine had white
+    pub first_white: bool,
+    pub outcome: Outcome,
+    pub pgn: PgnGame,
+}
+
+/// Plays a game from `opening` between two engines, adjudicating it as `adjudication` says
+pub fn play_game(
+    white: &EngineConfig,
+    black: &EngineConfig,
+    opening: &str,
+    adjudication: &Adjudication,
+) -> (Outcome, Game) {
+    let mut game = Game::new(ChessState::from_fen(opening));
+    let mut bots = [white.bot(Color::White), black.bot(Color::Black)];
+    let mut adjudicator = Adjudicator::new(*adjudication);
+    let outcome = loop {
+        if let Some(outcome) = game.outcome() {
+            break outcome;
+        }
+        let state = game.state().copy();
+        if adjudicator.out_of_moves(&state) {
+            break Outcome::Adjudication(None);
+        }
+        let (bot, config) = if state.turn.is_white() {
+            (&mut bots[0], white)
+        } else {
+            (&mut bots[1], black)
+        };
+        bot.control = Arc::new(SearchControl::new(config.movetime, false));
+        let (Some(m), score) = bot.minimax_search(state.copy()) else {
+            break Outcome::Adjudication(None);
+        };
+        if let Some(outcome) = adjudicator.update(&state, score) {
+            break outcome;
+        }
+        game.play(m);
+    };
+    (outcome, game)
+}
+
+/// Plays the match on `options.concurrency` threads, handing every finished game and the
+/// score of the first engine so far to `report`. Returns that score.
+pub fn run_match(options: &MatchOptions, report: &mut dyn FnMut(&MatchGame, &Score)) -> Score {
+    let mut score = Score::default();
+    if options.openings.is_empty() {
+        return score;
+    }
+    let next = AtomicUsize::new(0);
+    let (sender, receiver) = mpsc::channel::<MatchGame>();
+    thread::scope(|scope| {
+        for _ in 0..options.concurrency.max(1) {
+            let sender = sender.clone();
+            let next = &next;
+            scope.spawn(move || loop {
+                let round = next.fetch_add(1, Ordering::SeqCst);
+                if round >= options.games {
+                    break;
+                }
+                let opening = &options.openings[(round / 2) % options.openings.len()];
+                let first_white = round.is_multiple_of(2);
+                let [first, second] = &options.engines;
+                let (white, black) = if first_white {
+                    (first, second)
+                } else {
+                    (second, first)
+                };
+                let (outcome, game) = play_game(white, black, opening, &options.adjudication);
+                let pgn = match_pgn(
+                    &game,
+                    outcome,
+                    &outcome.to_string(),
+                    round,
+                    [&white.name, &black.name],
+                );
+                let finished = MatchGame {
+                    round,
+                    first_white,
+                    outcome,
+                    pgn,
+                };
+                if sender.send(finished).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(sender);
+        for game in receiver {
+            let first = if game.first_white {
+                Color::White
+            } else {
+                Color::Black
+            };
+            match game.outcome.winner() {
+                Some(winner) if winner == first => score.wins += 1,
+                Some(_) => score.losses += 1,
+                None => score.draws += 1,
+            }
+            report(&game, &score);
+        }
+    });
+    score
+}
+
+/// A game of a match as PGN, with `comment` on how it ended after the last move
+pub(crate) fn match_pgn(
+    game: &Game,
+    outcome: Outcome,
+    comment: &str,
+    round: usize,
+    [white, black]: [&str; 2],
+) -> PgnGame {
+    let mut pgn = game.to_pgn(Some(outcome));
+    pgn.set_tag("Event", "chess-rs match");
+    pgn.set_tag("Date", &pgn::today());
+    pgn.set_tag("Round", &(round + 1).to_string());
+    pgn.set_tag("White", white);
+    pgn.set_tag("Black", black);
+    match pgn.moves.last_mut() {
+        Some(last) => last.comments.push(comment.to_string()),
+        None => pgn.comments.push(comment.to_string()),
+    }
+    pgn
+}
+
+/// The command line options that set the fields of `Adjudication`
+pub(crate) const ADJUDICATION_OPTIONS: [&str; 6] = [
+    "--max-moves",
+    "--resign-score",
+    "--resign-moves",
+    "--draw-score",
+    "--draw-moves",
+    "--draw-after",
+];
+
+/// Sets the field of `adjudication` one of `ADJUDICATION_OPTIONS` names
+pub(crate) fn set_adjudication(
+    adjudication: &mut Adjudication,
+    option: &str,
+    value: &str,
+) -> Result<(), String> {
+    match option {
+        "--max-moves" => adjudication.max_moves = parse_number(value)?,
+        "--resign-score" => adjudication.resign_score = parse_number(value)?,
+        "--resign-moves" => adjudication.resign_moves = parse_number(value)?,
+        "--draw-score" => adjudication.draw_score = parse_number(value)?,
+        "--draw-moves" => adjudication.draw_moves = parse_number(value)?,
+        "--draw-after" => adjudication.draw_after = parse_number(value)?,
+        x => return Err(format!("Unknown adjudication option {}", x)),
+    }
+    Ok(())
+}
+
+/// The final positions of the games of a PGN file or the positions of an EPD file, as FENs
+pub fn load_openings(path: &str) -> Result<Vec<String>, String> {
+    let error = |err: std::io::Error| format!("Could not read {}: {}", path, err);
+    let openings: Vec<String> = if path.to_ascii_lowercase().ends_with(".pgn") {
+        PgnReader::new(BufReader::new(File::open(path).map_err(error)?))
+            .filter_map(|x| x.ok())
+            .map(|x| x.mainline_positions().last().unwrap().to_fen())
+            .collect()
+    } else {
+        epd::read_file(path)
+            .map_err(error)?
+            .iter()
+            .map(|x| x.state.to_fen())
+            .collect()
+    };
+    if openings.is_empty() {
+        return Err(format!("No positions in {}", path));
+    }
+    Ok(openings)
+}
+
+/// Runs `chess-rs match` with the arguments after the subcommand
+pub fn run(args: &[String]) -> Result<(), String> {
+    let mut engines: Vec<EngineConfig> = Vec::new();
+    let mut options = MatchOptions {
+        engines: Default::default(),
+        games: 2,
+        openings: vec![ChessState::new_board().to_fen()],
+        concurrency: 1,
+        adjudication: Adjudication::default(),
+    };
+    let mut output: Option<String> = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .cloned()
+                .ok_or_else(|| format!("{} needs a value\n{}", name, USAGE))
+        };
+        match arg.as_str() {
+            "--engine" => engines.push(value(arg)?.parse()?),
+            "--games" => options.games = parse_number(&value(arg)?)?,
+            "--openings" => options.openings = load_openings(&value(arg)?)?,
+            "--concurrency" => options.concurrency = parse_number::<usize>(&value(arg)?)?.max(1),
+            "--pgn" => output = Some(value(arg)?),
+            x if ADJUDICATION_OPTIONS.contains(&x) => {
+                set_adjudication(&mut options.adjudication, x, &value(x)?)?
+            }
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return Ok(());
+            }
+            x => return Err(format!("Unknown option {}\n{}", x, USAGE)),
+        }
+    }
+    let [first, second]: [EngineConfig; 2] = engines
+        .try_into()
+        .map_err(|_| format!("Expected two engines\n{}", USAGE))?;
+    //Two engines of the same name would be impossible to tell apart in the games
+    let second = if first.name == second.name {
+        EngineConfig {
+            name: format!("{} 2", second.name),
+            ..second
+        }
+    } else {
+        second
+    };
+    options.engines = [first, second];
+
+    let [first, second] = &options.engines;
+    let mut write_error: Option<String> = None;
+    let score = run_match(&options, &mut |game, score| {
+        println!(
+            "Game {} of {}: {} vs {}: {} {{{}}}",
+            game.round + 1,
+            options.games,
+            game.pgn.tag("White").unwrap_or("?"),
+            game.pgn.tag("Black").unwrap_or("?"),
+            game.outcome.result(),
+            game.outcome
+        );
+        println!(
+            "Score of {} vs {}: {} [{:.3}] {}",
+            first.name,
+            second.name,
+            score,
+            score.ratio(),
+            score.games()
+        );
+        if let Some(path) = &output {
+            if let Err(err) = game.pgn.append_to_file(path) {
+                write_error.get_or_insert(format!("Could not write to {}: {}", path, err));
+            }
+        }
+    });
+    match score.elo() {
+        Some((elo, margin)) => println!("Elo difference: {:+.1} +/- {:.1}", elo, margin),
+        None => println!("Elo difference: unknown after {}", score),
+    }
+    match write_error {
+        Some(err) => Err(err),
+        None => Ok(()),
+    }
+}
+
+pub(crate) fn parse_number<T: FromStr>(text: &str) -> Result<T, String> {
+    text.parse()
+        .map_err(|_| format!("Expected a number, got {}", text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn engine_specs() {
+        let config: EngineConfig = "name=new, depth=2,queen=90,movetime=50".parse().unwrap();
+        assert_eq!(config.name, "new");
+        assert_eq!(config.depth, 2);
+        assert_eq!(config.weights.queen, 90);
+        assert_eq!(config.movetime, Some(Duration::from_millis(50)));
+        assert!("depth=0".parse::<EngineConfig>().is_err());
+        assert!("speed=3".parse::<EngineConfig>().is_err());
+    }
+
+    #[test]
+    fn adjudicated_games() {
+        let engine = EngineConfig {
+            depth: 1,
+            ..EngineConfig::default()
+        };
+        //A queen up is called a win as soon as the scores agree for long enough
+        let adjudication = Adjudication {
+            resign_score: 500,
+            resign_moves: 2,
+            ..Adjudication::default()
+        };
+        let (outcome, game) = play_game(
+            &engine,
+            &engine,
+            "4k3/8/8/8/8/8/8/3QK3 w - - 0 1",
+            &adjudication,
+        );
+        assert_eq!(outcome, Outcome::Adjudication(Some(Color::White)));
+        assert_eq!(game.moves.len(), 1);
+
+        let short = Adjudication {
+            max_moves: 3,
+            ..Adjudication::default()
+        };
+        let (outcome, game) =
+            play_game(&engine, &engine, &ChessState::new_board().to_fen(), &short);
+        assert_eq!(outcome, Outcome::Adjudication(None));
+        assert_eq!(game.moves.len(), 6);
+    }
+
+    #[test]
+    fn matches() {
+        let engine = |name: &str| EngineConfig {
+            name: name.to_string(),
+            depth: 1,
+            ..EngineConfig::default()
+        };
+        let options = MatchOptions {
+            engines: [engine("a"), engine("b")],
+            games: 4,
+            openings: vec![
+                ChessState::new_board().to_fen(),
+                "4k3/8/8/8/8/8/8/3QK3 w - - 0 1".to_string(),
+            ],
+            concurrency: 2,
+            adjudication: Adjudication {
+                max_moves: 10,
+                ..Adjudication::default()
+            },
+        };
+        let mut rounds: Vec<usize> = Vec::new();
+        let score = run_match(&options, &mut |game, _| {
+            assert_eq!(
+                game.pgn.tag("White"),
+                Some(if game.first_white { "a" } else { "b" })
+            );
+            rounds.push(game.round);
+        });
+        rounds.sort();
+        assert_eq!(rounds, [0, 1, 2, 3]);
+        assert_eq!(score.games(), 4);
+        //Each side gets the queen once, and equal engines play it the same way
+        assert_eq!(score.wins, score.losses);
+    }
+}
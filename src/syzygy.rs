@@ -0,0 +1,1144 @@
+use crate::chess_init::ChessState;
+use crate::endgame::PIECE_ORDER;
+use crate::moves::Move;
+use crate::types::{Color, PieceKind};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+const WDL_MAGIC: [u8; 4] = [0x71, 0xE8, 0x23, 0x5D];
+const DTZ_MAGIC: [u8; 4] = [0xD7, 0x66, 0x0C, 0xA5];
+const TB_PIECES: usize = 7;
+const MAX_DTZ: i32 = 1 << 18;
+
+//Table flags. All but FLAG_SINGLE_VALUE are only used by DTZ tables.
+const FLAG_STM: u8 = 1;
+const FLAG_MAPPED: u8 = 2;
+const FLAG_WIN_PLIES: u8 = 4;
+const FLAG_LOSS_PLIES: u8 = 8;
+const FLAG_WIDE: u8 = 16;
+const FLAG_SINGLE_VALUE: u8 = 128;
+
+/// Win, draw or loss for the side to move. Cursed wins and blessed losses are
+/// wins and losses that the fifty-move rule turns into draws.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Wdl {
+    Loss,
+    BlessedLoss,
+    Draw,
+    CursedWin,
+    Win,
+}
+
+impl Wdl {
+    fn from_value(value: i32) -> Wdl {
+        match value {
+            -2 => Wdl::Loss,
+            -1 => Wdl::BlessedLoss,
+            1 => Wdl::CursedWin,
+            2 => Wdl::Win,
+            _ => Wdl::Draw,
+        }
+    }
+}
+
+/// Lookup tables for turning a position into a table index
+struct Indices {
+    map_pawns: [usize; 64],
+    map_b1h1h7: [u64; 64],
+    map_a1d1d4: [usize; 64],
+    map_kk: [[u64; 64]; 10],
+    /// `binomial[k][n]` ways to choose k of n squares
+    binomial: [[u64; 64]; 6],
+    lead_pawn_idx: [[u64; 64]; 6],
+    lead_pawns_size: [[u64; 4]; 6],
+}
+
+/// Rank minus file, 0 on the a1-h8 diagonal and negative below it
+fn off_a1h8(sq: usize) -> i32 {
+    (sq / 8) as i32 - (sq % 8) as i32
+}
+
+impl Indices {
+    fn new() -> Indices {
+        let mut ix = Indices {
+            map_pawns: [0; 64],
+            map_b1h1h7: [0; 64],
+            map_a1d1d4: [0; 64],
+            map_kk: [[0; 64]; 10],
+            binomial: [[0; 64]; 6],
+            lead_pawn_idx: [[0; 64]; 6],
+            lead_pawns_size: [[0; 4]; 6],
+        };
+        let mut code = 0;
+        for sq in 0..64 {
+            if off_a1h8(sq) < 0 {
+                ix.map_b1h1h7[sq] = code;
+                code += 1;
+            }
+        }
+        //The a1-d1-d4 triangle, with the diagonal squares last
+        let mut diagonal: Vec<usize> = Vec::new();
+        let mut code = 0;
+        for sq in 0..28 {
+            if off_a1h8(sq) < 0 && sq % 8 <= 3 {
+                ix.map_a1d1d4[sq] = code;
+                code += 1;
+            } else if off_a1h8(sq) == 0 && sq % 8 <= 3 {
+                diagonal.push(sq);
+            }
+        }
+        for sq in diagonal {
+            ix.map_a1d1d4[sq] = code;
+            code += 1;
+        }
+        //The 462 legal placements of two kings with the first in the a1-d1-d4 triangle
+        let mut both_on_diagonal: Vec<(usize, usize)> = Vec::new();
+        let mut code = 0;
+        for idx in 0..10 {
+            for s1 in 0..28 {
+                if ix.map_a1d1d4[s1] != idx || (idx == 0 && s1 != 1) {
+                    continue;
+                }
+                for s2 in 0..64 {
+                    let touching = (s1 / 8).abs_diff(s2 / 8) <= 1 && (s1 % 8).abs_diff(s2 % 8) <= 1;
+                    if touching || (off_a1h8(s1) == 0 && off_a1h8(s2) > 0) {
+                        continue;
+                    } else if off_a1h8(s1) == 0 && off_a1h8(s2) == 0 {
+                        both_on_diagonal.push((idx, s2));
+                    } else {
+                        ix.map_kk[idx][s2] = code;
+                        code += 1;
+                    }
+                }
+            }
+        }
+        for (idx, s2) in both_on_diagonal {
+            ix.map_kk[idx][s2] = code;
+            code += 1;
+        }
+        ix.binomial[0][0] = 1;
+        for n in 1..64 {
+            for k in 0..6.min(n + 1) {
+                ix.binomial[k][n] = if k > 0 { ix.binomial[k - 1][n - 1] } else { 0 }
+                    + if k < n { ix.binomial[k][n - 1] } else { 0 };
+            }
+        }
+        //Pawn squares numbered from the edges inwards, so the leading pawn has the highest
+        let mut available = 47;
+        for lead_pawns in 1..=5 {
+            for file in 0..4 {
+                let mut idx = 0;
+                for rank in 1..7 {
+                    let sq = rank * 8 + file;
+                    if lead_pawns == 1 {
+                        ix.map_pawns[sq] = available;
+                        ix.map_pawns[sq ^ 7] = available - 1;
+                        available = available.saturating_sub(2);
+                    }
+                    ix.lead_pawn_idx[lead_pawns][sq] = idx;
+                    idx += ix.binomial[lead_pawns - 1][ix.map_pawns[sq]];
+                }
+                ix.lead_pawns_size[lead_pawns][file] = idx;
+            }
+        }
+        ix
+    }
+}
+
+/// Decoding information for one part of a table: a side to move and, with pawns, a file
+#[derive(Debug, Clone, Default)]
+struct PairsData {
+    flags: u8,
+    max_sym_len: u8,
+    min_sym_len: u8,
+    num_blocks: u64,
+    sizeof_block: u64,
+    span: u64,
+    //Offsets into the file
+    lowest_sym: usize,
+    btree: usize,
+    block_length: usize,
+    block_length_size: u64,
+    sparse_index: usize,
+    sparse_index_size: u64,
+    data: usize,
+    base64: Vec<u64>,
+    /// Number of values (minus one) each symbol expands to
+    symlen: Vec<u8>,
+    pieces: [u8; TB_PIECES],
+    group_idx: [u64; TB_PIECES + 1],
+    group_len: [usize; TB_PIECES + 1],
+    /// Where the DTZ values of wins, losses, cursed wins and blessed losses start
+    map_idx: [u16; 4],
+}
+
+/// A WDL or DTZ file read into memory
+struct Table {
+    data: Vec<u8>,
+    /// `[side to move][file]`
+    items: Vec<Vec<PairsData>>,
+    sides: usize,
+    /// Start of the DTZ value maps
+    map: usize,
+}
+
+impl Table {
+    fn get(&self, stm: usize, file: usize, has_pawns: bool) -> &PairsData {
+        &self.items[stm % self.sides][if has_pawns { file } else { 0 }]
+    }
+}
+
+/// A material combination with a table file, such as KRvK
+struct TableInfo {
+    /// The file name without extension, white being the side listed first
+    name: String,
+    /// The name with the sides the other way round
+    swapped: String,
+    piece_count: usize,
+    has_pawns: bool,
+    has_unique_pieces: bool,
+    /// Pawns of the leading color and of the other one
+    pawn_count: [usize; 2],
+    wdl_path: PathBuf,
+    dtz_path: Option<PathBuf>,
+    wdl: OnceLock<Option<Table>>,
+    dtz: OnceLock<Option<Table>>,
+}
+
+impl TableInfo {
+    fn new(name: &str, wdl_path: PathBuf) -> Option<TableInfo> {
+        let (white, black) = name.split_once('v')?;
+        let valid = |side: &str| {
+            side.starts_with('K')
+                && side.matches('K').count() == 1
+                && side.chars().all(|x| PIECE_ORDER.contains(x))
+        };
+        if !valid(white) || !valid(black) || white.len() + black.len() > TB_PIECES {
+            return None;
+        }
+        let has_unique_pieces = [white, black]
+            .iter()
+            .any(|side| "QRBNP".chars().any(|x| side.matches(x).count() == 1));
+        let (white_pawns, black_pawns) = (white.matches('P').count(), black.matches('P').count());
+        //The side with fewer pawns leads, as that compresses better
+        let white_leads = black_pawns == 0 || (white_pawns > 0 && black_pawns >= white_pawns);
+        let dtz_path = wdl_path.with_extension("rtbz");
+        Some(TableInfo {
+            name: name.to_string(),
+            swapped: format!("{}v{}", black, white),
+            piece_count: white.len() + black.len(),
+            has_pawns: white_pawns + black_pawns > 0,
+            has_unique_pieces,
+            pawn_count: if white_leads {
+                [white_pawns, black_pawns]
+            } else {
+                [black_pawns, white_pawns]
+            },
+            dtz_path: dtz_path.exists().then_some(dtz_path),
+            wdl_path,
+            wdl: OnceLock::new(),
+            dtz: OnceLock::new(),
+        })
+    }
+
+    fn symmetric(&self) -> bool {
+        self.name == self.swapped
+    }
+}
+
+fn read_u16_le(data: &[u8], pos: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(data.get(pos..pos + 2)?.try_into().ok()?))
+}
+
+fn read_u32_le(data: &[u8], pos: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(data.get(pos..pos + 4)?.try_into().ok()?))
+}
+
+fn read_u32_be(data: &[u8], pos: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(data.get(pos..pos + 4)?.try_into().ok()?))
+}
+
+fn read_u64_be(data: &[u8], pos: usize) -> Option<u64> {
+    Some(u64::from_be_bytes(data.get(pos..pos + 8)?.try_into().ok()?))
+}
+
+/// The two symbols a symbol of the pairing tree expands to
+fn btree_pair(data: &[u8], btree: usize, sym: usize) -> Option<(usize, usize)> {
+    let lr = data.get(btree + 3 * sym..btree + 3 * sym + 3)?;
+    let left = (((lr[1] & 0xF) as usize) << 8) | lr[0] as usize;
+    let right = ((lr[2] as usize) << 4) | (lr[1] >> 4) as usize;
+    Some((left, right))
+}
+
+/// Groups the pieces that are encoded together. Pieces of the same kind form a group, except
+/// the leading group which without pawns holds three different pieces, or the two kings.
+fn set_groups(info: &TableInfo, d: &mut PairsData, order: [u8; 2], file: usize, ix: &Indices) {
+    let mut n = 0;
+    let mut first_len: i32 = if info.has_pawns {
+        0
+    } else if info.has_unique_pieces {
+        3
+    } else {
+        2
+    };
+    d.group_len[n] = 1;
+    for i in 1..info.piece_count {
+        first_len -= 1;
+        if first_len > 0 || d.pieces[i] == d.pieces[i - 1] {
+            d.group_len[n] += 1;
+        } else {
+            n += 1;
+            d.group_len[n] = 1;
+        }
+    }
+    n += 1;
+    d.group_len[n] = 0;
+
+    //The groups are encoded in the order the file gives, not necessarily the order above
+    let pawns_on_both_sides = info.has_pawns && info.pawn_count[1] > 0;
+    let mut next = if pawns_on_both_sides { 2 } else { 1 };
+    let mut free_squares = 64
+        - d.group_len[0]
+        - if pawns_on_both_sides {
+            d.group_len[1]
+        } else {
+            0
+        };
+    let mut idx: u64 = 1;
+    let mut k = 0;
+    while next < n || k == order[0] as usize || k == order[1] as usize {
+        if k == order[0] as usize {
+            d.group_idx[0] = idx;
+            idx *= if info.has_pawns {
+                ix.lead_pawns_size[d.group_len[0]][file]
+            } else if info.has_unique_pieces {
+                31332
+            } else {
+                462
+            };
+        } else if k == order[1] as usize {
+            d.group_idx[1] = idx;
+            idx *= ix.binomial[d.group_len[1]][48 - d.group_len[0]];
+        } else {
+            d.group_idx[next] = idx;
+            idx *= ix.binomial[d.group_len[next]][free_squares];
+            free_squares -= d.group_len[next];
+            next += 1;
+        }
+        k += 1;
+    }
+    d.group_idx[n] = idx;
+}
+
+/// Expands a symbol of the pairing tree to find how many values it stands for
+fn set_symlen(d: &mut PairsData, data: &[u8], sym: usize, visited: &mut [bool]) -> Option<u8> {
+    visited[sym] = true;
+    let (left, right) = btree_pair(data, d.btree, sym)?;
+    if right == 0xFFF {
+        return Some(0);
+    }
+    if !*visited.get(left)? {
+        d.symlen[left] = set_symlen(d, data, left, visited)?;
+    }
+    if !*visited.get(right)? {
+        d.symlen[right] = set_symlen(d, data, right, visited)?;
+    }
+    Some(d.symlen[left].wrapping_add(d.symlen[right]).wrapping_add(1))
+}
+
+/// Reads the sizes and the canonical Huffman code of one part, returning where it ends
+fn set_sizes(d: &mut PairsData, data: &[u8], mut pos: usize) -> Option<usize> {
+    d.flags = *data.get(pos)?;
+    pos += 1;
+    if d.flags & FLAG_SINGLE_VALUE != 0 {
+        //The single value every position has
+        d.min_sym_len = *data.get(pos)?;
+        return Some(pos + 1);
+    }
+    let groups = d.group_len.iter().position(|x| *x == 0)?;
+    let tb_size = d.group_idx[groups];
+    d.sizeof_block = 1u64 << *data.get(pos)?;
+    d.span = 1u64 << *data.get(pos + 1)?;
+    d.sparse_index_size = tb_size.div_ceil(d.span);
+    let padding = *data.get(pos + 2)? as u64;
+    d.num_blocks = read_u32_le(data, pos + 3)? as u64;
+    //Padded so the sparse index never points past the end
+    d.block_length_size = d.num_blocks + padding;
+    d.max_sym_len = *data.get(pos + 7)?;
+    d.min_sym_len = *data.get(pos + 8)?;
+    pos += 9;
+    d.lowest_sym = pos;
+    if d.min_sym_len == 0 || d.max_sym_len < d.min_sym_len {
+        return None;
+    }
+    let lengths = (d.max_sym_len - d.min_sym_len + 1) as usize;
+    d.base64 = vec![0; lengths];
+    //Longer symbols have lower values, so base64[i] is the lowest symbol of length i
+    //padded to 64 bits and the length of a symbol is found by comparing against them
+    for i in (0..lengths - 1).rev() {
+        let lowest = read_u16_le(data, d.lowest_sym + 2 * i)? as u64;
+        let next_lowest = read_u16_le(data, d.lowest_sym + 2 * (i + 1))? as u64;
+        d.base64[i] = (d.base64[i + 1] + lowest).checked_sub(next_lowest)? / 2;
+    }
+    for i in 0..lengths {
+        d.base64[i] = d.base64[i]
+            .checked_shl(64 - i as u32 - d.min_sym_len as u32)
+            .unwrap_or(0);
+    }
+    pos += lengths * 2;
+    let symbols = read_u16_le(data, pos)? as usize;
+    pos += 2;
+    d.btree = pos;
+    d.symlen = vec![0; symbols];
+    let mut visited = vec![false; symbols];
+    for sym in 0..symbols {
+        if !visited[sym] {
+            d.symlen[sym] = set_symlen(d, data, sym, &mut visited)?;
+        }
+    }
+    Some(pos + symbols * 3 + (symbols & 1))
+}
+
+/// Reads a table file and the layout of its parts
+fn load_table(info: &TableInfo, path: &Path, dtz: bool, ix: &Indices) -> Option<Table> {
+    let data = fs::read(path).ok()?;
+    let magic = if dtz { DTZ_MAGIC } else { WDL_MAGIC };
+    if data.get(0..4)? != magic {
+        return None;
+    }
+    let sides = if !dtz && !info.symmetric() { 2 } else { 1 };
+    let mut table = Table {
+        data: Vec::new(),
+        items: vec![vec![PairsData::default(); 4]; 2],
+        sides: if dtz { 1 } else { 2 },
+        map: 0,
+    };
+    let max_file = if info.has_pawns { 3 } else { 0 };
+    let pawns_on_both_sides = info.has_pawns && info.pawn_count[1] > 0;
+    //Skip the byte of flags after the magic
+    let mut pos = 5;
+    for file in 0..=max_file {
+        let first = *data.get(pos)?;
+        let second = if pawns_on_both_sides {
+            *data.get(pos + 1)?
+        } else {
+            0xFF
+        };
+        let orders = [[first & 0xF, second & 0xF], [first >> 4, second >> 4]];
+        pos += 1 + pawns_on_both_sides as usize;
+        for k in 0..info.piece_count {
+            let byte = *data.get(pos)?;
+            for i in 0..sides {
+                table.items[i][file].pieces[k] = if i == 1 { byte >> 4 } else { byte & 0xF };
+            }
+            pos += 1;
+        }
+        for (i, order) in orders.iter().enumerate().take(sides) {
+            set_groups(info, &mut table.items[i][file], *order, file, ix);
+        }
+    }
+    pos += pos & 1;
+    for file in 0..=max_file {
+        for i in 0..sides {
+            pos = set_sizes(&mut table.items[i][file], &data, pos)?;
+        }
+    }
+    if dtz {
+        table.map = pos;
+        for file in 0..=max_file {
+            let d = &mut table.items[0][file];
+            if d.flags & FLAG_MAPPED == 0 {
+                continue;
+            }
+            if d.flags & FLAG_WIDE != 0 {
+                pos += pos & 1;
+                for i in 0..4 {
+                    d.map_idx[i] = ((pos - table.map) / 2 + 1) as u16;
+                    pos += 2 * read_u16_le(&data, pos)? as usize + 2;
+                }
+            } else {
+                for i in 0..4 {
+                    d.map_idx[i] = (pos - table.map + 1) as u16;
+                    pos += *data.get(pos)? as usize + 1;
+                }
+            }
+        }
+        pos += pos & 1;
+    }
+    for file in 0..=max_file {
+        for i in 0..sides {
+            let d = &mut table.items[i][file];
+            d.sparse_index = pos;
+            pos += d.sparse_index_size as usize * 6;
+        }
+    }
+    for file in 0..=max_file {
+        for i in 0..sides {
+            let d = &mut table.items[i][file];
+            d.block_length = pos;
+            pos += d.block_length_size as usize * 2;
+        }
+    }
+    for file in 0..=max_file {
+        for i in 0..sides {
+            let d = &mut table.items[i][file];
+            pos = (pos + 0x3F) & !0x3F;
+            d.data = pos;
+            pos += (d.num_blocks * d.sizeof_block) as usize;
+        }
+    }
+    if pos > data.len() {
+        return None;
+    }
+    table.data = data;
+    Some(table)
+}
+
+/// The value stored at `idx`
+fn decompress_pairs(data: &[u8], d: &PairsData, idx: u64) -> Option<i32> {
+    if d.flags & FLAG_SINGLE_VALUE != 0 {
+        return Some(d.min_sym_len as i32);
+    }
+    //The sparse index points close to the block holding idx, then walk to the right one
+    let entry = d.sparse_index + 6 * (idx / d.span) as usize;
+    let mut block = read_u32_le(data, entry)? as usize;
+    let mut offset = read_u16_le(data, entry + 4)? as i64;
+    offset += (idx % d.span) as i64 - (d.span / 2) as i64;
+    let block_length = |block: usize| read_u16_le(data, d.block_length + 2 * block);
+    while offset < 0 {
+        block = block.checked_sub(1)?;
+        offset += block_length(block)? as i64 + 1;
+    }
+    while offset > block_length(block)? as i64 {
+        offset -= block_length(block)? as i64 + 1;
+        block += 1;
+    }
+
+    //Read symbols from the start of the block until the one covering offset
+    let mut ptr = d.data + block * d.sizeof_block as usize;
+    let mut buf64 = read_u64_be(data, ptr)?;
+    ptr += 8;
+    let mut buf64_size: i32 = 64;
+    let mut sym: usize;
+    loop {
+        let mut len = 0;
+        while buf64 < *d.base64.get(len)? {
+            len += 1;
+        }
+        sym = ((buf64 - d.base64[len]) >> (64 - len - d.min_sym_len as usize)) as usize;
+        sym += read_u16_le(data, d.lowest_sym + 2 * len)? as usize;
+        let count = *d.symlen.get(sym)? as i64 + 1;
+        if offset < count {
+            break;
+        }
+        offset -= count;
+        len += d.min_sym_len as usize;
+        buf64 = buf64.checked_shl(len as u32).unwrap_or(0);
+        buf64_size -= len as i32;
+        if buf64_size <= 32 {
+            buf64_size += 32;
+            buf64 |= (read_u32_be(data, ptr)? as u64) << (64 - buf64_size);
+            ptr += 4;
+        }
+    }
+
+    //Expand the symbol down to the single value at offset
+    while d.symlen[sym] != 0 {
+        let (left, right) = btree_pair(data, d.btree, sym)?;
+        let left_count = *d.symlen.get(left)? as i64 + 1;
+        if offset < left_count {
+            sym = left;
+        } else {
+            offset -= left_count;
+            sym = right;
+        }
+    }
+    btree_pair(data, d.btree, sym).map(|(value, _)| value as i32)
+}
+
+/// What a table lookup found
+enum TableResult {
+    Value(i32),
+    /// DTZ tables only store one side to move and this is the other one
+    ChangeStm,
+}
+
+/// The DTZ of the move before a capture or pawn move, which DTZ tables do not store
+fn dtz_before_zeroing(wdl: i32) -> i32 {
+    match wdl {
+        2 => 1,
+        1 => 101,
+        -1 => -101,
+        -2 => -1,
+        _ => 0,
+    }
+}
+
+/// The piece type numbers of the table files
+fn piece_type(kind: PieceKind) -> u8 {
+    match kind {
+        PieceKind::Pawn => 1,
+        PieceKind::Knight => 2,
+        PieceKind::Bishop => 3,
+        PieceKind::Rook => 4,
+        PieceKind::Queen => 5,
+        PieceKind::King => 6,
+    }
+}
+
+fn piece_count(state: &ChessState) -> usize {
+    state.board.count()
+}
+
+/// Syzygy tables found in the `SyzygyPath` directories. Files are only read once probed.
+pub struct Tablebases {
+    tables: Vec<TableInfo>,
+    /// Both orderings of every table's material to its index in `tables`
+    keys: HashMap<String, usize>,
+    indices: Indices,
+    /// Most pieces of any table, positions with more are never probed
+    pub max_pieces: usize,
+}
+
+impl Default for Tablebases {
+    fn default() -> Tablebases {
+        Tablebases::new("")
+    }
+}
+
+impl Tablebases {
+    /// Finds the tables in `paths`, a list of directories separated like `PATH`
+    pub fn new(paths: &str) -> Tablebases {
+        let mut tablebases = Tablebases {
+            tables: Vec::new(),
+            keys: HashMap::new(),
+            indices: Indices::new(),
+            max_pieces: 0,
+        };
+        if paths.trim().is_empty() || paths == "<empty>" {
+            return tablebases;
+        }
+        for dir in std::env::split_paths(paths) {
+            let Ok(entries) = fs::read_dir(&dir) else {
+                continue;
+            };
+            let mut files: Vec<PathBuf> =
+                entries.filter_map(|x| x.ok()).map(|x| x.path()).collect();
+            files.sort();
+            for path in files {
+                if path.extension().is_none_or(|x| x != "rtbw") {
+                    continue;
+                }
+                let Some(name) = path.file_stem().and_then(|x| x.to_str()) else {
+                    continue;
+                };
+                if tablebases.keys.contains_key(name) {
+                    continue;
+                }
+                let Some(info) = TableInfo::new(name, path.clone()) else {
+                    continue;
+                };
+                tablebases.max_pieces = tablebases.max_pieces.max(info.piece_count);
+                tablebases
+                    .keys
+                    .insert(info.name.clone(), tablebases.tables.len());
+                tablebases
+                    .keys
+                    .insert(info.swapped.clone(), tablebases.tables.len());
+                tablebases.tables.push(info);
+            }
+        }
+        tablebases
+    }
+
+    pub fn len(&self) -> usize {
+        self.tables.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tables.is_empty()
+    }
+
+    /// Whether the tables cover `state`. Tables know nothing of castling.
+    pub fn covers(&self, state: &ChessState) -> bool {
+        state.castling.is_empty() && piece_count(state) <= self.max_pieces
+    }
+
+    fn probe_table(&self, state: &ChessState, dtz: bool, wdl: i32) -> Option<TableResult> {
+        if piece_count(state) == 2 {
+            //Only the kings are left
+            return Some(TableResult::Value(0));
+        }
+        let white = state.material(Color::White);
+        let black = state.material(Color::Black);
+        let info = &self.tables[*self.keys.get(&format!("{}v{}", white, black))?];
+        let table = if dtz {
+            let path = info.dtz_path.as_ref()?;
+            info.dtz
+                .get_or_init(|| load_table(info, path, true, &self.indices))
+        } else {
+            info.wdl
+                .get_or_init(|| load_table(info, &info.wdl_path, false, &self.indices))
+        };
+        let table = table.as_ref()?;
+        self.probe_loaded(state, info, table, dtz, wdl, format!("{}v{}", white, black))
+    }
+
+    fn probe_loaded(
+        &self,
+        state: &ChessState,
+        info: &TableInfo,
+        table: &Table,
+        dtz: bool,
+        wdl: i32,
+        key: String,
+    ) -> Option<TableResult> {
+        let ix = &self.indices;
+        //Tables are stored with white as the stronger side, and symmetric ones only with
+        //white to move, so other positions are looked up with the colors swapped
+        let flip = (info.symmetric() && !state.turn.is_white()) || key != info.name;
+        let flip_color: u8 = if flip { 8 } else { 0 };
+        let flip_squares: usize = if flip { 56 } else { 0 };
+        let stm = (flip as usize) ^ (!state.turn.is_white() as usize);
+
+        let board_pieces: Vec<(usize, u8)> = state
+            .board
+            .pieces()
+            .map(|(sq, piece)| {
+                let color = if piece.color.is_white() { 0 } else { 8 };
+                (sq.index(), piece_type(piece.kind) | color)
+            })
+            .collect();
+        let mut squares = [0usize; TB_PIECES];
+        let mut pieces = [0u8; TB_PIECES];
+        let mut size = 0;
+        let mut lead_pawns = 0;
+        let mut file = 0;
+        let mut lead_pawn: u8 = 0xFF;
+        if info.has_pawns {
+            //Pawns come first and the first piece is a pawn of the leading color
+            lead_pawn = table.get(0, 0, true).pieces[0] ^ flip_color;
+            for (sq, piece) in board_pieces.iter() {
+                if *piece == lead_pawn {
+                    squares[size] = sq ^ flip_squares;
+                    size += 1;
+                }
+            }
+            lead_pawns = size;
+            //The leading pawn is the one closest to the edge and lowest on its file
+            let lead = (0..lead_pawns).max_by_key(|x| ix.map_pawns[squares[*x]])?;
+            squares.swap(0, lead);
+            file = (squares[0] % 8).min(7 - squares[0] % 8);
+        }
+        if dtz {
+            let flags = table.get(stm, file, info.has_pawns).flags;
+            //Symmetric pawnless tables hold both sides to move
+            let one_sided = info.has_pawns || !info.symmetric();
+            if one_sided && (flags & FLAG_STM) as usize != stm {
+                return Some(TableResult::ChangeStm);
+            }
+        }
+        for (sq, piece) in board_pieces.iter() {
+            if *piece != lead_pawn {
+                squares[size] = sq ^ flip_squares;
+                pieces[size] = piece ^ flip_color;
+                size += 1;
+            }
+        }
+        let d = table.get(stm, file, info.has_pawns);
+        //Put the pieces in the order the table encodes them
+        for i in lead_pawns..size - 1 {
+            for j in i + 1..size {
+                if d.pieces[i] == pieces[j] {
+                    pieces.swap(i, j);
+                    squares.swap(i, j);
+                    break;
+                }
+            }
+        }
+        //Mirror so the leading piece is on files a to d
+        if squares[0] % 8 > 3 {
+            for sq in squares.iter_mut().take(size) {
+                *sq ^= 7;
+            }
+        }
+
+        let mut idx: u64;
+        if info.has_pawns {
+            idx = ix.lead_pawn_idx[lead_pawns][squares[0]];
+            squares[1..lead_pawns].sort_by_key(|x| ix.map_pawns[*x]);
+            for (i, sq) in squares.iter().enumerate().take(lead_pawns).skip(1) {
+                idx += ix.binomial[i][ix.map_pawns[*sq]];
+            }
+        } else {
+            //Mirror so the leading piece is on ranks 1 to 4, then below the a1-h8 diagonal
+            if squares[0] / 8 > 3 {
+                for sq in squares.iter_mut().take(size) {
+                    *sq ^= 56;
+                }
+            }
+            for i in 0..d.group_len[0] {
+                if off_a1h8(squares[i]) == 0 {
+                    continue;
+                }
+                if off_a1h8(squares[i]) > 0 {
+                    for sq in squares.iter_mut().take(size).skip(i) {
+                        *sq = ((*sq >> 3) | (*sq << 3)) & 63;
+                    }
+                }
+                break;
+            }
+            if info.has_unique_pieces {
+                let (s0, s1, s2) = (squares[0], squares[1], squares[2]);
+                let adjust1 = (s1 > s0) as u64;
+                let adjust2 = (s2 > s0) as u64 + (s2 > s1) as u64;
+                let rank = |sq: usize| (sq / 8) as u64;
+                idx = if off_a1h8(s0) != 0 {
+                    (ix.map_a1d1d4[s0] as u64 * 63 + (s1 as u64 - adjust1)) * 62 + s2 as u64
+                        - adjust2
+                } else if off_a1h8(s1) != 0 {
+                    (6 * 63 + rank(s0) * 28 + ix.map_b1h1h7[s1]) * 62 + s2 as u64 - adjust2
+                } else if off_a1h8(s2) != 0 {
+                    6 * 63 * 62
+                        + 4 * 28 * 62
+                        + rank(s0) * 7 * 28
+                        + (rank(s1) - adjust1) * 28
+                        + ix.map_b1h1h7[s2]
+                } else {
+                    6 * 63 * 62
+                        + 4 * 28 * 62
+                        + 4 * 7 * 28
+                        + rank(s0) * 7 * 6
+                        + (rank(s1) - adjust1) * 6
+                        + (rank(s2) - adjust2)
+                };
+            } else {
+                idx = ix.map_kk[ix.map_a1d1d4[squares[0]]][squares[1]];
+            }
+        }
+
+        //The remaining groups, each as a combination of the squares the earlier groups left
+        idx *= d.group_idx[0];
+        let mut start = d.group_len[0];
+        let mut remaining_pawns = info.has_pawns && info.pawn_count[1] > 0;
+        let mut next = 1;
+        while d.group_len[next] != 0 {
+            let len = d.group_len[next];
+            squares[start..start + len].sort();
+            let mut n: u64 = 0;
+            for i in 0..len {
+                let sq = squares[start + i];
+                let adjust = squares[..start].iter().filter(|x| sq > **x).count();
+                n += ix.binomial[i + 1][sq - adjust - if remaining_pawns { 8 } else { 0 }];
+            }
+            remaining_pawns = false;
+            idx += n * d.group_idx[next];
+            start += len;
+            next += 1;
+        }
+
+        let value = decompress_pairs(&table.data, d, idx)?;
+        if !dtz {
+            return Some(TableResult::Value(value - 2));
+        }
+        //DTZ values may be mapped and stored in moves rather than plies
+        const WDL_MAP: [usize; 5] = [1, 3, 0, 2, 0];
+        let d = table.get(0, file, info.has_pawns);
+        let mut value = value as usize;
+        if d.flags & FLAG_MAPPED != 0 {
+            let idx = d.map_idx[WDL_MAP[(wdl + 2) as usize]] as usize;
+            value = if d.flags & FLAG_WIDE != 0 {
+                read_u16_le(&table.data, table.map + 2 * (idx + value))? as usize
+            } else {
+                *table.data.get(table.map + idx + value)? as usize
+            };
+        }
+        let mut value = value as i32;
+        if (wdl == 2 && d.flags & FLAG_WIN_PLIES == 0)
+            || (wdl == -2 && d.flags & FLAG_LOSS_PLIES == 0)
+            || wdl == 1
+            || wdl == -1
+        {
+            value *= 2;
+        }
+        Some(TableResult::Value(value + 1))
+    }
+
+    /// WDL as -2..2 and whether a capture, or with `check_zeroing` a pawn move, is best.
+    /// Tables store "don't care" values where a capture wins, so captures are searched.
+    fn search(&self, state: &ChessState, check_zeroing: bool) -> Option<(i32, bool)> {
+        let moves = state.get_all_possible_moves();
+        let mut searched = 0;
+        let mut best = -2;
+        for m in moves.iter() {
+            if !m.is_capture() && (!check_zeroing || m.piece != PieceKind::Pawn) {
+                continue;
+            }
+            searched += 1;
+            let value = -self.search(&state.do_move(m), false)?.0;
+            if value > best {
+                best = value;
+                if value >= 2 {
+                    return Some((value, true));
+                }
+            }
+        }
+        //With every move searched the table is not needed, and could be wrong about en passant
+        let no_more_moves = searched > 0 && searched == moves.len();
+        let value = if no_more_moves {
+            best
+        } else {
+            match self.probe_table(state, false, 0)? {
+                TableResult::Value(value) => value,
+                TableResult::ChangeStm => return None,
+            }
+        };
+        if best >= value {
+            return Some((best, best > 0 || no_more_moves));
+        }
+        Some((value, false))
+    }
+
+    pub fn probe_wdl(&self, state: &ChessState) -> Option<Wdl> {
+        self.search(state, false).map(|x| Wdl::from_value(x.0))
+    }
+
+    /// Distance to the next capture or pawn move in plies, positive when winning and
+    /// counted past 100 for wins and losses the fifty-move rule spoils
+    pub fn probe_dtz(&self, state: &ChessState) -> Option<i32> {
+        let (wdl, zeroing) = self.search(state, true)?;
+        if wdl == 0 {
+            return Some(0);
+        }
+        if zeroing {
+            return Some(dtz_before_zeroing(wdl));
+        }
+        match self.probe_table(state, true, wdl)? {
+            TableResult::Value(dtz) => {
+                let cursed = if wdl.abs() == 1 { 100 } else { 0 };
+                return Some((dtz + cursed) * wdl.signum());
+            }
+            TableResult::ChangeStm => {}
+        }
+        //The table has the other side to move, so look one ply ahead
+        let mut min_dtz = 0xFFFF;
+        for m in state.get_all_possible_moves().iter() {
+            let zeroing = m.is_capture() || m.piece == PieceKind::Pawn;
+            let next = state.do_move(m);
+            let mut dtz = if zeroing {
+                -dtz_before_zeroing(self.search(&next, false)?.0)
+            } else {
+                -self.probe_dtz(&next)?
+            };
+            if dtz == 1 && next.check() && next.is_terminal() {
+                min_dtz = 1;
+            }
+            if !zeroing {
+                dtz += dtz.signum();
+            }
+            if dtz < min_dtz && dtz.signum() == wdl.signum() {
+                min_dtz = dtz;
+            }
+        }
+        Some(if min_dtz == 0xFFFF { -1 } else { min_dtz })
+    }
+
+    /// The move keeping the best result in reach of the fifty-move rule, winning as fast
+    /// as DTZ allows or losing as slowly, with its WDL for the side to move
+    pub fn probe_root(&self, state: &ChessState) -> Option<(Move, Wdl)> {
+        let halfmoves = state.halfmoves as i32;
+        let mut best: Option<(i32, i32, Move)> = None;
+        for m in state.get_all_possible_moves() {
+            let next = state.do_move(&m);
+            let mut dtz = if next.halfmoves == 0 {
+                dtz_before_zeroing(-self.search(&next, false)?.0)
+            } else if next.halfmoves >= 100 {
+                0
+            } else {
+                let dtz = -self.probe_dtz(&next)?;
+                dtz + dtz.signum()
+            };
+            if dtz == 2 && next.check() && next.is_terminal() {
+                dtz = 1;
+            }
+            //Wins and losses that the fifty-move rule spoils rank closer to draws
+            let rank = if dtz > 0 {
+                if dtz + halfmoves <= 99 {
+                    MAX_DTZ
+                } else {
+                    MAX_DTZ - (dtz + halfmoves)
+                }
+            } else if dtz < 0 {
+                if -dtz * 2 + halfmoves < 100 {
+                    -MAX_DTZ
+                } else {
+                    -MAX_DTZ + (-dtz + halfmoves)
+                }
+            } else {
+                0
+            };
+            if best.as_ref().is_none_or(|x| (rank, -dtz) > (x.0, -x.1)) {
+                best = Some((rank, dtz, m));
+            }
+        }
+        let (rank, _, m) = best?;
+        let wdl = match rank {
+            MAX_DTZ => Wdl::Win,
+            x if x > 0 => Wdl::CursedWin,
+            0 => Wdl::Draw,
+            x if x > -MAX_DTZ => Wdl::BlessedLoss,
+            _ => Wdl::Loss,
+        };
+        Some((m, wdl))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::endgame::KNOWN_WIN;
+    use crate::types::Piece;
+
+    const TEST_DATA: &str = "test-data/syzygy";
+
+    #[test]
+    fn indices() {
+        let ix = Indices::new();
+        //Every square pair of the a1-d1-d4 triangle and the board, less touching kings
+        let kk = ix.map_kk.iter().flatten().max().unwrap() + 1;
+        assert_eq!(kk, 462);
+        assert_eq!(ix.binomial[2][4], 6);
+        assert_eq!(ix.map_pawns[8], 47);
+        assert_eq!(ix.map_pawns[15], 46);
+    }
+
+    #[test]
+    fn no_tables() {
+        let tablebases = Tablebases::new("");
+        assert!(tablebases.is_empty());
+        assert!(!tablebases.covers(&ChessState::new_board()));
+    }
+
+    #[test]
+    fn wdl() {
+        let tablebases = Tablebases::new(TEST_DATA);
+        let wdl = |fen: &str| tablebases.probe_wdl(&ChessState::from_fen(fen));
+        assert_eq!(wdl("8/8/8/8/8/8/8/K1k5 w - - 0 1"), Some(Wdl::Draw));
+        assert_eq!(wdl("8/8/8/4k3/8/8/8/KQ6 w - - 0 1"), Some(Wdl::Win));
+        assert_eq!(wdl("8/8/8/4k3/8/8/8/KQ6 b - - 0 1"), Some(Wdl::Loss));
+        assert_eq!(wdl("8/8/8/4k3/8/8/8/KR6 b - - 0 1"), Some(Wdl::Loss));
+        assert_eq!(wdl("8/8/8/4k3/8/8/8/KN6 w - - 0 1"), Some(Wdl::Draw));
+        //The king in front of the pawn wins, a rook pawn or a king left behind draws
+        assert_eq!(wdl("4k3/8/4K3/4P3/8/8/8/8 b - - 0 1"), Some(Wdl::Loss));
+        assert_eq!(wdl("k7/8/8/8/8/8/P7/K7 w - - 0 1"), Some(Wdl::Draw));
+        assert_eq!(wdl("8/8/8/4k3/8/8/4P3/K7 w - - 0 1"), Some(Wdl::Draw));
+        //A black pawn is looked up with the colors swapped
+        assert_eq!(wdl("8/8/8/8/4p3/4k3/8/4K3 b - - 0 1"), Some(Wdl::Win));
+        //Queen against rook wins, unless Rb2+ Kxb2 stalemates
+        assert_eq!(wdl("8/7r/8/4k3/8/8/8/K2Q4 w - - 0 1"), Some(Wdl::Win));
+        assert_eq!(wdl("8/7r/8/4k3/8/8/8/K2Q4 b - - 0 1"), Some(Wdl::Loss));
+        assert_eq!(wdl("8/8/8/8/8/8/2KQ4/kr6 w - - 0 1"), Some(Wdl::Win));
+        assert_eq!(wdl("8/8/8/8/8/8/2KQ4/kr6 b - - 0 1"), Some(Wdl::Draw));
+        //No table for two rooks
+        assert_eq!(wdl("8/8/8/4k3/8/8/7r/KR6 w - - 0 1"), None);
+    }
+
+    #[test]
+    fn dtz() {
+        let tablebases = Tablebases::new(TEST_DATA);
+        let dtz = |fen: &str| tablebases.probe_dtz(&ChessState::from_fen(fen));
+        assert!(dtz("8/8/8/4k3/8/8/8/KQ6 w - - 0 1").is_some_and(|x| x > 0));
+        assert!(dtz("8/8/8/4k3/8/8/8/KQ6 b - - 0 1").is_some_and(|x| x < 0));
+        //Without captures or pawn moves it is the distance to mate, here mate in three
+        assert_eq!(dtz("8/8/8/8/8/8/8/R3K2k w - - 0 1"), Some(5));
+        assert_eq!(dtz("8/8/8/8/4k3/8/8/K6R b - - 0 1"), Some(-30));
+        //The pawn runs, so the next move zeroes
+        assert_eq!(dtz("8/8/8/8/8/8/P7/K5k1 w - - 0 1"), Some(1));
+        assert_eq!(dtz("8/8/8/8/8/8/P7/K5k1 b - - 0 1"), Some(-2));
+        assert_eq!(dtz("k7/8/8/8/8/8/P7/K7 w - - 0 1"), Some(0));
+        //KQvKR only has a WDL file
+        assert_eq!(dtz("8/7r/8/4k3/8/8/8/K2Q4 w - - 0 1"), None);
+
+        let state = ChessState::from_fen("8/8/8/8/8/8/8/R3K2k w - - 0 1");
+        let (m, wdl) = tablebases.probe_root(&state).unwrap();
+        assert_eq!((m.to_string().as_str(), wdl), ("Ke1-f1", Wdl::Win));
+        //Mate in one is a DTZ of one
+        let state = ChessState::from_fen("k7/8/1K6/8/8/8/8/7Q w - - 0 1");
+        let (m, wdl) = tablebases.probe_root(&state).unwrap();
+        assert_eq!(wdl, Wdl::Win);
+        assert!(state.do_move(&m).is_terminal());
+    }
+
+    /// The legal position with `pieces` on their square indices, or None
+    fn place(pieces: &[(char, usize)], turn: char) -> Option<ChessState> {
+        let mut board = ['.'; 64];
+        for &(piece, sq) in pieces {
+            let pawn_rank = piece.eq_ignore_ascii_case(&'p') && !(8..56).contains(&sq);
+            if board[sq] != '.' || pawn_rank {
+                return None;
+            }
+            board[sq] = piece;
+        }
+        let ranks: Vec<String> = board
+            .chunks(8)
+            .rev()
+            .map(|rank| {
+                let mut text = String::new();
+                for run in rank.chunk_by(|a, b| (*a == '.') == (*b == '.')) {
+                    if run[0] == '.' {
+                        text.push_str(&run.len().to_string());
+                    } else {
+                        text.extend(run);
+                    }
+                }
+                text
+            })
+            .collect();
+        let state = ChessState::from_fen(&format!("{} {} - - 0 1", ranks.join("/"), turn));
+        let king = state.board.find(Piece::new(PieceKind::King, !state.turn))?;
+        (!state.is_square_attacked(king, state.turn)).then_some(state)
+    }
+
+    #[test]
+    fn known_values() {
+        let tablebases = Tablebases::new(TEST_DATA);
+        //Without captures or pawn moves DTZ is the distance to mate. These are longest
+        //mates, 10 moves with a queen and 16 with a rook.
+        let dtz = |fen: &str| tablebases.probe_dtz(&ChessState::from_fen(fen));
+        assert_eq!(dtz("8/8/8/5k2/8/8/1Q6/K7 w - - 0 1"), Some(19));
+        assert_eq!(dtz("8/8/8/8/8/3k4/2R5/1K6 w - - 0 1"), Some(31));
+        //KPvK agrees with the KPK bitbase, with a pawn on a5 and on e4
+        for pawn in [32, 28] {
+            for king in 0..64 {
+                for other in 0..64 {
+                    for turn in ['w', 'b'] {
+                        let pieces = [('K', king), ('k', other), ('P', pawn)];
+                        let Some(state) = place(&pieces, turn) else {
+                            continue;
+                        };
+                        if state.is_terminal() {
+                            continue;
+                        }
+                        let wdl = tablebases.probe_wdl(&state).unwrap();
+                        let white_wins = wdl == if turn == 'w' { Wdl::Win } else { Wdl::Loss };
+                        let bitbase = state.endgame_eval().unwrap() >= KNOWN_WIN;
+                        assert_eq!(white_wins, bitbase, "{}", state.to_fen());
+                    }
+                }
+            }
+        }
+        //Classic positions: the opposition, the key squares and the rook pawn
+        let wdl = |fen: &str| tablebases.probe_wdl(&ChessState::from_fen(fen));
+        assert_eq!(wdl("8/4k3/8/4K3/4P3/8/8/8 w - - 0 1"), Some(Wdl::Draw));
+        assert_eq!(wdl("8/4k3/8/4K3/4P3/8/8/8 b - - 0 1"), Some(Wdl::Loss));
+        assert_eq!(wdl("4k3/8/3K4/4P3/8/8/8/8 w - - 0 1"), Some(Wdl::Win));
+        assert_eq!(wdl("8/8/8/4k3/8/4K3/4P3/8 w - - 0 1"), Some(Wdl::Draw));
+        assert_eq!(wdl("8/8/8/4k3/8/4K3/4P3/8 b - - 0 1"), Some(Wdl::Loss));
+        assert_eq!(wdl("7k/8/6K1/7P/8/8/8/8 w - - 0 1"), Some(Wdl::Draw));
+        //The square of the pawn: inside it the king catches the pawn, outside it does not
+        assert_eq!(wdl("8/8/8/5P2/1k6/8/8/K7 b - - 0 1"), Some(Wdl::Draw));
+        assert_eq!(wdl("8/8/8/5P2/1k6/8/8/K7 w - - 0 1"), Some(Wdl::Win));
+    }
+}
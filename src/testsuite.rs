@@ -0,0 +1,265 @@
+use crate::chessbot::{MinimaxBot, PvLine, SearchControl};
+use crate::epd::{self, EpdRecord};
+use crate::eval;
+use crate::moves::Move;
+use crate::translator;
+use std::sync::Arc;
+use std::time::Duration;
+
+const USAGE: &str = "usage: chess-rs testsuite FILE.epd [--depth N] [--movetime MS] \
+[--threads N] [--hash MB]
+Positions are checked against their bm, am and dm operations, and STS points are taken from c0";
+
+/// How each position of a suite is searched
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SuiteOptions {
+    pub depth: u8,
+    pub movetime: Option<Duration>,
+    pub threads: usize,
+    /// Transposition table size in megabytes
+    pub hash: usize,
+}
+
+impl Default for SuiteOptions {
+    fn default() -> SuiteOptions {
+        SuiteOptions {
+            depth: 4,
+            movetime: None,
+            threads: 1,
+            hash: 16,
+        }
+    }
+}
+
+/// How the search did on one position
+#[derive(Debug, Clone, PartialEq)]
+pub struct PositionResult {
+    pub id: String,
+    /// The move the search settled on, `None` when there was no legal move
+    pub played: Option<Move>,
+    /// In SAN
+    pub played_san: String,
+    pub passed: bool,
+    /// When the search found the move it kept until the end, if it passed
+    pub time_to_solve: Option<Duration>,
+    /// The STS points for the move and the most the position gives, when `c0` has them
+    pub points: Option<(u32, u32)>,
+    pub elapsed: Duration,
+}
+
+/// Whether `line` is correct by the record's best moves, avoid moves and mate distance
+fn solves(record: &EpdRecord, line: &PvLine) -> bool {
+    let Some(m) = line.moves.first() else {
+        return false;
+    };
+    let best = record.moves("bm");
+    if !best.is_empty() && !best.contains(m) {
+        return false;
+    }
+    if record.moves("am").contains(m) {
+        return false;
+    }
+    if let Some(moves) = record.mate_in() {
+        return eval::mate_in(line.score, record.state.turn)
+            .is_some_and(|x| x > 0 && x as u32 <= moves);
+    }
+    true
+}
+
+/// Searches the position of `record` and checks the move against it
+pub fn run_position(record: &EpdRecord, options: &SuiteOptions) -> PositionResult {
+    let state = &record.state;
+    let control = Arc::new(SearchControl::new(options.movetime, false));
+    let mut bot = MinimaxBot::new(state.turn, options.depth, control.clone(), options.hash);
+    bot.threads = options.threads.max(1);
+    let mut solved_at: Option<Duration> = None;
+    let lines = bot.search(state, 1, &mut |_, lines| {
+        let solved = lines.first().is_some_and(|x| solves(record, x));
+        solved_at = match solved_at {
+            Some(at) if solved => Some(at),
+            _ if solved => Some(control.elapsed()),
+            _ => None,
+        };
+    });
+    let line = lines.first();
+    let played = line.and_then(|x| x.moves.first().copied());
+    let passed = line.is_some_and(|x| solves(record, x));
+    let move_points = record.move_points();
+    let points = move_points.iter().map(|x| x.1).max().map(|most| {
+        let earned = move_points
+            .iter()
+            .find(|x| Some(x.0) == played)
+            .map_or(0, |x| x.1);
+        (earned, most)
+    });
+    PositionResult {
+        id: record.id().unwrap_or("?").to_string(),
+        played,
+        played_san: played.map_or("none".to_string(), |x| translator::to_san(state, &x)),
+        passed,
+        time_to_solve: if passed { solved_at } else { None },
+        points,
+        elapsed: control.elapsed(),
+    }
+}
+
+/// What the record expects, as in `bm Qg6 am Qh5 dm 3`
+fn expectation(record: &EpdRecord) -> String {
+    let mut parts: Vec<String> = Vec::new();
+    for opcode in ["bm", "am", "dm"] {
+        if let Some(operands) = record.operation(opcode) {
+            parts.push(format!("{} {}", opcode, operands.join(" ")));
+        }
+    }
+    parts.join(", ")
+}
+
+/// The solve rate, average time to solve and STS points of a finished suite
+pub fn summary(results: &[PositionResult]) -> String {
+    let solved: Vec<&PositionResult> = results.iter().filter(|x| x.passed).collect();
+    let mut text = format!(
+        "Solved {} of {} ({:.1}%)",
+        solved.len(),
+        results.len(),
+        solved.len() as f64 * 100.0 / results.len().max(1) as f64
+    );
+    let times: Vec<Duration> = solved.iter().filter_map(|x| x.time_to_solve).collect();
+    if !times.is_empty() {
+        let average = times.iter().sum::<Duration>() / times.len() as u32;
+        text += &format!(", average time to solve {:.3} s", average.as_secs_f64());
+    }
+    let (earned, most) = results
+        .iter()
+        .filter_map(|x| x.points)
+        .fold((0, 0), |(earned, most), x| (earned + x.0, most + x.1));
+    if most > 0 {
+        text += &format!(
+            "\nSTS score {} of {} ({:.1}%)",
+            earned,
+            most,
+            earned as f64 * 100.0 / most as f64
+        );
+    }
+    text
+}
+
+/// Runs `chess-rs testsuite` with the arguments after the subcommand
+pub fn run(args: &[String]) -> Result<(), String> {
+    let mut options = SuiteOptions::default();
+    let mut depth: Option<u8> = None;
+    let mut path: Option<String> = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            let text = args
+                .next()
+                .ok_or_else(|| format!("{} needs a value\n{}", name, USAGE))?;
+            text.parse::<u64>()
+                .ok()
+                .filter(|x| *x > 0)
+                .ok_or_else(|| format!("{} has to be a positive number, got {}", name, text))
+        };
+        match arg.as_str() {
+            "--depth" => depth = Some(value(arg)?.min(64) as u8),
+            "--movetime" => options.movetime = Some(Duration::from_millis(value(arg)?)),
+            "--threads" => options.threads = value(arg)? as usize,
+            "--hash" => options.hash = value(arg)? as usize,
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return Ok(());
+            }
+            x if !x.starts_with('-') && path.is_none() => path = Some(x.to_string()),
+            x => return Err(format!("Unknown option {}\n{}", x, USAGE)),
+        }
+    }
+    //With a time limit the search goes as deep as the time allows
+    options.depth = match (depth, options.movetime) {
+        (Some(depth), _) => depth,
+        (None, Some(_)) => 64,
+        (None, None) => options.depth,
+    };
+    let path = path.ok_or_else(|| USAGE.to_string())?;
+    let records =
+        epd::read_file(&path).map_err(|err| format!("Could not read {}: {}", path, err))?;
+    if records.is_empty() {
+        return Err(format!("No positions in {}", path));
+    }
+    let mut results: Vec<PositionResult> = Vec::new();
+    for record in records.iter() {
+        let result = run_position(record, &options);
+        let points = match result.points {
+            Some((earned, most)) => format!(" {}/{} points", earned, most),
+            None => String::new(),
+        };
+        println!(
+            "{} {} {} ({}){} {:.3} s",
+            result.id,
+            if result.passed { "pass" } else { "FAIL" },
+            result.played_san,
+            expectation(record),
+            points,
+            result.time_to_solve.unwrap_or(result.elapsed).as_secs_f64()
+        );
+        results.push(result);
+    }
+    println!("{}", summary(&results));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::epd::parse_line;
+
+    #[test]
+    fn positions() {
+        let options = SuiteOptions {
+            depth: 3,
+            ..SuiteOptions::default()
+        };
+        let mate = parse_line("6k1/5ppp/8/8/8/8/8/R5K1 w - - dm 1; bm Ra8#; id \"mate\";").unwrap();
+        let result = run_position(&mate, &options);
+        assert!(result.passed);
+        assert_eq!(result.played_san, "Ra8#");
+        assert!(result.time_to_solve.is_some());
+
+        //Taking the rook loses the queen to the pawn. Both checks keep the queen, and the
+        //points are those of the move played rather than the most the position gives.
+        let avoid =
+            parse_line("4k3/8/8/2p5/1r6/8/8/1Q2K3 w - - am Qxb4; c0 \"Qe4+=10, Qg6+=6, Qxb4=0\";")
+                .unwrap();
+        let result = run_position(&avoid, &options);
+        assert!(result.passed);
+        assert_eq!(result.played_san, "Qg6+");
+        assert_eq!(result.points, Some((6, 10)));
+
+        let wrong = parse_line("6k1/5ppp/8/8/8/8/8/R5K1 w - - bm Kf1;").unwrap();
+        assert!(!run_position(&wrong, &options).passed);
+
+        let results = [
+            run_position(&mate, &options),
+            run_position(&wrong, &options),
+        ];
+        assert!(summary(&results).starts_with("Solved 1 of 2 (50.0%), average time to solve"));
+    }
+
+    #[test]
+    fn mate_distance() {
+        //Mate in two, 1. Kb6 Kb8 2. Rh8#, with the line cut short by a table hit
+        let record =
+            |dm: u32| parse_line(&format!("k7/8/2K5/8/8/8/8/7R w - - dm {};", dm)).unwrap();
+        let state = &record(2).state;
+        let line = |score: i32| PvLine {
+            moves: vec![state.parse_lan("Kc6-b6").unwrap()],
+            score,
+        };
+        assert!(solves(&record(2), &line(eval::MATE - 3)));
+        assert!(solves(&record(3), &line(eval::MATE - 3)));
+        assert!(!solves(&record(1), &line(eval::MATE - 3)));
+        //A longer mate or none at all fails, however short the line
+        assert!(!solves(&record(2), &line(eval::MATE - 5)));
+        assert!(!solves(&record(2), &line(900)));
+        //Mating the wrong side
+        assert!(!solves(&record(2), &line(3 - eval::MATE)));
+    }
+}
@@ -0,0 +1,47 @@
+use std::time::Duration;
+
+/// Moves assumed to be left when the GUI does not say how many there are until the next time control
+const DEFAULT_MOVES_TO_GO: u64 = 30;
+
+/// How long to think about one move with `time_left` on the clock, all in milliseconds.
+/// `overhead` is held back for the time lost talking to the GUI. With the opponent's clock,
+/// half of the lead over it is spread over the moves to go, or half of the deficit made up,
+/// but never by thinking less than half as long as without it.
+pub fn allocate(
+    time_left: u64,
+    increment: u64,
+    moves_to_go: Option<u32>,
+    opponent_time: Option<u64>,
+    overhead: u64,
+) -> Duration {
+    let moves = moves_to_go.map_or(DEFAULT_MOVES_TO_GO, |x| x.max(1) as u64);
+    let mut budget = time_left / moves + increment * 3 / 4;
+    if let Some(opponent) = opponent_time {
+        let lead = (time_left as i64 - opponent as i64) / (2 * moves as i64);
+        budget = (budget as i64 + lead).max(budget as i64 / 2) as u64;
+    }
+    //Never plan to use more than is on the clock
+    let millis = budget
+        .saturating_sub(overhead)
+        .min(time_left.saturating_sub(overhead))
+        .max(1);
+    Duration::from_millis(millis)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allocation() {
+        let millis = |x: Duration| x.as_millis() as u64;
+        assert_eq!(millis(allocate(60000, 0, None, None, 0)), 2000);
+        assert_eq!(millis(allocate(60000, 1000, Some(10), None, 50)), 6700);
+        assert_eq!(millis(allocate(100, 0, Some(1), None, 200)), 1);
+        //The same clocks change nothing, a lead is spent and a deficit made up
+        assert_eq!(millis(allocate(60000, 0, None, Some(60000), 0)), 2000);
+        assert_eq!(millis(allocate(60000, 0, None, Some(30000), 0)), 2500);
+        assert_eq!(millis(allocate(60000, 0, None, Some(90000), 0)), 1500);
+        assert_eq!(millis(allocate(60000, 0, None, Some(600000), 0)), 1000);
+    }
+}
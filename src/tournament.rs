@@ -0,0 +1,595 @@
+use crate::chess_init::ChessState;
+use crate::elo::{Pentanomial, Score, Sprt, SprtResult};
+use crate::game::{Game, Outcome};
+use crate::pgn::PgnGame;
+use crate::play::TimeControl;
+use crate::selfplay::{self, Adjudication, Adjudicator};
+use crate::translator;
+use crate::types::Color;
+use crate::uci::GoParams;
+use crate::uci_client::UciEngine;
+use std::collections::HashMap;
+use std::io;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+const USAGE: &str = "usage: chess-rs tournament --engine SPEC --engine SPEC \
+[--tc SECONDS+INCREMENT] [--movetime MS] [--depth N] [--nodes N] [--games N] \
+[--sprt elo0=0,elo1=5,alpha=0.05,beta=0.05] [--openings FILE.epd|FILE.pgn] [--concurrency N] \
+[--pgn OUT.pgn] [--time-margin MS] [--max-moves N] [--resign-score CP] [--resign-moves N] \
+[--draw-score CP] [--draw-moves N] [--draw-after N]
+SPEC is a comma separated list of cmd=PATH, name=NAME, arg=ARGUMENT and option.NAME=VALUE, \
+where arg and option can be given more than once";
+
+/// How to start an engine and set it up
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EngineCommand {
+    /// The name in results and PGN, by default the one the engine gives
+    pub name: Option<String>,
+    pub command: String,
+    pub args: Vec<String>,
+    /// Sent with `setoption` once the engine has started
+    pub options: Vec<(String, String)>,
+}
+
+impl FromStr for EngineCommand {
+    type Err = String;
+
+    /// Reads `cmd=./engine,name=new,arg=--uci,option.Hash=64`
+    fn from_str(spec: &str) -> Result<EngineCommand, String> {
+        let mut engine = EngineCommand::default();
+        for pair in spec.split(',').filter(|x| !x.trim().is_empty()) {
+            let Some((key, value)) = pair.split_once('=') else {
+                return Err(format!("Expected key=value, got {}", pair));
+            };
+            let (key, value) = (key.trim(), value.trim().to_string());
+            match key {
+                "cmd" => engine.command = value,
+                "name" => engine.name = Some(value),
+                "arg" => engine.args.push(value),
+                _ => match key.strip_prefix("option.") {
+                    Some(option) => engine.options.push((option.to_string(), value)),
+                    None => return Err(format!("Unknown engine setting {}", key)),
+                },
+            }
+        }
+        if engine.command.is_empty() {
+            return Err(format!("No cmd in engine {}", spec));
+        }
+        Ok(engine)
+    }
+}
+
+impl EngineCommand {
+    /// Starts the engine with its options set and waits until it is ready
+    pub fn start(&self) -> io::Result<UciEngine> {
+        let mut engine = UciEngine::spawn(&self.command, &self.args)?;
+        for (name, value) in self.options.iter() {
+            engine.set_option(name, Some(value))?;
+        }
+        engine.is_ready()?;
+        Ok(engine)
+    }
+
+    pub fn display_name(&self) -> &str {
+        self.name.as_deref().unwrap_or(&self.command)
+    }
+}
+
+/// What each `go` is limited by. With a time control every game has its own clocks.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct SearchLimits {
+    pub time_control: Option<TimeControl>,
+    pub movetime: Option<Duration>,
+    pub depth: Option<u8>,
+    pub nodes: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TournamentOptions {
+    pub engines: [EngineCommand; 2],
+    pub limits: SearchLimits,
+    /// Games to play at most, rounded up to whole pairs. `None` plays until the SPRT decides.
+    pub games: Option<usize>,
+    pub sprt: Option<Sprt>,
+    /// FENs to start from, each played by a pair of games with colours swapped
+    pub openings: Vec<String>,
+    /// Pairs played at the same time, each by its own two engine processes
+    pub concurrency: usize,
+    pub adjudication: Adjudication,
+    /// How far an engine may go over its clock before it loses on time
+    pub time_margin: Duration,
+}
+
+/// A finished game of a tournament
+pub struct TournamentGame {
+    /// Counting from 0, the games of pair `n` being rounds `2n` and `2n + 1`
+    pub round: usize,
+    /// Whether the first engine had white
+    pub first_white: bool,
+    pub outcome: Outcome,
+    pub pgn: PgnGame,
+}
+
+/// The results so far, from the first engine's point of view
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Standings {
+    pub score: Score,
+    pub pentanomial: Pentanomial,
+    /// The log likelihood ratio of the SPRT and what it has decided
+    pub sprt: Option<(f64, SprtResult)>,
+}
+
+/// How a game between two engine processes ended
+pub struct EngineGame {
+    pub outcome: Outcome,
+    pub game: Game,
+    /// Why the game ended, in words
+    pub comment: String,
+    /// The side whose engine crashed or stopped answering, which has to be restarted
+    pub failed: Option<Color>,
+}
+
+/// Plays a game from `opening` between `engines`, white first. A side loses when its engine
+/// fails, plays an illegal move or runs out of time.
+pub fn play_engine_game(
+    mut engines: [&mut UciEngine; 2],
+    opening: &str,
+    limits: &SearchLimits,
+    adjudication: &Adjudication,
+    time_margin: Duration,
+) -> EngineGame {
+    let mut game = Game::new(ChessState::from_fen(opening));
+    let fen = game.start.to_fen();
+    let mut adjudicator = Adjudicator::new(*adjudication);
+    let mut clocks = [limits.time_control.map_or(Duration::ZERO, |x| x.base); 2];
+    let mut moves: Vec<String> = Vec::new();
+    let lost = |game: Game, loser: Color, comment: String, failed: bool| EngineGame {
+        outcome: Outcome::Adjudication(Some(!loser)),
+        game,
+        comment,
+        failed: if failed { Some(loser) } else { None },
+    };
+    for (idx, engine) in engines.iter_mut().enumerate() {
+        if let Err(err) = engine.new_game() {
+            let loser = if idx == 0 { Color::White } else { Color::Black };
+            return lost(game, loser, format!("{} failed: {}", loser, err), true);
+        }
+    }
+    loop {
+        if let Some(outcome) = game.outcome() {
+            return EngineGame {
+                outcome,
+                game,
+                comment: outcome.to_string(),
+                failed: None,
+            };
+        }
+        let state = game.state().copy();
+        if adjudicator.out_of_moves(&state) {
+            let outcome = Outcome::Adjudication(None);
+            return EngineGame {
+                outcome,
+                game,
+                comment: format!("{} at the move limit", outcome),
+                failed: None,
+            };
+        }
+        let side = if state.turn.is_white() { 0 } else { 1 };
+        let millis = |x: Duration| x.as_millis() as u64;
+        let mut params = GoParams {
+            depth: limits.depth,
+            nodes: limits.nodes,
+            movetime: limits.movetime.map(millis),
+            ..GoParams::default()
+        };
+        let mut timeout = limits.movetime;
+        if let Some(time_control) = limits.time_control {
+            params.wtime = Some(millis(clocks[0]));
+            params.btime = Some(millis(clocks[1]));
+            params.winc = Some(millis(time_control.increment));
+            params.binc = Some(millis(time_control.increment));
+            timeout = Some(clocks[side]);
+        }
+        let engine = &mut engines[side];
+        let result = engine
+            .position(Some(&fen), &moves)
+            .and_then(|_| engine.go(&params, timeout.map(|x| x + time_margin)));
+        let result = match result {
+            Ok(result) => result,
+            Err(err) => {
+                return lost(
+                    game,
+                    state.turn,
+                    format!("{} failed: {}", state.turn, err),
+                    true,
+                )
+            }
+        };
+        if let Some(time_control) = limits.time_control {
+            if result.elapsed > clocks[side] + time_margin {
+                let outcome = Outcome::Time(!state.turn);
+                return EngineGame {
+                    outcome,
+                    game,
+                    comment: outcome.to_string(),
+                    failed: None,
+                };
+            }
+            clocks[side] = clocks[side].saturating_sub(result.elapsed) + time_control.increment;
+        }
+        let Some(m) = translator::uci_to_lan(&state, &result.best)
+            .filter(|x| state.get_all_possible_moves().contains(x))
+        else {
+            let comment = format!("{} played the illegal move {}", state.turn, result.best);
+            return lost(game, state.turn, comment, false);
+        };
+        let score = result
+            .info
+            .and_then(|x| x.score)
+            .map(|x| x.white_relative(state.turn.is_white()));
+        if let Some(outcome) = score.and_then(|x| adjudicator.update(&state, x)) {
+            return EngineGame {
+                outcome,
+                game,
+                comment: outcome.to_string(),
+                failed: None,
+            };
+        }
+        moves.push(result.best);
+        game.play(m);
+    }
+}
+
+enum Event {
+    Game(TournamentGame),
+    Failed(String),
+}
+
+/// Plays pairs of games on `options.concurrency` threads until the games are played or the
+/// SPRT decides, handing every finished game and the standings so far to `report`
+pub fn run_tournament(
+    options: &TournamentOptions,
+    report: &mut dyn FnMut(&TournamentGame, &Standings),
+) -> Result<Standings, String> {
+    let mut standings = Standings::default();
+    if options.openings.is_empty() {
+        return Ok(standings);
+    }
+    let pairs = match (options.games, options.sprt) {
+        (Some(games), _) => games.div_ceil(2),
+        (None, Some(_)) => usize::MAX,
+        (None, None) => 1,
+    };
+    let next = AtomicUsize::new(0);
+    let stop = AtomicBool::new(false);
+    let (sender, receiver) = mpsc::channel::<Event>();
+    let mut error: Option<String> = None;
+    thread::scope(|scope| {
+        for _ in 0..options.concurrency.max(1) {
+            let sender = sender.clone();
+            let (next, stop) = (&next, &stop);
+            scope.spawn(move || {
+                let mut engines: [Option<UciEngine>; 2] = [None, None];
+                while !stop.load(Ordering::SeqCst) {
+                    let pair = next.fetch_add(1, Ordering::SeqCst);
+                    if pair >= pairs {
+                        break;
+                    }
+                    let opening = &options.openings[pair % options.openings.len()];
+                    for first_white in [true, false] {
+                        for (engine, command) in engines.iter_mut().zip(options.engines.iter()) {
+                            if engine.is_none() {
+                                match command.start() {
+                                    Ok(started) => *engine = Some(started),
+                                    Err(err) => {
+                                        let message = format!(
+                                            "Could not start {}: {}",
+                                            command.display_name(),
+                                            err
+                                        );
+                                        let _ = sender.send(Event::Failed(message));
+                                        return;
+                                    }
+                                }
+                            }
+                        }
+                        let [first, second] = &mut engines;
+                        let (first, second) = (first.as_mut().unwrap(), second.as_mut().unwrap());
+                        let [first_name, second_name] =
+                            [0, 1].map(|x| options.engines[x].display_name());
+                        let (players, names) = if first_white {
+                            ([first, second], [first_name, second_name])
+                        } else {
+                            ([second, first], [second_name, first_name])
+                        };
+                        let played = play_engine_game(
+                            players,
+                            opening,
+                            &options.limits,
+                            &options.adjudication,
+                            options.time_margin,
+                        );
+                        //A crashed engine is started again for the next game
+                        if let Some(color) = played.failed {
+                            let first_failed = color.is_white() == first_white;
+                            engines[if first_failed { 0 } else { 1 }] = None;
+                        }
+                        let round = pair * 2 + if first_white { 0 } else { 1 };
+                        let finished = TournamentGame {
+                            round,
+                            first_white,
+                            outcome: played.outcome,
+                            pgn: selfplay::match_pgn(
+                                &played.game,
+                                played.outcome,
+                                &played.comment,
+                                round,
+                                names,
+                            ),
+                        };
+                        if sender.send(Event::Game(finished)).is_err() {
+                            return;
+                        }
+                    }
+                }
+            });
+        }
+        drop(sender);
+        //The points of the first game of each pair until the second one is in
+        let mut halves: HashMap<usize, f64> = HashMap::new();
+        for event in receiver {
+            let game = match event {
+                Event::Game(game) => game,
+                Event::Failed(err) => {
+                    error.get_or_insert(err);
+                    stop.store(true, Ordering::SeqCst);
+                    continue;
+                }
+            };
+            let first = if game.first_white {
+                Color::White
+            } else {
+                Color::Black
+            };
+            let points = match game.outcome.winner() {
+                Some(winner) if winner == first => {
+                    standings.score.wins += 1;
+                    1.0
+                }
+                Some(_) => {
+                    standings.score.losses += 1;
+                    0.0
+                }
+                None => {
+                    standings.score.draws += 1;
+                    0.5
+                }
+            };
+            if let Some(other) = halves.remove(&(game.round / 2)) {
+                standings.pentanomial.add(other, points);
+                let decided = matches!(standings.sprt, Some((_, x)) if x != SprtResult::Continue);
+                //Games still running when the test decides do not take the decision back
+                if let Some(sprt) = options.sprt.filter(|_| !decided) {
+                    let result = sprt.result(&standings.pentanomial);
+                    standings.sprt = Some((sprt.llr(&standings.pentanomial), result));
+                    if result != SprtResult::Continue {
+                        stop.store(true, Ordering::SeqCst);
+                    }
+                }
+            } else {
+                halves.insert(game.round / 2, points);
+            }
+            report(&game, &standings);
+        }
+    });
+    match error {
+        Some(err) => Err(err),
+        None => Ok(standings),
+    }
+}
+
+/// Reads a time control in seconds such as `10+0.1`
+fn parse_time_control(text: &str) -> Result<TimeControl, String> {
+    let (base, increment) = text.split_once('+').unwrap_or((text, "0"));
+    let seconds = |x: &str| {
+        x.trim()
+            .parse::<f64>()
+            .ok()
+            .filter(|x| *x >= 0.0 && x.is_finite())
+            .map(Duration::from_secs_f64)
+            .ok_or_else(|| format!("Expected a time control such as 10+0.1, got {}", text))
+    };
+    Ok(TimeControl {
+        base: seconds(base)?,
+        increment: seconds(increment)?,
+    })
+}
+
+/// Runs `chess-rs tournament` with the arguments after the subcommand
+pub fn run(args: &[String]) -> Result<(), String> {
+    let mut engines: Vec<EngineCommand> = Vec::new();
+    let mut options = TournamentOptions {
+        engines: Default::default(),
+        limits: SearchLimits::default(),
+        games: None,
+        sprt: None,
+        openings: vec![ChessState::new_board().to_fen()],
+        concurrency: 1,
+        adjudication: Adjudication::default(),
+        time_margin: Duration::from_millis(100),
+    };
+    let mut output: Option<String> = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .cloned()
+                .ok_or_else(|| format!("{} needs a value\n{}", name, USAGE))
+        };
+        let limits = &mut options.limits;
+        match arg.as_str() {
+            "--engine" => engines.push(value(arg)?.parse()?),
+            "--tc" => limits.time_control = Some(parse_time_control(&value(arg)?)?),
+            "--movetime" => {
+                limits.movetime = Some(Duration::from_millis(selfplay::parse_number(&value(arg)?)?))
+            }
+            "--depth" => limits.depth = Some(selfplay::parse_number(&value(arg)?)?),
+            "--nodes" => limits.nodes = Some(selfplay::parse_number(&value(arg)?)?),
+            "--games" => options.games = Some(selfplay::parse_number(&value(arg)?)?),
+            "--sprt" => options.sprt = Some(value(arg)?.parse()?),
+            "--openings" => options.openings = selfplay::load_openings(&value(arg)?)?,
+            "--concurrency" => {
+                options.concurrency = selfplay::parse_number::<usize>(&value(arg)?)?.max(1)
+            }
+            "--pgn" => output = Some(value(arg)?),
+            "--time-margin" => {
+                options.time_margin = Duration::from_millis(selfplay::parse_number(&value(arg)?)?)
+            }
+            x if selfplay::ADJUDICATION_OPTIONS.contains(&x) => {
+                selfplay::set_adjudication(&mut options.adjudication, x, &value(x)?)?
+            }
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return Ok(());
+            }
+            x => return Err(format!("Unknown option {}\n{}", x, USAGE)),
+        }
+    }
+    let limits = &mut options.limits;
+    if limits.time_control.is_none()
+        && limits.movetime.is_none()
+        && limits.depth.is_none()
+        && limits.nodes.is_none()
+    {
+        limits.time_control = Some(parse_time_control("10+0.1")?);
+    }
+    let [mut first, mut second]: [EngineCommand; 2] = engines
+        .try_into()
+        .map_err(|_| format!("Expected two engines\n{}", USAGE))?;
+    //Names come from the engines themselves unless given, which needs them started once
+    for engine in [&mut first, &mut second] {
+        if engine.name.is_none() {
+            let started = engine
+                .start()
+                .map_err(|err| format!("Could not start {}: {}", engine.command, err))?;
+            engine.name = Some(started.name.clone());
+            started.quit();
+        }
+    }
+    if first.name == second.name {
+        second.name = Some(format!("{} 2", second.display_name()));
+    }
+    options.engines = [first, second];
+
+    let [first, second] = [0, 1].map(|x| options.engines[x].display_name());
+    if let Some(sprt) = options.sprt {
+        let (lower, upper) = sprt.bounds();
+        println!(
+            "SPRT: elo0 {} elo1 {} alpha {} beta {}, LLR bounds ({:.2}, {:.2})",
+            sprt.elo0, sprt.elo1, sprt.alpha, sprt.beta, lower, upper
+        );
+    }
+    let mut write_error: Option<String> = None;
+    let standings = run_tournament(&options, &mut |game, standings| {
+        println!(
+            "Game {}: {} vs {}: {} {{{}}}",
+            game.round + 1,
+            game.pgn.tag("White").unwrap_or("?"),
+            game.pgn.tag("Black").unwrap_or("?"),
+            game.outcome.result(),
+            game.pgn
+                .moves
+                .last()
+                .and_then(|x| x.comments.last())
+                .unwrap_or(&game.outcome.to_string())
+        );
+        println!(
+            "Score of {} vs {}: {} [{:.3}] {}",
+            first,
+            second,
+            standings.score,
+            standings.score.ratio(),
+            standings.score.games()
+        );
+        if let Some((llr, _)) = standings.sprt {
+            println!("LLR: {:.2} {}", llr, standings.pentanomial);
+        }
+        if let Some(path) = &output {
+            if let Err(err) = game.pgn.append_to_file(path) {
+                write_error.get_or_insert(format!("Could not write to {}: {}", path, err));
+            }
+        }
+    })?;
+    match standings.score.elo() {
+        Some((elo, margin)) => println!("Elo difference: {:+.1} +/- {:.1}", elo, margin),
+        None => println!("Elo difference: unknown after {}", standings.score),
+    }
+    println!("Pentanomial: {}", standings.pentanomial);
+    match standings.sprt {
+        Some((llr, SprtResult::AcceptH1)) => println!("SPRT: H1 accepted, LLR {:.2}", llr),
+        Some((llr, SprtResult::AcceptH0)) => println!("SPRT: H0 accepted, LLR {:.2}", llr),
+        Some((llr, SprtResult::Continue)) => println!("SPRT: no decision, LLR {:.2}", llr),
+        None => {}
+    }
+    match write_error {
+        Some(err) => Err(err),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn engine_commands() {
+        let engine: EngineCommand =
+            "cmd=./engine, name=new,arg=--uci,option.Hash=64,option.Move Overhead=20"
+                .parse()
+                .unwrap();
+        assert_eq!(engine.command, "./engine");
+        assert_eq!(engine.display_name(), "new");
+        assert_eq!(engine.args, ["--uci"]);
+        assert_eq!(
+            engine.options,
+            [
+                ("Hash".to_string(), "64".to_string()),
+                ("Move Overhead".to_string(), "20".to_string())
+            ]
+        );
+        assert!("name=new".parse::<EngineCommand>().is_err());
+        assert!("cmd=a,depth=3".parse::<EngineCommand>().is_err());
+
+        let time_control = parse_time_control("10+0.1").unwrap();
+        assert_eq!(time_control.base, Duration::from_secs(10));
+        assert_eq!(time_control.increment, Duration::from_millis(100));
+        assert_eq!(parse_time_control("60").unwrap().increment, Duration::ZERO);
+        assert!(parse_time_control("fast").is_err());
+    }
+
+    #[test]
+    fn missing_engine() {
+        let options = TournamentOptions {
+            engines: [
+                "cmd=/nonexistent/engine".parse().unwrap(),
+                "cmd=/nonexistent/engine".parse().unwrap(),
+            ],
+            limits: SearchLimits {
+                depth: Some(1),
+                ..SearchLimits::default()
+            },
+            games: Some(2),
+            sprt: None,
+            openings: vec![ChessState::new_board().to_fen()],
+            concurrency: 1,
+            adjudication: Adjudication::default(),
+            time_margin: Duration::from_millis(100),
+        };
+        let result = run_tournament(&options, &mut |_, _| panic!("No game can be played"));
+        assert!(result.unwrap_err().starts_with("Could not start"));
+    }
+}
@@ -1,84 +1,359 @@
 use crate::chess_init::ChessState;
-const PIECES: &str = " PRNBQK";
+use crate::moves::{Move, MoveKind};
+use crate::types::{PieceKind, Square};
+use std::fmt;
 
-fn slice_to_coord(col: char, row: char) -> (usize, usize) {
-    (
-        row.to_digit(10).unwrap() as u8 as usize - 1,
+fn slice_to_coord(col: char, row: char) -> Square {
+    Square::new(
         (col as u8 - 97) as usize,
+        row.to_digit(10).unwrap() as usize - 1,
     )
 }
-pub fn lan_to_uci(state: &ChessState, m: &String) -> String {
+
+pub fn lan_to_uci(state: &ChessState, m: &Move) -> String {
     //Castling
-    if m == "O-O-O" {
-        return if state.turn {
-            "e1a1".to_string()
-        } else {
-            "e8a8".to_string()
-        };
-    } else if m == "O-O" {
-        return if state.turn {
-            "e1g1".to_string()
+    if let MoveKind::Castle { king_side } = m.kind {
+        let target_file = if state.chess960 {
+            //UCI_Chess960 encodes castling as king takes rook
+            m.to.file()
+        } else if king_side {
+            6
         } else {
-            "e8g8".to_string()
+            2
         };
+        return format!("{}{}", m.from, Square::new(target_file, m.from.rank()));
     }
-    //Normal pieces
-    else if PIECES.contains(m.chars().nth(0).unwrap_or('_')) {
-        return m
-            .chars()
-            .filter(|x| *x != 'x' && *x != '-' && !PIECES.contains(*x))
-            .collect::<String>();
-    }
-    //Pawns
-    //Promotion
-    else if m.len() == 6 {
-        return m
-            .chars()
-            .filter(|x| *x != 'x' && *x != '-')
-            .map(|x| x.to_ascii_lowercase())
-            .collect::<String>();
-    }
-    //normal
-    else {
-        return m.chars().filter(|x| *x != 'x' && *x != '-').collect();
-    }
+    let promotion = m
+        .promotion()
+        .map(|x| x.letter().to_ascii_lowercase().to_string())
+        .unwrap_or_default();
+    format!("{}{}{}", m.from, m.to, promotion)
 }
 
-pub fn uci_to_lan(state: &ChessState, m: &String) -> String {
+/// The move `m` in UCI notation describes in `state`, which need not be legal. `None` if
+/// it is not a move of a piece of the side to move.
+pub fn uci_to_lan(state: &ChessState, m: &str) -> Option<Move> {
+    let source: Square = m.get(..2)?.parse().ok()?;
+    let target: Square = m.get(2..4)?.parse().ok()?;
+    let piece = state.board[source].filter(|x| x.color == state.turn)?.kind;
+    let own = |square: Square, kind: PieceKind| {
+        state.board[square].is_some_and(|x| x.color == state.turn && x.kind == kind)
+    };
     //Castling
-    println!("info {}", m);
-    if m == "e1a1" || m == "e8a8" {
-        return "O-O-O".to_string();
-    } else if m == "e1g1" || m == "e8g8" {
-        return "O-O".to_string();
+    if piece == PieceKind::King {
+        let castle = |king_side: bool| {
+            let rook = Square::new(state.castling_rook(king_side)?, source.rank());
+            Some(Move::new(
+                piece,
+                source,
+                rook,
+                MoveKind::Castle { king_side },
+            ))
+        };
+        if own(target, PieceKind::Rook) {
+            //King takes rook
+            return castle(target.file() > source.file());
+        } else if !state.chess960
+            && source.file() == 4
+            && source.rank() == state.turn.back_rank()
+            && target.rank() == source.rank()
+        {
+            match target.file() {
+                6 => return castle(true),
+                2 => return castle(false),
+                _ => {}
+            }
+        }
     }
-    //Promotion
-    let source = slice_to_coord(m.chars().nth(0).unwrap(), m.chars().nth(1).unwrap());
-    let target = slice_to_coord(m.chars().nth(2).unwrap(), m.chars().nth(3).unwrap());
-    if m.len() == 5 {
-        return m[..2].to_string()
-            + if state.board[target.0][target.1] == 0 {
-                &"-"
+    let capture = state.board[target].is_some();
+    let kind = match m.get(4..) {
+        Some("") | None => {
+            if piece != PieceKind::Pawn {
+                if capture {
+                    MoveKind::Capture
+                } else {
+                    MoveKind::Quiet
+                }
+            } else if source.file() != target.file() {
+                if capture {
+                    MoveKind::Capture
+                } else {
+                    MoveKind::EnPassant
+                }
+            } else if source.rank().abs_diff(target.rank()) == 2 {
+                MoveKind::DoublePush
             } else {
-                &"x"
+                MoveKind::Quiet
+            }
+        }
+        Some(promotion) => {
+            let mut chars = promotion.chars();
+            let piece = chars.next().and_then(PieceKind::from_letter)?;
+            if chars.next().is_some() {
+                return None;
             }
-            + &m[2..4]
-            + &m.chars()
-                .nth(4)
-                .map(|x| x.to_ascii_uppercase().to_string())
-                .unwrap_or_default();
+            MoveKind::Promotion { piece, capture }
+        }
+    };
+    Some(Move::new(piece, source, target, kind))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SanError {
+    /// The text is not a move in any recognised notation
+    Invalid(String),
+    /// The text describes a move that is not legal in the position
+    Illegal(String),
+    /// The text matches more than one legal move
+    Ambiguous(String),
+}
+
+impl fmt::Display for SanError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SanError::Invalid(san) => write!(f, "invalid move: {}", san),
+            SanError::Illegal(san) => write!(f, "illegal move: {}", san),
+            SanError::Ambiguous(san) => write!(f, "ambiguous move: {}", san),
+        }
+    }
+}
+
+impl std::error::Error for SanError {}
+
+pub fn to_san(state: &ChessState, m: &Move) -> String {
+    let mut san = if m.is_castle() {
+        m.to_string()
     } else {
-        let piece = PIECES
-            .chars()
-            .nth(state.board[source.0][source.1].abs() as usize)
-            .unwrap();
-        return piece.to_string()
-            + &m[..2]
-            + if state.board[target.0][target.1] == 0 {
-                &"-"
-            } else {
-                &"x"
+        let mut san = String::new();
+        if m.piece == PieceKind::Pawn {
+            if m.is_capture() {
+                san.push((m.from.file() as u8 + 97) as char);
+            }
+        } else {
+            san.push(m.piece.letter());
+            //Only disambiguate between pieces of the same kind reaching the same square
+            let others: Vec<Square> = state
+                .moves()
+                .filter(|x| x != m && x.piece == m.piece && !x.is_castle() && x.to == m.to)
+                .map(|x| x.from)
+                .collect();
+            if !others.is_empty() {
+                if others.iter().all(|x| x.file() != m.from.file()) {
+                    san.push((m.from.file() as u8 + 97) as char);
+                } else if others.iter().all(|x| x.rank() != m.from.rank()) {
+                    san.push((m.from.rank() as u8 + 49) as char);
+                } else {
+                    san += &m.from.to_string();
+                }
+            }
+        }
+        if m.is_capture() {
+            san.push('x');
+        }
+        san += &m.to.to_string();
+        if let Some(promotion) = m.promotion() {
+            san.push('=');
+            san.push(promotion.letter());
+        }
+        san
+    };
+    let new_state = state.do_move(m);
+    if new_state.check() {
+        san.push(if new_state.is_terminal() { '#' } else { '+' });
+    }
+    san
+}
+
+/// Parses SAN leniently, accepting `0-0`, a missing `x`, `e8Q` style promotions and annotations
+pub fn parse_san(state: &ChessState, san: &str) -> Result<Move, SanError> {
+    let cleaned: String = san
+        .trim()
+        .trim_end_matches(['+', '#', '!', '?'])
+        .trim_end_matches("e.p.")
+        .trim()
+        .to_string();
+    let castling = cleaned.replace('0', "O");
+    let moves = state.get_all_possible_moves();
+    if castling == "O-O" || castling == "O-O-O" {
+        let king_side = castling == "O-O";
+        return moves
+            .iter()
+            .find(|x| x.kind == MoveKind::Castle { king_side })
+            .copied()
+            .ok_or(SanError::Illegal(san.to_string()));
+    }
+
+    let mut chars: Vec<char> = cleaned
+        .chars()
+        .filter(|x| !['x', 'X', ':', '-', '='].contains(x))
+        .collect();
+    let piece = match chars.first() {
+        Some(x) if "KQRBN".contains(*x) => PieceKind::from_letter(chars.remove(0)).unwrap(),
+        Some(_) => PieceKind::Pawn,
+        None => return Err(SanError::Invalid(san.to_string())),
+    };
+    let promotion = match chars.last() {
+        Some(x) if piece == PieceKind::Pawn && "QRBNqrbn".contains(*x) && chars.len() > 2 => {
+            PieceKind::from_letter(chars.pop().unwrap())
+        }
+        _ => None,
+    };
+    if chars.len() < 2 || chars.len() > 4 {
+        return Err(SanError::Invalid(san.to_string()));
+    }
+    let (rank, file) = (chars.pop().unwrap(), chars.pop().unwrap());
+    if !('a'..='h').contains(&file) || !('1'..='8').contains(&rank) {
+        return Err(SanError::Invalid(san.to_string()));
+    }
+    let target = slice_to_coord(file, rank);
+    let mut from_file: Option<usize> = None;
+    let mut from_rank: Option<usize> = None;
+    for x in chars {
+        match x {
+            'a'..='h' => from_file = Some((x as u8 - 97) as usize),
+            '1'..='8' => from_rank = Some((x as u8 - 49) as usize),
+            _ => return Err(SanError::Invalid(san.to_string())),
+        }
+    }
+
+    let candidates: Vec<&Move> = moves
+        .iter()
+        .filter(|x| {
+            !x.is_castle()
+                && x.piece == piece
+                && x.to == target
+                && x.promotion() == promotion
+                && from_file.is_none_or(|f| x.from.file() == f)
+                && from_rank.is_none_or(|r| x.from.rank() == r)
+        })
+        .collect();
+    match candidates.len() {
+        0 => Err(SanError::Illegal(san.to_string())),
+        1 => Ok(*candidates[0]),
+        _ => Err(SanError::Ambiguous(san.to_string())),
+    }
+}
+
+/// A sequence of moves from `state` in UCI notation, separated by spaces
+pub fn pv_to_uci(state: &ChessState, pv: &[Move]) -> String {
+    let mut state = state.copy();
+    let mut uci_moves: Vec<String> = Vec::new();
+    for m in pv.iter() {
+        uci_moves.push(lan_to_uci(&state, m));
+        state = state.do_move(m);
+    }
+    uci_moves.join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn castling_notation() {
+        for (fen, king) in [
+            ("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1", "e1"),
+            ("r3k2r/8/8/8/8/8/8/R3K2R b KQkq - 0 1", "e8"),
+        ] {
+            let state = ChessState::from_fen(fen);
+            for (target, rook, king_side) in [("c", "a", false), ("g", "h", true)] {
+                let rank = &king[1..];
+                let uci = format!("{}{}{}", king, target, rank);
+                let m = uci_to_lan(&state, &uci).unwrap();
+                assert_eq!(m.kind, MoveKind::Castle { king_side });
+                assert!(state.moves().any(|x| x == m));
+                assert_eq!(lan_to_uci(&state, &m), uci);
+                //Standard chess also takes king takes rook, but writes the king's target
+                let takes_rook = format!("{}{}{}", king, rook, rank);
+                assert_eq!(uci_to_lan(&state, &takes_rook), Some(m));
             }
-            + &m[2..4];
+        }
+
+        //Under UCI_Chess960 castling is always king takes rook, even when the king lands on
+        //the rook's square
+        let mut state = ChessState::from_fen("4k3/8/8/8/8/8/8/1R2K1R1 w GB - 0 1");
+        state.chess960 = true;
+        let short = uci_to_lan(&state, "e1g1").unwrap();
+        assert_eq!(short.kind, MoveKind::Castle { king_side: true });
+        let long = uci_to_lan(&state, "e1b1").unwrap();
+        assert_eq!(long.kind, MoveKind::Castle { king_side: false });
+        for m in [short, long] {
+            assert!(state.moves().any(|x| x == m));
+        }
+        assert_eq!(lan_to_uci(&state, &short), "e1g1");
+        assert_eq!(lan_to_uci(&state, &long), "e1b1");
+        assert_eq!(pv_to_uci(&state, &[long]), "e1b1");
+        //The king's target square is then a plain king move
+        let mut state = ChessState::from_fen("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1");
+        state.chess960 = true;
+        let castle = uci_to_lan(&state, "e1a1").unwrap();
+        assert_eq!(lan_to_uci(&state, &castle), "e1a1");
+        assert!(!uci_to_lan(&state, "e1c1").unwrap().is_castle());
+    }
+
+    fn san_moves(fen: &str, sans: &[&str]) {
+        let state = ChessState::from_fen(fen);
+        for san in sans {
+            let m = parse_san(&state, san).unwrap();
+            assert_eq!(to_san(&state, &m), *san);
+        }
+    }
+
+    #[test]
+    fn san() {
+        //File, rank and full square disambiguation
+        san_moves("4k3/8/8/8/8/8/8/1N2KN2 w - - 0 1", &["Nbd2", "Nfd2", "Nc3"]);
+        san_moves("4k3/8/8/R7/8/8/8/R3K3 w - - 0 1", &["R5a3", "R1a3", "Rb5"]);
+        san_moves(
+            "K7/8/k7/8/4Q2Q/8/8/7Q w - - 0 1",
+            &["Qh4e1", "Qee1", "Q1e1", "Qf2"],
+        );
+        //Check and mate
+        san_moves("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1", &["Ra8#", "Ra7"]);
+        san_moves("4k3/8/8/8/8/8/8/R3K3 w - - 0 1", &["Ra8+", "Kd2"]);
+        //Promotion, with and without a capture
+        san_moves(
+            "2r1k3/3P4/8/8/8/8/8/4K3 w - - 0 1",
+            &["dxc8=Q+", "d8=N", "d8=R+"],
+        );
+        let state = ChessState::from_fen("1k6/4P3/8/8/8/8/8/4K3 w - - 0 1");
+        let queen = parse_san(&state, "e8=Q+").unwrap();
+        assert_eq!(queen.promotion(), Some(PieceKind::Queen));
+
+        //Lenient input
+        for san in ["e8Q", "e8q", "e8=Q", "e7e8Q", "e8Q+!"] {
+            assert_eq!(parse_san(&state, san), Ok(queen));
+        }
+        let state = ChessState::from_fen("4k3/8/8/3p4/4P3/2N5/8/R3K2R w KQ - 0 1");
+        assert_eq!(parse_san(&state, "ed5"), parse_san(&state, "exd5"));
+        assert_eq!(parse_san(&state, "Nd5"), parse_san(&state, "Nxd5"));
+        assert_eq!(parse_san(&state, "0-0"), parse_san(&state, "O-O"));
+        assert!(parse_san(&state, "0-0-0").unwrap().is_castle());
+        assert_eq!(
+            parse_san(&state, "Ng5"),
+            Err(SanError::Illegal("Ng5".to_string()))
+        );
+        let knights = ChessState::from_fen("4k3/8/8/8/8/8/8/1N2KN2 w - - 0 1");
+        assert_eq!(
+            parse_san(&knights, "Nd2"),
+            Err(SanError::Ambiguous("Nd2".to_string()))
+        );
+        assert_eq!(
+            parse_san(&state, "Zz9"),
+            Err(SanError::Invalid("Zz9".to_string()))
+        );
+
+        //Chess960 castling is written the same way, wherever the king and rooks start
+        let mut state = ChessState::from_fen("4k3/8/8/8/8/8/8/1R2K1R1 w GB - 0 1");
+        state.chess960 = true;
+        for (san, king_side) in [("O-O", true), ("O-O-O", false)] {
+            let m = parse_san(&state, san).unwrap();
+            assert_eq!(m.kind, MoveKind::Castle { king_side });
+            assert_eq!(to_san(&state, &m), san);
+        }
+        assert_eq!(
+            to_san(&state, &parse_san(&state, "0-0-0").unwrap()),
+            "O-O-O"
+        );
     }
 }
@@ -0,0 +1,101 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// How a stored score relates to the real value of the position
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bound {
+    Exact,
+    /// The real value is at least the score
+    Lower,
+    /// The real value is at most the score
+    Upper,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TtEntry {
+    /// Score from white's point of view
+    pub score: i32,
+    pub depth: u8,
+    pub bound: Bound,
+    /// The best move as `Move::pack` gives it, only to be used if it is legal in the position
+    pub best: Option<u16>,
+}
+
+impl TtEntry {
+    fn pack(&self) -> u64 {
+        let bound: u64 = match self.bound {
+            Bound::Exact => 0,
+            Bound::Lower => 1,
+            Bound::Upper => 2,
+        };
+        let best: u64 = self.best.map_or(0, |x| x as u64 + 1);
+        (self.score as u32 as u64) | (self.depth as u64) << 32 | bound << 40 | best << 48
+    }
+
+    fn unpack(data: u64) -> TtEntry {
+        let bound = match (data >> 40) & 0xff {
+            0 => Bound::Exact,
+            1 => Bound::Lower,
+            _ => Bound::Upper,
+        };
+        let best = ((data >> 48) & 0xffff) as u16;
+        TtEntry {
+            score: data as u32 as i32,
+            depth: (data >> 32) as u8,
+            bound,
+            best: best.checked_sub(1),
+        }
+    }
+}
+
+/// A transposition table shared by all search threads without locks. Every slot keeps the
+/// key xor-ed with the data, so a slot torn by two threads writing at once fails the key
+/// check instead of returning another position's entry.
+pub struct TranspositionTable {
+    slots: Vec<[AtomicU64; 2]>,
+}
+
+impl TranspositionTable {
+    /// A table using about `megabytes` of memory
+    pub fn new(megabytes: usize) -> TranspositionTable {
+        let count = (megabytes * 1024 * 1024 / std::mem::size_of::<[AtomicU64; 2]>()).max(1);
+        TranspositionTable {
+            slots: (0..count)
+                .map(|_| [AtomicU64::new(0), AtomicU64::new(0)])
+                .collect(),
+        }
+    }
+
+    pub fn clear(&self) {
+        for slot in self.slots.iter() {
+            slot[0].store(0, Ordering::Relaxed);
+            slot[1].store(0, Ordering::Relaxed);
+        }
+    }
+
+    fn slot(&self, hash: u64) -> &[AtomicU64; 2] {
+        &self.slots[(hash % self.slots.len() as u64) as usize]
+    }
+
+    pub fn probe(&self, hash: u64) -> Option<TtEntry> {
+        let slot = self.slot(hash);
+        let check = slot[0].load(Ordering::Relaxed);
+        let data = slot[1].load(Ordering::Relaxed);
+        if data == 0 || check ^ data != hash {
+            return None;
+        }
+        Some(TtEntry::unpack(data))
+    }
+
+    /// Stores an entry, keeping a deeper one for the same position
+    pub fn store(&self, hash: u64, entry: TtEntry) {
+        let slot = self.slot(hash);
+        if let Some(old) = self.probe(hash) {
+            if old.depth > entry.depth {
+                return;
+            }
+        }
+        let data = entry.pack();
+        slot[0].store(hash ^ data, Ordering::Relaxed);
+        slot[1].store(data, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,403 @@
+use std::fmt;
+use std::ops::{Index, IndexMut, Not};
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Color {
+    White,
+    Black,
+}
+
+impl Color {
+    pub fn is_white(self) -> bool {
+        self == Color::White
+    }
+
+    /// 1 for white and -1 for black, scores are white-relative
+    pub fn sign(self) -> i8 {
+        if self.is_white() {
+            1
+        } else {
+            -1
+        }
+    }
+
+    /// The rank the pieces of this color start on
+    pub fn back_rank(self) -> usize {
+        if self.is_white() {
+            0
+        } else {
+            7
+        }
+    }
+}
+
+impl fmt::Display for Color {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Color::White => write!(f, "White"),
+            Color::Black => write!(f, "Black"),
+        }
+    }
+}
+
+impl Not for Color {
+    type Output = Color;
+
+    fn not(self) -> Color {
+        match self {
+            Color::White => Color::Black,
+            Color::Black => Color::White,
+        }
+    }
+}
+
+/// The kinds of piece, numbered the way the engine always has: 1 pawn up to 6 king
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum PieceKind {
+    Pawn = 1,
+    Rook = 2,
+    Knight = 3,
+    Bishop = 4,
+    Queen = 5,
+    King = 6,
+}
+
+impl PieceKind {
+    pub const ALL: [PieceKind; 6] = [
+        PieceKind::Pawn,
+        PieceKind::Rook,
+        PieceKind::Knight,
+        PieceKind::Bishop,
+        PieceKind::Queen,
+        PieceKind::King,
+    ];
+
+    /// Index into tables laid out pawn, rook, knight, bishop, queen, king from 1
+    pub fn index(self) -> usize {
+        self as usize
+    }
+
+    /// Uppercase letter, as in SAN and white FEN pieces
+    pub fn letter(self) -> char {
+        match self {
+            PieceKind::Pawn => 'P',
+            PieceKind::Rook => 'R',
+            PieceKind::Knight => 'N',
+            PieceKind::Bishop => 'B',
+            PieceKind::Queen => 'Q',
+            PieceKind::King => 'K',
+        }
+    }
+
+    /// Reads a piece letter in either case
+    pub fn from_letter(letter: char) -> Option<PieceKind> {
+        PieceKind::ALL
+            .into_iter()
+            .find(|x| x.letter() == letter.to_ascii_uppercase())
+    }
+
+    pub fn is_slider(self) -> bool {
+        matches!(self, PieceKind::Rook | PieceKind::Bishop | PieceKind::Queen)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Piece {
+    pub kind: PieceKind,
+    pub color: Color,
+}
+
+impl Piece {
+    pub fn new(kind: PieceKind, color: Color) -> Piece {
+        Piece { kind, color }
+    }
+
+    /// FEN letter, uppercase for white
+    pub fn to_char(self) -> char {
+        if self.color.is_white() {
+            self.kind.letter()
+        } else {
+            self.kind.letter().to_ascii_lowercase()
+        }
+    }
+
+    pub fn from_char(c: char) -> Option<Piece> {
+        let color = if c.is_ascii_uppercase() {
+            Color::White
+        } else {
+            Color::Black
+        };
+        PieceKind::from_letter(c).map(|kind| Piece::new(kind, color))
+    }
+}
+
+/// A square numbered `rank * 8 + file` from 0 (a1) to 63 (h8)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Square(u8);
+
+impl Square {
+    pub fn new(file: usize, rank: usize) -> Square {
+        debug_assert!(file < 8 && rank < 8);
+        Square((rank * 8 + file) as u8)
+    }
+
+    pub const fn from_index(index: usize) -> Square {
+        debug_assert!(index < 64);
+        Square(index as u8)
+    }
+
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+
+    pub fn file(self) -> usize {
+        self.0 as usize % 8
+    }
+
+    pub fn rank(self) -> usize {
+        self.0 as usize / 8
+    }
+
+    /// The bit of this square in a `u64` square set
+    pub fn bit(self) -> u64 {
+        1 << self.0
+    }
+
+    /// The square `direction` (files, ranks) away, if it is on the board
+    pub fn offset(self, direction: (i8, i8)) -> Option<Square> {
+        let file = self.file() as i8 + direction.0;
+        let rank = self.rank() as i8 + direction.1;
+        if (0..8).contains(&file) && (0..8).contains(&rank) {
+            return Some(Square::new(file as usize, rank as usize));
+        }
+        None
+    }
+
+    /// The same square seen from the other side of the board
+    pub fn flip(self) -> Square {
+        Square(self.0 ^ 56)
+    }
+
+    pub fn all() -> impl Iterator<Item = Square> {
+        (0..64).map(Square)
+    }
+
+    /// The squares of a `u64` square set, from a1 to h8
+    pub fn in_set(bits: u64) -> impl Iterator<Item = Square> {
+        let mut bits = bits;
+        std::iter::from_fn(move || {
+            if bits == 0 {
+                return None;
+            }
+            let square = Square(bits.trailing_zeros() as u8);
+            bits &= bits - 1;
+            Some(square)
+        })
+    }
+}
+
+impl fmt::Display for Square {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}{}",
+            (b'a' + self.file() as u8) as char,
+            self.rank() + 1
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseSquareError(pub String);
+
+impl fmt::Display for ParseSquareError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid square: {}", self.0)
+    }
+}
+
+impl std::error::Error for ParseSquareError {}
+
+impl FromStr for Square {
+    type Err = ParseSquareError;
+
+    /// Reads algebraic notation such as `e4`
+    fn from_str(s: &str) -> Result<Square, ParseSquareError> {
+        let mut chars = s.chars();
+        match (chars.next(), chars.next(), chars.next()) {
+            (Some(file @ 'a'..='h'), Some(rank @ '1'..='8'), None) => Ok(Square::new(
+                (file as u8 - b'a') as usize,
+                (rank as u8 - b'1') as usize,
+            )),
+            _ => Err(ParseSquareError(s.to_string())),
+        }
+    }
+}
+
+/// The pieces on the 64 squares, indexed by [`Square`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Board([Option<Piece>; 64]);
+
+impl Board {
+    pub fn empty() -> Board {
+        Board([None; 64])
+    }
+
+    /// The occupied squares with their pieces, from a1 to h8
+    pub fn pieces(&self) -> impl Iterator<Item = (Square, Piece)> + '_ {
+        Square::all().filter_map(|x| self[x].map(|piece| (x, piece)))
+    }
+
+    /// One bit per square holding `piece`
+    pub fn find_all(&self, piece: Piece) -> u64 {
+        self.pieces()
+            .filter(|(_, x)| *x == piece)
+            .fold(0, |bits, (square, _)| bits | square.bit())
+    }
+
+    pub fn find(&self, piece: Piece) -> Option<Square> {
+        self.pieces()
+            .find(|(_, x)| *x == piece)
+            .map(|(square, _)| square)
+    }
+
+    /// One bit per occupied square
+    pub fn occupancy(&self) -> u64 {
+        self.pieces()
+            .fold(0, |bits, (square, _)| bits | square.bit())
+    }
+
+    /// One bit per square holding a piece of `color`
+    pub fn color_occupancy(&self, color: Color) -> u64 {
+        self.pieces()
+            .filter(|(_, piece)| piece.color == color)
+            .fold(0, |bits, (square, _)| bits | square.bit())
+    }
+
+    pub fn count(&self) -> usize {
+        self.0.iter().flatten().count()
+    }
+}
+
+impl Index<Square> for Board {
+    type Output = Option<Piece>;
+
+    fn index(&self, square: Square) -> &Option<Piece> {
+        &self.0[square.index()]
+    }
+}
+
+impl IndexMut<Square> for Board {
+    fn index_mut(&mut self, square: Square) -> &mut Option<Piece> {
+        &mut self.0[square.index()]
+    }
+}
+
+/// Castling rights as one bit per rook file and color, which covers Chess960 too:
+/// bit `file` for white and bit `8 + file` for black
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct CastlingRights(u16);
+
+impl CastlingRights {
+    pub const NONE: CastlingRights = CastlingRights(0);
+
+    fn bit(color: Color, file: usize) -> u16 {
+        debug_assert!(file < 8);
+        1 << (file + if color.is_white() { 0 } else { 8 })
+    }
+
+    pub fn bits(self) -> u16 {
+        self.0
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn contains(self, color: Color, file: usize) -> bool {
+        self.0 & CastlingRights::bit(color, file) != 0
+    }
+
+    pub fn insert(&mut self, color: Color, file: usize) {
+        self.0 |= CastlingRights::bit(color, file);
+    }
+
+    pub fn remove(&mut self, color: Color, file: usize) {
+        self.0 &= !CastlingRights::bit(color, file);
+    }
+
+    /// Removes every right of `color`, as when its king moves
+    pub fn remove_color(&mut self, color: Color) {
+        self.0 &= if color.is_white() { 0xff00 } else { 0x00ff };
+    }
+
+    /// The rook files `color` may still castle with, highest first
+    pub fn files(self, color: Color) -> impl Iterator<Item = usize> {
+        (0..8).rev().filter(move |x| self.contains(color, *x))
+    }
+}
+
+impl fmt::Display for CastlingRights {
+    /// Shredder-FEN style, the rook files with white uppercase ("HAha"), or "-"
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_empty() {
+            return write!(f, "-");
+        }
+        for color in [Color::White, Color::Black] {
+            for file in self.files(color) {
+                let letter = (b'a' + file as u8) as char;
+                let letter = if color.is_white() {
+                    letter.to_ascii_uppercase()
+                } else {
+                    letter
+                };
+                write!(f, "{}", letter)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn squares() {
+        let e4: Square = "e4".parse().unwrap();
+        assert_eq!((e4.file(), e4.rank()), (4, 3));
+        assert_eq!(e4.index(), 28);
+        assert_eq!(e4.to_string(), "e4");
+        assert_eq!(e4.offset((1, 2)), Some(Square::new(5, 5)));
+        assert_eq!(Square::new(7, 7).offset((1, 0)), None);
+        assert!("i1".parse::<Square>().is_err());
+        assert!("e9".parse::<Square>().is_err());
+        let set = Square::new(0, 0).bit() | e4.bit() | Square::new(7, 7).bit();
+        assert_eq!(
+            Square::in_set(set)
+                .map(|x| x.to_string())
+                .collect::<Vec<_>>(),
+            ["a1", "e4", "h8"]
+        );
+        assert_eq!(Square::in_set(0).count(), 0);
+    }
+
+    #[test]
+    fn pieces_and_rights() {
+        let rook = Piece::from_char('r').unwrap();
+        assert_eq!(rook, Piece::new(PieceKind::Rook, Color::Black));
+        assert_eq!(rook.kind.index(), 2);
+        assert_eq!(rook.to_char(), 'r');
+
+        let mut rights = CastlingRights::NONE;
+        for (color, file) in [(Color::White, 7), (Color::White, 0), (Color::Black, 7)] {
+            rights.insert(color, file);
+        }
+        assert_eq!(rights.to_string(), "HAh");
+        rights.remove_color(Color::White);
+        assert_eq!(rights.to_string(), "h");
+        rights.remove(Color::Black, 7);
+        assert!(rights.is_empty());
+    }
+}
@@ -0,0 +1,574 @@
+use crate::chess_init::ChessState;
+use crate::chessbot::PvLine;
+use crate::eval;
+use crate::time_manager;
+use crate::translator;
+use crate::types::Color;
+use std::fmt;
+use std::time::Duration;
+
+/// Parameters of a `go` command, all optional
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GoParams {
+    pub searchmoves: Vec<String>,
+    pub ponder: bool,
+    pub wtime: Option<u64>,
+    pub btime: Option<u64>,
+    pub winc: Option<u64>,
+    pub binc: Option<u64>,
+    pub movestogo: Option<u32>,
+    pub depth: Option<u8>,
+    pub nodes: Option<u64>,
+    pub mate: Option<u32>,
+    pub movetime: Option<u64>,
+    pub infinite: bool,
+    /// Non-standard `go perft <depth>`
+    pub perft: Option<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum UciCommand {
+    Uci,
+    Debug(bool),
+    IsReady,
+    SetOption {
+        name: String,
+        value: Option<String>,
+    },
+    Register,
+    UciNewGame,
+    /// `fen` is `None` for `startpos`; the moves are in UCI notation
+    Position {
+        fen: Option<String>,
+        moves: Vec<String>,
+    },
+    Go(GoParams),
+    Stop,
+    PonderHit,
+    Quit,
+    Unknown(String),
+}
+
+const COMMANDS: [&str; 11] = [
+    "uci",
+    "debug",
+    "isready",
+    "setoption",
+    "register",
+    "ucinewgame",
+    "position",
+    "go",
+    "stop",
+    "ponderhit",
+    "quit",
+];
+const GO_KEYWORDS: [&str; 13] = [
+    "searchmoves",
+    "ponder",
+    "wtime",
+    "btime",
+    "winc",
+    "binc",
+    "movestogo",
+    "depth",
+    "nodes",
+    "mate",
+    "movetime",
+    "infinite",
+    "perft",
+];
+
+/// Parses one line from the GUI. Unknown tokens before the command are skipped as the protocol requires.
+pub fn parse_command(line: &str) -> UciCommand {
+    let tokens: Vec<&str> = line.split_whitespace().collect();
+    let Some(start) = tokens.iter().position(|x| COMMANDS.contains(x)) else {
+        return UciCommand::Unknown(line.trim().to_string());
+    };
+    let args = &tokens[start + 1..];
+    match tokens[start] {
+        "uci" => UciCommand::Uci,
+        "debug" => UciCommand::Debug(args.first() != Some(&"off")),
+        "isready" => UciCommand::IsReady,
+        "setoption" => parse_setoption(args),
+        "register" => UciCommand::Register,
+        "ucinewgame" => UciCommand::UciNewGame,
+        "position" => parse_position(args),
+        "go" => UciCommand::Go(parse_go(args)),
+        "stop" => UciCommand::Stop,
+        "ponderhit" => UciCommand::PonderHit,
+        _ => UciCommand::Quit,
+    }
+}
+
+fn parse_setoption(args: &[&str]) -> UciCommand {
+    let name_start = args.iter().position(|x| *x == "name").map_or(0, |x| x + 1);
+    let value_start = args.iter().position(|x| *x == "value");
+    let name = args[name_start..value_start.unwrap_or(args.len()).max(name_start)].join(" ");
+    let value = value_start.map(|x| args[x + 1..].join(" "));
+    UciCommand::SetOption { name, value }
+}
+
+fn parse_position(args: &[&str]) -> UciCommand {
+    let moves_start = args.iter().position(|x| *x == "moves");
+    let setup = &args[..moves_start.unwrap_or(args.len())];
+    let fen = match setup.iter().position(|x| *x == "fen") {
+        Some(idx) if idx + 1 < setup.len() => Some(setup[idx + 1..].join(" ")),
+        _ => None,
+    };
+    let moves = match moves_start {
+        Some(idx) => args[idx + 1..].iter().map(|x| x.to_string()).collect(),
+        None => Vec::new(),
+    };
+    UciCommand::Position { fen, moves }
+}
+
+fn parse_go(args: &[&str]) -> GoParams {
+    let mut params = GoParams::default();
+    let mut idx = 0;
+    while idx < args.len() {
+        let value = args.get(idx + 1).copied().unwrap_or("");
+        let mut consumed = 2;
+        match args[idx] {
+            "searchmoves" => {
+                let moves: Vec<String> = args[idx + 1..]
+                    .iter()
+                    .take_while(|x| !GO_KEYWORDS.contains(x))
+                    .map(|x| x.to_string())
+                    .collect();
+                consumed = moves.len() + 1;
+                params.searchmoves = moves;
+            }
+            "ponder" => {
+                params.ponder = true;
+                consumed = 1;
+            }
+            "infinite" => {
+                params.infinite = true;
+                consumed = 1;
+            }
+            "wtime" => params.wtime = parse_time(value),
+            "btime" => params.btime = parse_time(value),
+            "winc" => params.winc = parse_time(value),
+            "binc" => params.binc = parse_time(value),
+            "movestogo" => params.movestogo = value.parse().ok(),
+            "depth" => params.depth = value.parse().ok(),
+            "nodes" => params.nodes = value.parse().ok(),
+            "mate" => params.mate = value.parse().ok(),
+            "movetime" => params.movetime = parse_time(value),
+            "perft" => params.perft = value.parse().ok(),
+            _ => consumed = 1,
+        }
+        idx += consumed;
+    }
+    params
+}
+
+impl fmt::Display for UciCommand {
+    /// The command as it is sent to an engine, so that `parse_command` reads it back
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UciCommand::Uci => write!(f, "uci"),
+            UciCommand::Debug(on) => write!(f, "debug {}", if *on { "on" } else { "off" }),
+            UciCommand::IsReady => write!(f, "isready"),
+            UciCommand::SetOption { name, value } => match value {
+                Some(value) => write!(f, "setoption name {} value {}", name, value),
+                None => write!(f, "setoption name {}", name),
+            },
+            UciCommand::Register => write!(f, "register later"),
+            UciCommand::UciNewGame => write!(f, "ucinewgame"),
+            UciCommand::Position { fen, moves } => {
+                match fen {
+                    Some(fen) => write!(f, "position fen {}", fen)?,
+                    None => write!(f, "position startpos")?,
+                }
+                if !moves.is_empty() {
+                    write!(f, " moves {}", moves.join(" "))?;
+                }
+                Ok(())
+            }
+            UciCommand::Go(params) => write!(f, "{}", params),
+            UciCommand::Stop => write!(f, "stop"),
+            UciCommand::PonderHit => write!(f, "ponderhit"),
+            UciCommand::Quit => write!(f, "quit"),
+            UciCommand::Unknown(line) => write!(f, "{}", line),
+        }
+    }
+}
+
+impl fmt::Display for GoParams {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "go")?;
+        if !self.searchmoves.is_empty() {
+            write!(f, " searchmoves {}", self.searchmoves.join(" "))?;
+        }
+        if self.ponder {
+            write!(f, " ponder")?;
+        }
+        let numbers = [
+            ("wtime", self.wtime),
+            ("btime", self.btime),
+            ("winc", self.winc),
+            ("binc", self.binc),
+            ("movestogo", self.movestogo.map(u64::from)),
+            ("depth", self.depth.map(u64::from)),
+            ("nodes", self.nodes),
+            ("mate", self.mate.map(u64::from)),
+            ("movetime", self.movetime),
+            ("perft", self.perft.map(u64::from)),
+        ];
+        for (keyword, value) in numbers {
+            if let Some(value) = value {
+                write!(f, " {} {}", keyword, value)?;
+            }
+        }
+        if self.infinite {
+            write!(f, " infinite")?;
+        }
+        Ok(())
+    }
+}
+
+/// Where the win and loss chances of `wdl` cross one half, and how quickly they get there,
+/// in evaluation units
+const WDL_MIDPOINT: f64 = 600.0;
+const WDL_SCALE: f64 = 150.0;
+
+/// Win, draw and loss chances in per mille for a score from the side to move's point of view,
+/// from a logistic model of the evaluation
+pub fn wdl(score: i32) -> (u32, u32, u32) {
+    let chance = |x: f64| (1000.0 / (1.0 + ((WDL_MIDPOINT - x) / WDL_SCALE).exp())).round() as u32;
+    let win = chance(score as f64);
+    let loss = chance(-(score as f64));
+    (win, 1000 - win - loss, loss)
+}
+
+/// The `info` line for one principal variation, with the `wdl` chances when `show_wdl` is set
+#[allow(clippy::too_many_arguments)]
+pub fn info_line(
+    state: &ChessState,
+    depth: u8,
+    multi_pv: usize,
+    line: &PvLine,
+    nodes: u64,
+    tbhits: u64,
+    elapsed: Duration,
+    show_wdl: bool,
+) -> String {
+    let score = match eval::mate_in(line.score, state.turn) {
+        Some(moves) => format!("mate {}", moves),
+        None => format!("cp {}", line.score * state.turn.sign() as i32),
+    };
+    let score = if show_wdl {
+        let (win, draw, loss) = wdl(line.score.saturating_mul(state.turn.sign() as i32));
+        format!("{} wdl {} {} {}", score, win, draw, loss)
+    } else {
+        score
+    };
+    let millis = elapsed.as_millis().max(1) as u64;
+    format!(
+        "info depth {} multipv {} score {} nodes {} nps {} tbhits {} time {} pv {}",
+        depth,
+        multi_pv,
+        score,
+        nodes,
+        nodes * 1000 / millis,
+        tbhits,
+        millis,
+        translator::pv_to_uci(state, &line.moves)
+    )
+}
+
+/// How long a `go` may think for the side to move, or `None` to search without a clock
+pub fn time_limit(params: &GoParams, color: Color, overhead: u64) -> Option<Duration> {
+    if let Some(movetime) = params.movetime {
+        return Some(Duration::from_millis(
+            movetime.saturating_sub(overhead).max(1),
+        ));
+    }
+    let (time_left, increment, opponent_time) = if color.is_white() {
+        (params.wtime, params.winc, params.btime)
+    } else {
+        (params.btime, params.binc, params.wtime)
+    };
+    time_left.map(|x| {
+        time_manager::allocate(
+            x,
+            increment.unwrap_or(0),
+            params.movestogo,
+            opponent_time,
+            overhead,
+        )
+    })
+}
+
+/// Whether a token has the shape of a UCI move such as `e2e4` or `a7a8q`
+pub fn is_move(token: &str) -> bool {
+    let chars: Vec<char> = token.chars().collect();
+    (chars.len() == 4 || (chars.len() == 5 && "qrbn".contains(chars[4])))
+        && ('a'..='h').contains(&chars[0])
+        && ('1'..='8').contains(&chars[1])
+        && ('a'..='h').contains(&chars[2])
+        && ('1'..='8').contains(&chars[3])
+}
+
+/// Times in milliseconds. Some GUIs send negative times when a clock runs out.
+fn parse_time(value: &str) -> Option<u64> {
+    value.parse::<i64>().ok().map(|x| x.max(0) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn simple_commands() {
+        assert_eq!(parse_command("uci"), UciCommand::Uci);
+        assert_eq!(parse_command("isready\n"), UciCommand::IsReady);
+        assert_eq!(parse_command("ucinewgame"), UciCommand::UciNewGame);
+        assert_eq!(parse_command("register later"), UciCommand::Register);
+        assert_eq!(parse_command("stop"), UciCommand::Stop);
+        assert_eq!(parse_command("ponderhit"), UciCommand::PonderHit);
+        assert_eq!(parse_command("quit"), UciCommand::Quit);
+    }
+
+    #[test]
+    fn debug() {
+        assert_eq!(parse_command("debug on"), UciCommand::Debug(true));
+        assert_eq!(parse_command("debug off"), UciCommand::Debug(false));
+    }
+
+    #[test]
+    fn unknown_tokens() {
+        assert_eq!(parse_command("joho debug on"), UciCommand::Debug(true));
+        assert_eq!(
+            parse_command("  hello   world "),
+            UciCommand::Unknown("hello   world".to_string())
+        );
+        assert_eq!(parse_command(""), UciCommand::Unknown(String::new()));
+    }
+
+    #[test]
+    fn setoption() {
+        assert_eq!(
+            parse_command("setoption name Hash value 128"),
+            UciCommand::SetOption {
+                name: "Hash".to_string(),
+                value: Some("128".to_string())
+            }
+        );
+        assert_eq!(
+            parse_command("setoption  name Move Overhead   value 30 "),
+            UciCommand::SetOption {
+                name: "Move Overhead".to_string(),
+                value: Some("30".to_string())
+            }
+        );
+        assert_eq!(
+            parse_command("setoption name Clear Hash"),
+            UciCommand::SetOption {
+                name: "Clear Hash".to_string(),
+                value: None
+            }
+        );
+        assert_eq!(
+            parse_command("setoption name SyzygyPath value /home/user/tb files"),
+            UciCommand::SetOption {
+                name: "SyzygyPath".to_string(),
+                value: Some("/home/user/tb files".to_string())
+            }
+        );
+    }
+
+    #[test]
+    fn position_startpos() {
+        assert_eq!(
+            parse_command("position startpos"),
+            UciCommand::Position {
+                fen: None,
+                moves: Vec::new()
+            }
+        );
+        assert_eq!(
+            parse_command("position   startpos  moves e2e4 \t e7e5 "),
+            UciCommand::Position {
+                fen: None,
+                moves: vec!["e2e4".to_string(), "e7e5".to_string()]
+            }
+        );
+    }
+
+    #[test]
+    fn position_fen() {
+        assert_eq!(
+            parse_command(
+                "position fen rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1"
+            ),
+            UciCommand::Position {
+                fen: Some(
+                    "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1".to_string()
+                ),
+                moves: Vec::new()
+            }
+        );
+        assert_eq!(
+            parse_command("position fen 7k/P5pp/8/8/8/8/8/R3K2R w KQ -  moves a7a8q h8g8"),
+            UciCommand::Position {
+                fen: Some("7k/P5pp/8/8/8/8/8/R3K2R w KQ -".to_string()),
+                moves: vec!["a7a8q".to_string(), "h8g8".to_string()]
+            }
+        );
+    }
+
+    #[test]
+    fn position_empty_moves() {
+        assert_eq!(
+            parse_command("position startpos moves"),
+            UciCommand::Position {
+                fen: None,
+                moves: Vec::new()
+            }
+        );
+    }
+
+    #[test]
+    fn moves() {
+        assert!(is_move("e2e4"));
+        assert!(is_move("a7a8q"));
+        assert!(!is_move("e2e9"));
+        assert!(!is_move("e7e8k"));
+        assert!(!is_move("O-O"));
+    }
+
+    #[test]
+    fn go() {
+        assert_eq!(parse_command("go"), UciCommand::Go(GoParams::default()));
+        assert_eq!(
+            parse_command("go wtime 300000 btime -20 winc 2000 binc 2000 movestogo 40"),
+            UciCommand::Go(GoParams {
+                wtime: Some(300000),
+                btime: Some(0),
+                winc: Some(2000),
+                binc: Some(2000),
+                movestogo: Some(40),
+                ..GoParams::default()
+            })
+        );
+        assert_eq!(
+            parse_command("go depth 6 nodes 100000 mate 3 movetime 1000"),
+            UciCommand::Go(GoParams {
+                depth: Some(6),
+                nodes: Some(100000),
+                mate: Some(3),
+                movetime: Some(1000),
+                ..GoParams::default()
+            })
+        );
+    }
+
+    #[test]
+    fn go_flags() {
+        assert_eq!(
+            parse_command("go infinite"),
+            UciCommand::Go(GoParams {
+                infinite: true,
+                ..GoParams::default()
+            })
+        );
+        assert_eq!(
+            parse_command("go ponder wtime 1000 btime 1000"),
+            UciCommand::Go(GoParams {
+                ponder: true,
+                wtime: Some(1000),
+                btime: Some(1000),
+                ..GoParams::default()
+            })
+        );
+        assert_eq!(
+            parse_command("go perft 4"),
+            UciCommand::Go(GoParams {
+                perft: Some(4),
+                ..GoParams::default()
+            })
+        );
+    }
+
+    #[test]
+    fn go_searchmoves() {
+        assert_eq!(
+            parse_command("go searchmoves e2e4 d2d4 depth 3"),
+            UciCommand::Go(GoParams {
+                searchmoves: vec!["e2e4".to_string(), "d2d4".to_string()],
+                depth: Some(3),
+                ..GoParams::default()
+            })
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let commands = [
+            "uci",
+            "debug off",
+            "setoption name Move Overhead value 30",
+            "setoption name Clear Hash",
+            "ucinewgame",
+            "position startpos",
+            "position fen 7k/P5pp/8/8/8/8/8/R3K2R w KQ - 0 1 moves a7a8q h8g8",
+            "go searchmoves e2e4 d2d4 ponder wtime 1000 btime 900 winc 10 binc 10 depth 3",
+            "go movetime 500",
+            "go infinite",
+            "quit",
+        ];
+        for line in commands {
+            let command = parse_command(line);
+            assert_eq!(command.to_string(), line);
+            assert_eq!(parse_command(&command.to_string()), command);
+        }
+    }
+
+    #[test]
+    fn wdl_output() {
+        let (win, draw, loss) = wdl(0);
+        assert_eq!(win, loss);
+        assert_eq!(win + draw + loss, 1000);
+        assert!(draw > 900);
+        let (win, _, loss) = wdl(1500);
+        assert!(win > 990 && loss == 0);
+        assert_eq!(wdl(-1500), (loss, 1000 - win - loss, win));
+        assert_eq!(wdl(eval::MATE - 3), (1000, 0, 0));
+
+        //Chances are for the side to move, like the score
+        let state = ChessState::from_fen("4k3/8/8/8/8/8/8/4K2Q b - - 0 1");
+        let line = PvLine {
+            moves: Vec::new(),
+            score: 1500,
+        };
+        let info = info_line(&state, 1, 1, &line, 10, 0, Duration::ZERO, true);
+        let (win, draw, loss) = wdl(-1500);
+        assert!(info.contains(&format!(
+            "score cp -1500 wdl {} {} {} nodes",
+            win, draw, loss
+        )));
+        let info = info_line(&state, 1, 1, &line, 10, 0, Duration::ZERO, false);
+        assert!(info.contains("score cp -1500 nodes") && !info.contains("wdl"));
+    }
+
+    #[test]
+    fn mate_output() {
+        //The distance comes from the score, even when a table hit cut the line short
+        let state = ChessState::from_fen("k7/8/2K5/8/8/8/8/7R w - - 0 1");
+        let line = PvLine {
+            moves: vec![state.parse_lan("Kc6-b6").unwrap()],
+            score: eval::MATE - 3,
+        };
+        let info = info_line(&state, 4, 1, &line, 10, 0, Duration::ZERO, false);
+        assert!(info.contains("score mate 2 nodes"));
+        let state = ChessState::from_fen("k7/8/1K6/8/8/8/8/7R b - - 0 1");
+        let line = PvLine {
+            moves: Vec::new(),
+            score: eval::MATE - 2,
+        };
+        let info = info_line(&state, 4, 1, &line, 10, 0, Duration::ZERO, false);
+        assert!(info.contains("score mate -1 nodes"));
+    }
+}
@@ -0,0 +1,313 @@
+use crate::eval::MATE;
+use crate::uci::{GoParams, UciCommand};
+use std::io::{self, BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long an engine may take to answer `uci` and `isready`
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long an engine gets to exit after `quit` before it is killed
+const QUIT_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// A score as an engine reports it, from the side to move's point of view
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EngineScore {
+    Cp(i32),
+    /// Moves until mate, negative when the side to move is getting mated
+    Mate(i32),
+}
+
+impl EngineScore {
+    /// The score from white's point of view, with mates scored `MATE` less the half-moves to
+    /// mate like the scores of our own search
+    pub fn white_relative(self, white_to_move: bool) -> i32 {
+        let score = match self {
+            EngineScore::Cp(x) => x,
+            EngineScore::Mate(x) if x > 0 => MATE - (2 * x - 1),
+            EngineScore::Mate(x) => -(MATE + 2 * x),
+        };
+        if white_to_move {
+            score
+        } else {
+            -score
+        }
+    }
+}
+
+/// The parts of an `info` line used to follow a search. Fields the line leaves out are `None`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SearchInfo {
+    pub depth: Option<u32>,
+    pub multipv: Option<u32>,
+    pub score: Option<EngineScore>,
+    pub nodes: Option<u64>,
+    pub time: Option<u64>,
+    pub pv: Vec<String>,
+}
+
+/// Reads an `info` line, `None` for any other line and for `info string`
+pub fn parse_info(line: &str) -> Option<SearchInfo> {
+    let tokens: Vec<&str> = line.split_whitespace().collect();
+    if tokens.first() != Some(&"info") || tokens.get(1) == Some(&"string") {
+        return None;
+    }
+    let mut info = SearchInfo::default();
+    let mut idx = 1;
+    while idx < tokens.len() {
+        let value = tokens.get(idx + 1).copied().unwrap_or("");
+        match tokens[idx] {
+            "depth" => info.depth = value.parse().ok(),
+            "multipv" => info.multipv = value.parse().ok(),
+            "nodes" => info.nodes = value.parse().ok(),
+            "time" => info.time = value.parse().ok(),
+            "score" => {
+                let amount = tokens.get(idx + 2).and_then(|x| x.parse().ok());
+                info.score = match (value, amount) {
+                    ("cp", Some(x)) => Some(EngineScore::Cp(x)),
+                    ("mate", Some(x)) => Some(EngineScore::Mate(x)),
+                    _ => None,
+                };
+                idx += 1;
+            }
+            "pv" => {
+                info.pv = tokens[idx + 1..].iter().map(|x| x.to_string()).collect();
+                break;
+            }
+            //Bounds and the other single word fields have no value to skip
+            _ => {
+                idx += 1;
+                continue;
+            }
+        }
+        idx += 2;
+    }
+    Some(info)
+}
+
+/// The answer to `go`
+#[derive(Debug, Clone, PartialEq)]
+pub struct BestMove {
+    /// In UCI notation, `0000` when the engine had no move
+    pub best: String,
+    pub ponder: Option<String>,
+    /// The last `info` of the main line that had a score
+    pub info: Option<SearchInfo>,
+    /// From sending `go` until `bestmove` arrived
+    pub elapsed: Duration,
+}
+
+/// A UCI engine running as a child process
+pub struct UciEngine {
+    /// From `id name`, or the command when the engine does not say
+    pub name: String,
+    pub author: Option<String>,
+    /// The names of the options the engine announced
+    pub options: Vec<String>,
+    child: Child,
+    stdin: ChildStdin,
+    /// Lines from the engine, read on their own thread so reads can time out
+    lines: Receiver<String>,
+}
+
+impl UciEngine {
+    /// Starts `command` with `args` and goes through the `uci` handshake
+    pub fn spawn(command: &str, args: &[String]) -> io::Result<UciEngine> {
+        let mut child = Command::new(command)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+        let stdin = child.stdin.take().unwrap();
+        let stdout = child.stdout.take().unwrap();
+        let (sender, lines) = mpsc::channel::<String>();
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                let Ok(line) = line else {
+                    break;
+                };
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        let mut engine = UciEngine {
+            name: command.to_string(),
+            author: None,
+            options: Vec::new(),
+            child,
+            stdin,
+            lines,
+        };
+        engine.send(&UciCommand::Uci)?;
+        let deadline = Instant::now() + HANDSHAKE_TIMEOUT;
+        loop {
+            let line = engine.read_line(Some(deadline))?;
+            let line = line.trim();
+            if line == "uciok" {
+                break;
+            }
+            if let Some(name) = line.strip_prefix("id name ") {
+                engine.name = name.trim().to_string();
+            } else if let Some(author) = line.strip_prefix("id author ") {
+                engine.author = Some(author.trim().to_string());
+            } else if let Some(option) = line.strip_prefix("option name ") {
+                let name = option.split(" type ").next().unwrap_or(option);
+                engine.options.push(name.trim().to_string());
+            }
+        }
+        Ok(engine)
+    }
+
+    pub fn send(&mut self, command: &UciCommand) -> io::Result<()> {
+        writeln!(self.stdin, "{}", command)?;
+        self.stdin.flush()
+    }
+
+    /// The next line from the engine, failing with `TimedOut` once `deadline` passes and
+    /// with `UnexpectedEof` when the engine has exited
+    pub fn read_line(&mut self, deadline: Option<Instant>) -> io::Result<String> {
+        match deadline {
+            Some(deadline) => self
+                .lines
+                .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+                .map_err(|err| match err {
+                    RecvTimeoutError::Timeout => io::Error::from(io::ErrorKind::TimedOut),
+                    RecvTimeoutError::Disconnected => io::Error::from(io::ErrorKind::UnexpectedEof),
+                }),
+            None => self
+                .lines
+                .recv()
+                .map_err(|_| io::Error::from(io::ErrorKind::UnexpectedEof)),
+        }
+    }
+
+    pub fn set_option(&mut self, name: &str, value: Option<&str>) -> io::Result<()> {
+        self.send(&UciCommand::SetOption {
+            name: name.to_string(),
+            value: value.map(str::to_string),
+        })
+    }
+
+    /// Sends `isready` and waits for `readyok`
+    pub fn is_ready(&mut self) -> io::Result<()> {
+        self.send(&UciCommand::IsReady)?;
+        let deadline = Instant::now() + HANDSHAKE_TIMEOUT;
+        while self.read_line(Some(deadline))?.trim() != "readyok" {}
+        Ok(())
+    }
+
+    /// Tells the engine the next position is from another game and waits until it is ready
+    pub fn new_game(&mut self) -> io::Result<()> {
+        self.send(&UciCommand::UciNewGame)?;
+        self.is_ready()
+    }
+
+    /// Sets up the position after `moves`, in UCI notation, from `fen` or the start position
+    pub fn position(&mut self, fen: Option<&str>, moves: &[String]) -> io::Result<()> {
+        self.send(&UciCommand::Position {
+            fen: fen.map(str::to_string),
+            moves: moves.to_vec(),
+        })
+    }
+
+    /// Searches the position and waits for `bestmove`. Past `timeout` the engine is told to
+    /// stop, and if it still does not answer the search fails with `TimedOut`.
+    pub fn go(&mut self, params: &GoParams, timeout: Option<Duration>) -> io::Result<BestMove> {
+        let start = Instant::now();
+        self.send(&UciCommand::Go(params.clone()))?;
+        let mut deadline = timeout.map(|x| start + x);
+        let mut stopped = false;
+        let mut last_info: Option<SearchInfo> = None;
+        loop {
+            let line = match self.read_line(deadline) {
+                Ok(line) => line,
+                Err(err) if err.kind() == io::ErrorKind::TimedOut && !stopped => {
+                    self.send(&UciCommand::Stop)?;
+                    stopped = true;
+                    deadline = Some(Instant::now() + HANDSHAKE_TIMEOUT);
+                    continue;
+                }
+                Err(err) => return Err(err),
+            };
+            if let Some(info) = parse_info(&line) {
+                if info.score.is_some() && info.multipv.unwrap_or(1) == 1 {
+                    last_info = Some(info);
+                }
+                continue;
+            }
+            let mut tokens = line.split_whitespace();
+            if tokens.next() != Some("bestmove") {
+                continue;
+            }
+            let elapsed = start.elapsed();
+            let best = tokens.next().unwrap_or("0000").to_string();
+            let ponder = match (tokens.next(), tokens.next()) {
+                (Some("ponder"), Some(m)) => Some(m.to_string()),
+                _ => None,
+            };
+            return Ok(BestMove {
+                best,
+                ponder,
+                info: last_info,
+                elapsed,
+            });
+        }
+    }
+
+    /// Sends `quit` and waits for the engine to exit, killing it if it does not
+    pub fn quit(mut self) {
+        self.shut_down();
+    }
+
+    fn shut_down(&mut self) {
+        let _ = self.send(&UciCommand::Quit);
+        let deadline = Instant::now() + QUIT_TIMEOUT;
+        while Instant::now() < deadline {
+            if let Ok(Some(_)) = self.child.try_wait() {
+                return;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+impl Drop for UciEngine {
+    fn drop(&mut self) {
+        if let Ok(None) = self.child.try_wait() {
+            self.shut_down();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn info_lines() {
+        let info = parse_info(
+            "info depth 12 seldepth 18 multipv 1 score cp -35 lowerbound nodes 52011 nps 1000 \
+             time 52 pv e2e4 e7e5 g1f3",
+        )
+        .unwrap();
+        assert_eq!(info.depth, Some(12));
+        assert_eq!(info.multipv, Some(1));
+        assert_eq!(info.score, Some(EngineScore::Cp(-35)));
+        assert_eq!(info.nodes, Some(52011));
+        assert_eq!(info.time, Some(52));
+        assert_eq!(info.pv, ["e2e4", "e7e5", "g1f3"]);
+
+        let mate = parse_info("info depth 3 score mate -2 pv h7h6").unwrap();
+        assert_eq!(mate.score, Some(EngineScore::Mate(-2)));
+        assert_eq!(EngineScore::Mate(-2).white_relative(false), MATE - 4);
+        assert_eq!(EngineScore::Mate(3).white_relative(true), MATE - 5);
+        assert_eq!(EngineScore::Cp(-35).white_relative(false), 35);
+        assert_eq!(parse_info("info string Found 5 tablebases"), None);
+        assert_eq!(parse_info("bestmove e2e4"), None);
+    }
+}
@@ -0,0 +1,308 @@
+use crate::chess_init::ChessState;
+use crate::chessbot::PvLine;
+use crate::eval;
+use crate::translator;
+use crate::types::{Color, PieceKind};
+use crate::uci;
+use std::time::Duration;
+
+/// A time control set by `level`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Level {
+    /// Moves per time control, 0 for the whole game
+    pub moves: u32,
+    pub base: Duration,
+    pub increment: Duration,
+}
+
+/// A command from a CECP (XBoard/WinBoard) GUI
+#[derive(Debug, Clone, PartialEq)]
+pub enum XboardCommand {
+    Xboard,
+    Protover(u32),
+    New,
+    Quit,
+    Force,
+    Go,
+    PlayOther,
+    Level(Level),
+    /// `st`, exactly this long for every move
+    MoveTime(Duration),
+    /// `sd`
+    Depth(u8),
+    /// `time`, the engine's clock in centiseconds
+    Time(u64),
+    /// `otim`, the opponent's clock in centiseconds
+    Otim(u64),
+    /// A move in coordinate notation, with or without `usermove`
+    UserMove(String),
+    /// `?`, move at once
+    MoveNow,
+    Ping(String),
+    /// `result` with the result and the comment explaining it
+    Result(String),
+    SetBoard(String),
+    Undo,
+    Remove,
+    Post,
+    NoPost,
+    Analyze,
+    /// `exit`, leaving analyze mode
+    Exit,
+    /// `memory`, the hash size in megabytes
+    Memory(usize),
+    /// `cores`
+    Cores(usize),
+    /// Commands the engine accepts without doing anything, such as `hard` or `accepted`
+    Ignored(String),
+    Unknown(String),
+}
+
+/// Commands the engine has nothing to do for. Pondering, draw offers, hints and opponent
+/// information are not supported.
+const IGNORED: [&str; 18] = [
+    "accepted", "rejected", "random", "hard", "easy", "draw", "hint", "bk", "computer", "name",
+    "rating", "ics", "white", "black", "variant", ".", "nps", "option",
+];
+
+/// Parses one line from the GUI
+pub fn parse_command(line: &str) -> XboardCommand {
+    let line = line.trim();
+    let (command, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    let rest = rest.trim();
+    let unknown = || XboardCommand::Unknown(line.to_string());
+    match command {
+        "xboard" => XboardCommand::Xboard,
+        "protover" => rest
+            .parse()
+            .map_or_else(|_| unknown(), XboardCommand::Protover),
+        "new" => XboardCommand::New,
+        "quit" => XboardCommand::Quit,
+        "force" => XboardCommand::Force,
+        "go" => XboardCommand::Go,
+        "playother" => XboardCommand::PlayOther,
+        "level" => parse_level(rest).map_or_else(unknown, XboardCommand::Level),
+        "st" => parse_seconds(rest).map_or_else(unknown, XboardCommand::MoveTime),
+        "sd" => rest
+            .parse()
+            .map_or_else(|_| unknown(), XboardCommand::Depth),
+        //Some GUIs send negative times when a clock runs out
+        "time" | "otim" => match rest.parse::<i64>() {
+            Ok(x) if command == "time" => XboardCommand::Time(x.max(0) as u64),
+            Ok(x) => XboardCommand::Otim(x.max(0) as u64),
+            Err(_) => unknown(),
+        },
+        "usermove" => XboardCommand::UserMove(rest.to_string()),
+        "?" => XboardCommand::MoveNow,
+        "ping" => XboardCommand::Ping(rest.to_string()),
+        "result" => XboardCommand::Result(rest.to_string()),
+        "setboard" => XboardCommand::SetBoard(rest.to_string()),
+        "undo" => XboardCommand::Undo,
+        "remove" => XboardCommand::Remove,
+        "post" => XboardCommand::Post,
+        "nopost" => XboardCommand::NoPost,
+        "analyze" => XboardCommand::Analyze,
+        "exit" => XboardCommand::Exit,
+        "memory" => rest
+            .parse()
+            .map_or_else(|_| unknown(), XboardCommand::Memory),
+        "cores" => rest
+            .parse()
+            .map_or_else(|_| unknown(), XboardCommand::Cores),
+        x if IGNORED.contains(&x) => XboardCommand::Ignored(line.to_string()),
+        //Without usermove=1 moves arrive on their own
+        x if uci::is_move(x) && rest.is_empty() => XboardCommand::UserMove(x.to_string()),
+        _ => unknown(),
+    }
+}
+
+/// Reads `40 5 0`, `0 2:30 1` or `0 5 0.5`: moves per control, minutes or minutes:seconds,
+/// and the increment in seconds
+fn parse_level(args: &str) -> Option<Level> {
+    let args: Vec<&str> = args.split_whitespace().collect();
+    let [moves, base, increment] = args[..] else {
+        return None;
+    };
+    let (minutes, seconds) = base.split_once(':').unwrap_or((base, "0"));
+    let base = minutes.parse::<u64>().ok()? * 60 + seconds.parse::<u64>().ok()?;
+    Some(Level {
+        moves: moves.parse().ok()?,
+        base: Duration::from_secs(base),
+        increment: parse_seconds(increment)?,
+    })
+}
+
+fn parse_seconds(text: &str) -> Option<Duration> {
+    text.parse::<f64>()
+        .ok()
+        .filter(|x| *x >= 0.0 && x.is_finite())
+        .map(Duration::from_secs_f64)
+}
+
+/// The `feature` line answering `protover 2`
+pub fn features() -> String {
+    format!(
+        "feature myname=\"chess-rs {}\" setboard=1 usermove=1 ping=1 playother=1 san=0 \
+         analyze=1 colors=0 time=1 draw=0 sigint=0 sigterm=0 reuse=1 memory=1 smp=1 done=1",
+        env!("CARGO_PKG_VERSION")
+    )
+}
+
+/// The thinking output for a principal variation: depth, score in centipawns for the side to
+/// move, time in centiseconds, nodes and the moves in SAN
+pub fn thinking_line(
+    state: &ChessState,
+    depth: u8,
+    line: &PvLine,
+    nodes: u64,
+    elapsed: Duration,
+) -> String {
+    //Mates are shown as 100000 plus the moves to mate, the convention GUIs understand
+    let score: i64 = match eval::mate_in(line.score, state.turn) {
+        Some(moves) if moves > 0 => 100000 + moves as i64,
+        Some(moves) => -100000 + moves as i64,
+        None => line.score as i64 * state.turn.sign() as i64,
+    };
+    let mut pv: Vec<String> = Vec::new();
+    let mut position = state.copy();
+    for m in line.moves.iter() {
+        pv.push(translator::to_san(&position, m));
+        position = position.do_move(m);
+    }
+    format!(
+        "{} {} {} {} {}",
+        depth,
+        score,
+        elapsed.as_millis() / 10,
+        nodes,
+        pv.join(" ")
+    )
+}
+
+/// Whether a `setboard` position can be played: one king of each colour and the side that
+/// just moved not in check
+pub fn playable(state: &ChessState) -> bool {
+    let kings = |color: Color| {
+        state
+            .board
+            .pieces()
+            .filter(|(_, piece)| piece.kind == PieceKind::King && piece.color == color)
+            .count()
+    };
+    if kings(Color::White) != 1 || kings(Color::Black) != 1 {
+        return false;
+    }
+    let mut other = state.copy();
+    other.turn = !state.turn;
+    !other.check()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eval::MATE;
+
+    #[test]
+    fn commands() {
+        assert_eq!(parse_command("protover 2"), XboardCommand::Protover(2));
+        assert_eq!(
+            parse_command("level 40 2:30 0.5"),
+            XboardCommand::Level(Level {
+                moves: 40,
+                base: Duration::from_secs(150),
+                increment: Duration::from_millis(500),
+            })
+        );
+        assert_eq!(
+            parse_command("level 0 5 3"),
+            XboardCommand::Level(Level {
+                moves: 0,
+                base: Duration::from_secs(300),
+                increment: Duration::from_secs(3),
+            })
+        );
+        assert_eq!(
+            parse_command("st 2"),
+            XboardCommand::MoveTime(Duration::from_secs(2))
+        );
+        assert_eq!(parse_command("sd 6"), XboardCommand::Depth(6));
+        assert_eq!(parse_command("time 30000"), XboardCommand::Time(30000));
+        assert_eq!(parse_command("otim -20"), XboardCommand::Otim(0));
+        assert_eq!(
+            parse_command("usermove e7e8q"),
+            XboardCommand::UserMove("e7e8q".to_string())
+        );
+        assert_eq!(
+            parse_command("e2e4"),
+            XboardCommand::UserMove("e2e4".to_string())
+        );
+        assert_eq!(
+            parse_command("result 1-0 {White mates}"),
+            XboardCommand::Result("1-0 {White mates}".to_string())
+        );
+        assert_eq!(
+            parse_command("setboard 4k3/8/8/8/8/8/8/4K3 w - - 0 1"),
+            XboardCommand::SetBoard("4k3/8/8/8/8/8/8/4K3 w - - 0 1".to_string())
+        );
+        assert_eq!(
+            parse_command("ping 7"),
+            XboardCommand::Ping("7".to_string())
+        );
+        assert_eq!(
+            parse_command("hard"),
+            XboardCommand::Ignored("hard".to_string())
+        );
+        assert_eq!(
+            parse_command("level 40"),
+            XboardCommand::Unknown("level 40".to_string())
+        );
+        assert!(features().ends_with("done=1"));
+    }
+
+    #[test]
+    fn output() {
+        let state = ChessState::from_fen("6k1/5ppp/8/8/8/8/8/R5K1 b - - 0 1");
+        let m = state.parse_lan("Kg8-f8").unwrap();
+        let line = PvLine {
+            score: -120,
+            moves: vec![m],
+        };
+        assert_eq!(
+            thinking_line(&state, 3, &line, 500, Duration::from_millis(1234)),
+            "3 120 123 500 Kf8"
+        );
+        let white = ChessState::from_fen("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1");
+        let mate = PvLine {
+            score: MATE - 1,
+            moves: vec![white.parse_lan("Ra1-a8").unwrap()],
+        };
+        assert!(thinking_line(&white, 1, &mate, 1, Duration::ZERO).starts_with("1 100001 0 1 Ra8#"));
+        //A PV cut short by the table still shows the mate distance of the score
+        let state = ChessState::from_fen("k7/8/2K5/8/8/8/8/7R w - - 0 1");
+        let mate = PvLine {
+            score: MATE - 3,
+            moves: vec![state.parse_lan("Kc6-b6").unwrap()],
+        };
+        assert_eq!(
+            thinking_line(&state, 4, &mate, 9, Duration::ZERO),
+            "4 100002 0 9 Kb6"
+        );
+        let state = ChessState::from_fen("k7/8/1K6/8/8/8/8/7R b - - 0 1");
+        let mated = PvLine {
+            score: MATE - 2,
+            moves: vec![state.parse_lan("Ka8-b8").unwrap()],
+        };
+        assert_eq!(
+            thinking_line(&state, 4, &mated, 9, Duration::ZERO),
+            "4 -100001 0 9 Kb8"
+        );
+
+        assert!(playable(&state));
+        assert!(!playable(&ChessState::from_fen(
+            "8/8/8/8/8/8/8/4K3 w - - 0 1"
+        )));
+        assert!(!playable(&ChessState::from_fen(
+            "4k3/8/8/8/8/8/8/4R2K w - - 0 1"
+        )));
+    }
+}
@@ -0,0 +1,47 @@
+use crate::chess_init::ChessState;
+
+const PIECE_KEYS: usize = 12 * 64;
+const CASTLING_KEYS: usize = 16;
+const EN_PASSANT_KEYS: usize = 8;
+const KEY_COUNT: usize = PIECE_KEYS + CASTLING_KEYS + EN_PASSANT_KEYS + 1;
+
+/// Random keys generated at compile time with splitmix64, so there is no global state to set up
+const KEYS: [u64; KEY_COUNT] = {
+    let mut keys = [0u64; KEY_COUNT];
+    let mut seed: u64 = 0x2545_f491_4f6c_dd1d;
+    let mut idx = 0;
+    while idx < KEY_COUNT {
+        seed = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = seed;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        keys[idx] = z ^ (z >> 31);
+        idx += 1;
+    }
+    keys
+};
+
+impl ChessState {
+    /// Zobrist hash of the position, used to key the transposition table
+    pub fn hash(&self) -> u64 {
+        let mut hash: u64 = 0;
+        for (square, piece) in self.board.pieces() {
+            //White pieces take the first six blocks of keys and black the last six
+            let kind = piece.kind.index() - 1 + if piece.color.is_white() { 0 } else { 6 };
+            hash ^= KEYS[kind * 64 + square.index()];
+        }
+        //One key per castling right, laid out like the bits of the rights
+        for bit in 0..CASTLING_KEYS {
+            if self.castling.bits() & (1 << bit) != 0 {
+                hash ^= KEYS[PIECE_KEYS + bit];
+            }
+        }
+        if let Some(file) = self.en_passant.iter().position(|x| *x != 0) {
+            hash ^= KEYS[PIECE_KEYS + CASTLING_KEYS + file];
+        }
+        if !self.turn.is_white() {
+            hash ^= KEYS[KEY_COUNT - 1];
+        }
+        hash
+    }
+}
@@ -0,0 +1,64 @@
+use chess_rs::selfplay::Adjudication;
+use chess_rs::tournament::{run_tournament, EngineCommand, SearchLimits, TournamentOptions};
+use chess_rs::ChessState;
+use std::time::Duration;
+
+fn engine(name: &str) -> EngineCommand {
+    EngineCommand {
+        name: Some(name.to_string()),
+        command: env!("CARGO_BIN_EXE_chess-rs").to_string(),
+        ..EngineCommand::default()
+    }
+}
+
+#[test]
+fn self_tournament() {
+    let options = TournamentOptions {
+        engines: [engine("first"), engine("second")],
+        limits: SearchLimits {
+            depth: Some(1),
+            ..SearchLimits::default()
+        },
+        games: Some(4),
+        sprt: None,
+        openings: vec![
+            ChessState::new_board().to_fen(),
+            "rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq - 0 2".to_string(),
+        ],
+        concurrency: 1,
+        adjudication: Adjudication {
+            max_moves: 40,
+            ..Adjudication::default()
+        },
+        time_margin: Duration::from_secs(1),
+    };
+    let mut rounds = Vec::new();
+    let standings = run_tournament(&options, &mut |game, _| {
+        let (white, black) = if game.first_white {
+            ("first", "second")
+        } else {
+            ("second", "first")
+        };
+        assert_eq!(game.pgn.tag("White"), Some(white));
+        assert_eq!(game.pgn.tag("Black"), Some(black));
+        assert_eq!(game.pgn.tag("Event"), Some("chess-rs match"));
+        let round = (game.round + 1).to_string();
+        assert_eq!(game.pgn.tag("Round"), Some(round.as_str()));
+        assert_eq!(game.pgn.result, game.outcome.result());
+        //The first game of each pair gives the first engine white
+        assert_eq!(game.first_white, game.round % 2 == 0);
+        rounds.push(game.round);
+    })
+    .unwrap();
+    rounds.sort();
+    assert_eq!(rounds, [0, 1, 2, 3]);
+    let score = standings.score;
+    assert_eq!(score.wins + score.draws + score.losses, 4);
+    assert_eq!(standings.pentanomial.counts.iter().sum::<u32>(), 2);
+    //The pairs hold the same half points as the games
+    let half_points: usize = (0..5)
+        .map(|x| x * standings.pentanomial.counts[x] as usize)
+        .sum();
+    assert_eq!(half_points as u32, 2 * score.wins + score.draws);
+    assert_eq!(standings.sprt, None);
+}
@@ -0,0 +1,136 @@
+use chess_rs::uci::{GoParams, UciCommand};
+use chess_rs::uci_client::{EngineScore, UciEngine};
+use chess_rs::{translator, ChessState};
+use std::time::{Duration, Instant};
+
+/// The engine's next line starting with `prefix`
+fn read_until(engine: &mut UciEngine, prefix: &str) -> String {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    loop {
+        let line = engine.read_line(Some(deadline)).unwrap();
+        if line.starts_with(prefix) {
+            return line;
+        }
+    }
+}
+
+#[test]
+fn bad_options() {
+    let mut engine = UciEngine::spawn(env!("CARGO_BIN_EXE_chess-rs"), &[]).unwrap();
+    assert!(engine.options.iter().any(|x| x == "UCI_ShowWDL"));
+    for (name, value, message) in [
+        (
+            "Hash",
+            Some("lots"),
+            "info string Hash must be a number, got 'lots'",
+        ),
+        (
+            "Threads",
+            Some("0"),
+            "info string Threads must be between 1 and 512, got 0",
+        ),
+        (
+            "Ponder",
+            Some("yes"),
+            "info string Ponder must be true or false, got 'yes'",
+        ),
+        (
+            "Ponder",
+            None,
+            "info string Ponder must be true or false, got ''",
+        ),
+        (
+            "No Such Option",
+            Some("1"),
+            "info string No such option: No Such Option",
+        ),
+    ] {
+        engine.set_option(name, value).unwrap();
+        assert_eq!(read_until(&mut engine, "info string"), message);
+    }
+    //The engine is still there to answer
+    engine.is_ready().unwrap();
+    engine.quit();
+}
+
+#[test]
+fn show_wdl() {
+    let mut engine = UciEngine::spawn(env!("CARGO_BIN_EXE_chess-rs"), &[]).unwrap();
+    engine.position(None, &[]).unwrap();
+    let params = GoParams {
+        depth: Some(1),
+        ..GoParams::default()
+    };
+    engine.send(&UciCommand::Go(params.clone())).unwrap();
+    assert!(!read_until(&mut engine, "info depth").contains(" wdl "));
+    read_until(&mut engine, "bestmove");
+
+    engine.set_option("UCI_ShowWDL", Some("true")).unwrap();
+    engine.send(&UciCommand::Go(params)).unwrap();
+    let info = read_until(&mut engine, "info depth");
+    let tokens: Vec<&str> = info.split_whitespace().collect();
+    let idx = tokens.iter().position(|x| *x == "wdl").unwrap();
+    let chances: u32 = tokens[idx + 1..idx + 4]
+        .iter()
+        .map(|x| x.parse::<u32>().unwrap())
+        .sum();
+    assert_eq!(chances, 1000);
+    read_until(&mut engine, "bestmove");
+    engine.quit();
+}
+
+#[test]
+fn go_limits() {
+    let mut engine = UciEngine::spawn(env!("CARGO_BIN_EXE_chess-rs"), &[]).unwrap();
+    let timeout = Some(Duration::from_secs(30));
+    //A node limit replaces the Depth option, and the search stops soon after it
+    engine.position(None, &[]).unwrap();
+    let params = GoParams {
+        nodes: Some(3000),
+        ..GoParams::default()
+    };
+    let answer = engine.go(&params, timeout).unwrap();
+    assert!(answer.info.unwrap().nodes.unwrap() < 3300);
+
+    engine.position(None, &[]).unwrap();
+    let params = GoParams {
+        searchmoves: vec!["a2a3".to_string(), "h2h3".to_string()],
+        depth: Some(2),
+        ..GoParams::default()
+    };
+    let answer = engine.go(&params, timeout).unwrap();
+    assert!(["a2a3", "h2h3"].contains(&answer.best.as_str()));
+
+    //Mate in two: 1. Kc7 Ka7 2. Ra2#, found at depth 3 and not searched past it
+    engine
+        .position(Some("k7/8/2K5/8/8/8/1R6/8 w - - 0 1"), &[])
+        .unwrap();
+    let params = GoParams {
+        mate: Some(2),
+        ..GoParams::default()
+    };
+    let answer = engine.go(&params, timeout).unwrap();
+    let info = answer.info.unwrap();
+    assert_eq!(info.score, Some(EngineScore::Mate(2)));
+    assert_eq!(info.depth, Some(3));
+    engine.quit();
+}
+
+#[test]
+fn stop_after_timeout() {
+    let mut engine = UciEngine::spawn(env!("CARGO_BIN_EXE_chess-rs"), &[]).unwrap();
+    engine.position(None, &[]).unwrap();
+    //An infinite search only answers once the client tells it to stop
+    let params = GoParams {
+        infinite: true,
+        ..GoParams::default()
+    };
+    let answer = engine
+        .go(&params, Some(Duration::from_millis(300)))
+        .unwrap();
+    assert!(answer.elapsed >= Duration::from_millis(300));
+    let state = ChessState::new_board();
+    assert!(translator::uci_to_lan(&state, &answer.best)
+        .is_some_and(|m| state.get_all_possible_moves().contains(&m)));
+    engine.quit();
+}
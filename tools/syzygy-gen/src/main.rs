@@ -0,0 +1,1200 @@
+//Writes the small Syzygy tables in test-data/syzygy. Each endgame is solved by retrograde
+//iteration with its own move generator, written in the Syzygy format with a simple
+//compressor, and every position is then probed back through the engine's `Tablebases`.
+use chess_rs::chess_init::ChessState;
+use chess_rs::syzygy::{Tablebases, Wdl};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::process::ExitCode;
+
+/// The tables written, in an order where each one's captures and promotions lead to tables
+/// already solved, with the piece order to encode them in and whether they get a DTZ file
+const TABLES: [(&str, Option<[usize; 4]>, bool); 6] = [
+    ("KBvK", None, false),
+    ("KNvK", None, false),
+    ("KQvK", None, true),
+    ("KRvK", None, true),
+    ("KPvK", None, true),
+    //This order packs the WDL table best; its DTZ file would be about 400 KB
+    ("KQvKR", Some([2, 0, 3, 1]), false),
+];
+
+const PAWN: u8 = 1;
+const KING: u8 = 6;
+const BLACK: u8 = 8;
+
+const UNKNOWN: i8 = 100;
+const MATED: i8 = -3;
+
+fn letter_code(c: char) -> u8 {
+    match c {
+        'P' => 1,
+        'N' => 2,
+        'B' => 3,
+        'R' => 4,
+        'Q' => 5,
+        'K' => 6,
+        _ => panic!("bad piece {}", c),
+    }
+}
+
+fn code_letter(code: u8) -> char {
+    ['?', 'P', 'N', 'B', 'R', 'Q', 'K'][(code & 7) as usize]
+}
+
+const ORDER: &str = "KQRBNP";
+
+fn material_key(pcs: &[(u8, usize)]) -> String {
+    let side = |black: bool| {
+        let mut v: Vec<char> = pcs
+            .iter()
+            .filter(|(c, _)| (c & BLACK != 0) == black)
+            .map(|(c, _)| code_letter(*c))
+            .collect();
+        v.sort_by_key(|x| ORDER.find(*x));
+        v.into_iter().collect::<String>()
+    };
+    format!("{}v{}", side(false), side(true))
+}
+
+struct Spec {
+    name: String,
+    /// Piece codes in the order the table encodes them
+    pieces: Vec<u8>,
+    pawns: bool,
+    parts: usize,
+    part_size: usize,
+}
+
+fn spec(name: &str) -> Spec {
+    let (w, b) = name.split_once('v').unwrap();
+    let wc: Vec<u8> = w.chars().map(letter_code).collect();
+    let bc: Vec<u8> = b.chars().map(|x| letter_code(x) | BLACK).collect();
+    let pawns = wc.contains(&PAWN) || bc.contains(&PAWN);
+    assert!(!bc.contains(&(PAWN | BLACK)));
+    assert!(wc.iter().filter(|x| **x == PAWN).count() <= 1);
+    let mut pieces = Vec::new();
+    if pawns {
+        pieces.push(PAWN);
+    }
+    pieces.push(KING);
+    pieces.push(KING | BLACK);
+    for c in wc.iter().chain(bc.iter()) {
+        if *c & 7 != KING && *c != PAWN {
+            pieces.push(*c);
+        }
+    }
+    //All pieces must be unique for the simple grouping used here
+    for i in 0..pieces.len() {
+        for j in i + 1..pieces.len() {
+            assert_ne!(pieces[i], pieces[j]);
+        }
+    }
+    let n = pieces.len();
+    let (parts, mut size) = if pawns { (4, 6usize) } else { (1, 31332usize) };
+    let lead = if pawns { 1 } else { 3 };
+    for i in lead..n {
+        size *= 64 - i;
+    }
+    Spec {
+        name: name.to_string(),
+        pieces,
+        pawns,
+        parts,
+        part_size: size,
+    }
+}
+
+fn off(sq: usize) -> i32 {
+    (sq / 8) as i32 - (sq % 8) as i32
+}
+
+struct Maps {
+    a1d1d4: [u64; 64],
+    b1h1h7: [u64; 64],
+}
+
+fn maps() -> Maps {
+    let mut m = Maps {
+        a1d1d4: [0; 64],
+        b1h1h7: [0; 64],
+    };
+    let mut code = 0;
+    for sq in 0..64 {
+        if off(sq) < 0 {
+            m.b1h1h7[sq] = code;
+            code += 1;
+        }
+    }
+    let mut code = 0;
+    for sq in 0..28 {
+        if off(sq) < 0 && sq % 8 <= 3 {
+            m.a1d1d4[sq] = code;
+            code += 1;
+        }
+    }
+    for sq in 0..28 {
+        if off(sq) == 0 && sq % 8 <= 3 {
+            m.a1d1d4[sq] = code;
+            code += 1;
+        }
+    }
+    m
+}
+
+/// Table part and index of squares given in table order
+fn encode(spec: &Spec, m: &Maps, sqs: &[usize]) -> (usize, usize) {
+    let n = sqs.len();
+    let mut s = [0usize; 8];
+    s[..n].copy_from_slice(sqs);
+    if s[0] % 8 > 3 {
+        for x in s[..n].iter_mut() {
+            *x ^= 7;
+        }
+    }
+    let part;
+    let mut idx: u64;
+    let lead;
+    if spec.pawns {
+        part = s[0] % 8;
+        idx = (s[0] / 8 - 1) as u64;
+        lead = 1;
+    } else {
+        part = 0;
+        if s[0] / 8 > 3 {
+            for x in s[..n].iter_mut() {
+                *x ^= 56;
+            }
+        }
+        for i in 0..3 {
+            if off(s[i]) == 0 {
+                continue;
+            }
+            if off(s[i]) > 0 {
+                for x in s[i..n].iter_mut() {
+                    *x = ((*x >> 3) | (*x << 3)) & 63;
+                }
+            }
+            break;
+        }
+        let (s0, s1, s2) = (s[0], s[1], s[2]);
+        let a1 = (s1 > s0) as u64;
+        let a2 = (s2 > s0) as u64 + (s2 > s1) as u64;
+        let r = |x: usize| (x / 8) as u64;
+        idx = if off(s0) != 0 {
+            (m.a1d1d4[s0] * 63 + (s1 as u64 - a1)) * 62 + s2 as u64 - a2
+        } else if off(s1) != 0 {
+            (6 * 63 + r(s0) * 28 + m.b1h1h7[s1]) * 62 + s2 as u64 - a2
+        } else if off(s2) != 0 {
+            6 * 63 * 62 + 4 * 28 * 62 + r(s0) * 7 * 28 + (r(s1) - a1) * 28 + m.b1h1h7[s2]
+        } else {
+            6 * 63 * 62 + 4 * 28 * 62 + 4 * 7 * 28 + r(s0) * 7 * 6 + (r(s1) - a1) * 6 + (r(s2) - a2)
+        };
+        lead = 3;
+    }
+    let mut mult: u64 = if spec.pawns { 6 } else { 31332 };
+    for i in lead..n {
+        let adj = s[..i].iter().filter(|x| **x < s[i]).count();
+        idx += (s[i] - adj) as u64 * mult;
+        mult *= (64 - i) as u64;
+    }
+    (part, idx as usize)
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Entry {
+    wdl: i8,
+    dtz: i16,
+}
+
+struct Solved {
+    spec: Spec,
+    rep: [Vec<u32>; 2],
+    wdl: [Vec<i8>; 2],
+    dtz: [Vec<i16>; 2],
+}
+
+fn is_white(code: u8) -> bool {
+    code & BLACK == 0
+}
+
+fn slides(kind: u8) -> (bool, bool) {
+    //(orthogonal, diagonal)
+    match kind {
+        4 => (true, false),
+        3 => (false, true),
+        5 => (true, true),
+        _ => (false, false),
+    }
+}
+
+fn attacks(board: &[u8; 64], from: usize, code: u8, to: usize) -> bool {
+    let (ff, fr) = ((from % 8) as i32, (from / 8) as i32);
+    let (tf, tr) = ((to % 8) as i32, (to / 8) as i32);
+    let (df, dr) = (tf - ff, tr - fr);
+    match code & 7 {
+        6 => df.abs() <= 1 && dr.abs() <= 1 && (df, dr) != (0, 0),
+        2 => (df.abs() == 1 && dr.abs() == 2) || (df.abs() == 2 && dr.abs() == 1),
+        1 => df.abs() == 1 && dr == if is_white(code) { 1 } else { -1 },
+        kind => {
+            let (orth, diag) = slides(kind);
+            let line = (df == 0) != (dr == 0);
+            let diagonal = df != 0 && df.abs() == dr.abs();
+            if !((orth && line) || (diag && diagonal)) {
+                return false;
+            }
+            let (sf, sr) = (df.signum(), dr.signum());
+            let (mut f, mut r) = (ff + sf, fr + sr);
+            while (f, r) != (tf, tr) {
+                if board[(r * 8 + f) as usize] != 0 {
+                    return false;
+                }
+                f += sf;
+                r += sr;
+            }
+            true
+        }
+    }
+}
+
+fn in_check(board: &[u8; 64], pcs: &[(u8, usize)], white: bool) -> bool {
+    let king = pcs
+        .iter()
+        .find(|(c, _)| *c & 7 == KING && is_white(*c) == white)
+        .unwrap()
+        .1;
+    pcs.iter()
+        .any(|(c, s)| is_white(*c) != white && attacks(board, *s, *c, king))
+}
+
+fn make_board(pcs: &[(u8, usize)]) -> [u8; 64] {
+    let mut b = [0u8; 64];
+    for (c, s) in pcs {
+        b[*s] = *c;
+    }
+    b
+}
+
+struct Child {
+    pcs: Vec<(u8, usize)>,
+    zeroing: bool,
+}
+
+/// Legal moves of `white` as the positions they lead to
+fn children(pcs: &[(u8, usize)], white: bool, out: &mut Vec<Child>) {
+    out.clear();
+    let board = make_board(pcs);
+    for (i, (code, from)) in pcs.iter().enumerate() {
+        if is_white(*code) != white {
+            continue;
+        }
+        let mut targets: Vec<(usize, Option<u8>)> = Vec::new();
+        let (ff, fr) = ((*from % 8) as i32, (*from / 8) as i32);
+        let on = |f: i32, r: i32| (0..8).contains(&f) && (0..8).contains(&r);
+        let ok = |sq: usize| board[sq] == 0 || is_white(board[sq]) != white;
+        match code & 7 {
+            6 | 2 => {
+                let steps: &[(i32, i32)] = if code & 7 == 6 {
+                    &[
+                        (1, 0),
+                        (-1, 0),
+                        (0, 1),
+                        (0, -1),
+                        (1, 1),
+                        (1, -1),
+                        (-1, 1),
+                        (-1, -1),
+                    ]
+                } else {
+                    &[
+                        (1, 2),
+                        (2, 1),
+                        (-1, 2),
+                        (-2, 1),
+                        (1, -2),
+                        (2, -1),
+                        (-1, -2),
+                        (-2, -1),
+                    ]
+                };
+                for (df, dr) in steps {
+                    let (f, r) = (ff + df, fr + dr);
+                    if on(f, r) && ok((r * 8 + f) as usize) {
+                        targets.push(((r * 8 + f) as usize, None));
+                    }
+                }
+            }
+            1 => {
+                let dir = if white { 1 } else { -1 };
+                let last = if white { 7 } else { 0 };
+                let promos: &[Option<u8>] = &[Some(5), Some(4), Some(3), Some(2)];
+                let push = |sq: usize, v: &mut Vec<(usize, Option<u8>)>| {
+                    if (sq / 8) as i32 == last {
+                        for p in promos {
+                            v.push((sq, *p));
+                        }
+                    } else {
+                        v.push((sq, None));
+                    }
+                };
+                let one = fr + dir;
+                if on(ff, one) && board[(one * 8 + ff) as usize] == 0 {
+                    push((one * 8 + ff) as usize, &mut targets);
+                    let start = if white { 1 } else { 6 };
+                    let two = fr + 2 * dir;
+                    if fr == start && board[(two * 8 + ff) as usize] == 0 {
+                        targets.push(((two * 8 + ff) as usize, None));
+                    }
+                }
+                for df in [-1, 1] {
+                    let f = ff + df;
+                    if on(f, one) {
+                        let sq = (one * 8 + f) as usize;
+                        if board[sq] != 0 && is_white(board[sq]) != white {
+                            push(sq, &mut targets);
+                        }
+                    }
+                }
+            }
+            kind => {
+                let (orth, diag) = slides(kind);
+                let mut dirs: Vec<(i32, i32)> = Vec::new();
+                if orth {
+                    dirs.extend([(1, 0), (-1, 0), (0, 1), (0, -1)]);
+                }
+                if diag {
+                    dirs.extend([(1, 1), (1, -1), (-1, 1), (-1, -1)]);
+                }
+                for (df, dr) in dirs {
+                    let (mut f, mut r) = (ff + df, fr + dr);
+                    while on(f, r) {
+                        let sq = (r * 8 + f) as usize;
+                        if board[sq] != 0 {
+                            if is_white(board[sq]) != white {
+                                targets.push((sq, None));
+                            }
+                            break;
+                        }
+                        targets.push((sq, None));
+                        f += df;
+                        r += dr;
+                    }
+                }
+            }
+        }
+        for (to, promo) in targets {
+            let capture = board[to] != 0;
+            assert!(board[to] & 7 != KING || !capture, "king capture");
+            let mut next: Vec<(u8, usize)> = Vec::with_capacity(pcs.len());
+            for (j, (c, s)) in pcs.iter().enumerate() {
+                if j == i {
+                    let c = match promo {
+                        Some(p) => p | (c & BLACK),
+                        None => *c,
+                    };
+                    next.push((c, to));
+                } else if *s != to {
+                    next.push((*c, *s));
+                }
+            }
+            let nb = make_board(&next);
+            if in_check(&nb, &next, white) {
+                continue;
+            }
+            out.push(Child {
+                pcs: next,
+                zeroing: capture || code & 7 == PAWN,
+            });
+        }
+    }
+}
+
+fn decode(spec: &Spec, c: u32) -> Vec<(u8, usize)> {
+    let mut x = c as usize;
+    spec.pieces
+        .iter()
+        .map(|p| {
+            let s = x % 64;
+            x /= 64;
+            (*p, s)
+        })
+        .collect()
+}
+
+struct Gen {
+    maps: Maps,
+    done: HashMap<String, Solved>,
+}
+
+impl Gen {
+    /// Flat index of a position in the table of `spec`
+    fn locate(&self, spec: &Spec, pcs: &[(u8, usize)]) -> usize {
+        let mut sqs = [0usize; 8];
+        for (k, p) in spec.pieces.iter().enumerate() {
+            sqs[k] = pcs.iter().find(|(c, _)| c == p).unwrap().1;
+        }
+        let (part, idx) = encode(spec, &self.maps, &sqs[..spec.pieces.len()]);
+        part * spec.part_size + idx
+    }
+
+    /// WDL and DTZ of a finished table's position, None for the current table
+    fn lookup(&self, pcs: &[(u8, usize)], stm: usize, cur: &str) -> Option<Entry> {
+        let key = material_key(pcs);
+        if key == "KvK" {
+            return Some(Entry { wdl: 0, dtz: 0 });
+        }
+        if key == cur {
+            return None;
+        }
+        if let Some(t) = self.done.get(&key) {
+            let flat = self.locate(&t.spec, pcs);
+            return Some(Entry {
+                wdl: t.wdl[stm][flat],
+                dtz: t.dtz[stm][flat],
+            });
+        }
+        let (w, b) = key.split_once('v').unwrap();
+        let swapped = format!("{}v{}", b, w);
+        let t = self
+            .done
+            .get(&swapped)
+            .unwrap_or_else(|| panic!("missing table {}", key));
+        let flipped: Vec<(u8, usize)> = pcs.iter().map(|(c, s)| (c ^ BLACK, s ^ 56)).collect();
+        let flat = self.locate(&t.spec, &flipped);
+        Some(Entry {
+            wdl: t.wdl[stm ^ 1][flat],
+            dtz: t.dtz[stm ^ 1][flat],
+        })
+    }
+
+    /// What the reader accepts at each index: WDL values a capture already reaches may
+    /// be stored lower, and DTZ values it never reads may be anything
+    fn constraints(&self, name: &str) -> (Ranges, Ranges) {
+        let t = &self.done[name];
+        let total = t.spec.parts * t.spec.part_size;
+        let mut wdl = [vec![ANY; total], vec![ANY; total]];
+        let mut dtz = [vec![ANY; total], vec![ANY; total]];
+        let mut kids = Vec::new();
+        for stm in 0..2 {
+            for flat in 0..total {
+                if t.rep[stm][flat] == u32::MAX {
+                    continue;
+                }
+                let pcs = decode(&t.spec, t.rep[stm][flat]);
+                children(&pcs, stm == 0, &mut kids);
+                let v = t.wdl[stm][flat].max(-2);
+                let (mut caps, mut best_cap, mut zeros, mut best_zero) = (0, -2, 0, -2);
+                for k in kids.iter() {
+                    let capture = k.pcs.len() < pcs.len();
+                    if !k.zeroing {
+                        continue;
+                    }
+                    let e = self.lookup(&k.pcs, stm ^ 1, "").unwrap();
+                    let value = -e.wdl.max(-2);
+                    zeros += 1;
+                    best_zero = best_zero.max(value);
+                    if capture {
+                        caps += 1;
+                        best_cap = best_cap.max(value);
+                    }
+                }
+                let exact = (v + 2) as u16;
+                wdl[stm][flat] = if caps > 0 && caps == kids.len() {
+                    ANY
+                } else if caps > 0 && best_cap == v {
+                    (0, exact)
+                } else {
+                    (exact, exact)
+                };
+                let no_more = zeros > 0 && zeros == kids.len();
+                dtz[stm][flat] = if v == 0 || best_zero == 2 || (v == -2 && no_more) {
+                    ANY
+                } else {
+                    let d = t.dtz[stm][flat].unsigned_abs() - 1;
+                    (d, d)
+                };
+            }
+        }
+        (wdl, dtz)
+    }
+
+    fn solve(&mut self, name: &str) {
+        let spec = spec(name);
+        let n = spec.pieces.len();
+        let total = spec.parts * spec.part_size;
+        let mut rep: [Vec<u32>; 2] = [vec![u32::MAX; total], vec![u32::MAX; total]];
+        let mut sq = [0usize; 4];
+        let count = 64usize.pow(n as u32);
+        for c in 0..count {
+            let mut x = c;
+            for s in sq.iter_mut().take(n) {
+                *s = x % 64;
+                x /= 64;
+            }
+            let distinct = (0..n).all(|i| (i + 1..n).all(|j| sq[i] != sq[j]));
+            if !distinct {
+                continue;
+            }
+            let pcs: Vec<(u8, usize)> = (0..n).map(|i| (spec.pieces[i], sq[i])).collect();
+            if pcs
+                .iter()
+                .any(|(c, s)| *c & 7 == PAWN && (*s / 8 == 0 || *s / 8 == 7))
+            {
+                continue;
+            }
+            let board = make_board(&pcs);
+            let flat = self.locate(&spec, &pcs);
+            for (stm, side) in rep.iter_mut().enumerate() {
+                //The side that just moved may not be in check
+                if !in_check(&board, &pcs, stm == 1) && side[flat] == u32::MAX {
+                    side[flat] = c as u32;
+                }
+            }
+        }
+        let decode = |c: u32| decode(&spec, c);
+        let legal: usize = rep
+            .iter()
+            .map(|r| r.iter().filter(|x| **x != u32::MAX).count())
+            .sum();
+        eprintln!("{}: {} indices, {} legal", name, 2 * total, legal);
+
+        let mut wdl: [Vec<i8>; 2] = [vec![UNKNOWN; total], vec![UNKNOWN; total]];
+        let mut dtz: [Vec<i16>; 2] = [vec![0; total], vec![0; total]];
+        let mut kids = Vec::new();
+        let mut pass = 0;
+        loop {
+            pass += 1;
+            let mut changed = 0;
+            for stm in 0..2 {
+                for flat in 0..total {
+                    if rep[stm][flat] == u32::MAX || wdl[stm][flat] != UNKNOWN {
+                        continue;
+                    }
+                    let pcs = decode(rep[stm][flat]);
+                    children(&pcs, stm == 0, &mut kids);
+                    let result = if kids.is_empty() {
+                        let board = make_board(&pcs);
+                        if in_check(&board, &pcs, stm == 0) {
+                            MATED
+                        } else {
+                            0
+                        }
+                    } else {
+                        let mut all_win = true;
+                        let mut win = false;
+                        for k in kids.iter() {
+                            let v = match self.lookup(&k.pcs, stm ^ 1, &spec.name) {
+                                Some(e) => e.wdl,
+                                None => wdl[stm ^ 1][self.locate(&spec, &k.pcs)],
+                            };
+                            if v == -2 || v == MATED {
+                                win = true;
+                                break;
+                            }
+                            if v != 2 {
+                                all_win = false;
+                            }
+                        }
+                        if win {
+                            2
+                        } else if all_win {
+                            -2
+                        } else {
+                            UNKNOWN
+                        }
+                    };
+                    if result != UNKNOWN {
+                        wdl[stm][flat] = result;
+                        changed += 1;
+                    }
+                }
+            }
+            eprintln!("  wdl pass {}: {}", pass, changed);
+            if changed == 0 {
+                break;
+            }
+        }
+        for stm in 0..2 {
+            for flat in 0..total {
+                if rep[stm][flat] != u32::MAX && wdl[stm][flat] == UNKNOWN {
+                    wdl[stm][flat] = 0;
+                }
+            }
+        }
+
+        let mut level: i16 = 0;
+        loop {
+            level += 1;
+            let mut unresolved = 0;
+            for stm in 0..2 {
+                for flat in 0..total {
+                    if rep[stm][flat] == u32::MAX || wdl[stm][flat] == 0 || dtz[stm][flat] != 0 {
+                        continue;
+                    }
+                    if wdl[stm][flat] == MATED {
+                        dtz[stm][flat] = -1;
+                        continue;
+                    }
+                    let pcs = decode(rep[stm][flat]);
+                    children(&pcs, stm == 0, &mut kids);
+                    let winning = wdl[stm][flat] == 2;
+                    let mut result: i16 = 0;
+                    let mut worst: i16 = 0;
+                    let mut resolved = true;
+                    for k in kids.iter() {
+                        let (cw, cd) = match self.lookup(&k.pcs, stm ^ 1, &spec.name) {
+                            Some(e) => (e.wdl, e.dtz),
+                            None => {
+                                let f = self.locate(&spec, &k.pcs);
+                                (wdl[stm ^ 1][f], dtz[stm ^ 1][f])
+                            }
+                        };
+                        if winning {
+                            if cw == MATED || (k.zeroing && cw == -2) {
+                                result = 1;
+                                break;
+                            }
+                            if !k.zeroing && cw == -2 && cd < 0 && -cd == level - 1 {
+                                result = level;
+                            }
+                        } else {
+                            assert!(cw == 2, "losing side reaches {} in {}", cw, name);
+                            if k.zeroing {
+                                worst = worst.max(1);
+                            } else if cd > 0 && cd < level {
+                                worst = worst.max(cd + 1);
+                            } else {
+                                resolved = false;
+                                break;
+                            }
+                        }
+                    }
+                    if !winning && resolved {
+                        assert!(worst <= level && worst > 0);
+                        result = -worst;
+                    }
+                    if result != 0 {
+                        dtz[stm][flat] = result;
+                    } else {
+                        unresolved += 1;
+                    }
+                }
+            }
+            if unresolved == 0 {
+                break;
+            }
+            assert!(level < 300, "dtz does not converge");
+        }
+        let max = dtz.iter().flatten().map(|x| x.abs()).max().unwrap();
+        eprintln!("  max dtz {}", max);
+        assert!(max <= 100, "cursed results are not supported");
+        self.done.insert(
+            name.to_string(),
+            Solved {
+                spec,
+                rep,
+                wdl,
+                dtz,
+            },
+        );
+    }
+}
+
+//Writing the files
+
+enum Sym {
+    Leaf(u16),
+    Pair(usize, usize),
+}
+
+struct Part {
+    sizes: Vec<u8>,
+    sparse: Vec<u8>,
+    lengths: Vec<u8>,
+    data: Vec<u8>,
+}
+
+fn huffman_lengths(freq: &[u64], limit: u32) -> Vec<u32> {
+    let mut f: Vec<u64> = freq.iter().map(|x| (*x).max(1)).collect();
+    loop {
+        let n = f.len();
+        let mut heap: BinaryHeap<Reverse<(u64, usize)>> = BinaryHeap::new();
+        let mut parent: Vec<usize> = vec![usize::MAX; n];
+        for (i, x) in f.iter().enumerate() {
+            heap.push(Reverse((*x, i)));
+        }
+        let mut next = n;
+        while heap.len() > 1 {
+            let Reverse((a, i)) = heap.pop().unwrap();
+            let Reverse((b, j)) = heap.pop().unwrap();
+            parent.push(usize::MAX);
+            parent[i] = next;
+            parent[j] = next;
+            heap.push(Reverse((a + b, next)));
+            next += 1;
+        }
+        let mut len = vec![0u32; n];
+        for (i, l) in len.iter_mut().enumerate() {
+            let mut p = i;
+            while parent[p] != usize::MAX {
+                p = parent[p];
+                *l += 1;
+            }
+        }
+        if len.iter().all(|x| *x <= limit) {
+            return len;
+        }
+        for x in f.iter_mut() {
+            *x = *x / 2 + 1;
+        }
+    }
+}
+
+const ANY: (u16, u16) = (0, 0xFFE);
+
+/// The values each index of a table may take for either side to move, as inclusive ranges
+type Ranges = [Vec<(u16, u16)>; 2];
+
+fn compress(ranges: &[(u16, u16)], flags: u8) -> Part {
+    //Values free to choose continue the run before them where they can
+    let mut last = ranges.iter().find(|x| x.0 == x.1).map(|x| x.0).unwrap_or(0);
+    let vals: Vec<u16> = ranges
+        .iter()
+        .map(|(lo, hi)| {
+            last = last.clamp(*lo, *hi);
+            last
+        })
+        .collect();
+    if vals.iter().all(|x| *x == vals[0]) {
+        return Part {
+            sizes: vec![flags | 128, vals[0] as u8],
+            sparse: vec![],
+            lengths: vec![],
+            data: vec![],
+        };
+    }
+    //Symbols for runs of 2^k equal values
+    let mut syms: Vec<Sym> = Vec::new();
+    let mut run_sym: HashMap<(u16, u32), usize> = HashMap::new();
+    fn get(syms: &mut Vec<Sym>, run_sym: &mut HashMap<(u16, u32), usize>, v: u16, k: u32) -> usize {
+        if let Some(x) = run_sym.get(&(v, k)) {
+            return *x;
+        }
+        let s = if k == 0 {
+            Sym::Leaf(v)
+        } else {
+            let h = get(syms, run_sym, v, k - 1);
+            Sym::Pair(h, h)
+        };
+        syms.push(s);
+        run_sym.insert((v, k), syms.len() - 1);
+        syms.len() - 1
+    }
+    let mut seq: Vec<usize> = Vec::new();
+    let mut i = 0;
+    while i < vals.len() {
+        let v = vals[i];
+        let mut j = i;
+        while j < vals.len() && vals[j] == v {
+            j += 1;
+        }
+        let mut len = j - i;
+        while len > 0 {
+            let k = (usize::BITS - 1 - len.leading_zeros()).min(8);
+            seq.push(get(&mut syms, &mut run_sym, v, k));
+            len -= 1 << k;
+        }
+        i = j;
+    }
+    let mut counts: Vec<usize> = Vec::new();
+    for sym in syms.iter() {
+        counts.push(match sym {
+            Sym::Leaf(_) => 1,
+            Sym::Pair(a, b) => counts[*a] + counts[*b],
+        });
+    }
+    //Re-Pair: replace the most frequent neighbouring symbols by new ones
+    loop {
+        let room = 4000usize.saturating_sub(syms.len());
+        if room == 0 {
+            break;
+        }
+        let mut pairs: HashMap<(usize, usize), u32> = HashMap::new();
+        for w in seq.windows(2) {
+            *pairs.entry((w[0], w[1])).or_insert(0) += 1;
+        }
+        let mut cand: Vec<((usize, usize), u32)> = pairs
+            .into_iter()
+            .filter(|((a, b), f)| *f >= 12 && counts[*a] + counts[*b] <= 256)
+            .collect();
+        if cand.is_empty() {
+            break;
+        }
+        cand.sort_by_key(|(p, f)| (Reverse(*f), *p));
+        cand.truncate(room.min(32));
+        let mut new: HashMap<(usize, usize), usize> = HashMap::new();
+        for ((a, b), _) in cand {
+            syms.push(Sym::Pair(a, b));
+            counts.push(counts[a] + counts[b]);
+            new.insert((a, b), syms.len() - 1);
+        }
+        let mut out = Vec::with_capacity(seq.len());
+        let mut i = 0;
+        while i < seq.len() {
+            if i + 1 < seq.len() {
+                if let Some(x) = new.get(&(seq[i], seq[i + 1])) {
+                    out.push(*x);
+                    i += 2;
+                    continue;
+                }
+            }
+            out.push(seq[i]);
+            i += 1;
+        }
+        seq = out;
+    }
+    assert!(syms.len() < 4095);
+    let mut freq = vec![0u64; syms.len()];
+    for s in seq.iter() {
+        freq[*s] += 1;
+    }
+    let lens = huffman_lengths(&freq, 24);
+    let max_len = *lens.iter().max().unwrap();
+    let min_len = *lens.iter().min().unwrap();
+    //Longer codes take the lower symbol numbers
+    let mut order: Vec<usize> = (0..syms.len()).collect();
+    order.sort_by_key(|s| (Reverse(lens[*s]), *s));
+    let mut number = vec![0usize; syms.len()];
+    for (n, s) in order.iter().enumerate() {
+        number[*s] = n;
+    }
+    let nlen = (max_len - min_len + 1) as usize;
+    let mut count_of = vec![0u64; 40];
+    for l in lens.iter() {
+        count_of[*l as usize] += 1;
+    }
+    let mut lowest = vec![0u64; 40];
+    let mut acc = 0;
+    for l in (min_len..=max_len).rev() {
+        lowest[l as usize] = acc;
+        acc += count_of[l as usize];
+    }
+    let mut base = vec![0u64; 40];
+    for l in (min_len..max_len).rev() {
+        let sum = base[l as usize + 1] + count_of[l as usize + 1];
+        assert!(sum.is_multiple_of(2));
+        base[l as usize] = sum / 2;
+    }
+    assert_eq!(
+        base[min_len as usize] + count_of[min_len as usize],
+        1 << min_len
+    );
+    let code = |s: usize| -> (u64, u32) {
+        let l = lens[s];
+        (base[l as usize] + number[s] as u64 - lowest[l as usize], l)
+    };
+
+    //Blocks of whole symbols
+    const BLOCK_LOG: u8 = 6;
+    let block_bits = 8usize << BLOCK_LOG;
+    const CAP: usize = 57000;
+    let mut blocks: Vec<(Vec<u8>, usize)> = Vec::new();
+    let mut bits: Vec<bool> = Vec::new();
+    let mut values_in = 0;
+    let flush =
+        |bits: &mut Vec<bool>, values_in: &mut usize, blocks: &mut Vec<(Vec<u8>, usize)>| {
+            let mut bytes = vec![0u8; 1 << BLOCK_LOG];
+            for (i, b) in bits.iter().enumerate() {
+                if *b {
+                    bytes[i / 8] |= 0x80 >> (i % 8);
+                }
+            }
+            blocks.push((bytes, *values_in));
+            bits.clear();
+            *values_in = 0;
+        };
+    for s in seq.iter() {
+        let (c, l) = code(*s);
+        if bits.len() + l as usize > block_bits || values_in + counts[*s] > CAP {
+            flush(&mut bits, &mut values_in, &mut blocks);
+        }
+        for b in (0..l).rev() {
+            bits.push((c >> b) & 1 == 1);
+        }
+        values_in += counts[*s];
+    }
+    flush(&mut bits, &mut values_in, &mut blocks);
+    let total = vals.len();
+    let num_blocks = blocks.len();
+    let span_log = {
+        let avg = total / num_blocks;
+        let mut l = 6u8;
+        while (1usize << l) < avg && l < 14 {
+            l += 1;
+        }
+        l
+    };
+    let span = 1usize << span_log;
+    let mut starts = Vec::with_capacity(num_blocks);
+    let mut acc = 0;
+    for (_, c) in blocks.iter() {
+        starts.push(acc);
+        acc += c;
+    }
+    assert_eq!(acc, total);
+    let mut sparse = Vec::new();
+    let entries = total.div_ceil(span);
+    let mut b = 0;
+    for i in 0..entries {
+        let a = i * span + span / 2;
+        while b + 1 < num_blocks && starts[b + 1] <= a {
+            b += 1;
+        }
+        let offset = a - starts[b];
+        assert!(offset <= 0xFFFF);
+        sparse.extend_from_slice(&(b as u32).to_le_bytes());
+        sparse.extend_from_slice(&(offset as u16).to_le_bytes());
+    }
+    let mut lengths = Vec::new();
+    for (_, c) in blocks.iter() {
+        lengths.extend_from_slice(&((c - 1) as u16).to_le_bytes());
+    }
+    let mut sizes = vec![flags, BLOCK_LOG, span_log, 0];
+    sizes.extend_from_slice(&(num_blocks as u32).to_le_bytes());
+    sizes.push(max_len as u8);
+    sizes.push(min_len as u8);
+    for i in 0..nlen {
+        sizes.extend_from_slice(&(lowest[min_len as usize + i] as u16).to_le_bytes());
+    }
+    sizes.extend_from_slice(&(syms.len() as u16).to_le_bytes());
+    for s in order.iter() {
+        let (left, right) = match syms[*s] {
+            Sym::Leaf(v) => (v as usize, 0xFFF),
+            Sym::Pair(a, b) => (number[a], number[b]),
+        };
+        sizes.push((left & 0xFF) as u8);
+        sizes.push(((left >> 8) & 0xF) as u8 | ((right & 0xF) << 4) as u8);
+        sizes.push((right >> 4) as u8);
+    }
+    if syms.len() & 1 == 1 {
+        sizes.push(0);
+    }
+    let data = blocks.into_iter().flat_map(|(b, _)| b).collect();
+    Part {
+        sizes,
+        sparse,
+        lengths,
+        data,
+    }
+}
+
+fn write_table(spec: &Spec, parts: Vec<Vec<Part>>, dtz: bool, path: &str) -> std::io::Result<()> {
+    let sides = parts[0].len();
+    let mut out: Vec<u8> = if dtz {
+        vec![0xD7, 0x66, 0x0C, 0xA5]
+    } else {
+        vec![0x71, 0xE8, 0x23, 0x5D]
+    };
+    out.push((sides == 2) as u8 | (spec.pawns as u8) << 1);
+    //Every part starts with its order byte, 0 as there is at most one pawn
+    let mut part = vec![0];
+    part.extend(
+        spec.pieces
+            .iter()
+            .map(|p| if sides == 2 { p | p << 4 } else { *p }),
+    );
+    for _ in 0..spec.parts {
+        out.extend_from_slice(&part);
+    }
+    if out.len() & 1 == 1 {
+        out.push(0);
+    }
+    for part in parts.iter() {
+        for side in part.iter() {
+            out.extend_from_slice(&side.sizes);
+        }
+    }
+    if dtz && out.len() & 1 == 1 {
+        out.push(0);
+    }
+    for part in parts.iter() {
+        for side in part.iter() {
+            out.extend_from_slice(&side.sparse);
+        }
+    }
+    for part in parts.iter() {
+        for side in part.iter() {
+            out.extend_from_slice(&side.lengths);
+        }
+    }
+    for part in parts.iter() {
+        for side in part.iter() {
+            while !out.len().is_multiple_of(64) {
+                out.push(0);
+            }
+            out.extend_from_slice(&side.data);
+        }
+    }
+    //The decoder reads a little past the last block
+    out.extend_from_slice(&[0; 16]);
+    std::fs::write(path, &out)?;
+    eprintln!("  wrote {} ({} bytes)", path, out.len());
+    Ok(())
+}
+
+/// Rewrites the constraints of `spec`'s table for the same pieces encoded in `order`
+fn reorder(g: &Gen, spec: &Spec, order: &Spec, ranges: (Ranges, Ranges)) -> (Ranges, Ranges) {
+    let t = &g.done[&spec.name];
+    let n = spec.pieces.len();
+    let total = spec.parts * spec.part_size;
+    let mut wdl = [vec![ANY; total], vec![ANY; total]];
+    let mut dtz = [vec![ANY; total], vec![ANY; total]];
+    for c in 0..64usize.pow(n as u32) {
+        let pcs = decode(spec, c as u32);
+        if (0..n).any(|i| (i + 1..n).any(|j| pcs[i].1 == pcs[j].1)) {
+            continue;
+        }
+        let from = g.locate(spec, &pcs);
+        let to = g.locate(order, &pcs);
+        for stm in 0..2 {
+            if t.rep[stm][from] != u32::MAX {
+                wdl[stm][to] = ranges.0[stm][from];
+                dtz[stm][to] = ranges.1[stm][from];
+            }
+        }
+    }
+    (wdl, dtz)
+}
+
+/// The position of a table entry in FEN
+fn fen(spec: &Spec, c: u32, stm: usize) -> String {
+    let mut board = [0u8; 64];
+    for (code, square) in decode(spec, c) {
+        board[square] = code;
+    }
+    let mut fen = String::new();
+    for rank in (0..8).rev() {
+        let mut empty = 0;
+        for file in 0..8 {
+            let c = board[rank * 8 + file];
+            if c == 0 {
+                empty += 1;
+                continue;
+            }
+            if empty > 0 {
+                fen.push_str(&empty.to_string());
+                empty = 0;
+            }
+            let l = code_letter(c);
+            fen.push(if is_white(c) {
+                l
+            } else {
+                l.to_ascii_lowercase()
+            });
+        }
+        if empty > 0 {
+            fen.push_str(&empty.to_string());
+        }
+        if rank > 0 {
+            fen.push('/');
+        }
+    }
+    format!("{} {} - - 0 1", fen, if stm == 0 { "w" } else { "b" })
+}
+
+/// Probes every position of the solved table `name` through the files in `dir`, and for
+/// KPvK also through the engine's KPK bitbase. Returns the number of disagreements.
+fn verify(g: &Gen, dir: &str, name: &str, dtz: bool) -> usize {
+    let tables = Tablebases::new(dir);
+    let t = &g.done[name];
+    let mut wrong = 0;
+    for stm in 0..2 {
+        for flat in 0..t.spec.parts * t.spec.part_size {
+            if t.rep[stm][flat] == u32::MAX {
+                continue;
+            }
+            let fen = fen(&t.spec, t.rep[stm][flat], stm);
+            let state = ChessState::from_fen(&fen);
+            let expected = match t.wdl[stm][flat] {
+                0 => Wdl::Draw,
+                2 => Wdl::Win,
+                _ => Wdl::Loss,
+            };
+            let mut ok = tables.probe_wdl(&state) == Some(expected);
+            if dtz {
+                ok &= tables.probe_dtz(&state) == Some(t.dtz[stm][flat] as i32);
+            }
+            if name == "KPvK" && !state.is_terminal() {
+                let white_wins =
+                    (expected == Wdl::Win) == state.turn.is_white() && expected != Wdl::Draw;
+                ok &= white_wins == (state.endgame_eval().unwrap() >= 10000);
+            }
+            if !ok {
+                wrong += 1;
+                if wrong <= 10 {
+                    eprintln!("  {} disagrees", fen);
+                }
+            }
+        }
+    }
+    eprintln!("  {}: {} disagreements", name, wrong);
+    wrong
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().collect();
+    let Some(dir) = args.get(1) else {
+        eprintln!("usage: syzygy-gen DIR, writing every table into DIR and checking it");
+        return ExitCode::FAILURE;
+    };
+    let mut g = Gen {
+        maps: maps(),
+        done: HashMap::new(),
+    };
+    let mut wrong = 0;
+    for (name, order, with_dtz) in TABLES {
+        g.solve(name);
+        let spec = &g.done[name].spec;
+        let mut ranges = g.constraints(name);
+        let mut encoded = self::spec(name);
+        if let Some(order) = order {
+            encoded.pieces = order.iter().map(|i| spec.pieces[*i]).collect();
+            ranges = reorder(&g, spec, &encoded, ranges);
+        }
+        let (wdl_ranges, dtz_ranges) = ranges;
+        let mut wdl_parts = Vec::new();
+        let mut dtz_parts = Vec::new();
+        for part in 0..encoded.parts {
+            let range = part * encoded.part_size..(part + 1) * encoded.part_size;
+            let sides = (0..2)
+                .map(|stm| compress(&wdl_ranges[stm][range.clone()], 0))
+                .collect();
+            wdl_parts.push(sides);
+            //Distances in plies for wins and losses, for the side to move that packs smaller
+            let best = (0..2)
+                .map(|stm| compress(&dtz_ranges[stm][range.clone()], 4 | 8 | stm as u8))
+                .min_by_key(|x| x.sizes.len() + x.sparse.len() + x.lengths.len() + x.data.len())
+                .unwrap();
+            dtz_parts.push(vec![best]);
+        }
+        let written = write_table(
+            &encoded,
+            wdl_parts,
+            false,
+            &format!("{}/{}.rtbw", dir, name),
+        )
+        .and_then(|_| match with_dtz {
+            true => write_table(&encoded, dtz_parts, true, &format!("{}/{}.rtbz", dir, name)),
+            false => Ok(()),
+        });
+        if let Err(err) = written {
+            eprintln!("Could not write {} into {}: {}", name, dir, err);
+            return ExitCode::FAILURE;
+        }
+        wrong += verify(&g, dir, name, with_dtz);
+    }
+    if wrong > 0 {
+        return ExitCode::FAILURE;
+    }
+    ExitCode::SUCCESS
+}